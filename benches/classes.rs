@@ -0,0 +1,44 @@
+use std::io::{stderr, stdout};
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use loxide::VM;
+
+/// A base class with `methods` methods, and a function deriving a chain of
+/// `depth` classes from it. Each class in the chain overrides one method when
+/// `override` is set.
+fn hierarchy(methods: usize, depth: usize, override_: bool) -> String {
+    let base: String = (0..methods)
+        .map(|i| format!("m{i}() {{ return {i}; }}\n"))
+        .collect();
+    let body = if override_ { "m0() { return -1; }" } else { "" };
+    format!(
+        r#"
+        class Base {{ {base} }}
+        fun derive() {{
+            var current = Base;
+            for (var i = 0; i < {depth}; i = i + 1) {{
+                class Derived < current {{ {body} }}
+                current = Derived;
+            }}
+            return current;
+        }}
+        derive()().m{last}();
+        "#,
+        last = methods - 1
+    )
+}
+
+pub fn classes_benchmark(c: &mut Criterion) {
+    let mut vm = VM::new(stdout(), stderr());
+    let inheriting = hierarchy(50, 1000, false);
+    c.bench_function("inherit 50 methods 1000 deep", |b| {
+        b.iter(|| vm.interpret(black_box(&inheriting)))
+    });
+    let overriding = hierarchy(50, 1000, true);
+    c.bench_function("override 1 of 50 methods 1000 deep", |b| {
+        b.iter(|| vm.interpret(black_box(&overriding)))
+    });
+}
+
+criterion_group!(benches, classes_benchmark);
+criterion_main!(benches);
@@ -0,0 +1,55 @@
+use std::io::{stderr, stdout};
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use loxide::VM;
+
+/// A script of `count` functions, each declaring a class whose methods use
+/// locals, upvalues, strings and control flow, so every part of the scanner
+/// and compiler gets exercised. Each class lives in its own function to keep
+/// the script's chunk within its limits on constants and globals.
+fn generated_source(count: usize) -> String {
+    let mut source = String::new();
+    for i in 0..count {
+        source.push_str(&format!(
+            r#"
+            fun make{i}() {{
+            class Shape{i} {{
+                init(width, height) {{
+                    this.width = width;
+                    this.height = height;
+                    this.name = "shape number {i}";
+                }}
+                area() {{
+                    var total = 0;
+                    for (var row = 0; row < this.height; row = row + 1) {{
+                        total = total + this.width;
+                    }}
+                    return total;
+                }}
+                scaled(factor) {{
+                    fun scale(value) {{ return value * factor; }}
+                    if (factor > 1 and factor < 100) {{
+                        return Shape{i}(scale(this.width), scale(this.height));
+                    }} else {{
+                        return this;
+                    }}
+                }}
+            }}
+            return Shape{i}({i}, {i} + 1.5);
+            }}
+            "#
+        ));
+    }
+    source
+}
+
+pub fn compile_benchmark(c: &mut Criterion) {
+    let source = generated_source(200);
+    let mut vm = VM::new(stdout(), stderr());
+    c.bench_function("compile 200 classes", |b| {
+        b.iter(|| vm.compile(black_box(&source)))
+    });
+}
+
+criterion_group!(benches, compile_benchmark);
+criterion_main!(benches);
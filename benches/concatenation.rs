@@ -0,0 +1,39 @@
+use std::io::{stderr, stdout};
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use loxide::VM;
+
+pub fn concatenation_benchmark(c: &mut Criterion) {
+    let concatenated = r#"
+        var s = "";
+        for (var i = 0; i < 2000; i = i + 1) {
+            s = s + "piece";
+        }
+    "#;
+    let built = r#"
+        var builder = stringBuilder();
+        for (var i = 0; i < 2000; i = i + 1) {
+            append(builder, "piece");
+        }
+        var s = toString(builder);
+    "#;
+    // Every result fits inline, so none of these strings allocate on their own
+    let short = r#"
+        for (var i = 0; i < 2000; i = i + 1) {
+            var s = "key" + "name";
+        }
+    "#;
+    let mut vm = VM::new(stdout(), stderr());
+    c.bench_function("concatenate 2000", |b| {
+        b.iter(|| vm.interpret(black_box(concatenated)))
+    });
+    c.bench_function("string builder 2000", |b| {
+        b.iter(|| vm.interpret(black_box(built)))
+    });
+    c.bench_function("short concatenations 2000", |b| {
+        b.iter(|| vm.interpret(black_box(short)))
+    });
+}
+
+criterion_group!(benches, concatenation_benchmark);
+criterion_main!(benches);
@@ -1,7 +1,7 @@
 use std::io::{stderr, stdout};
 
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
-use loxide::vm::VM;
+use loxide::VM;
 
 pub fn fibonacci_benchmark(c: &mut Criterion) {
     let source = r#"
@@ -0,0 +1,29 @@
+use std::fs::File;
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use loxide::{OutputBuffering, VM};
+
+pub fn output_benchmark(c: &mut Criterion) {
+    let lines = r#"
+        for (var i = 0; i < 100000; i = i + 1) {
+            print i;
+        }
+    "#;
+    let mut group = c.benchmark_group("print 100000 lines");
+    group.sample_size(10);
+    for (name, buffering) in [
+        ("line", OutputBuffering::Line),
+        ("block", OutputBuffering::Block(8 * 1024)),
+        ("manual", OutputBuffering::Manual),
+    ] {
+        // A real file, so every write the VM makes is a system call
+        let null = File::create("/dev/null").expect("Failed to open /dev/null");
+        let mut vm = VM::new(null, std::io::stderr());
+        vm.set_output_buffering(buffering);
+        group.bench_function(name, |b| b.iter(|| vm.interpret(black_box(lines))));
+    }
+    group.finish();
+}
+
+criterion_group!(benches, output_benchmark);
+criterion_main!(benches);
@@ -0,0 +1,99 @@
+//! A calculator that evaluates each line of its input as a Lox expression.
+//!
+//! Run it with `cargo run --example calculator` and type expressions such as
+//! `sqrt(2) * 10`. The last result is kept in `ans`, so `ans + 1` builds on it.
+//! Shows evaluating host input, registering natives and reading values back.
+
+use std::io::{stdin, BufRead};
+
+use loxide::{LoxValue, VM};
+
+struct Calculator {
+    vm: VM<Vec<u8>, Vec<u8>>,
+}
+
+impl Calculator {
+    fn new() -> Self {
+        let mut vm = VM::new(Vec::new(), Vec::new());
+        vm.register_native("sqrt", 1, |args| match args[0] {
+            LoxValue::Number(n) if n >= 0.0 => Ok(n.sqrt().into()),
+            LoxValue::Number(_) => Err("Can't take the square root of a negative number.".into()),
+            _ => Err("Operand must be a number.".into()),
+        });
+        vm.register_native("pow", 2, |args| match (&args[0], &args[1]) {
+            (LoxValue::Number(base), LoxValue::Number(exponent)) => Ok(base.powf(*exponent).into()),
+            _ => Err("Operands must be numbers.".into()),
+        });
+        vm.interpret("var ans = 0;").expect("Failed to define ans");
+        Self { vm }
+    }
+
+    /// Evaluates `expression`, returning its value or the error it raised.
+    fn evaluate(&mut self, expression: &str) -> Result<LoxValue, String> {
+        let (result, _, errors) = self.vm.interpret_captured(&format!("ans = {expression};"));
+        match result {
+            Ok(()) => Ok(self.vm.global("ans").unwrap_or(LoxValue::Nil)),
+            Err(error) => {
+                // Leave the VM ready for the next line, keeping `ans`
+                self.vm.reset(false);
+                match errors.lines().next() {
+                    Some(message) => Err(message.into()),
+                    None => Err(error.to_string()),
+                }
+            }
+        }
+    }
+}
+
+fn main() {
+    let mut calculator = Calculator::new();
+    for line in stdin().lock().lines() {
+        let line = line.expect("Failed to read input");
+        if line.trim().is_empty() {
+            continue;
+        }
+        match calculator.evaluate(&line) {
+            Ok(value) => println!("{value}"),
+            Err(message) => eprintln!("error: {message}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_evaluates_expressions() {
+        let mut calculator = Calculator::new();
+        assert_eq!(calculator.evaluate("1 + 2 * 3"), Ok(LoxValue::Number(7.0)));
+        assert_eq!(calculator.evaluate("ans * 2"), Ok(LoxValue::Number(14.0)));
+        assert_eq!(
+            calculator.evaluate("pow(2, 10)"),
+            Ok(LoxValue::Number(1024.0))
+        );
+        assert_eq!(
+            calculator.evaluate("sqrt(16) + ans"),
+            Ok(LoxValue::Number(1028.0))
+        );
+        assert_eq!(
+            calculator.evaluate("\"a\" + \"b\""),
+            Ok(LoxValue::String("ab".into()))
+        );
+    }
+
+    #[test]
+    fn it_reports_errors_and_keeps_going() {
+        let mut calculator = Calculator::new();
+        calculator.evaluate("5").expect("Failed to evaluate");
+        assert_eq!(
+            calculator.evaluate("sqrt(-1)"),
+            Err("Can't take the square root of a negative number.".into())
+        );
+        assert_eq!(
+            calculator.evaluate("1 + nil"),
+            Err("Operands must be two numbers or two strings.".into())
+        );
+        assert_eq!(calculator.evaluate("ans"), Ok(LoxValue::Number(5.0)));
+    }
+}
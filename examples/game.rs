@@ -0,0 +1,147 @@
+//! A game loop whose behaviour is scripted in Lox.
+//!
+//! Run it with `cargo run --example game`. The host owns the world and hands
+//! scripts natives to read and change it, then calls the script's `onTick`
+//! every frame. Whatever the script prints or raises goes to the host's log.
+
+use std::{cell::RefCell, collections::BTreeMap, rc::Rc};
+
+use loxide::{LoxValue, VM};
+
+const SCRIPT: &str = r#"
+spawn("slime", 3);
+spawn("goblin", 5);
+
+var goblinsTurn = false;
+
+fun onTick(tick) {
+    goblinsTurn = !goblinsTurn;
+    var target = "slime";
+    if (goblinsTurn) target = "goblin";
+    if (health(target) > 0) {
+        if (damage(target, 2) > 0) {
+            print target + " was hit";
+        } else {
+            print target + " was defeated";
+        }
+    }
+}
+"#;
+
+/// The state the script reads and changes through natives.
+#[derive(Debug, Default)]
+struct World {
+    health: BTreeMap<String, f64>,
+}
+
+struct Game {
+    vm: VM,
+    world: Rc<RefCell<World>>,
+    log: Rc<RefCell<Vec<String>>>,
+}
+
+impl Game {
+    fn new(script: &str) -> Result<Self, loxide::Error> {
+        let world = Rc::new(RefCell::new(World::default()));
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let mut vm = VM::new(std::io::stdout(), std::io::stderr());
+
+        let lines = log.clone();
+        vm.on_print(move |line| lines.borrow_mut().push(line.to_string()));
+        let errors = log.clone();
+        vm.on_error(move |error| {
+            errors
+                .borrow_mut()
+                .push(format!("error: {}", error.message))
+        });
+
+        let spawned = world.clone();
+        vm.register_native("spawn", 2, move |args| match args {
+            [LoxValue::String(name), LoxValue::Number(health)] => {
+                spawned.borrow_mut().health.insert(name.clone(), *health);
+                Ok(LoxValue::Nil)
+            }
+            _ => Err("spawn expects a name and a health.".into()),
+        });
+        let read = world.clone();
+        vm.register_native("health", 1, move |args| match &args[0] {
+            LoxValue::String(name) => Ok(read
+                .borrow()
+                .health
+                .get(name)
+                .copied()
+                .unwrap_or(0.0)
+                .into()),
+            _ => Err("health expects a name.".into()),
+        });
+        let damaged = world.clone();
+        vm.register_native("damage", 2, move |args| match args {
+            [LoxValue::String(name), LoxValue::Number(amount)] => {
+                let mut world = damaged.borrow_mut();
+                let Some(health) = world.health.get_mut(name) else {
+                    return Err(format!("Nothing called '{name}' to damage."));
+                };
+                *health = (*health - amount).max(0.0);
+                Ok((*health).into())
+            }
+            _ => Err("damage expects a name and an amount.".into()),
+        });
+
+        vm.interpret(script)?;
+        Ok(Self { vm, world, log })
+    }
+
+    fn tick(&mut self, tick: usize) -> Result<(), loxide::Error> {
+        self.vm.interpret(&format!("onTick({tick});"))
+    }
+}
+
+fn main() {
+    let mut game = Game::new(SCRIPT).expect("Failed to load the script");
+    for tick in 1..=6 {
+        if game.tick(tick).is_err() {
+            break;
+        }
+    }
+    for line in game.log.borrow().iter() {
+        println!("{line}");
+    }
+    println!("{:?}", game.world.borrow().health);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_runs_the_script_each_tick() {
+        let mut game = Game::new(SCRIPT).expect("Failed to load the script");
+        for tick in 1..=6 {
+            game.tick(tick).expect("Failed to run a tick");
+        }
+        assert_eq!(
+            *game.log.borrow(),
+            vec![
+                "goblin was hit",
+                "slime was hit",
+                "goblin was hit",
+                "slime was defeated",
+                "goblin was defeated",
+            ]
+        );
+        let world = game.world.borrow();
+        assert_eq!(world.health["goblin"], 0.0);
+        assert_eq!(world.health["slime"], 0.0);
+    }
+
+    #[test]
+    fn it_stops_a_tick_when_a_native_fails() {
+        let mut game = Game::new("fun onTick(tick) { damage(\"dragon\", 1); }")
+            .expect("Failed to load the script");
+        assert_eq!(game.tick(1), Err(loxide::Error::Runtime));
+        assert_eq!(
+            *game.log.borrow(),
+            vec!["error: Nothing called 'dragon' to damage."]
+        );
+    }
+}
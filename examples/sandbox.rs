@@ -0,0 +1,143 @@
+//! Runs untrusted scripts with limits on how long and how deep they may go.
+//!
+//! Run it with `cargo run --example sandbox -- script.lox...`. Each script
+//! gets a fresh VM that stops it after a fixed number of instructions and
+//! can't reach files or run code it builds itself: `import`, `load()` and
+//! `eval()` are disabled. Its output and errors are collected instead of
+//! written to the terminal.
+
+use std::{cell::RefCell, fs, rc::Rc};
+
+use loxide::{CompileError, Error, RuntimeErrorInfo, VM};
+
+/// How many instructions a script may run before it's stopped.
+const INSTRUCTION_LIMIT: u64 = 100_000;
+/// How deeply a script's expressions may nest.
+const MAX_NESTING_DEPTH: usize = 32;
+/// The natives that read files or compile source, with their arities.
+const DISABLED_NATIVES: [(&str, usize); 2] = [("load", 1), ("eval", 2)];
+
+/// What running a script in the sandbox produced.
+#[derive(Debug, Default)]
+struct Outcome {
+    output: Vec<String>,
+    compile_errors: Vec<CompileError>,
+    error: Option<RuntimeErrorInfo>,
+    result: Option<Error>,
+}
+
+fn run_sandboxed(source: &str) -> Outcome {
+    let output = Rc::new(RefCell::new(Vec::new()));
+    let error = Rc::new(RefCell::new(None));
+    let mut vm = VM::new(std::io::sink(), std::io::sink());
+    vm.set_instruction_limit(Some(INSTRUCTION_LIMIT));
+    vm.set_max_nesting_depth(MAX_NESTING_DEPTH);
+    vm.set_module_resolver(|_: &str| Err(Error::Runtime));
+    for (name, arity) in DISABLED_NATIVES {
+        vm.register_native(name, arity, move |_| {
+            Err(format!("{name}() is disabled in the sandbox."))
+        });
+    }
+    let printed = output.clone();
+    vm.on_print(move |line| printed.borrow_mut().push(line.to_string()));
+    let raised = error.clone();
+    vm.on_error(move |info| *raised.borrow_mut() = Some(info.clone()));
+
+    let result = vm.interpret(source).err();
+    let compile_errors = vm.diagnostics().errors.clone();
+    drop(vm);
+    Outcome {
+        output: output.take(),
+        compile_errors,
+        error: error.take(),
+        result,
+    }
+}
+
+fn main() {
+    for path in std::env::args().skip(1) {
+        let source = match fs::read_to_string(&path) {
+            Ok(source) => source,
+            Err(error) => {
+                eprintln!("{path}: {error}");
+                continue;
+            }
+        };
+        let outcome = run_sandboxed(&source);
+        println!("== {path} ==");
+        for line in &outcome.output {
+            println!("{line}");
+        }
+        for error in &outcome.compile_errors {
+            println!("{error}");
+        }
+        match (outcome.result, outcome.error) {
+            (None, _) => println!("-- finished"),
+            (Some(_), Some(error)) => print!("-- stopped: {error}"),
+            (Some(error), None) => println!("-- stopped: {error}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_collects_output() {
+        let outcome = run_sandboxed("for (var i = 0; i < 3; i = i + 1) print i;");
+        assert_eq!(outcome.output, vec!["0", "1", "2"]);
+        assert_eq!(outcome.result, None);
+    }
+
+    #[test]
+    fn it_stops_scripts_that_run_too_long() {
+        let outcome = run_sandboxed("print \"start\"; while (true) {}");
+        assert_eq!(outcome.output, vec!["start"]);
+        assert_eq!(outcome.result, Some(Error::Runtime));
+        let error = outcome.error.expect("Expected a runtime error");
+        assert_eq!(error.message, "Instruction limit exceeded.");
+    }
+
+    #[test]
+    fn it_reports_runtime_errors_with_a_trace() {
+        let outcome = run_sandboxed("fun f() { return nil + 1; }\nf();");
+        let error = outcome.error.expect("Expected a runtime error");
+        assert_eq!(
+            error.message,
+            "Operands must be two numbers or two strings."
+        );
+        assert_eq!(error.trace.len(), 2);
+        assert_eq!(error.trace[0].function.as_deref(), Some("f"));
+    }
+
+    #[test]
+    fn it_rejects_deeply_nested_scripts() {
+        let source = format!("print {}1{};", "(".repeat(64), ")".repeat(64));
+        let outcome = run_sandboxed(&source);
+        assert_eq!(outcome.result, Some(Error::Compile));
+        assert!(outcome.output.is_empty());
+        assert_eq!(outcome.compile_errors.len(), 1);
+    }
+
+    #[test]
+    fn it_keeps_scripts_from_reaching_files_or_compiling_code() {
+        let sources = [
+            (
+                "import \"./sandbox\";",
+                "Could not find module './sandbox'.",
+            ),
+            (
+                "load(\"sandbox.lox\");",
+                "load() is disabled in the sandbox.",
+            ),
+            ("eval(\"1\", true);", "eval() is disabled in the sandbox."),
+        ];
+        for (source, message) in sources {
+            let outcome = run_sandboxed(source);
+            assert_eq!(outcome.result, Some(Error::Runtime), "{source}");
+            let error = outcome.error.expect("Expected a runtime error");
+            assert_eq!(error.message, message);
+        }
+    }
+}
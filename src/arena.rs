@@ -0,0 +1,175 @@
+//! Per-compile storage for lexemes.
+//!
+//! The scanner builds every lexeme in one reused scratch buffer and stores it
+//! in an [`Arena`], which hands out shared [`Lexeme`]s. Repeated names share
+//! one allocation, and cloning a token, which the compiler does for every
+//! token it consumes, only bumps a count. The arena belongs to the scanner, so
+//! everything in it is freed together once compilation finishes.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    hash::{BuildHasherDefault, Hash, Hasher},
+    ops::Deref,
+    rc::Rc,
+};
+
+use crate::object::{obj_string::hash_str, ObjStringHasher};
+
+/// The text of a token, shared with every other token spelled the same way.
+/// It's hashed once when created, so comparing lexemes that differ rarely
+/// looks at their bytes.
+#[derive(Debug, Clone)]
+pub struct Lexeme {
+    text: Rc<str>,
+    hash: u32,
+}
+
+impl Lexeme {
+    pub fn as_str(&self) -> &str {
+        &self.text
+    }
+
+    /// The lexeme's hash, the same as an [`ObjString`](crate::object::ObjString)
+    /// of its text would have.
+    pub fn hash_code(&self) -> u32 {
+        self.hash
+    }
+}
+
+impl Default for Lexeme {
+    fn default() -> Self {
+        Self::from("")
+    }
+}
+
+impl Deref for Lexeme {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.text
+    }
+}
+
+impl fmt::Display for Lexeme {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+impl From<&str> for Lexeme {
+    fn from(text: &str) -> Self {
+        Self {
+            hash: hash_str(text),
+            text: text.into(),
+        }
+    }
+}
+
+impl From<String> for Lexeme {
+    fn from(text: String) -> Self {
+        Self {
+            hash: hash_str(&text),
+            text: text.into(),
+        }
+    }
+}
+
+impl From<Lexeme> for String {
+    fn from(lexeme: Lexeme) -> Self {
+        lexeme.text.to_string()
+    }
+}
+
+impl Hash for Lexeme {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_u32(self.hash);
+    }
+}
+
+impl PartialEq for Lexeme {
+    fn eq(&self, other: &Self) -> bool {
+        self.hash == other.hash
+            && (Rc::ptr_eq(&self.text, &other.text) || self.text == other.text)
+    }
+}
+
+impl Eq for Lexeme {}
+
+impl PartialEq<str> for Lexeme {
+    fn eq(&self, other: &str) -> bool {
+        &*self.text == other
+    }
+}
+
+impl PartialEq<&str> for Lexeme {
+    fn eq(&self, other: &&str) -> bool {
+        &*self.text == *other
+    }
+}
+
+/// Lexemes scanned so far, and a buffer for building the next one.
+#[derive(Debug, Default)]
+pub struct Arena {
+    /// Lexemes by their hash
+    lexemes: HashMap<u32, Vec<Lexeme>, BuildHasherDefault<ObjStringHasher>>,
+    scratch: String,
+}
+
+impl Arena {
+    /// The lexeme spelled `text`, stored the first time it's seen.
+    pub fn alloc(&mut self, text: &str) -> Lexeme {
+        let hash = hash_str(text);
+        let bucket = self.lexemes.entry(hash).or_default();
+        if let Some(lexeme) = bucket.iter().find(|lexeme| lexeme.as_str() == text) {
+            return lexeme.clone();
+        }
+        let lexeme = Lexeme {
+            text: text.into(),
+            hash,
+        };
+        bucket.push(lexeme.clone());
+        lexeme
+    }
+
+    /// Stores the scratch buffer as a lexeme and clears it for the next one.
+    pub fn alloc_scratch(&mut self) -> Lexeme {
+        let scratch = std::mem::take(&mut self.scratch);
+        let lexeme = self.alloc(&scratch);
+        self.scratch = scratch;
+        self.scratch.clear();
+        lexeme
+    }
+
+    /// The buffer to build a lexeme in, emptied by [`Arena::alloc_scratch`].
+    pub fn scratch(&mut self) -> &mut String {
+        &mut self.scratch
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::object::ObjString;
+
+    #[test]
+    fn it_shares_lexemes_spelled_the_same() {
+        let mut arena = Arena::default();
+        let first = arena.alloc("name");
+        arena.scratch().push_str("na");
+        arena.scratch().push_str("me");
+        let second = arena.alloc_scratch();
+        assert_eq!(first, second);
+        assert!(Rc::ptr_eq(&first.text, &second.text));
+        assert!(arena.scratch().is_empty());
+
+        let other = arena.alloc("other");
+        assert_eq!(other, "other");
+        assert_eq!(arena.lexemes.values().map(Vec::len).sum::<usize>(), 2);
+
+        // Lexemes made outside the arena still compare by their text
+        assert_eq!(Lexeme::from("name"), first);
+        assert_ne!(Lexeme::from("nam"), first);
+        assert_eq!(first.hash_code(), ObjString::from("name").hash);
+    }
+}
@@ -1,11 +1,11 @@
-use std::ptr::null;
+use std::{ops::Range, ptr::null};
 
 use crate::{
     chunk::Chunk,
     object::{ObjClosure, Pointer},
 };
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy)]
 pub struct CallFrame {
     /// A reference to the currently executing function
     pub(crate) chunk: *const Chunk,
@@ -13,8 +13,6 @@ pub struct CallFrame {
     pub(crate) closure: Pointer<ObjClosure>,
     /// The index into the closure's code
     pub(crate) ip: usize,
-    /// How many stack slots the call frame accesses
-    pub(crate) slots: usize,
     /// The absolute index of the start of the call frame
     pub(crate) start_stack_index: usize,
 }
@@ -25,8 +23,16 @@ impl Default for CallFrame {
             chunk: null(),
             closure: Pointer::default(),
             ip: 0,
-            slots: 0,
             start_stack_index: 0,
         }
     }
 }
+
+impl CallFrame {
+    /// The stack slots of the frame's locals, starting with the callee in slot
+    /// zero. `stack_top` is the first slot past the frame: the start of the
+    /// frame it called, or the top of the stack for the innermost frame.
+    pub(crate) fn locals(&self, stack_top: usize) -> Range<usize> {
+        self.start_stack_index..stack_top.max(self.start_stack_index)
+    }
+}
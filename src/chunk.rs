@@ -1,12 +1,91 @@
-use std::fmt::{Display, Error};
+use std::{
+    cell::OnceCell,
+    fmt::{Display, Error},
+    ops::Range,
+    sync::Arc,
+};
 
-use crate::value::constant::ConstantValue;
+use crate::{error, object::ObjString, value::constant::ConstantValue};
 
-#[derive(Clone, Debug, Default, PartialEq)]
+#[derive(Clone, Debug, Default)]
 pub struct Chunk {
     pub code: Vec<u8>,
     pub lines: Vec<usize>,
     pub constants: Vec<ConstantValue>,
+    /// The names of the globals used by this chunk, which global instructions index
+    pub globals: Vec<ObjString>,
+    /// The VM slot of each name in `globals`, filled in when the chunk is linked
+    pub global_slots: Vec<usize>,
+    /// Source spans for each byte of `code`, present when compiled with debug info
+    pub debug_info: Option<DebugInfo>,
+    /// The opcode at the start of each instruction the chunk can reach, filled
+    /// in by the verifier before the chunk first runs
+    pub decoded: OnceCell<Box<[OpCode]>>,
+}
+
+/// Chunks are equal when their bytecode and tables are, whether or not
+/// either has been verified yet.
+impl PartialEq for Chunk {
+    fn eq(&self, other: &Self) -> bool {
+        self.code == other.code
+            && self.lines == other.lines
+            && self.constants == other.constants
+            && self.globals == other.globals
+            && self.global_slots == other.global_slots
+            && self.debug_info == other.debug_info
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct DebugInfo {
+    pub source: Arc<str>,
+    /// The `(start, end)` byte span in `source` of each byte of code
+    pub spans: Vec<(usize, usize)>,
+    /// The named locals of the function, in the order they were declared
+    pub locals: Vec<LocalName>,
+}
+
+/// A local variable and the code over which its slot holds its value.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LocalName {
+    pub name: String,
+    pub slot: usize,
+    /// The code offsets the local is in scope for
+    pub live: Range<usize>,
+}
+
+impl DebugInfo {
+    pub fn new(source: Arc<str>) -> Self {
+        Self {
+            source,
+            spans: vec![],
+            locals: vec![],
+        }
+    }
+
+    /// The named locals in scope at the instruction byte at `offset`, in slot order.
+    pub fn locals_at(&self, offset: usize) -> impl Iterator<Item = &LocalName> {
+        self.locals
+            .iter()
+            .filter(move |local| local.live.contains(&offset))
+    }
+
+    /// Renders the source line containing `span` with a caret under the span.
+    pub fn caret(&self, span: (usize, usize)) -> String {
+        let (line, column) = self.line_of(span);
+        let width = (span.1 - span.0).clamp(1, (line.len() - column).max(1));
+        format!("    {line}\n    {}{}\n", " ".repeat(column), "^".repeat(width))
+    }
+
+    /// The full source line containing `span`, along with the span's offset within it.
+    pub fn line_of(&self, span: (usize, usize)) -> (&str, usize) {
+        let start = span.0.min(self.source.len());
+        let line_start = self.source[..start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = self.source[start..]
+            .find('\n')
+            .map_or(self.source.len(), |i| start + i);
+        (&self.source[line_start..line_end], start - line_start)
+    }
 }
 
 impl Chunk {
@@ -15,81 +94,268 @@ impl Chunk {
         self.lines.push(line);
     }
 
+    /// Writes `byte`, recording `span` when the chunk carries debug info.
+    pub fn write_spanned(&mut self, byte: u8, line: usize, span: (usize, usize)) {
+        self.write(byte, line);
+        if let Some(debug_info) = self.debug_info.as_mut() {
+            debug_info.spans.push(span);
+        }
+    }
+
+    /// Drops the code from `len` on, along with its lines, spans and the
+    /// locals that only come into scope there.
+    pub fn truncate(&mut self, len: usize) {
+        self.code.truncate(len);
+        self.lines.truncate(len);
+        if let Some(debug_info) = self.debug_info.as_mut() {
+            debug_info.spans.truncate(len);
+            debug_info.locals.retain(|local| local.live.start < len);
+        }
+    }
+
+    /// The source span of the instruction byte at `offset`, if debug info was recorded.
+    pub fn span(&self, offset: usize) -> Option<(usize, usize)> {
+        self.debug_info.as_ref()?.spans.get(offset).copied()
+    }
+
     pub fn add_constant(&mut self, value: ConstantValue) -> usize {
         self.constants.push(value);
         self.constants.len() - 1
     }
 
+    /// The index of `name` in the global name table, adding it if it's new.
+    pub fn add_global(&mut self, name: ObjString) -> usize {
+        if let Some(index) = self.globals.iter().position(|global| *global == name) {
+            return index;
+        }
+        self.globals.push(name);
+        self.globals.len() - 1
+    }
+
+    /// The size in bytes of the instruction at `offset`, opcode included.
+    /// An instruction cut short by the end of the chunk only counts the bytes
+    /// that are there.
+    pub fn instruction_len(&self, offset: usize) -> usize {
+        self.encoded_len(offset).min(self.code.len() - offset)
+    }
+
+    /// The size the instruction at `offset` should have, going by its opcode
+    /// and any operands that say how many follow.
+    fn encoded_len(&self, offset: usize) -> usize {
+        let opcode = OpCode::try_from(self.code[offset]).unwrap_or(OpCode::Unknown);
+        let byte = |index: usize| self.code.get(offset + index).copied().unwrap_or(0) as usize;
+        let operands = match opcode.operands() {
+            Operands::Fixed(widths) => widths.iter().sum(),
+            Operands::Counted => 1 + byte(1),
+            Operands::Closure => {
+                let upvalue_count = match self.constants.get(byte(1)) {
+                    Some(ConstantValue::Function(function)) => function.upvalue_count,
+                    _ => 0,
+                };
+                1 + 2 * upvalue_count
+            }
+        };
+        1 + operands
+    }
+
+    /// Decodes the instruction starting at `offset`, or `None` past the end
+    /// of the chunk.
+    pub fn instruction_at(&self, offset: usize) -> Option<Instruction<'_>> {
+        let &byte = self.code.get(offset)?;
+        let len = self.instruction_len(offset);
+        let opcode = match OpCode::try_from(byte) {
+            Ok(opcode) if len == self.encoded_len(offset) => opcode,
+            _ => OpCode::Unknown,
+        };
+        Some(Instruction {
+            opcode,
+            operands: &self.code[offset + 1..offset + len],
+        })
+    }
+
+    /// Decodes the instructions of the chunk in order, each with its offset.
+    pub fn instructions(&self) -> Instructions<'_> {
+        Instructions {
+            chunk: self,
+            offset: 0,
+        }
+    }
+
     fn simple_instruction(
         &self,
         f: &mut std::fmt::Formatter<'_>,
-        opcode: OpCode,
-        offset: usize,
-    ) -> Result<usize, Error> {
-        writeln!(f, "{opcode}")?;
-        Ok(offset + 1)
+        instruction: Instruction,
+    ) -> Result<(), Error> {
+        writeln!(f, "{}", instruction.opcode)
     }
 
     fn constant_instruction(
         &self,
         f: &mut std::fmt::Formatter<'_>,
-        opcode: OpCode,
-        offset: usize,
-    ) -> Result<usize, Error> {
-        let constant = self.code[offset + 1] as usize;
-        write!(f, "{opcode:<16}\t{constant:4}\t'")?;
+        instruction: Instruction,
+    ) -> Result<(), Error> {
+        let constant = instruction.operand(0);
+        write!(f, "{:<16}\t{constant:4}\t'", instruction.opcode)?;
         write!(f, "{}", self.constants[constant])?;
-        writeln!(f, "'")?;
-        Ok(offset + 2)
+        writeln!(f, "'")
+    }
+
+    fn global_instruction(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+        instruction: Instruction,
+    ) -> Result<(), Error> {
+        let global = instruction.operand(0);
+        writeln!(
+            f,
+            "{:<16}\t{global:4}\t'{}'",
+            instruction.opcode, self.globals[global]
+        )
     }
 
     fn invoke_instruction(
         &self,
         f: &mut std::fmt::Formatter<'_>,
-        opcode: OpCode,
-        offset: usize,
-    ) -> Result<usize, Error> {
-        let constant = self.code[offset + 1] as usize;
-        let arg_count = self.code[offset + 2] as usize;
-        write!(f, "{opcode:<4} ({arg_count} args)\t{constant:4}\t'")?;
+        instruction: Instruction,
+    ) -> Result<(), Error> {
+        let constant = instruction.operand(0);
+        let arg_count = instruction.operand(1);
+        write!(
+            f,
+            "{:<4} ({arg_count} args)\t{constant:4}\t'",
+            instruction.opcode
+        )?;
         write!(f, "{}", self.constants[constant])?;
-        writeln!(f, "'")?;
-        Ok(offset + 3)
+        writeln!(f, "'")
     }
 
     fn byte_instruction(
         &self,
         f: &mut std::fmt::Formatter<'_>,
-        opcode: OpCode,
-        offset: usize,
-    ) -> Result<usize, Error> {
-        let slot = self.code[offset + 1] as usize;
-        writeln!(f, "{opcode:<16}\t{slot:4}")?;
-        Ok(offset + 2)
+        instruction: Instruction,
+    ) -> Result<(), Error> {
+        let slot = instruction.operand(0);
+        writeln!(f, "{:<16}\t{slot:4}", instruction.opcode)
     }
 
     fn jump_instruction(
         &self,
         f: &mut std::fmt::Formatter<'_>,
-        opcode: OpCode,
         sign: i16,
         offset: usize,
-    ) -> Result<usize, Error> {
-        let mut jump = (self.code[offset + 1] as i16) << 8;
-        jump |= self.code[offset + 2] as i16;
+        instruction: Instruction,
+    ) -> Result<(), Error> {
+        let jump = instruction.operand(0) as i16;
         writeln!(
             f,
-            "{opcode:<16}\t{offset:4x} -> {:x}",
-            offset as i16 + 3i16 + sign * jump
-        )?;
-        Ok(offset + 3)
+            "{:<16}\t{offset:4x} -> {:x}",
+            instruction.opcode,
+            (offset + instruction.size()) as i16 + sign * jump
+        )
+    }
+
+    fn for_in_instruction(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+        offset: usize,
+        instruction: Instruction,
+    ) -> Result<(), Error> {
+        let slot = instruction.operand(0);
+        let next = offset + instruction.size();
+        writeln!(
+            f,
+            "{:<16}\t{slot:4} -> {:x}",
+            instruction.opcode,
+            next + instruction.operand(1)
+        )
+    }
+
+    fn named_args_instruction(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+        instruction: Instruction,
+    ) -> Result<(), Error> {
+        let (count, names) = instruction.operands.split_first().unwrap_or((&0, &[]));
+        write!(f, "{:<16}\t{count:4}\t", instruction.opcode)?;
+        for (i, &constant) in names.iter().enumerate() {
+            let separator = if i == 0 { "" } else { " " };
+            write!(f, "{separator}'{}'", self.constants[constant as usize])?;
+        }
+        writeln!(f)
+    }
+
+    fn closure_instruction(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+        offset: usize,
+        instruction: Instruction,
+    ) -> Result<(), Error> {
+        let (&constant, upvalues) = instruction.operands.split_first().unwrap_or((&0, &[]));
+        write!(f, "{:<16}\t{:4}\t", instruction.opcode, constant)?;
+        writeln!(f, "{}", self.constants[constant as usize])?;
+        for (i, upvalue) in upvalues.chunks(2).enumerate() {
+            write!(f, "{:04x}        |\t", offset + 2 + 2 * i)?;
+            if upvalue[0] != 0 {
+                write!(f, "local ")?;
+            } else {
+                write!(f, "upvalue ")?;
+            }
+            writeln!(f, "{}", upvalue.get(1).copied().unwrap_or(0))?;
+        }
+        Ok(())
+    }
+}
+
+/// An instruction decoded from a chunk.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Instruction<'a> {
+    /// `OpCode::Unknown` for bytes that don't decode, or for an instruction
+    /// cut short by the end of the chunk
+    pub opcode: OpCode,
+    /// The bytes after the opcode, laid out as [`OpCode::operands`] describes
+    pub operands: &'a [u8],
+}
+
+impl Instruction<'_> {
+    /// The size in bytes of the instruction, opcode included.
+    pub fn size(&self) -> usize {
+        1 + self.operands.len()
+    }
+
+    /// The `index`th operand of an instruction with fixed width operands,
+    /// read big-endian.
+    pub fn operand(&self, index: usize) -> usize {
+        let Operands::Fixed(widths) = self.opcode.operands() else {
+            return self.operands[index] as usize;
+        };
+        let start: usize = widths[..index].iter().sum();
+        self.operands[start..start + widths[index]]
+            .iter()
+            .fold(0, |value, &byte| value << 8 | byte as usize)
+    }
+}
+
+/// The instructions of a chunk and their offsets, from [`Chunk::instructions`].
+#[derive(Debug, Clone)]
+pub struct Instructions<'a> {
+    chunk: &'a Chunk,
+    offset: usize,
+}
+
+impl<'a> Iterator for Instructions<'a> {
+    type Item = (usize, Instruction<'a>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let offset = self.offset;
+        let instruction = self.chunk.instruction_at(offset)?;
+        self.offset += instruction.size();
+        Some((offset, instruction))
     }
 }
 
 impl Display for Chunk {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let mut offset = 0;
-        while offset < self.code.len() {
+        for (offset, instruction) in self.instructions() {
             write!(f, "{offset:04x}\t")?;
             if offset > 0 && self.lines[offset] == self.lines[offset - 1] {
                 write!(f, "    |\t")?;
@@ -97,75 +363,67 @@ impl Display for Chunk {
                 write!(f, "{:4}\t", self.lines[offset])?;
             }
 
-            let instruction: OpCode = self.code[offset].into();
-            offset = match instruction {
-                o @ OpCode::Constant
-                | o @ OpCode::GetGlobal
-                | o @ OpCode::SetGlobal
-                | o @ OpCode::DefineGlobal
-                | o @ OpCode::GetProperty
-                | o @ OpCode::SetProperty
-                | o @ OpCode::GetSuper
-                | o @ OpCode::Class
-                | o @ OpCode::Method => self.constant_instruction(f, o, offset)?,
-                o @ OpCode::Nil
-                | o @ OpCode::True
-                | o @ OpCode::False
-                | o @ OpCode::Pop
-                | o @ OpCode::Equal
-                | o @ OpCode::Greater
-                | o @ OpCode::Less
-                | o @ OpCode::Add
-                | o @ OpCode::Subtract
-                | o @ OpCode::Multiply
-                | o @ OpCode::Divide
-                | o @ OpCode::Not
-                | o @ OpCode::Negate
-                | o @ OpCode::Print
-                | o @ OpCode::CloseUpvalue
-                | o @ OpCode::Return
-                | o @ OpCode::Inherit
-                | o @ OpCode::Unknown => self.simple_instruction(f, o, offset)?,
-                o @ OpCode::GetLocal
-                | o @ OpCode::SetLocal
-                | o @ OpCode::GetUpvalue
-                | o @ OpCode::SetUpvalue
-                | o @ OpCode::Call => self.byte_instruction(f, o, offset)?,
-                o @ OpCode::Jump | o @ OpCode::JumpIfFalse => {
-                    self.jump_instruction(f, o, 1, offset)?
+            match instruction.opcode {
+                OpCode::GetGlobal | OpCode::SetGlobal | OpCode::DefineGlobal => {
+                    self.global_instruction(f, instruction)?
                 }
-                o @ OpCode::Loop => self.jump_instruction(f, o, -1, offset)?,
-                o @ OpCode::Invoke | o @ OpCode::SuperInvoke => {
-                    self.invoke_instruction(f, o, offset)?
+                OpCode::Constant
+                | OpCode::GetProperty
+                | OpCode::SetProperty
+                | OpCode::GetThisProperty
+                | OpCode::SetThisProperty
+                | OpCode::GetSuper
+                | OpCode::Class
+                | OpCode::Method
+                | OpCode::StaticMethod
+                | OpCode::Import => self.constant_instruction(f, instruction)?,
+                OpCode::Nil
+                | OpCode::True
+                | OpCode::False
+                | OpCode::Pop
+                | OpCode::Equal
+                | OpCode::Greater
+                | OpCode::Less
+                | OpCode::Add
+                | OpCode::Subtract
+                | OpCode::Multiply
+                | OpCode::Divide
+                | OpCode::Not
+                | OpCode::Negate
+                | OpCode::Print
+                | OpCode::CloseUpvalue
+                | OpCode::Return
+                | OpCode::Inherit
+                | OpCode::IterInit
+                | OpCode::Range
+                | OpCode::RangeInclusive
+                | OpCode::GetIndex
+                | OpCode::SetIndex
+                | OpCode::BitAnd
+                | OpCode::BitOr
+                | OpCode::BitXor
+                | OpCode::BitNot
+                | OpCode::ShiftLeft
+                | OpCode::ShiftRight
+                | OpCode::SameClass
+                | OpCode::Unknown => self.simple_instruction(f, instruction)?,
+                OpCode::GetLocal
+                | OpCode::SetLocal
+                | OpCode::GetUpvalue
+                | OpCode::SetUpvalue
+                | OpCode::Call
+                | OpCode::Mixin
+                | OpCode::BuildList => self.byte_instruction(f, instruction)?,
+                OpCode::Jump | OpCode::JumpIfFalse => {
+                    self.jump_instruction(f, 1, offset, instruction)?
                 }
-                o @ OpCode::Closure => {
-                    offset += 1;
-                    let constant = self.code[offset] as usize;
-                    offset += 1;
-                    write!(f, "{:<16}\t{:4}\t", o, constant)?;
-                    writeln!(f, "{}", self.constants[constant])?;
-                    let function = match &self.constants[constant] {
-                        ConstantValue::Function(fun) => fun,
-                        _ => panic!("Failed to get function from closure."),
-                    };
-
-                    for _ in 0..function.upvalue_count {
-                        let is_local = self.code[offset];
-                        offset += 1;
-                        let index = self.code[offset];
-                        offset += 1;
-                        write!(f, "{:04x}        |\t", offset - 2)?;
-                        if is_local != 0 {
-                            write!(f, "local ")?;
-                        } else {
-                            write!(f, "upvalue ")?;
-                        }
-
-                        writeln!(f, "{index}")?;
-                    }
-
-                    offset
+                OpCode::Loop => self.jump_instruction(f, -1, offset, instruction)?,
+                OpCode::ForIn => self.for_in_instruction(f, offset, instruction)?,
+                OpCode::NamedArgs => self.named_args_instruction(f, instruction)?,
+                OpCode::Invoke | OpCode::InvokeThis | OpCode::SuperInvoke => {
+                    self.invoke_instruction(f, instruction)?
                 }
+                OpCode::Closure => self.closure_instruction(f, offset, instruction)?,
             }
         }
         Ok(())
@@ -212,50 +470,190 @@ pub enum OpCode {
     Class = 34,
     Inherit = 35,
     Method = 36,
+    GetThisProperty = 37,
+    SetThisProperty = 38,
+    InvokeThis = 39,
+    Mixin = 40,
+    BuildList = 41,
+    IterInit = 42,
+    ForIn = 43,
+    Range = 44,
+    RangeInclusive = 45,
+    GetIndex = 46,
+    SetIndex = 47,
+    NamedArgs = 48,
+    Import = 49,
+    BitAnd = 50,
+    BitOr = 51,
+    BitXor = 52,
+    BitNot = 53,
+    ShiftLeft = 54,
+    ShiftRight = 55,
+    StaticMethod = 56,
+    SameClass = 57,
     Unknown = 255,
 }
 
-impl From<u8> for OpCode {
-    fn from(value: u8) -> Self {
-        match value {
-            x if x == OpCode::Constant as u8 => OpCode::Constant,
-            x if x == OpCode::Nil as u8 => OpCode::Nil,
-            x if x == OpCode::True as u8 => OpCode::True,
-            x if x == OpCode::False as u8 => OpCode::False,
-            x if x == OpCode::Pop as u8 => OpCode::Pop,
-            x if x == OpCode::GetLocal as u8 => OpCode::GetLocal,
-            x if x == OpCode::SetLocal as u8 => OpCode::SetLocal,
-            x if x == OpCode::GetGlobal as u8 => OpCode::GetGlobal,
-            x if x == OpCode::SetGlobal as u8 => OpCode::SetGlobal,
-            x if x == OpCode::DefineGlobal as u8 => OpCode::DefineGlobal,
-            x if x == OpCode::GetUpvalue as u8 => OpCode::GetUpvalue,
-            x if x == OpCode::SetUpvalue as u8 => OpCode::SetUpvalue,
-            x if x == OpCode::GetProperty as u8 => OpCode::GetProperty,
-            x if x == OpCode::SetProperty as u8 => OpCode::SetProperty,
-            x if x == OpCode::GetSuper as u8 => OpCode::GetSuper,
-            x if x == OpCode::Equal as u8 => OpCode::Equal,
-            x if x == OpCode::Greater as u8 => OpCode::Greater,
-            x if x == OpCode::Less as u8 => OpCode::Less,
-            x if x == OpCode::Add as u8 => OpCode::Add,
-            x if x == OpCode::Subtract as u8 => OpCode::Subtract,
-            x if x == OpCode::Multiply as u8 => OpCode::Multiply,
-            x if x == OpCode::Divide as u8 => OpCode::Divide,
-            x if x == OpCode::Not as u8 => OpCode::Not,
-            x if x == OpCode::Negate as u8 => OpCode::Negate,
-            x if x == OpCode::Print as u8 => OpCode::Print,
-            x if x == OpCode::Jump as u8 => OpCode::Jump,
-            x if x == OpCode::JumpIfFalse as u8 => OpCode::JumpIfFalse,
-            x if x == OpCode::Loop as u8 => OpCode::Loop,
-            x if x == OpCode::Call as u8 => OpCode::Call,
-            x if x == OpCode::Invoke as u8 => OpCode::Invoke,
-            x if x == OpCode::SuperInvoke as u8 => OpCode::SuperInvoke,
-            x if x == OpCode::Closure as u8 => OpCode::Closure,
-            x if x == OpCode::CloseUpvalue as u8 => OpCode::CloseUpvalue,
-            x if x == OpCode::Return as u8 => OpCode::Return,
-            x if x == OpCode::Class as u8 => OpCode::Class,
-            x if x == OpCode::Inherit as u8 => OpCode::Inherit,
-            x if x == OpCode::Method as u8 => OpCode::Method,
-            _ => OpCode::Unknown,
+impl OpCode {
+    /// Every opcode the VM executes, in encoding order. `Unknown` is left out
+    /// since it only stands in for bytes that don't decode.
+    pub const ALL: [OpCode; 58] = [
+        OpCode::Constant,
+        OpCode::Nil,
+        OpCode::True,
+        OpCode::False,
+        OpCode::Pop,
+        OpCode::GetLocal,
+        OpCode::SetLocal,
+        OpCode::GetGlobal,
+        OpCode::SetGlobal,
+        OpCode::DefineGlobal,
+        OpCode::GetUpvalue,
+        OpCode::SetUpvalue,
+        OpCode::GetProperty,
+        OpCode::SetProperty,
+        OpCode::GetSuper,
+        OpCode::Equal,
+        OpCode::Greater,
+        OpCode::Less,
+        OpCode::Add,
+        OpCode::Subtract,
+        OpCode::Multiply,
+        OpCode::Divide,
+        OpCode::Not,
+        OpCode::Negate,
+        OpCode::Print,
+        OpCode::Jump,
+        OpCode::JumpIfFalse,
+        OpCode::Loop,
+        OpCode::Call,
+        OpCode::Invoke,
+        OpCode::SuperInvoke,
+        OpCode::Closure,
+        OpCode::CloseUpvalue,
+        OpCode::Return,
+        OpCode::Class,
+        OpCode::Inherit,
+        OpCode::Method,
+        OpCode::GetThisProperty,
+        OpCode::SetThisProperty,
+        OpCode::InvokeThis,
+        OpCode::Mixin,
+        OpCode::BuildList,
+        OpCode::IterInit,
+        OpCode::ForIn,
+        OpCode::Range,
+        OpCode::RangeInclusive,
+        OpCode::GetIndex,
+        OpCode::SetIndex,
+        OpCode::NamedArgs,
+        OpCode::Import,
+        OpCode::BitAnd,
+        OpCode::BitOr,
+        OpCode::BitXor,
+        OpCode::BitNot,
+        OpCode::ShiftLeft,
+        OpCode::ShiftRight,
+        OpCode::StaticMethod,
+        OpCode::SameClass,
+    ];
+
+    /// Maps each byte to its opcode, built from [`OpCode::ALL`] so the two
+    /// can't disagree.
+    const DECODE: [OpCode; 256] = {
+        let mut table = [OpCode::Unknown; 256];
+        let mut i = 0;
+        while i < Self::ALL.len() {
+            table[Self::ALL[i] as usize] = Self::ALL[i];
+            i += 1;
+        }
+        table
+    };
+
+    /// The operands that follow this opcode, which tools walking bytecode
+    /// use to find where the next instruction starts.
+    pub const fn operands(self) -> Operands {
+        match self {
+            OpCode::Constant
+            | OpCode::GetLocal
+            | OpCode::SetLocal
+            | OpCode::GetGlobal
+            | OpCode::SetGlobal
+            | OpCode::DefineGlobal
+            | OpCode::GetUpvalue
+            | OpCode::SetUpvalue
+            | OpCode::GetProperty
+            | OpCode::SetProperty
+            | OpCode::GetSuper
+            | OpCode::Call
+            | OpCode::Class
+            | OpCode::Method
+            | OpCode::StaticMethod
+            | OpCode::GetThisProperty
+            | OpCode::SetThisProperty
+            | OpCode::Mixin
+            | OpCode::BuildList
+            | OpCode::Import => Operands::Fixed(&[1]),
+            // A constant index and an argument count
+            OpCode::Invoke | OpCode::SuperInvoke | OpCode::InvokeThis => Operands::Fixed(&[1, 1]),
+            OpCode::Jump | OpCode::JumpIfFalse | OpCode::Loop => Operands::Fixed(&[2]),
+            // The loop variable's slot and the jump past the loop body
+            OpCode::ForIn => Operands::Fixed(&[1, 2]),
+            OpCode::NamedArgs => Operands::Counted,
+            OpCode::Closure => Operands::Closure,
+            OpCode::Nil
+            | OpCode::True
+            | OpCode::False
+            | OpCode::Pop
+            | OpCode::Equal
+            | OpCode::Greater
+            | OpCode::Less
+            | OpCode::Add
+            | OpCode::Subtract
+            | OpCode::Multiply
+            | OpCode::Divide
+            | OpCode::Not
+            | OpCode::Negate
+            | OpCode::Print
+            | OpCode::CloseUpvalue
+            | OpCode::Return
+            | OpCode::Inherit
+            | OpCode::IterInit
+            | OpCode::Range
+            | OpCode::RangeInclusive
+            | OpCode::GetIndex
+            | OpCode::SetIndex
+            | OpCode::BitAnd
+            | OpCode::BitOr
+            | OpCode::BitXor
+            | OpCode::BitNot
+            | OpCode::ShiftLeft
+            | OpCode::ShiftRight
+            | OpCode::SameClass
+            | OpCode::Unknown => Operands::Fixed(&[]),
+        }
+    }
+}
+
+/// The operands an instruction carries after its opcode byte.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Operands {
+    /// Operands of a fixed width, listed by their size in bytes
+    Fixed(&'static [usize]),
+    /// A count byte followed by that many one-byte constant indices
+    Counted,
+    /// A function constant index followed by an `is_local`, `index` byte
+    /// pair for each of the function's upvalues
+    Closure,
+}
+
+impl TryFrom<u8> for OpCode {
+    type Error = error::Error;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match Self::DECODE[value as usize] {
+            OpCode::Unknown => Err(error::Error::InternalFault("Unknown opcode.")),
+            opcode => Ok(opcode),
         }
     }
 }
@@ -266,69 +664,311 @@ impl From<OpCode> for u8 {
     }
 }
 
-impl Display for OpCode {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl OpCode {
+    /// The opcode's name in disassembly, e.g. `OP_CONSTANT`.
+    pub fn name(self) -> &'static str {
         match self {
-            Self::Constant => write!(f, "OP_CONSTANT"),
-            Self::Nil => write!(f, "OP_NIL"),
-            Self::True => write!(f, "OP_TRUE"),
-            Self::False => write!(f, "OP_FALSE"),
-            Self::Pop => write!(f, "OP_POP"),
-            Self::GetLocal => write!(f, "OP_GET_LOCAL"),
-            Self::SetLocal => write!(f, "OP_SET_LOCAL"),
-            Self::GetGlobal => write!(f, "OP_GET_GLOBAL"),
-            Self::SetGlobal => write!(f, "OP_SET_GLOBAL"),
-            Self::DefineGlobal => write!(f, "OP_DEFINE_GLOBAL"),
-            Self::GetUpvalue => write!(f, "OP_GET_UPVALUE"),
-            Self::SetUpvalue => write!(f, "OP_SET_UPVALUE"),
-            Self::GetProperty => write!(f, "OP_GET_PROPERTY"),
-            Self::SetProperty => write!(f, "OP_SET_PROPERTY"),
-            Self::GetSuper => write!(f, "OP_GET_SUPER"),
-            Self::Equal => write!(f, "OP_EQUAL"),
-            Self::Greater => write!(f, "OP_GREATER"),
-            Self::Less => write!(f, "OP_LESS"),
-            Self::Add => write!(f, "OP_ADD"),
-            Self::Subtract => write!(f, "OP_SUBTRACT"),
-            Self::Multiply => write!(f, "OP_MULTIPLY"),
-            Self::Divide => write!(f, "OP_DIVIDE"),
-            Self::Not => write!(f, "OP_NOT"),
-            Self::Negate => write!(f, "OP_NEGATE"),
-            Self::Print => write!(f, "OP_PRINT"),
-            Self::Jump => write!(f, "OP_JUMP"),
-            Self::JumpIfFalse => write!(f, "OP_JUMP_IF_FALSE"),
-            Self::Loop => write!(f, "OP_LOOP"),
-            Self::Call => write!(f, "OP_CALL"),
-            Self::Invoke => write!(f, "OP_INVOKE"),
-            Self::SuperInvoke => write!(f, "OP_SUPER_INVOKE"),
-            Self::Closure => write!(f, "OP_CLOSURE"),
-            Self::CloseUpvalue => write!(f, "OP_CLOSE_UPVALUE"),
-            Self::Return => write!(f, "OP_RETURN"),
-            Self::Class => write!(f, "OP_CLASS"),
-            Self::Inherit => write!(f, "OP_INHERIT"),
-            Self::Method => write!(f, "OP_METHOD"),
-            Self::Unknown => write!(f, "OP_UNKNOWN"),
+            Self::Constant => "OP_CONSTANT",
+            Self::Nil => "OP_NIL",
+            Self::True => "OP_TRUE",
+            Self::False => "OP_FALSE",
+            Self::Pop => "OP_POP",
+            Self::GetLocal => "OP_GET_LOCAL",
+            Self::SetLocal => "OP_SET_LOCAL",
+            Self::GetGlobal => "OP_GET_GLOBAL",
+            Self::SetGlobal => "OP_SET_GLOBAL",
+            Self::DefineGlobal => "OP_DEFINE_GLOBAL",
+            Self::GetUpvalue => "OP_GET_UPVALUE",
+            Self::SetUpvalue => "OP_SET_UPVALUE",
+            Self::GetProperty => "OP_GET_PROPERTY",
+            Self::SetProperty => "OP_SET_PROPERTY",
+            Self::GetSuper => "OP_GET_SUPER",
+            Self::Equal => "OP_EQUAL",
+            Self::Greater => "OP_GREATER",
+            Self::Less => "OP_LESS",
+            Self::Add => "OP_ADD",
+            Self::Subtract => "OP_SUBTRACT",
+            Self::Multiply => "OP_MULTIPLY",
+            Self::Divide => "OP_DIVIDE",
+            Self::Not => "OP_NOT",
+            Self::Negate => "OP_NEGATE",
+            Self::Print => "OP_PRINT",
+            Self::Jump => "OP_JUMP",
+            Self::JumpIfFalse => "OP_JUMP_IF_FALSE",
+            Self::Loop => "OP_LOOP",
+            Self::Call => "OP_CALL",
+            Self::Invoke => "OP_INVOKE",
+            Self::SuperInvoke => "OP_SUPER_INVOKE",
+            Self::Closure => "OP_CLOSURE",
+            Self::CloseUpvalue => "OP_CLOSE_UPVALUE",
+            Self::Return => "OP_RETURN",
+            Self::Class => "OP_CLASS",
+            Self::Inherit => "OP_INHERIT",
+            Self::Method => "OP_METHOD",
+            Self::StaticMethod => "OP_STATIC_METHOD",
+            Self::GetThisProperty => "OP_GET_THIS_PROPERTY",
+            Self::SetThisProperty => "OP_SET_THIS_PROPERTY",
+            Self::InvokeThis => "OP_INVOKE_THIS",
+            Self::Mixin => "OP_MIXIN",
+            Self::BuildList => "OP_BUILD_LIST",
+            Self::IterInit => "OP_ITER_INIT",
+            Self::ForIn => "OP_FOR_IN",
+            Self::Range => "OP_RANGE",
+            Self::RangeInclusive => "OP_RANGE_INCLUSIVE",
+            Self::GetIndex => "OP_GET_INDEX",
+            Self::SetIndex => "OP_SET_INDEX",
+            Self::NamedArgs => "OP_NAMED_ARGS",
+            Self::Import => "OP_IMPORT",
+            Self::BitAnd => "OP_BIT_AND",
+            Self::BitOr => "OP_BIT_OR",
+            Self::BitXor => "OP_BIT_XOR",
+            Self::BitNot => "OP_BIT_NOT",
+            Self::ShiftLeft => "OP_SHIFT_LEFT",
+            Self::ShiftRight => "OP_SHIFT_RIGHT",
+            Self::SameClass => "OP_SAME_CLASS",
+            Self::Unknown => "OP_UNKNOWN",
         }
     }
 }
 
+impl Display for OpCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
     use crate::object::obj_function::ObjFunction;
 
+    #[test]
+    fn it_round_trips_every_opcode() {
+        for opcode in OpCode::ALL {
+            assert_eq!(OpCode::try_from(u8::from(opcode)), Ok(opcode));
+        }
+    }
+
+    #[test]
+    fn it_rejects_bytes_that_are_not_opcodes() {
+        for byte in 0..=u8::MAX {
+            match OpCode::try_from(byte) {
+                Ok(opcode) => assert_eq!(u8::from(opcode), byte),
+                Err(error) => {
+                    assert_eq!(error, error::Error::InternalFault("Unknown opcode."));
+                    assert!(!OpCode::ALL.iter().any(|&opcode| opcode as u8 == byte));
+                }
+            }
+        }
+        assert!(OpCode::try_from(OpCode::Unknown as u8).is_err());
+    }
+
+    #[test]
+    fn it_lists_every_opcode() {
+        // A new variant fails to compile here until it's sorted into the
+        // match, and then fails the test until it's added to `OpCode::ALL`
+        fn executable(opcode: OpCode) -> bool {
+            match opcode {
+                OpCode::Constant
+                | OpCode::Nil
+                | OpCode::True
+                | OpCode::False
+                | OpCode::Pop
+                | OpCode::GetLocal
+                | OpCode::SetLocal
+                | OpCode::GetGlobal
+                | OpCode::SetGlobal
+                | OpCode::DefineGlobal
+                | OpCode::GetUpvalue
+                | OpCode::SetUpvalue
+                | OpCode::GetProperty
+                | OpCode::SetProperty
+                | OpCode::GetSuper
+                | OpCode::Equal
+                | OpCode::Greater
+                | OpCode::Less
+                | OpCode::Add
+                | OpCode::Subtract
+                | OpCode::Multiply
+                | OpCode::Divide
+                | OpCode::Not
+                | OpCode::Negate
+                | OpCode::Print
+                | OpCode::Jump
+                | OpCode::JumpIfFalse
+                | OpCode::Loop
+                | OpCode::Call
+                | OpCode::Invoke
+                | OpCode::SuperInvoke
+                | OpCode::Closure
+                | OpCode::CloseUpvalue
+                | OpCode::Return
+                | OpCode::Class
+                | OpCode::Inherit
+                | OpCode::Method
+                | OpCode::GetThisProperty
+                | OpCode::SetThisProperty
+                | OpCode::InvokeThis
+                | OpCode::Mixin
+                | OpCode::BuildList
+                | OpCode::IterInit
+                | OpCode::ForIn
+                | OpCode::Range
+                | OpCode::RangeInclusive
+                | OpCode::GetIndex
+                | OpCode::SetIndex
+                | OpCode::NamedArgs
+                | OpCode::Import
+                | OpCode::BitAnd
+                | OpCode::BitOr
+                | OpCode::BitXor
+                | OpCode::BitNot
+                | OpCode::ShiftLeft
+                | OpCode::ShiftRight
+                | OpCode::SameClass
+                | OpCode::StaticMethod => true,
+                OpCode::Unknown => false,
+            }
+        }
+        let listed = (0..=u8::MAX)
+            .filter_map(|byte| OpCode::try_from(byte).ok())
+            .count();
+        assert_eq!(listed, OpCode::ALL.len());
+        for opcode in OpCode::ALL {
+            assert!(executable(opcode));
+        }
+    }
+
+    #[test]
+    fn it_measures_instructions() {
+        let mut chunk = Chunk::default();
+        let function = ObjFunction::script(Chunk::default()).with_upvalues(2);
+        chunk.add_constant(function.into());
+        for byte in [
+            OpCode::Add as u8,
+            OpCode::GetLocal as u8,
+            0,
+            OpCode::Invoke as u8,
+            0,
+            1,
+            OpCode::ForIn as u8,
+            0,
+            0,
+            1,
+            OpCode::NamedArgs as u8,
+            2,
+            0,
+            0,
+            OpCode::Closure as u8,
+            0,
+            1,
+            0,
+            0,
+            1,
+        ] {
+            chunk.write(byte, 1);
+        }
+        let mut lengths = vec![];
+        let mut offset = 0;
+        while offset < chunk.code.len() {
+            let len = chunk.instruction_len(offset);
+            lengths.push(len);
+            offset += len;
+        }
+        assert_eq!(lengths, [1, 2, 3, 4, 4, 6]);
+    }
+
+    #[test]
+    fn it_decodes_instructions() {
+        let mut chunk = Chunk::default();
+        chunk.add_constant(ObjFunction::script(Chunk::default()).with_upvalues(1).into());
+        for byte in [
+            OpCode::Nil as u8,
+            OpCode::ForIn as u8,
+            3,
+            1,
+            2,
+            OpCode::Closure as u8,
+            0,
+            1,
+            7,
+            200,
+            OpCode::Jump as u8,
+            0,
+        ] {
+            chunk.write(byte, 1);
+        }
+        let instructions: Vec<_> = chunk.instructions().collect();
+        let offsets: Vec<_> = instructions.iter().map(|(offset, _)| *offset).collect();
+        assert_eq!(offsets, [0, 1, 5, 9, 10]);
+        let (_, for_in) = instructions[1];
+        assert_eq!(for_in.opcode, OpCode::ForIn);
+        assert_eq!((for_in.operand(0), for_in.operand(1)), (3, 258));
+        let (_, closure) = instructions[2];
+        assert_eq!(closure.operands, [0, 1, 7]);
+        assert_eq!(instructions[3].1.opcode, OpCode::Unknown);
+        // The jump is missing its second operand byte
+        assert_eq!(instructions[4].1.opcode, OpCode::Unknown);
+        assert_eq!(instructions[4].1.operands, [0]);
+    }
+
+    #[test]
+    fn it_measures_every_instruction_of_a_compiled_program() {
+        let source = r#"
+            class A { init(x) { this.x = x; } get() { return this.x; } }
+            fun f(a, b) {
+                var c = 0;
+                for (var i in 0..a) { c = c + i; }
+                fun g() { return a + b + c; }
+                return g;
+            }
+            print f(3, b: 4)() + A(1).get();
+            while (false) {}
+        "#;
+        let function = crate::compiler::Compiler::new(source.into())
+            .compile()
+            .unwrap();
+        let mut chunks = vec![function.chunk];
+        while let Some(chunk) = chunks.pop() {
+            let mut end = 0;
+            for (offset, instruction) in chunk.instructions() {
+                assert_eq!(offset, end);
+                assert_ne!(instruction.opcode, OpCode::Unknown);
+                end += instruction.size();
+            }
+            assert_eq!(end, chunk.code.len());
+            for constant in chunk.constants {
+                if let ConstantValue::Function(function) = constant {
+                    chunks.push(function.chunk.clone());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn it_compares_chunks_without_their_decoded_code() {
+        let function = crate::compiler::Compiler::new("print 1;".into())
+            .compile()
+            .unwrap();
+        let unverified = function.chunk.clone();
+        crate::verifier::verify(&function).expect("Failed to verify");
+        assert!(function.chunk.decoded.get().is_some());
+        assert_eq!(function.chunk, unverified);
+    }
+
     #[test]
     fn it_prints_constant_ops() {
         let mut chunk = Chunk::default();
         let constant_ops = [
             OpCode::Constant,
-            OpCode::GetGlobal,
-            OpCode::SetGlobal,
-            OpCode::DefineGlobal,
             OpCode::GetProperty,
             OpCode::SetProperty,
             OpCode::GetSuper,
             OpCode::Class,
             OpCode::Method,
+            OpCode::GetThisProperty,
+            OpCode::SetThisProperty,
+            OpCode::StaticMethod,
         ];
 
         for constant_op in constant_ops {
@@ -338,7 +978,21 @@ mod test {
 
         chunk.add_constant(1.0.into());
         let chunk_display = format!("{chunk}");
-        assert_eq!(&chunk_display, "0000\t   1\tOP_CONSTANT\t   0\t'1'\n0002\t    |\tOP_GET_GLOBAL\t   0\t'1'\n0004\t    |\tOP_SET_GLOBAL\t   0\t'1'\n0006\t    |\tOP_DEFINE_GLOBAL\t   0\t'1'\n0008\t    |\tOP_GET_PROPERTY\t   0\t'1'\n000a\t    |\tOP_SET_PROPERTY\t   0\t'1'\n000c\t    |\tOP_GET_SUPER\t   0\t'1'\n000e\t    |\tOP_CLASS\t   0\t'1'\n0010\t    |\tOP_METHOD\t   0\t'1'\n");
+        assert_eq!(&chunk_display, "0000\t   1\tOP_CONSTANT\t   0\t'1'\n0002\t    |\tOP_GET_PROPERTY\t   0\t'1'\n0004\t    |\tOP_SET_PROPERTY\t   0\t'1'\n0006\t    |\tOP_GET_SUPER\t   0\t'1'\n0008\t    |\tOP_CLASS\t   0\t'1'\n000a\t    |\tOP_METHOD\t   0\t'1'\n000c\t    |\tOP_GET_THIS_PROPERTY\t   0\t'1'\n000e\t    |\tOP_SET_THIS_PROPERTY\t   0\t'1'\n0010\t    |\tOP_STATIC_METHOD\t   0\t'1'\n");
+    }
+
+    #[test]
+    fn it_prints_global_ops() {
+        let mut chunk = Chunk::default();
+        for global_op in [OpCode::GetGlobal, OpCode::SetGlobal, OpCode::DefineGlobal] {
+            chunk.write(global_op as u8, 1);
+            chunk.write(1, 1);
+        }
+        assert_eq!(chunk.add_global("a".into()), 0);
+        assert_eq!(chunk.add_global("b".into()), 1);
+        assert_eq!(chunk.add_global("a".into()), 0);
+        let chunk_display = format!("{chunk}");
+        assert_eq!(&chunk_display, "0000\t   1\tOP_GET_GLOBAL\t   1\t'b'\n0002\t    |\tOP_SET_GLOBAL\t   1\t'b'\n0004\t    |\tOP_DEFINE_GLOBAL\t   1\t'b'\n");
     }
 
     #[test]
@@ -362,6 +1016,18 @@ mod test {
             OpCode::CloseUpvalue,
             OpCode::Return,
             OpCode::Inherit,
+            OpCode::IterInit,
+            OpCode::Range,
+            OpCode::RangeInclusive,
+            OpCode::GetIndex,
+            OpCode::SetIndex,
+            OpCode::BitAnd,
+            OpCode::BitOr,
+            OpCode::BitXor,
+            OpCode::BitNot,
+            OpCode::ShiftLeft,
+            OpCode::ShiftRight,
+            OpCode::SameClass,
             OpCode::Unknown,
         ];
 
@@ -370,7 +1036,7 @@ mod test {
         }
 
         let chunk_display = format!("{chunk}");
-        let expected_chunk_display = "0000\t   1\tOP_NIL\n0001\t    |\tOP_TRUE\n0002\t    |\tOP_FALSE\n0003\t    |\tOP_POP\n0004\t    |\tOP_EQUAL\n0005\t    |\tOP_GREATER\n0006\t    |\tOP_LESS\n0007\t    |\tOP_ADD\n0008\t    |\tOP_SUBTRACT\n0009\t    |\tOP_MULTIPLY\n000a\t    |\tOP_DIVIDE\n000b\t    |\tOP_NOT\n000c\t    |\tOP_NEGATE\n000d\t    |\tOP_PRINT\n000e\t    |\tOP_CLOSE_UPVALUE\n000f\t    |\tOP_RETURN\n0010\t    |\tOP_INHERIT\n0011\t    |\tOP_UNKNOWN\n";
+        let expected_chunk_display = "0000\t   1\tOP_NIL\n0001\t    |\tOP_TRUE\n0002\t    |\tOP_FALSE\n0003\t    |\tOP_POP\n0004\t    |\tOP_EQUAL\n0005\t    |\tOP_GREATER\n0006\t    |\tOP_LESS\n0007\t    |\tOP_ADD\n0008\t    |\tOP_SUBTRACT\n0009\t    |\tOP_MULTIPLY\n000a\t    |\tOP_DIVIDE\n000b\t    |\tOP_NOT\n000c\t    |\tOP_NEGATE\n000d\t    |\tOP_PRINT\n000e\t    |\tOP_CLOSE_UPVALUE\n000f\t    |\tOP_RETURN\n0010\t    |\tOP_INHERIT\n0011\t    |\tOP_ITER_INIT\n0012\t    |\tOP_RANGE\n0013\t    |\tOP_RANGE_INCLUSIVE\n0014\t    |\tOP_GET_INDEX\n0015\t    |\tOP_SET_INDEX\n0016\t    |\tOP_BIT_AND\n0017\t    |\tOP_BIT_OR\n0018\t    |\tOP_BIT_XOR\n0019\t    |\tOP_BIT_NOT\n001a\t    |\tOP_SHIFT_LEFT\n001b\t    |\tOP_SHIFT_RIGHT\n001c\t    |\tOP_SAME_CLASS\n001d\t    |\tOP_UNKNOWN\n";
         assert_eq!(&chunk_display, expected_chunk_display);
     }
 
@@ -383,6 +1049,8 @@ mod test {
             OpCode::GetUpvalue,
             OpCode::SetUpvalue,
             OpCode::Call,
+            OpCode::Mixin,
+            OpCode::BuildList,
         ];
 
         for (slot, &byte_op) in byte_ops.iter().enumerate() {
@@ -390,7 +1058,7 @@ mod test {
             chunk.write(slot as u8, 1);
         }
         let chunk_display = format!("{chunk}");
-        assert_eq!(chunk_display, "0000\t   1\tOP_GET_LOCAL\t   0\n0002\t    |\tOP_SET_LOCAL\t   1\n0004\t    |\tOP_GET_UPVALUE\t   2\n0006\t    |\tOP_SET_UPVALUE\t   3\n0008\t    |\tOP_CALL\t   4\n");
+        assert_eq!(chunk_display, "0000\t   1\tOP_GET_LOCAL\t   0\n0002\t    |\tOP_SET_LOCAL\t   1\n0004\t    |\tOP_GET_UPVALUE\t   2\n0006\t    |\tOP_SET_UPVALUE\t   3\n0008\t    |\tOP_CALL\t   4\n000a\t    |\tOP_MIXIN\t   5\n000c\t    |\tOP_BUILD_LIST\t   6\n");
     }
 
     #[test]
@@ -409,10 +1077,44 @@ mod test {
         assert_eq!(&chunk_display, "0000\t   1\tOP_JUMP\t   0 -> 2\n0003\t    |\tOP_JUMP_IF_FALSE\t   3 -> 5\n0006\t    |\tOP_LOOP\t   6 -> a\n");
     }
 
+    #[test]
+    fn it_prints_for_in_ops() {
+        let mut chunk = Chunk::default();
+        chunk.write(OpCode::ForIn as u8, 1);
+        chunk.write(1, 1);
+        chunk.write(0x00, 1);
+        chunk.write(0x10, 1);
+
+        let chunk_display = format!("{chunk}");
+        assert_eq!(&chunk_display, "0000\t   1\tOP_FOR_IN\t   1 -> 14\n");
+    }
+
+    #[test]
+    fn it_prints_named_args_ops() {
+        let mut chunk = Chunk::default();
+        chunk.write(OpCode::NamedArgs as u8, 1);
+        chunk.write(2, 1);
+        chunk.write(0, 1);
+        chunk.write(1, 1);
+        chunk.add_constant("x".to_string().into());
+        chunk.add_constant("y".to_string().into());
+
+        let chunk_display = format!("{chunk}");
+        assert_eq!(&chunk_display, "0000\t   1\tOP_NAMED_ARGS\t   2\t'x' 'y'\n");
+    }
+
+    #[test]
+    fn it_finds_the_source_line_of_a_span() {
+        let debug_info = DebugInfo::new("var a;\nprint a + b;\n".into());
+        assert_eq!(debug_info.line_of((0, 3)), ("var a;", 0));
+        assert_eq!(debug_info.line_of((15, 16)), ("print a + b;", 8));
+        assert_eq!(debug_info.line_of((20, 20)), ("", 0));
+    }
+
     #[test]
     fn it_prints_invoke_ops() {
         let mut chunk = Chunk::default();
-        let invoke_ops = [OpCode::Invoke, OpCode::SuperInvoke];
+        let invoke_ops = [OpCode::Invoke, OpCode::SuperInvoke, OpCode::InvokeThis];
 
         for invoke_op in invoke_ops {
             chunk.add_constant(0.0.into());
@@ -422,19 +1124,13 @@ mod test {
         }
 
         let chunk_display = format!("{chunk}");
-        assert_eq!(&chunk_display, "0000\t   1\tOP_INVOKE (0 args)\t   0\t'0'\n0003\t    |\tOP_SUPER_INVOKE (0 args)\t   0\t'0'\n");
+        assert_eq!(&chunk_display, "0000\t   1\tOP_INVOKE (0 args)\t   0\t'0'\n0003\t    |\tOP_SUPER_INVOKE (0 args)\t   0\t'0'\n0006\t    |\tOP_INVOKE_THIS (0 args)\t   0\t'0'\n");
     }
 
     #[test]
     fn it_prints_closure_ops() {
         let mut chunk = Chunk::default();
-        let function_name = "closure".into();
-        let function = ObjFunction {
-            arity: 0,
-            name: Some(function_name),
-            chunk: Chunk::default(),
-            upvalue_count: 2,
-        };
+        let function = ObjFunction::named("closure", 0, Chunk::default()).with_upvalues(2);
         chunk.add_constant(function.into());
         chunk.write(OpCode::Closure as u8, 1);
         chunk.write(0, 1);
@@ -12,6 +12,16 @@ pub enum BindingPower {
     EqualityRight,
     ComparisonLeft,
     ComparisonRight,
+    RangeLeft,
+    RangeRight,
+    BitOrLeft,
+    BitOrRight,
+    BitXorLeft,
+    BitXorRight,
+    BitAndLeft,
+    BitAndRight,
+    ShiftLeft,
+    ShiftRight,
     TermLeft,
     TermRight,
     FactorLeft,
@@ -47,13 +57,24 @@ impl TryFrom<TokenType> for InfixBindingPower {
             | TokenType::GreaterEqual => {
                 Ok((BindingPower::ComparisonLeft, BindingPower::ComparisonRight).into())
             }
+            TokenType::DotDot | TokenType::DotDotEqual => {
+                Ok((BindingPower::RangeLeft, BindingPower::RangeRight).into())
+            }
+            TokenType::Pipe => Ok((BindingPower::BitOrLeft, BindingPower::BitOrRight).into()),
+            TokenType::Caret => Ok((BindingPower::BitXorLeft, BindingPower::BitXorRight).into()),
+            TokenType::Ampersand => {
+                Ok((BindingPower::BitAndLeft, BindingPower::BitAndRight).into())
+            }
+            TokenType::LessLess | TokenType::GreaterGreater => {
+                Ok((BindingPower::ShiftLeft, BindingPower::ShiftRight).into())
+            }
             TokenType::Plus | TokenType::Minus => {
                 Ok((BindingPower::TermLeft, BindingPower::TermRight).into())
             }
             TokenType::Star | TokenType::Slash => {
                 Ok((BindingPower::FactorLeft, BindingPower::FactorRight).into())
             }
-            TokenType::Dot | TokenType::LeftParen => {
+            TokenType::Dot | TokenType::LeftParen | TokenType::LeftBracket => {
                 Ok((BindingPower::CallLeft, BindingPower::CallRight).into())
             }
             _ => Err(Error::Compile),
@@ -92,7 +113,7 @@ impl TryFrom<TokenType> for PrefixBindingPower {
     fn try_from(value: TokenType) -> Result<Self, Self::Error> {
         match value {
             TokenType::LeftParen => Ok(BindingPower::Group.into()),
-            TokenType::Bang | TokenType::Minus => Ok(BindingPower::Unary.into()),
+            TokenType::Bang | TokenType::Minus | TokenType::Tilde => Ok(BindingPower::Unary.into()),
             _ => Err(Error::Compile),
         }
     }
@@ -1,7 +1,14 @@
+//! Per-function compilation state.
+//!
+//! The compiler keeps a stack of [`Context`]s, one for the script and one for
+//! every function, method, or initializer currently being compiled. Tools
+//! built on the crate can inspect the stack through
+//! [`Compiler::contexts`](crate::compiler::Compiler::contexts).
+
 use std::array;
 
 use crate::{
-    chunk::OpCode,
+    chunk::Chunk,
     compiler::{local::Local, upvalue::Upvalue},
     object::ObjFunction,
     token::{Token, TokenType},
@@ -9,61 +16,132 @@ use crate::{
 
 #[derive(Debug)]
 pub struct Context {
+    /// The function whose bytecode is being emitted
     pub function: ObjFunction,
+    /// What kind of function this context compiles
     pub function_type: FunctionType,
+    /// How many blocks deep the compiler currently is, zero being the function's top level
     pub scope_depth: usize,
-    pub locals: [Local; u8::MAX as usize],
-    pub local_count: usize,
+    /// The index into the compiler's shared locals where this context's slot zero lives
+    pub locals_base: usize,
+    /// The variables captured from enclosing contexts, of which the first
+    /// `function.upvalue_count` are in use
     pub upvalues: [Upvalue; u8::MAX as usize],
-    pub upvalue_count: usize,
 }
 
 impl Context {
-    pub fn new(function_type: FunctionType, name: Option<String>) -> Self {
-        let mut locals = array::from_fn(|_| Local::default());
-        let local = &mut locals[0];
-        local.depth = 0;
+    pub fn new(function_type: FunctionType, name: Option<String>, locals_base: usize) -> Self {
+        let function = match name {
+            Some(name) if function_type != FunctionType::Script => {
+                ObjFunction::named(name, 0, Chunk::default())
+            }
+            _ => ObjFunction::script(Chunk::default()),
+        };
+
+        Self {
+            function,
+            scope_depth: 0,
+            function_type,
+            locals_base,
+            upvalues: array::from_fn(|_| Upvalue::default()),
+        }
+    }
+
+    /// The local reserved in slot zero of every call frame: the receiver for
+    /// methods and initializers, or an unnamed slot for the callee otherwise.
+    pub fn slot_zero(&self) -> Local {
         let mut token = Token::default();
-        local.name = if FunctionType::Function != function_type {
+        if self.is_method() {
             token.lexeme = "this".into();
             token.kind = TokenType::This;
-            token
         } else {
             token.lexeme = "".into();
             token.kind = TokenType::Identifier;
-            token
-        };
-
-        let mut function = ObjFunction::default();
-        if function_type != FunctionType::Script {
-            function.name = name
         }
 
-        Self {
-            function,
-            scope_depth: 0,
-            function_type,
-            local_count: 1,
-            locals,
-            upvalue_count: 0,
-            upvalues: array::from_fn(|_| Upvalue::default()),
+        Local {
+            name: token,
+            depth: 0,
+            ..Default::default()
         }
     }
 
-    pub fn write(&mut self, byte: u8, line: usize) {
-        self.function.chunk.write(byte, line);
+    /// Whether this context compiles a method or initializer, i.e. has a receiver in slot zero.
+    pub fn is_method(&self) -> bool {
+        self.function_type.is_method()
     }
 
-    pub fn write_opcode(&mut self, opcode: OpCode, line: usize) {
-        self.write(opcode as u8, line);
+    /// The upvalues captured so far, in the order the closure will receive them.
+    pub fn upvalues(&self) -> &[Upvalue] {
+        &self.upvalues[..self.function.upvalue_count]
     }
 }
 
 #[derive(Debug, Default, Copy, Clone, Eq, PartialEq)]
 pub enum FunctionType {
+    /// A function declared with `fun`
     Function,
+    /// A class's `init` method
     Initializer,
+    /// Any other method declared in a class body
     Method,
+    /// A method declared with `class` in a class body, called on the class
+    /// itself without a receiver
+    StaticMethod,
+    /// The top-level code of a script
     #[default]
     Script,
 }
+
+impl FunctionType {
+    pub fn is_method(&self) -> bool {
+        matches!(self, Self::Method | Self::Initializer)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_drops_the_name_of_a_script() {
+        let context = Context::new(FunctionType::Script, Some("script".into()), 0);
+        assert!(context.function.name.is_none());
+        let context = Context::new(FunctionType::Function, Some("foo".into()), 0);
+        assert_eq!(context.function.name.as_deref(), Some("foo"));
+    }
+
+    #[test]
+    fn it_reserves_slot_zero() {
+        for function_type in [FunctionType::Function, FunctionType::Script] {
+            let context = Context::new(function_type, Some("foo".into()), 0);
+            assert_eq!(context.slot_zero().name.lexeme, "");
+            assert_eq!(context.slot_zero().depth, 0);
+        }
+        for function_type in [FunctionType::Initializer, FunctionType::Method] {
+            let context = Context::new(function_type, Some("m".into()), 0);
+            assert_eq!(context.slot_zero().name.kind, TokenType::This);
+        }
+    }
+
+    #[test]
+    fn it_reports_methods() {
+        assert!(!FunctionType::Function.is_method());
+        assert!(FunctionType::Initializer.is_method());
+        assert!(FunctionType::Method.is_method());
+        assert!(!FunctionType::Script.is_method());
+        assert!(!FunctionType::StaticMethod.is_method());
+        assert!(Context::new(FunctionType::Method, None, 0).is_method());
+    }
+
+    #[test]
+    fn it_exposes_only_used_upvalues() {
+        let mut context = Context::new(FunctionType::Function, None, 0);
+        assert!(context.upvalues().is_empty());
+        context.upvalues[0].index = 3;
+        context.upvalues[0].is_local = true;
+        context.function.upvalue_count = 1;
+        assert_eq!(context.upvalues().len(), 1);
+        assert_eq!(context.upvalues()[0].index, 3);
+    }
+}
@@ -0,0 +1,128 @@
+//! Structured information gathered while compiling, for tools and tests.
+
+use std::fmt::Display;
+
+use crate::compiler::upvalue::UpvalueResolution;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Diagnostics {
+    /// Every captured variable in the order it was first resolved, when
+    /// upvalue tracing is enabled
+    pub upvalues: Vec<UpvalueResolution>,
+    /// Every local declared over a variable from an enclosing scope, when
+    /// shadowing warnings are enabled
+    pub shadowing: Vec<Shadowing>,
+    /// Every global declared again after its first declaration, when
+    /// redefinition warnings are enabled
+    pub redefinitions: Vec<Redefinition>,
+    /// The first statement of every run of statements a `return` before them
+    /// in the same block keeps from running, when unreachable code warnings
+    /// are enabled
+    pub unreachable: Vec<Unreachable>,
+    /// Every error reported, in order
+    pub errors: Vec<CompileError>,
+}
+
+/// What kind of mistake a [`CompileError`] is.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CompileErrorKind {
+    /// The source ended, or ended inside a string, where more was expected,
+    /// so adding to it could make it compile
+    UnexpectedEof,
+    /// Anything else
+    Invalid,
+}
+
+/// An error reported while compiling.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CompileError {
+    pub kind: CompileErrorKind,
+    pub line: usize,
+    /// The byte range of the token the error was found at
+    pub span: (usize, usize),
+    /// Where the error was found, e.g. ` at end` or ` at x`, or empty for
+    /// scan errors
+    pub location: String,
+    pub message: String,
+    /// The line the error is on with the error underlined, for scan errors
+    /// in code compiled with debug info
+    pub caret: Option<String>,
+}
+
+impl Display for CompileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "[line {}] Error{}: {}",
+            self.line, self.location, self.message
+        )
+    }
+}
+
+/// A local declared with the same name as a variable from an enclosing scope,
+/// hiding it for the rest of the local's scope.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Shadowing {
+    pub name: String,
+    pub line: usize,
+    /// The byte range of the shadowing declaration's name
+    pub span: (usize, usize),
+    /// Whether the shadowed variable is a global rather than an outer local
+    pub global: bool,
+    pub shadowed_line: usize,
+    /// The byte range of the shadowed declaration's name
+    pub shadowed_span: (usize, usize),
+}
+
+impl Display for Shadowing {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let kind = if self.global { "global" } else { "local" };
+        write!(
+            f,
+            "[line {}] Warning: '{}' shadows the {kind} declared on line {}.",
+            self.line, self.name, self.shadowed_line
+        )
+    }
+}
+
+/// A global declared again in the same script, overwriting the first
+/// declaration's value when it runs.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Redefinition {
+    pub name: String,
+    pub line: usize,
+    /// The byte range of the redefining declaration's name
+    pub span: (usize, usize),
+    pub previous_line: usize,
+    /// The byte range of the first declaration's name
+    pub previous_span: (usize, usize),
+}
+
+impl Display for Redefinition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "[line {}] Warning: '{}' redefines the global declared on line {}.",
+            self.line, self.name, self.previous_line
+        )
+    }
+}
+
+/// Statements after a `return` in the same block, which never run and are
+/// compiled to no code.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Unreachable {
+    pub line: usize,
+    /// The byte range of the first unreachable statement's first token
+    pub span: (usize, usize),
+}
+
+impl Display for Unreachable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "[line {}] Warning: Unreachable code after 'return'.",
+            self.line
+        )
+    }
+}
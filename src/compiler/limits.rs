@@ -0,0 +1,51 @@
+//! The most of each thing a function's bytecode can address.
+//!
+//! Operands are single bytes, so locals, upvalues, constants and the like are
+//! capped at what fits in one. Code generators targeting loxide can read the
+//! caps from [`limits`] rather than copying them.
+
+/// How many of each thing one function may have. Exceeding a limit is a
+/// compile error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Limits {
+    /// Locals in scope at once, counting the slot every function reserves
+    /// for itself or `this`
+    pub locals: usize,
+    /// Variables a closure captures
+    pub upvalues: usize,
+    /// Constants in one chunk
+    pub constants: usize,
+    /// Global names one chunk refers to
+    pub globals: usize,
+    pub parameters: usize,
+    /// Arguments in one call, named ones included
+    pub arguments: usize,
+    /// Items in one list literal
+    pub list_items: usize,
+    /// Fields of one data class
+    pub fields: usize,
+    /// Classes mixed into one class
+    pub mixins: usize,
+}
+
+pub(crate) const LIMITS: Limits = Limits {
+    locals: u8::MAX as usize,
+    upvalues: u8::MAX as usize,
+    constants: u8::MAX as usize + 1,
+    globals: u8::MAX as usize + 1,
+    parameters: u8::MAX as usize,
+    arguments: u8::MAX as usize,
+    list_items: u8::MAX as usize,
+    fields: u8::MAX as usize,
+    mixins: u8::MAX as usize,
+};
+
+/// The limits every script is compiled under. The locals and upvalues limits
+/// can be lowered with
+/// [`CompileOptions::max_locals`](crate::CompileOptions::max_locals) and
+/// [`CompileOptions::max_upvalues`](crate::CompileOptions::max_upvalues), but
+/// not raised.
+pub const fn limits() -> Limits {
+    LIMITS
+}
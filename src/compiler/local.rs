@@ -1,10 +1,15 @@
 pub use crate::token::Token;
 
+/// A local variable slot in a function's call frame.
 #[derive(Debug)]
 pub struct Local {
     pub name: Token,
+    /// The scope depth the local was declared at, or -1 while its initializer is compiling
     pub depth: isize,
+    /// Whether a closure captures the local, requiring it to be closed over when it leaves scope
     pub is_captured: bool,
+    /// Where the local is declared in the source
+    pub span: (usize, usize),
 }
 
 impl Default for Local {
@@ -13,6 +18,7 @@ impl Default for Local {
             name: Token::default(),
             depth: -1,
             is_captured: false,
+            span: (0, 0),
         }
     }
 }
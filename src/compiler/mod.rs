@@ -1,57 +1,332 @@
 pub mod binding_power;
 pub mod context;
+pub mod diagnostics;
+pub mod limits;
 pub mod local;
+pub mod number;
+pub mod options;
 pub mod upvalue;
+pub mod writer;
 
 use binding_power::{BindingPower, InfixBindingPower, PostfixBindingPower, PrefixBindingPower};
+use limits::LIMITS;
+use number::NumberError;
+pub use options::{CompileOptions, NumberOverflow};
 
 use crate::{
-    chunk::{Chunk, OpCode},
-    compiler::context::{Context, FunctionType},
+    arena::Lexeme,
+    chunk::{Chunk, DebugInfo, LocalName},
+    compiler::{
+        context::{Context, FunctionType},
+        diagnostics::{
+            CompileError, CompileErrorKind, Diagnostics, Redefinition, Shadowing, Unreachable,
+        },
+        local::Local,
+        upvalue::UpvalueResolution,
+        writer::{ChunkWriter, ForwardJump, Op, PendingJump},
+    },
     error::Error,
-    object::obj_function::ObjFunction,
-    scanner::Scanner,
-    token::{Token, TokenType},
+    object::{obj_class::is_private_member, obj_function::ObjFunction, ObjStringHasher},
+    scanner::{Scanner, SpannedTokens},
+    token::{ScanError, Token, TokenType},
     value::ConstantValue,
 };
-use std::iter::Peekable;
+use std::{
+    collections::HashMap, hash::BuildHasherDefault, io::Read, iter::Peekable, ops::Range,
+    sync::Arc,
+};
+
+/// Functions with more locals than this resolve names through the compiler's
+/// index of local names rather than by comparing against each local.
+const LINEAR_RESOLVE_LIMIT: usize = 8;
 
 #[derive(Debug)]
 pub struct Class {
-    pub has_super_class: bool,
+    /// The hidden local holding the superclass, reserved when the class declares one
+    pub superclass: Option<SlotLocation>,
+    /// Whether one of the class's static methods is being compiled, where
+    /// there is no receiver for `this` or `super` to refer to
+    pub in_static: bool,
+}
+
+/// A local slot in one of the contexts on the compiler's context stack.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct SlotLocation {
+    /// The position of the owning context, counted from the bottom of the stack
+    pub context: usize,
+    /// The local slot within that context
+    pub slot: usize,
 }
 
 #[derive(Debug)]
 pub struct Compiler {
-    scanner: Peekable<Scanner>,
+    scanner: Peekable<SpannedTokens>,
     had_error: bool,
     panic_mode: bool,
     previous_token: Option<Token>,
     line: usize,
+    /// The source span attached to emitted bytes, tracked like `line`
+    span: (usize, usize),
+    /// The source shared by every chunk's debug info, when enabled
+    debug_source: Option<Arc<str>>,
     context_stack: Vec<Context>,
+    /// The locals of every context on the stack, each context owning the
+    /// region starting at its `locals_base`
+    locals: Vec<Local>,
+    /// Where each name is declared in `locals`, innermost last, so resolving
+    /// a name in a function with many locals doesn't compare it against each
+    local_slots: HashMap<Lexeme, Vec<usize>, BuildHasherDefault<ObjStringHasher>>,
     class_stack: Vec<Class>,
+    /// How many expressions are currently being parsed inside one another
+    nesting_depth: usize,
+    max_nesting_depth: usize,
+    /// At most [`Limits::locals`](limits::Limits::locals)
+    max_locals: usize,
+    /// At most [`Limits::upvalues`](limits::Limits::upvalues)
+    max_upvalues: usize,
+    trace_upvalues: bool,
+    /// Whether locals declared over outer variables are reported in the diagnostics
+    warn_shadowing: bool,
+    /// Whether globals declared more than once are reported in the diagnostics
+    warn_redefinition: bool,
+    /// Whether statements after a `return` are reported in the diagnostics
+    warn_unreachable: bool,
+    /// Whether the statement just compiled always returns, making the rest of
+    /// its block unreachable
+    returns: bool,
+    /// Whether a script ending in an expression statement returns its value
+    expression_result: bool,
+    /// The line and span of each global declared so far, for shadowing and
+    /// redefinition warnings
+    declared_globals: HashMap<String, (usize, (usize, usize))>,
+    diagnostics: Diagnostics,
+    /// Whether literals without a fractional part are integers rather than floats
+    integers: bool,
+    /// What float literals too large to be finite compile to
+    number_overflow: NumberOverflow,
+    /// Whether `debug { ... }` blocks are compiled rather than skipped
+    debug_blocks: bool,
+    /// Whether errors are kept to the diagnostics rather than reported on stderr
+    quiet: bool,
 }
 
+/// How deeply expressions may nest before compiling fails instead of risking
+/// a stack overflow.
+pub const DEFAULT_MAX_NESTING_DEPTH: usize = 256;
+
 impl Compiler {
+    #[cfg_attr(not(feature = "internals"), allow(dead_code))]
     pub fn new(source: String) -> Self {
-        let scanner = Scanner::new(source).peekable();
-        let context_stack = vec![Context::new(FunctionType::Script, None)];
-        Self {
-            scanner,
+        Self::with_scanner(Scanner::new(source))
+    }
+
+    /// A compiler that scans `reader` as it parses instead of reading the
+    /// whole source up front. Debug info needs the whole source, so it isn't
+    /// recorded this way.
+    #[cfg_attr(not(feature = "internals"), allow(dead_code))]
+    pub fn from_reader(reader: impl Read + 'static) -> Self {
+        Self::with_scanner(Scanner::from_reader(reader))
+    }
+
+    fn with_scanner(scanner: Scanner) -> Self {
+        let mut compiler = Self {
+            scanner: scanner.spanned().peekable(),
             line: 1,
+            span: (0, 0),
+            debug_source: None,
             had_error: false,
             panic_mode: false,
             previous_token: None,
-            context_stack,
+            context_stack: Vec::new(),
+            locals: Vec::new(),
+            local_slots: HashMap::default(),
             class_stack: Vec::new(),
+            nesting_depth: 0,
+            max_nesting_depth: DEFAULT_MAX_NESTING_DEPTH,
+            max_locals: LIMITS.locals,
+            max_upvalues: LIMITS.upvalues,
+            trace_upvalues: false,
+            warn_shadowing: false,
+            warn_redefinition: false,
+            warn_unreachable: false,
+            returns: false,
+            expression_result: false,
+            declared_globals: HashMap::new(),
+            diagnostics: Diagnostics::default(),
+            integers: false,
+            number_overflow: NumberOverflow::default(),
+            debug_blocks: false,
+            quiet: false,
+        };
+        compiler.push_context(FunctionType::Script, None);
+        compiler
+    }
+
+    /// A compiler for `source` configured by `options`.
+    pub fn with_options(source: &str, options: &CompileOptions) -> Self {
+        let scanner = Scanner::new(source.into()).with_keywords(options.active_keywords());
+        let compiler = Self::with_scanner(scanner).configured(options);
+        if options.debug_info {
+            return compiler.with_debug_info(source);
+        }
+        compiler
+    }
+
+    /// A compiler streaming from `reader`, configured by `options` apart from
+    /// `debug_info`.
+    pub fn from_reader_with_options(reader: impl Read + 'static, options: &CompileOptions) -> Self {
+        let scanner = Scanner::from_reader(reader).with_keywords(options.active_keywords());
+        Self::with_scanner(scanner).configured(options)
+    }
+
+    /// Applies every option that doesn't need the source or the scanner.
+    fn configured(self, options: &CompileOptions) -> Self {
+        let mut compiler = self
+            .with_max_nesting_depth(options.max_nesting_depth)
+            .with_max_locals(options.max_locals)
+            .with_max_upvalues(options.max_upvalues)
+            .with_number_overflow(options.number_overflow);
+        if options.integers {
+            compiler = compiler.with_integers();
+        }
+        if options.upvalue_trace {
+            compiler = compiler.with_upvalue_trace();
+        }
+        if options.shadowing_warnings {
+            compiler = compiler.with_shadowing_warnings();
+        }
+        if options.debug_blocks {
+            compiler = compiler.with_debug_blocks();
         }
+        if options.redefinition_warnings {
+            compiler = compiler.with_redefinition_warnings();
+        }
+        if options.unreachable_warnings {
+            compiler = compiler.with_unreachable_warnings();
+        }
+        compiler
+    }
+
+    /// Records a source map in every chunk, enabling caret diagnostics at runtime.
+    pub fn with_debug_info(mut self, source: &str) -> Self {
+        let source: Arc<str> = source.into();
+        self.current_chunk().debug_info = Some(DebugInfo::new(source.clone()));
+        self.debug_source = Some(source);
+        self
+    }
+
+    /// Limits how deeply expressions may nest, e.g. through parentheses or
+    /// call arguments.
+    pub fn with_max_nesting_depth(mut self, depth: usize) -> Self {
+        self.max_nesting_depth = depth;
+        self
+    }
+
+    /// Limits how many locals a function may have in scope at once, counting
+    /// its reserved slot. Limits above [`limits::limits`]'s are lowered to it.
+    pub fn with_max_locals(mut self, count: usize) -> Self {
+        self.max_locals = count.min(LIMITS.locals);
+        self
+    }
+
+    /// Limits how many variables a closure may capture. Limits above
+    /// [`limits::limits`]'s are lowered to it.
+    pub fn with_max_upvalues(mut self, count: usize) -> Self {
+        self.max_upvalues = count.min(LIMITS.upvalues);
+        self
+    }
+
+    /// Compiles literals like `3` to integers. Arithmetic on two integers then
+    /// stays integral, except for division, which always produces a float.
+    pub fn with_integers(mut self) -> Self {
+        self.integers = true;
+        self
     }
 
-    pub fn compile(mut self) -> Result<ObjFunction, Error> {
+    /// Sets what float literals too large to be finite compile to.
+    pub fn with_number_overflow(mut self, overflow: NumberOverflow) -> Self {
+        self.number_overflow = overflow;
+        self
+    }
+
+    /// Records how every captured variable is resolved in the diagnostics.
+    pub fn with_upvalue_trace(mut self) -> Self {
+        self.trace_upvalues = true;
+        self
+    }
+
+    /// Reports every local declared over a local of an enclosing scope, or over
+    /// a global declared earlier in the script, in the diagnostics.
+    pub fn with_shadowing_warnings(mut self) -> Self {
+        self.warn_shadowing = true;
+        self
+    }
+
+    /// Reports every global the script declares again after its first
+    /// declaration in the diagnostics.
+    pub fn with_redefinition_warnings(mut self) -> Self {
+        self.warn_redefinition = true;
+        self
+    }
+
+    /// Reports the statements after a `return` in the same block in the
+    /// diagnostics. They never run, so they are compiled to no code whether
+    /// or not this is set.
+    pub fn with_unreachable_warnings(mut self) -> Self {
+        self.warn_unreachable = true;
+        self
+    }
+
+    /// Compiles the contents of `debug { ... }` blocks, which are otherwise
+    /// skipped without emitting any code.
+    pub fn with_debug_blocks(mut self) -> Self {
+        self.debug_blocks = true;
+        self
+    }
+
+    /// Has a script whose last statement is an expression statement return
+    /// the expression's value rather than nil. The semicolon ending it may
+    /// be left out.
+    pub fn with_expression_result(mut self) -> Self {
+        self.expression_result = true;
+        self
+    }
+
+    /// Keeps errors to the diagnostics rather than reporting them on stderr,
+    /// for callers that report them their own way.
+    pub(crate) fn quiet(mut self) -> Self {
+        self.quiet = true;
+        self
+    }
+
+    pub fn compile(self) -> Result<ObjFunction, Error> {
+        self.compile_with_diagnostics().0
+    }
+
+    /// Whether the source stops partway through, so that more of it could
+    /// make it compile: its first error is [`CompileErrorKind::UnexpectedEof`].
+    /// Nothing is reported on stderr.
+    pub fn ends_too_soon(mut self) -> bool {
+        self.quiet = true;
+        let _ = self.compile_script();
+        self.diagnostics
+            .errors
+            .first()
+            .is_some_and(|error| error.kind == CompileErrorKind::UnexpectedEof)
+    }
+
+    /// Compiles like [`Compiler::compile`], also returning what was gathered
+    /// along the way, even when compiling fails.
+    pub fn compile_with_diagnostics(mut self) -> (Result<ObjFunction, Error>, Diagnostics) {
+        let result = self.compile_script();
+        (result, self.diagnostics)
+    }
+
+    fn compile_script(&mut self) -> Result<ObjFunction, Error> {
         loop {
             match self.scanner.peek() {
                 None => break,
-                Some(t) => {
+                Some((t, _)) => {
                     if t.kind == TokenType::Eof {
                         break;
                     }
@@ -69,6 +344,12 @@ impl Compiler {
         Ok(context.function)
     }
 
+    /// The contexts currently being compiled, from the script outwards to the innermost function.
+    #[cfg_attr(not(feature = "internals"), allow(dead_code))]
+    pub fn contexts(&self) -> &[Context] {
+        &self.context_stack
+    }
+
     fn current_context(&mut self) -> &mut Context {
         self.context_stack
             .last_mut()
@@ -87,6 +368,7 @@ impl Compiler {
         &mut self.current_context().function
     }
 
+    #[cfg_attr(not(feature = "internals"), allow(dead_code))]
     pub fn current_class(&mut self) -> &mut Class {
         self.class_stack
             .last_mut()
@@ -105,63 +387,61 @@ impl Compiler {
     }
 
     fn identifiers_equal(a: &Token, b: &Token) -> bool {
-        a.lexeme.len() == b.lexeme.len() && a.lexeme == b.lexeme
+        // Lexemes compare their hashes before their text
+        a.lexeme == b.lexeme
     }
 
-    fn emit_byte(&mut self, byte: u8) {
-        let line = self.line;
-        self.current_chunk().write(byte, line);
+    fn push_local(&mut self, local: Local) {
+        self.local_slots
+            .entry(local.name.lexeme.clone())
+            .or_default()
+            .push(self.locals.len());
+        self.locals.push(local);
+    }
+
+    fn pop_local(&mut self) -> Option<Local> {
+        let local = self.locals.pop()?;
+        if let Some(slots) = self.local_slots.get_mut(&local.name.lexeme) {
+            slots.pop();
+            if slots.is_empty() {
+                self.local_slots.remove(&local.name.lexeme);
+            }
+        }
+        Some(local)
     }
 
-    fn emit_opcode(&mut self, opcode: OpCode) {
-        self.emit_byte(opcode as u8);
+    fn writer(&mut self) -> ChunkWriter<'_> {
+        let line = self.line;
+        let span = self.span;
+        ChunkWriter::new(self.current_chunk(), line, span)
     }
 
-    fn emit_bytes(&mut self, byte1: u8, byte2: u8) {
-        self.emit_byte(byte1);
-        self.emit_byte(byte2);
+    fn emit(&mut self, op: Op) {
+        self.writer().emit(op);
     }
 
     fn emit_return(&mut self) {
         if self.current_function_type() == FunctionType::Initializer {
-            self.emit_bytes(OpCode::GetLocal as u8, 0);
+            self.emit(Op::GetLocal(0));
         } else {
-            self.emit_byte(OpCode::Nil as u8);
+            self.emit(Op::Nil);
         }
-        self.emit_byte(OpCode::Return as u8);
+        self.emit(Op::Return);
     }
 
-    fn emit_jump(&mut self, opcode: OpCode) -> usize {
-        match opcode {
-            OpCode::Jump | OpCode::JumpIfFalse => {}
-            o => panic!("ICE: Tried to emit jump with non jump condition: {o}"),
-        }
-        self.emit_opcode(opcode);
-        self.emit_byte(0xffu8);
-        self.emit_byte(0xffu8);
-        self.current_chunk().code.len() - 2
+    fn emit_jump(&mut self, jump: ForwardJump) -> PendingJump {
+        self.writer().emit_jump(jump)
     }
 
     fn emit_loop(&mut self, loop_start: usize) {
-        self.emit_opcode(OpCode::Loop);
-
-        let offset = self.current_chunk().code.len() - loop_start + 2;
-        if offset > u16::MAX as usize {
-            self.error("Loop body too large.");
+        if let Err(message) = self.writer().emit_loop(loop_start) {
+            self.error(message);
         }
-
-        let offset = (offset as u16).to_le_bytes();
-
-        // High bits
-        self.emit_byte(offset[1]);
-        // Low bits
-        self.emit_byte(offset[0]);
     }
 
     fn emit_constant(&mut self, value: ConstantValue) {
-        self.emit_opcode(OpCode::Constant);
         let constant = self.make_constant(value);
-        self.emit_byte(constant);
+        self.emit(Op::Constant(constant));
     }
 
     fn make_constant(&mut self, value: ConstantValue) -> u8 {
@@ -173,23 +453,91 @@ impl Compiler {
         constant as u8
     }
 
-    fn patch_jump(&mut self, offset: usize) {
-        // -2 to adjust for the bytecode for the jump itself
-        let jump = self.current_chunk().code.len() - offset - 2;
-        if jump > u16::MAX as usize {
-            self.error("Too much code to jump over.");
+    fn patch_jump(&mut self, jump: PendingJump) {
+        if let Err(message) = self.writer().patch_jump(jump) {
+            self.error(message);
         }
+    }
 
-        // High bits
-        self.current_chunk().code[offset] = ((jump >> 8) & 0xff) as u8;
-        // Low bits
-        self.current_chunk().code[offset + 1] = (jump & 0xff) as u8;
+    fn push_context(&mut self, function_type: FunctionType, name: Option<String>) {
+        let mut context = Context::new(function_type, name, self.locals.len());
+        if let Some(source) = &self.debug_source {
+            context.function.chunk.debug_info = Some(DebugInfo::new(source.clone()));
+        }
+        self.push_local(context.slot_zero());
+        self.context_stack.push(context);
+        self.record_local_start();
     }
 
     fn pop_context(&mut self) -> Context {
-        self.context_stack
+        let mut context = self
+            .context_stack
             .pop()
-            .expect("ICE: Failed to pop context.")
+            .expect("ICE: Failed to pop context.");
+        while self.locals.len() > context.locals_base {
+            self.pop_local();
+        }
+        let end = context.function.chunk.code.len();
+        if let Some(debug_info) = context.function.chunk.debug_info.as_mut() {
+            for local in &mut debug_info.locals {
+                local.live.end = local.live.end.min(end);
+            }
+        }
+        context
+    }
+
+    /// Records the last local as live from here on, for debug info.
+    fn record_local_start(&mut self) {
+        let slot = self.current_locals().len() - 1;
+        let name = self.locals[self.locals.len() - 1].name.lexeme.to_string();
+        // Skip the unnamed slot zero of functions and hidden locals
+        if name.is_empty() || name.starts_with(' ') {
+            return;
+        }
+        let start = self.current_chunk().code.len();
+        if let Some(debug_info) = self.current_chunk().debug_info.as_mut() {
+            debug_info.locals.push(LocalName {
+                name,
+                slot,
+                live: start..usize::MAX,
+            });
+        }
+    }
+
+    /// Records the local in `slot` as going out of scope here, for debug info.
+    fn record_local_end(&mut self, slot: usize) {
+        let end = self.current_chunk().code.len();
+        if let Some(debug_info) = self.current_chunk().debug_info.as_mut() {
+            if let Some(local) = debug_info
+                .locals
+                .iter_mut()
+                .rev()
+                .find(|local| local.slot == slot && local.live.end == usize::MAX)
+            {
+                local.live.end = end;
+            }
+        }
+    }
+
+    /// The region of `locals` owned by the context `index` levels below the top of the stack
+    fn locals_range(&self, index: usize) -> Option<Range<usize>> {
+        if index >= self.context_stack.len() {
+            return None;
+        }
+        let position = self.context_stack.len() - index - 1;
+        let start = self.context_stack[position].locals_base;
+        let end = self
+            .context_stack
+            .get(position + 1)
+            .map_or(self.locals.len(), |c| c.locals_base);
+        Some(start..end)
+    }
+
+    fn current_locals(&self) -> &[Local] {
+        let range = self
+            .locals_range(0)
+            .expect("ICE: Failed to get current context");
+        &self.locals[range]
     }
 
     fn peek_context(&mut self, index: usize) -> Option<&mut Context> {
@@ -203,7 +551,7 @@ impl Compiler {
     fn synchronize(&mut self) {
         self.panic_mode = false;
 
-        while self.scanner.peek().unwrap().kind != TokenType::Eof {
+        while self.peek_scanner().kind != TokenType::Eof {
             if self
                 .previous_token
                 .as_ref()
@@ -214,13 +562,14 @@ impl Compiler {
 
             match self.scanner.next() {
                 None => break,
-                Some(t) => match t.kind {
+                Some((t, _)) => match t.kind {
                     TokenType::Class
                     | TokenType::Fun
                     | TokenType::Var
                     | TokenType::If
                     | TokenType::While
                     | TokenType::Print
+                    | TokenType::Import
                     | TokenType::Return => return,
                     _ => {}
                 },
@@ -230,23 +579,43 @@ impl Compiler {
 
     /// The scanner should never return a `None` value, so we panic if it does
     fn peek_scanner(&mut self) -> &Token {
-        self.scanner
+        &self
+            .scanner
             .peek()
             .expect("ICE: Failed to get token from scanner")
+            .0
+    }
+
+    /// The source span of the token being looked at.
+    fn peek_span(&mut self) -> (usize, usize) {
+        self.scanner
+            .peek()
+            .expect("ICE: Failed to peek token from scanner")
+            .1
+    }
+
+    fn take_token(&mut self) {
+        let (token, span) = self
+            .scanner
+            .next()
+            .expect("ICE: Failed to get token from scanner");
+        self.line = token.line;
+        self.span = span;
+        self.previous_token = Some(token);
     }
 
     fn advance_scanner(&mut self) {
-        self.previous_token = self.scanner.next();
-        self.line = self.previous_token.as_ref().unwrap().line;
+        self.take_token();
         loop {
             let current_token = self.peek_scanner();
-            let lexeme = current_token.lexeme.clone();
             match current_token.kind {
-                TokenType::Error => self.error_at_current(&lexeme),
+                TokenType::Error(error) => {
+                    let message = error.message(&current_token.lexeme);
+                    self.error_at_current(&message);
+                }
                 _ => break,
             }
-            self.previous_token = self.scanner.next();
-            self.line = self.previous_token.as_ref().unwrap().line;
+            self.take_token();
         }
     }
 
@@ -275,33 +644,59 @@ impl Compiler {
         self.error_at_current(message);
     }
 
-    fn error_at(&mut self, token: &Token, message: &str) {
+    fn error_at(&mut self, token: &Token, span: (usize, usize), message: &str) {
         if self.panic_mode {
             return;
         }
 
         self.panic_mode = true;
-        eprint!("[line {}] Error", token.line);
-
-        match token.kind {
-            TokenType::Eof => eprint!(" at end"),
-            TokenType::Error => {}
-            _ => eprint!(" at {}", token.lexeme),
-        }
-
-        eprintln!(": {}", message);
-
         self.had_error = true;
+        let kind = match token.kind {
+            TokenType::Eof | TokenType::Error(ScanError::UnterminatedString) => {
+                CompileErrorKind::UnexpectedEof
+            }
+            _ => CompileErrorKind::Invalid,
+        };
+        let location = match token.kind {
+            TokenType::Eof => " at end".to_string(),
+            TokenType::Error(_) => String::new(),
+            _ => format!(" at {}", token.lexeme),
+        };
+        // Scan errors are only ever reported on the token being looked at
+        let caret = match token.kind {
+            TokenType::Error(_) => {
+                let span = self.peek_span();
+                let debug_info = self.current_chunk().debug_info.as_ref();
+                debug_info.map(|debug_info| debug_info.caret(span))
+            }
+            _ => None,
+        };
+        let error = CompileError {
+            kind,
+            line: token.line,
+            span,
+            location,
+            message: message.to_string(),
+            caret,
+        };
+        if !self.quiet {
+            eprintln!("{error}");
+            if let Some(caret) = &error.caret {
+                eprint!("{caret}");
+            }
+        }
+        self.diagnostics.errors.push(error);
     }
 
     fn error(&mut self, message: &str) {
         let at_token = self.previous().clone();
-        self.error_at(&at_token, message);
+        self.error_at(&at_token, self.span, message);
     }
 
     fn error_at_current(&mut self, message: &str) {
         let at_token = self.peek_scanner().clone();
-        self.error_at(&at_token, message);
+        let span = self.peek_span();
+        self.error_at(&at_token, span, message);
     }
 
     fn begin_scope(&mut self) {
@@ -310,55 +705,76 @@ impl Compiler {
     }
 
     fn end_scope(&mut self) {
-        let line = self.line;
         let context = self.current_context();
         context.scope_depth -= 1;
-        while context.local_count > 0
-            && context.locals[context.local_count - 1].depth as usize > context.scope_depth
+        let scope_depth = context.scope_depth;
+        let locals_base = context.locals_base;
+        while self.locals.len() > locals_base
+            && self.locals[self.locals.len() - 1].depth as usize > scope_depth
         {
-            if context.locals[context.local_count - 1].is_captured {
-                context.write_opcode(OpCode::CloseUpvalue, line);
+            let slot = self.current_locals().len() - 1;
+            self.record_local_end(slot);
+            let local = self.pop_local().expect("ICE: Failed to pop local.");
+            if local.is_captured {
+                self.emit(Op::CloseUpvalue);
             } else {
-                context.write_opcode(OpCode::Pop, line);
+                self.emit(Op::Pop);
             }
-            context.local_count -= 1;
         }
     }
 
     fn mark_initialized(&mut self) {
-        let context = self.current_context();
-        if context.scope_depth == 0 {
+        let scope_depth = self.current_context().scope_depth;
+        if scope_depth == 0 {
             return;
         }
-        context.locals[context.local_count - 1].depth = context.scope_depth as isize;
+        let local = self
+            .locals
+            .last_mut()
+            .expect("ICE: Failed to get last local.");
+        // Function declarations are marked early so they can refer to themselves
+        let was_initialized = local.depth != -1;
+        local.depth = scope_depth as isize;
+        if !was_initialized {
+            self.record_local_start();
+        }
     }
 
     fn resolve_local(&mut self, name: &Token, index: usize) -> Option<usize> {
-        let context = self.peek_context(index)?;
-        for i in (0..context.local_count).rev() {
-            let local = &context.locals[i];
-            if Self::identifiers_equal(name, &local.name) {
-                if local.depth == -1 {
-                    self.error("can't read local variable in its own initializer.");
-                }
-                return Some(i);
-            }
+        let range = self.locals_range(index)?;
+        let slot = if range.len() > LINEAR_RESOLVE_LIMIT {
+            self.local_slots
+                .get(&name.lexeme)?
+                .iter()
+                .rev()
+                .find(|slot| range.contains(slot))
+                .copied()
+        } else {
+            range
+                .clone()
+                .rev()
+                .find(|&i| Self::identifiers_equal(name, &self.locals[i].name))
+        }?;
+        if self.locals[slot].depth == -1 {
+            self.error("can't read local variable in its own initializer.");
         }
-        None
+        Some(slot - range.start)
     }
 
     fn resolve_upvalue(&mut self, name: &Token, index: usize) -> Option<usize> {
         self.peek_context(index + 1)?;
         let local = self.resolve_local(name, index + 1);
-        let enclosing_context = self.peek_context(index + 1)?;
+        let locals_base = self.peek_context(index + 1)?.locals_base;
         match local {
             Some(l) => {
-                enclosing_context.locals[l].is_captured = true;
-                return self.add_upvalue(index, l, true).into();
+                self.locals[locals_base + l].is_captured = true;
+                return self.add_traced_upvalue(&name.lexeme, index, l, true).into();
             }
             None => {
                 if let Some(v) = self.resolve_upvalue(name, index + 1) {
-                    return self.add_upvalue(index, v, false).into();
+                    return self
+                        .add_traced_upvalue(&name.lexeme, index, v, false)
+                        .into();
                 }
             }
         }
@@ -366,8 +782,50 @@ impl Compiler {
         None
     }
 
+    /// Adds an upvalue like [`Compiler::add_upvalue`], recording the
+    /// resolution when tracing is enabled and the upvalue is new.
+    fn add_traced_upvalue(
+        &mut self,
+        name: &str,
+        context_index: usize,
+        upvalue_index: usize,
+        is_local: bool,
+    ) -> usize {
+        let upvalue_count = self
+            .peek_context(context_index)
+            .map(|c| c.function.upvalue_count);
+        let upvalue = self.add_upvalue(context_index, upvalue_index, is_local);
+        if self.trace_upvalues && upvalue_count == Some(upvalue) {
+            let function = self
+                .peek_context(context_index)
+                .and_then(|c| c.function.name.clone());
+            let enclosing = self
+                .peek_context(context_index + 1)
+                .and_then(|c| c.function.name.clone());
+            self.diagnostics.upvalues.push(UpvalueResolution {
+                name: name.to_string(),
+                function,
+                upvalue,
+                enclosing,
+                index: upvalue_index,
+                is_local,
+            });
+        }
+        upvalue
+    }
+
     fn identifier_constant(&mut self, name: Token) -> u8 {
-        self.make_constant(ConstantValue::from(name.lexeme))
+        self.make_constant(ConstantValue::from(name.lexeme.as_str()))
+    }
+
+    /// The index of `name` in the chunk's global name table.
+    fn global_index(&mut self, name: Token) -> u8 {
+        let index = self.current_chunk().add_global(name.lexeme.as_str().into());
+        if index > u8::MAX as usize {
+            self.error("Too many globals in one chunk.");
+            return 0;
+        }
+        index as u8
     }
 
     fn parse_variable(&mut self, error_message: &str) -> u8 {
@@ -376,7 +834,7 @@ impl Compiler {
         if self.current_context().scope_depth > 0 {
             return 0;
         }
-        self.identifier_constant(self.previous().clone())
+        self.global_index(self.previous().clone())
     }
 
     fn add_upvalue(&mut self, context_index: usize, upvalue_index: usize, is_local: bool) -> usize {
@@ -389,16 +847,23 @@ impl Compiler {
             let context = self
                 .peek_context(context_index)
                 .expect("ICE: Failed to peek context");
-            for i in 0..upvalue_count {
-                let upvalue = &context.upvalues[i];
+            for (i, upvalue) in context.upvalues().iter().enumerate() {
                 if upvalue.index == upvalue_index && upvalue.is_local == is_local {
                     return i;
                 }
             }
         }
 
-        if upvalue_count == u8::MAX as usize {
-            self.error("Too many closure variables in function.");
+        if upvalue_count >= self.max_upvalues {
+            let function = self
+                .peek_context(context_index)
+                .expect("ICE: Failed to peek context")
+                .function
+                .to_string();
+            let limit = self.max_upvalues;
+            self.error(&format!(
+                "Too many closure variables in {function} (limit {limit})."
+            ));
             return 0;
         }
 
@@ -413,43 +878,100 @@ impl Compiler {
     }
 
     fn add_local(&mut self, name: Token) {
-        let context = self.current_context();
-        if context.local_count == u8::MAX as usize {
-            self.error("Too many local variables in function.");
+        self.add_local_at(name, self.span);
+    }
+
+    fn add_local_at(&mut self, name: Token, span: (usize, usize)) {
+        if self.current_locals().len() >= self.max_locals {
+            let limit = self.max_locals;
+            let message = format!(
+                "Too many local variables in {} (limit {limit}).",
+                self.current_function()
+            );
+            self.error_at(&name, span, &message);
             return;
         }
 
-        let local = &mut context.locals[context.local_count];
-        context.local_count += 1;
-        local.name = name;
-        local.depth = -1;
-        local.is_captured = false;
+        self.push_local(Local {
+            name,
+            depth: -1,
+            is_captured: false,
+            span,
+        });
     }
 
     fn declare_variable(&mut self) {
         if self.current_context().scope_depth == 0 {
+            if self.warn_shadowing || self.warn_redefinition {
+                self.declare_global();
+            }
             return;
         }
-        let local_count = self.current_context().local_count;
         let scope_depth = self.current_context().scope_depth;
         let name = self.previous().clone();
 
-        for i in (0..local_count).rev() {
-            let locals = &self
-                .context_stack
-                .last()
-                .expect("ICE: Failed to read context stack.")
-                .locals;
-            let local = &locals[i];
-            if local.depth != -1 && (local.depth as usize) < scope_depth {
-                break;
-            }
+        let scope_start = self.locals.len()
+            - self
+                .current_locals()
+                .iter()
+                .rev()
+                .take_while(|local| local.depth == -1 || (local.depth as usize) >= scope_depth)
+                .count();
+        let is_redeclared = self.locals[scope_start..]
+            .iter()
+            .any(|local| Self::identifiers_equal(&name, &local.name));
+        if is_redeclared {
+            self.error("Robert can't make up his mind about whether to allow redefining an existing variable, so he made this an error in the local scope but not in the global one.");
+        } else if self.warn_shadowing {
+            self.warn_if_shadowing(&name, scope_start);
+        }
+        self.add_local(name);
+    }
 
-            if Self::identifiers_equal(&name, &local.name) {
-                self.error("Robert can't make up his mind about whether to allow redefining an existing variable, so he made this an error in the local scope but not in the global one.");
+    /// Remembers where the global just named was first declared, warning if
+    /// this declaration redefines it.
+    fn declare_global(&mut self) {
+        let name = self.previous().clone();
+        match self.declared_globals.get(name.lexeme.as_str()) {
+            Some(&(previous_line, previous_span)) if self.warn_redefinition => {
+                self.diagnostics.redefinitions.push(Redefinition {
+                    name: name.lexeme.to_string(),
+                    line: name.line,
+                    span: self.span,
+                    previous_line,
+                    previous_span,
+                });
+            }
+            Some(_) => {}
+            None => {
+                self.declared_globals
+                    .insert(name.lexeme.to_string(), (name.line, self.span));
             }
         }
-        self.add_local(name);
+    }
+
+    /// Records a warning if `name` is also a local declared before
+    /// `scope_start`, in an enclosing scope or function, or a global.
+    fn warn_if_shadowing(&mut self, name: &Token, scope_start: usize) {
+        let outer = self.locals[..scope_start]
+            .iter()
+            .rev()
+            .find(|local| Self::identifiers_equal(name, &local.name))
+            .map(|local| (false, local.name.line, local.span));
+        let global = || {
+            let &(line, span) = self.declared_globals.get(name.lexeme.as_str())?;
+            Some((true, line, span))
+        };
+        if let Some((global, shadowed_line, shadowed_span)) = outer.or_else(global) {
+            self.diagnostics.shadowing.push(Shadowing {
+                name: name.lexeme.to_string(),
+                line: name.line,
+                span: self.span,
+                global,
+                shadowed_line,
+                shadowed_span,
+            });
+        }
     }
 
     fn define_variable(&mut self, global: u8) {
@@ -457,8 +979,7 @@ impl Compiler {
             self.mark_initialized();
             return;
         }
-        self.emit_opcode(OpCode::DefineGlobal);
-        self.emit_byte(global);
+        self.emit(Op::DefineGlobal(global));
     }
 
     fn declaration(&mut self) {
@@ -479,12 +1000,23 @@ impl Compiler {
         let class_name = self.previous().clone();
         let name_constant = self.identifier_constant(class_name.clone());
         self.declare_variable();
+        let fields = if self.advance_if_eq(TokenType::LeftParen) {
+            Some(self.field_list())
+        } else {
+            None
+        };
 
-        self.emit_bytes(OpCode::Class as u8, name_constant);
-        self.define_variable(name_constant);
+        self.emit(Op::Class(name_constant));
+        let global = if self.current_context().scope_depth > 0 {
+            0
+        } else {
+            self.global_index(class_name.clone())
+        };
+        self.define_variable(global);
 
         let class = Class {
-            has_super_class: false,
+            superclass: None,
+            in_static: false,
         };
         self.class_stack.push(class);
 
@@ -509,32 +1041,163 @@ impl Compiler {
             });
             self.define_variable(0);
 
+            let superclass = SlotLocation {
+                context: self.context_stack.len() - 1,
+                slot: self.current_locals().len() - 1,
+            };
+
             self.named_variable(class_name.clone(), BindingPower::LogicalLeft);
-            self.emit_opcode(OpCode::Inherit);
-            self.peek_class(0).has_super_class = true;
+            self.emit(Op::Inherit);
+            self.peek_class(0).superclass = Some(superclass);
+        }
+
+        if self.advance_if_eq(TokenType::With) {
+            self.mixin_list(&class_name);
         }
 
         self.named_variable(class_name, BindingPower::LogicalLeft);
         self.consume(TokenType::LeftBrace, "Expect '{' before class body.");
 
+        let is_data_class = fields.is_some();
+        if let Some(fields) = fields {
+            self.data_class_methods(&fields);
+        }
+
         loop {
             let next_token = self.peek_scanner();
             if next_token.kind == TokenType::RightBrace || next_token.kind == TokenType::Eof {
                 break;
             }
 
-            self.method();
+            if self.advance_if_eq(TokenType::Class) {
+                self.static_method();
+            } else {
+                self.method(is_data_class);
+            }
         }
 
         self.consume(TokenType::RightBrace, "Expect '}' after class body.");
-        self.emit_opcode(OpCode::Pop);
-        if self.peek_class(0).has_super_class {
+        self.emit(Op::Pop);
+        if self.peek_class(0).superclass.is_some() {
             self.end_scope();
         }
 
         self.pop_class();
     }
 
+    /// Parses the mixins of `class A with B, C {}` and emits an instruction
+    /// copying their methods into the class.
+    fn mixin_list(&mut self, class_name: &Token) {
+        self.named_variable(class_name.clone(), BindingPower::LogicalLeft);
+        let mut mixins: Vec<Token> = vec![];
+        loop {
+            self.consume(TokenType::Identifier, "Expect mixin name.");
+            let mixin = self.previous().clone();
+            if Compiler::identifiers_equal(class_name, &mixin) {
+                self.error("A class can't mix in itself.");
+            } else if mixins
+                .iter()
+                .any(|m| Compiler::identifiers_equal(m, &mixin))
+            {
+                self.error("A mixin can't be listed more than once.");
+            } else if mixins.len() == u8::MAX as usize {
+                self.error("Can't have more than 255 mixins.");
+            }
+            self.variable(BindingPower::LogicalLeft);
+            mixins.push(mixin);
+            if !self.advance_if_eq(TokenType::Comma) {
+                break;
+            }
+        }
+        self.emit(Op::Mixin(mixins.len() as u8));
+    }
+
+    /// Parses the field names of a data class like `class Point(x, y) {}`.
+    fn field_list(&mut self) -> Vec<Token> {
+        let mut fields: Vec<Token> = vec![];
+        if self.peek_scanner().kind != TokenType::RightParen {
+            loop {
+                self.consume(TokenType::Identifier, "Expect field name.");
+                let field = self.previous().clone();
+                if fields.len() == u8::MAX as usize {
+                    self.error("Can't have more than 255 fields.");
+                } else if fields.iter().any(|f| Self::identifiers_equal(f, &field)) {
+                    self.error("Already a field with this name in this class.");
+                }
+                fields.push(field);
+                if !self.advance_if_eq(TokenType::Comma) {
+                    break;
+                }
+            }
+        }
+        self.consume(TokenType::RightParen, "Expect ')' after fields.");
+        fields
+    }
+
+    /// Generates `init` storing each field from its parameter, and `equals`
+    /// comparing each field with those of another instance. The class body
+    /// can't declare methods of those names.
+    fn data_class_methods(&mut self, fields: &[Token]) {
+        let parameters = fields.iter().map(|field| field.lexeme.to_string()).collect();
+        self.synthesized_method("init", FunctionType::Initializer, parameters, |c| {
+            for (slot, field) in fields.iter().enumerate() {
+                let name = c.identifier_constant(field.clone());
+                c.emit(Op::GetLocal(0));
+                c.emit(Op::GetLocal(slot as u8 + 1));
+                c.emit(Op::SetThisProperty(name));
+                c.emit(Op::Pop);
+            }
+        });
+        self.synthesized_method("equals", FunctionType::Method, vec!["other".into()], |c| {
+            // Only instances of the same class have the same fields to compare
+            c.emit(Op::GetLocal(0));
+            c.emit(Op::GetLocal(1));
+            c.emit(Op::SameClass);
+            let mut exit_jumps = vec![c.emit_jump(ForwardJump::JumpIfFalse)];
+            c.emit(Op::Pop);
+            if fields.is_empty() {
+                c.emit(Op::True);
+            }
+            for (i, field) in fields.iter().enumerate() {
+                if i > 0 {
+                    exit_jumps.push(c.emit_jump(ForwardJump::JumpIfFalse));
+                    c.emit(Op::Pop);
+                }
+                let name = c.identifier_constant(field.clone());
+                c.emit(Op::GetLocal(0));
+                c.emit(Op::GetThisProperty(name));
+                // `other` is of this class, so it may read private fields as `this` does
+                c.emit(Op::GetLocal(1));
+                c.emit(Op::GetThisProperty(name));
+                c.emit(Op::Equal);
+            }
+            for jump in exit_jumps {
+                c.patch_jump(jump);
+            }
+            c.emit(Op::Return);
+        });
+    }
+
+    /// Compiles a method whose body is emitted by `body` rather than parsed from source.
+    fn synthesized_method(
+        &mut self,
+        name: &str,
+        function_type: FunctionType,
+        parameters: Vec<String>,
+        body: impl FnOnce(&mut Self),
+    ) {
+        self.push_context(function_type, Some(name.into()));
+        self.current_function().arity = parameters.len();
+        self.current_function().parameters = parameters;
+        body(self);
+        self.emit_return();
+        let context = self.pop_context();
+        let function = self.make_constant(ConstantValue::from(context.function));
+        self.emit(Op::Closure(function, &[]));
+        let name = self.make_constant(ConstantValue::from(name.to_string()));
+        self.emit(Op::Method(name));
+    }
+
     fn fun_declaration(&mut self) {
         self.advance_scanner();
         let global = self.parse_variable("Expect function name.");
@@ -546,10 +1209,14 @@ impl Compiler {
     fn var_declaration(&mut self) {
         self.advance_scanner();
         let global = self.parse_variable("Expect variable name.");
+        self.var_initializer(global);
+    }
+
+    fn var_initializer(&mut self, global: u8) {
         if self.advance_if_eq(TokenType::Equal) {
             self.expression(BindingPower::AssignmentRight);
         } else {
-            self.emit_opcode(OpCode::Nil);
+            self.emit(Op::Nil);
         }
         self.consume(
             TokenType::Semicolon,
@@ -559,52 +1226,99 @@ impl Compiler {
     }
 
     fn named_variable(&mut self, name: Token, min_binding_power: BindingPower) {
-        let get_op: OpCode;
-        let set_op: OpCode;
+        let get_op: fn(u8) -> Op<'static>;
+        let set_op: fn(u8) -> Op<'static>;
         let mut arg = self.resolve_local(&name, 0);
         if arg.is_some() {
-            get_op = OpCode::GetLocal;
-            set_op = OpCode::SetLocal;
+            get_op = Op::GetLocal;
+            set_op = Op::SetLocal;
         } else if ({
             arg = self.resolve_upvalue(&name, 0);
             arg
         })
         .is_some()
         {
-            get_op = OpCode::GetUpvalue;
-            set_op = OpCode::SetUpvalue;
+            get_op = Op::GetUpvalue;
+            set_op = Op::SetUpvalue;
         } else {
-            arg = Some(self.identifier_constant(name) as usize);
-            get_op = OpCode::GetGlobal;
-            set_op = OpCode::SetGlobal;
+            arg = Some(self.global_index(name) as usize);
+            get_op = Op::GetGlobal;
+            set_op = Op::SetGlobal;
         }
 
         let can_assign = min_binding_power <= BindingPower::AssignmentLeft;
         if can_assign && self.advance_if_eq(TokenType::Equal) {
             self.expression(BindingPower::AssignmentRight);
-            self.emit_opcode(set_op);
-            self.emit_byte(arg.unwrap() as u8);
+            self.emit(set_op(arg.unwrap() as u8));
             return;
         }
 
-        self.emit_opcode(get_op);
-        self.emit_byte(arg.unwrap() as u8);
+        self.emit(get_op(arg.unwrap() as u8));
     }
 
     fn statement(&mut self) {
+        let next = self.peek_scanner();
+        if next.kind == TokenType::Identifier && next.lexeme == "debug" {
+            self.debug_statement();
+            return;
+        }
         match self.peek_scanner().kind {
             TokenType::Print => self.print_statement(),
+            TokenType::Import => self.import_statement(),
             TokenType::For => self.for_statement(),
             TokenType::If => self.if_statement(),
-            TokenType::Return => self.return_statement(),
+            TokenType::Return => {
+                self.return_statement();
+                self.returns = true;
+                return;
+            }
             TokenType::While => self.while_statement(),
             TokenType::LeftBrace => {
                 self.begin_scope();
-                self.block();
+                let returns = self.block();
                 self.end_scope();
+                self.returns = returns;
+                return;
             }
             _ => self.expression_statement(),
         }
+        // A `return` in a branch or loop body may not run
+        self.returns = false;
+    }
+
+    /// A `debug { ... }` block, or an expression statement that starts with a
+    /// variable called `debug`.
+    fn debug_statement(&mut self) {
+        self.advance_scanner();
+        if self.peek_scanner().kind != TokenType::LeftBrace {
+            self.expression_from_previous(BindingPower::AssignmentRight);
+            self.consume(TokenType::Semicolon, "Expect ';' after expression.");
+            self.emit(Op::Pop);
+            return;
+        }
+        if self.debug_blocks {
+            self.begin_scope();
+            self.block();
+            self.end_scope();
+            return;
+        }
+        // Skip to the matching brace without parsing, so the block leaves no trace
+        let mut depth = 0;
+        loop {
+            match self.peek_scanner().kind {
+                TokenType::Eof => {
+                    self.consume(TokenType::RightBrace, "Expect '}' after block.");
+                    return;
+                }
+                TokenType::LeftBrace => depth += 1,
+                TokenType::RightBrace => depth -= 1,
+                _ => {}
+            }
+            self.advance_scanner();
+            if depth == 0 {
+                return;
+            }
+        }
     }
 
     fn print_statement(&mut self) {
@@ -613,7 +1327,18 @@ impl Compiler {
         }
         self.expression(BindingPower::AssignmentRight);
         self.consume(TokenType::Semicolon, "Expect ';' after value.");
-        self.emit_byte(OpCode::Print as u8);
+        self.emit(Op::Print);
+    }
+
+    fn import_statement(&mut self) {
+        if !self.advance_if_eq(TokenType::Import) {
+            panic!("ICE: Failed to find 'import' token for import statement.");
+        }
+        self.consume(TokenType::String, "Expect module path after 'import'.");
+        let path = self.previous().lexeme.to_string();
+        let constant = self.make_constant(ConstantValue::from(path));
+        self.emit(Op::Import(constant));
+        self.consume(TokenType::Semicolon, "Expect ';' after module path.");
     }
 
     fn for_statement(&mut self) {
@@ -626,25 +1351,32 @@ impl Compiler {
         match self.peek_scanner().kind {
             TokenType::Semicolon => self.advance_scanner(),
             TokenType::Var => {
-                self.var_declaration();
+                self.advance_scanner();
+                let global = self.parse_variable("Expect variable name.");
+                if self.peek_scanner().kind == TokenType::In {
+                    self.for_in_statement();
+                    self.end_scope();
+                    return;
+                }
+                self.var_initializer(global);
             }
             _ => self.expression_statement(),
         }
 
         let mut loop_start = self.current_chunk().code.len();
-        let mut exit_jump = -1;
+        let mut exit_jump = None;
         if !self.advance_if_eq(TokenType::Semicolon) {
             self.expression(BindingPower::AssignmentRight);
             self.consume(TokenType::Semicolon, "Expect ';' after loop condition.");
-            exit_jump = self.emit_jump(OpCode::JumpIfFalse) as isize;
-            self.emit_opcode(OpCode::Pop);
+            exit_jump = Some(self.emit_jump(ForwardJump::JumpIfFalse));
+            self.emit(Op::Pop);
         }
 
         if !self.advance_if_eq(TokenType::RightParen) {
-            let body_jump = self.emit_jump(OpCode::Jump);
+            let body_jump = self.emit_jump(ForwardJump::Jump);
             let increment_start = self.current_chunk().code.len();
             self.expression(BindingPower::AssignmentRight);
-            self.emit_opcode(OpCode::Pop);
+            self.emit(Op::Pop);
             self.consume(TokenType::RightParen, "Expect ')' after for clauses.");
             self.emit_loop(loop_start);
             loop_start = increment_start;
@@ -653,13 +1385,55 @@ impl Compiler {
 
         self.statement();
         self.emit_loop(loop_start);
-        if exit_jump != -1 {
-            self.patch_jump(exit_jump as usize);
-            self.emit_opcode(OpCode::Pop);
+        if let Some(exit_jump) = exit_jump {
+            self.patch_jump(exit_jump);
+            self.emit(Op::Pop);
         }
         self.end_scope();
     }
 
+    /// Compiles the rest of `for (var x in collection) body` once the loop
+    /// variable has been declared. The collection and the iteration cursor
+    /// live in hidden locals, and each iteration binds the loop variable in
+    /// a fresh scope so closures capture the value of that iteration.
+    fn for_in_statement(&mut self) {
+        let variable = self
+            .locals
+            .pop()
+            .expect("ICE: Failed to pop for loop variable.");
+        self.consume(TokenType::In, "Expect 'in' after loop variable.");
+        self.expression(BindingPower::AssignmentRight);
+        self.consume(TokenType::RightParen, "Expect ')' after for clauses.");
+        self.emit(Op::IterInit);
+        let collection_slot = self.current_locals().len() as u8;
+        self.add_hidden_local(" collection");
+        let cursor = self.make_constant(0.0.into());
+        self.emit(Op::Constant(cursor));
+        self.add_hidden_local(" cursor");
+
+        let loop_start = self.current_chunk().code.len();
+        let exit_jump = self.emit_jump(ForwardJump::ForIn(collection_slot));
+
+        self.begin_scope();
+        self.add_local_at(variable.name, variable.span);
+        self.mark_initialized();
+        self.statement();
+        self.end_scope();
+
+        self.emit_loop(loop_start);
+        self.patch_jump(exit_jump);
+    }
+
+    /// Adds an initialized local whose name can't collide with an identifier.
+    fn add_hidden_local(&mut self, name: &str) {
+        self.add_local(Token {
+            kind: TokenType::Identifier,
+            lexeme: name.into(),
+            line: self.line,
+        });
+        self.mark_initialized();
+    }
+
     fn if_statement(&mut self) {
         if !self.advance_if_eq(TokenType::If) {
             panic!("ICE: Failed to find 'if' token for if statement.");
@@ -667,12 +1441,12 @@ impl Compiler {
         self.consume(TokenType::LeftParen, "Expect '(' after 'if'.");
         self.expression(BindingPower::Group);
         self.consume(TokenType::RightParen, "Expect ')' after condition.");
-        let then_jump = self.emit_jump(OpCode::JumpIfFalse);
-        self.emit_opcode(OpCode::Pop);
+        let then_jump = self.emit_jump(ForwardJump::JumpIfFalse);
+        self.emit(Op::Pop);
         self.statement();
-        let else_jump = self.emit_jump(OpCode::Jump);
+        let else_jump = self.emit_jump(ForwardJump::Jump);
         self.patch_jump(then_jump);
-        self.emit_opcode(OpCode::Pop);
+        self.emit(Op::Pop);
         if self.advance_if_eq(TokenType::Else) {
             self.statement();
         }
@@ -689,18 +1463,27 @@ impl Compiler {
         self.expression(BindingPower::AssignmentRight);
         self.consume(TokenType::RightParen, "Expect ')' after condition.");
 
-        let exit_jump = self.emit_jump(OpCode::JumpIfFalse);
-        self.emit_opcode(OpCode::Pop);
+        let exit_jump = self.emit_jump(ForwardJump::JumpIfFalse);
+        self.emit(Op::Pop);
         self.statement();
         self.emit_loop(loop_start);
         self.patch_jump(exit_jump);
-        self.emit_opcode(OpCode::Pop);
+        self.emit(Op::Pop);
     }
 
     fn expression_statement(&mut self) {
         self.expression(BindingPower::AssignmentRight);
-        self.consume(TokenType::Semicolon, "Expect ';' after expression.");
-        self.emit_opcode(OpCode::Pop);
+        let top_level = self.expression_result
+            && self.current_function_type() == FunctionType::Script
+            && self.current_context().scope_depth == 0;
+        if !(top_level && self.peek_scanner().kind == TokenType::Eof) {
+            self.consume(TokenType::Semicolon, "Expect ';' after expression.");
+        }
+        if top_level && self.peek_scanner().kind == TokenType::Eof {
+            self.emit(Op::Return);
+        } else {
+            self.emit(Op::Pop);
+        }
     }
 
     fn return_statement(&mut self) {
@@ -719,12 +1502,18 @@ impl Compiler {
         }
         self.expression(BindingPower::AssignmentRight);
         self.consume(TokenType::Semicolon, "Expect ';' after return value.");
-        self.emit_opcode(OpCode::Return);
+        self.emit(Op::Return);
     }
 
-    fn method(&mut self) {
+    fn method(&mut self, in_data_class: bool) {
         self.consume(TokenType::Identifier, "Expect method name.");
         let name = self.previous().clone();
+        if in_data_class && matches!(&*name.lexeme, "init" | "equals") {
+            self.error(&format!(
+                "A data class can't declare '{}', which it generates.",
+                name.lexeme
+            ));
+        }
         let constant = self.identifier_constant(name);
         let function_type = {
             if self.previous().lexeme == "init" {
@@ -735,24 +1524,45 @@ impl Compiler {
         };
 
         self.function(function_type);
-        self.emit_opcode(OpCode::Method);
-        self.emit_byte(constant);
+        self.emit(Op::Method(constant));
+    }
+
+    /// Compiles `class name() {}` in a class body, a method called on the
+    /// class itself.
+    fn static_method(&mut self) {
+        self.consume(TokenType::Identifier, "Expect method name.");
+        let name = self.previous().clone();
+        let constant = self.identifier_constant(name);
+        self.current_class().in_static = true;
+        self.function(FunctionType::StaticMethod);
+        self.current_class().in_static = false;
+        self.emit(Op::StaticMethod(constant));
     }
 
     fn function(&mut self, function_type: FunctionType) {
-        let name = self.previous().lexeme.clone();
-        let context = Context::new(function_type, name.into());
-        self.context_stack.push(context);
+        let name = self.previous().lexeme.to_string();
+        self.push_context(function_type, name.into());
         self.begin_scope();
 
         self.consume(TokenType::LeftParen, "Expect '(' after function name.");
         if self.peek_scanner().kind != TokenType::RightParen {
             loop {
-                self.current_function().arity += 1;
-                if self.current_function().arity > 255 {
-                    self.error_at_current("Can't have more than 255 parameters.");
+                if self.current_function().variadic {
+                    self.error_at_current("Rest parameter must be the last parameter.");
                 }
-                let constant = self.parse_variable("Expect parameter name.");
+                let constant = if self.advance_if_eq(TokenType::DotDotDot) {
+                    self.current_function().variadic = true;
+                    self.parse_variable("Expect rest parameter name.")
+                } else {
+                    self.current_function().arity += 1;
+                    if self.current_function().arity > 255 {
+                        self.error_at_current("Can't have more than 255 parameters.");
+                    }
+                    let constant = self.parse_variable("Expect parameter name.");
+                    let name = self.previous().lexeme.to_string();
+                    self.current_function().parameters.push(name);
+                    constant
+                };
                 self.define_variable(constant);
                 if !self.advance_if_eq(TokenType::Comma) {
                     break;
@@ -765,38 +1575,43 @@ impl Compiler {
         self.block();
         self.emit_return();
         let context = self.pop_context();
-        #[cfg(feature = "debug")]
-        {
-            println!(
-                "== {} ==",
-                context
-                    .function
-                    .name
-                    .as_ref()
-                    .unwrap_or(&"<script>".to_string())
-            );
-            println!("{}", (&context.function.chunk));
-        }
         let upvalues = &context.upvalues[..context.function.upvalue_count];
         let constant = self.make_constant(ConstantValue::from(context.function));
-        self.emit_opcode(OpCode::Closure);
-        self.emit_byte(constant);
-        for upvalue in upvalues {
-            self.emit_byte(upvalue.is_local as u8);
-            self.emit_byte(upvalue.index as u8);
-        }
+        self.emit(Op::Closure(constant, upvalues));
     }
 
-    fn block(&mut self) {
-        if !self.advance_if_eq(TokenType::LeftBrace) {
-            panic!("ICE: Failed to find '{{' token for block statement.");
-        }
+    /// Compiles a block, returning whether it always returns. Statements
+    /// after a `return` are still checked for errors, but emit no code.
+    fn block(&mut self) -> bool {
+        // Only a function body can be missing its brace, e.g. when the source
+        // ends after the parameters
+        self.consume(TokenType::LeftBrace, "Expect '{' before block.");
+        let mut returns = false;
+        let mut warned = false;
         while self.peek_scanner().kind != TokenType::RightBrace
             && self.peek_scanner().kind != TokenType::Eof
         {
+            self.returns = false;
+            if !returns {
+                self.declaration();
+                returns = self.returns;
+                continue;
+            }
+            if self.warn_unreachable && !warned {
+                warned = true;
+                let line = self.peek_scanner().line;
+                let span = self.peek_span();
+                self.diagnostics
+                    .unreachable
+                    .push(Unreachable { line, span });
+            }
+            let start = self.current_chunk().code.len();
             self.declaration();
+            self.current_chunk().truncate(start);
         }
         self.consume(TokenType::RightBrace, "Expect '}' after block.");
+        self.returns = false;
+        returns
     }
 
     fn variable(&mut self, min_binding_power: BindingPower) {
@@ -806,14 +1621,25 @@ impl Compiler {
 
     fn expression(&mut self, min_binding_power: BindingPower) {
         self.advance_scanner();
+        self.expression_from_previous(min_binding_power);
+    }
 
-        match self.previous().kind {
-            TokenType::Identifier => self.variable(min_binding_power),
+    /// Parses an expression whose first token has already been consumed.
+    fn expression_from_previous(&mut self, min_binding_power: BindingPower) {
+        if self.nesting_depth >= self.max_nesting_depth {
+            self.error("Expression too deeply nested.");
+            return;
+        }
+        self.nesting_depth += 1;
+
+        match self.previous().kind {
+            TokenType::Identifier => self.variable(min_binding_power),
             TokenType::True | TokenType::False | TokenType::Nil => self.literal(),
-            TokenType::Number => self.number(),
+            TokenType::Number | TokenType::Integer => self.number(),
             TokenType::String => self.string(),
-            TokenType::Super => self.super_(min_binding_power),
-            TokenType::This => self.this(min_binding_power),
+            TokenType::Super => self.super_(),
+            TokenType::This => self.this(),
+            TokenType::LeftBracket => self.list(),
             _ => {}
         }
 
@@ -821,7 +1647,9 @@ impl Compiler {
         if let Some(bp) = prefix_binding_power {
             match self.previous().kind {
                 TokenType::LeftParen => self.grouping(bp.binding_power),
-                TokenType::Minus | TokenType::Bang => self.unary(bp.binding_power),
+                TokenType::Minus | TokenType::Bang | TokenType::Tilde => {
+                    self.unary(bp.binding_power)
+                }
                 _ => {
                     panic!(
                         "ICE: Got token type {:?} but it doesn't have prefix binding power.",
@@ -851,21 +1679,32 @@ impl Compiler {
                 if bp < min_binding_power {
                     break;
                 }
+                let receiver_is_this = self.previous().kind == TokenType::This;
                 self.advance_scanner();
                 match &self.previous().kind {
                     TokenType::LeftParen => self.call(),
-                    TokenType::Dot => self.dot(),
+                    TokenType::Dot => self.dot(receiver_is_this),
+                    TokenType::LeftBracket => self.index(),
                     TokenType::Minus
                     | TokenType::Plus
                     | TokenType::Slash
                     | TokenType::Star
                     | TokenType::BangEqual
                     | TokenType::EqualEqual
-                    | TokenType::Equal
                     | TokenType::Greater
                     | TokenType::GreaterEqual
                     | TokenType::Less
-                    | TokenType::LessEqual => self.binary(bp.right_binding_power),
+                    | TokenType::LessEqual
+                    | TokenType::DotDot
+                    | TokenType::DotDotEqual
+                    | TokenType::Ampersand
+                    | TokenType::Pipe
+                    | TokenType::Caret
+                    | TokenType::LessLess
+                    | TokenType::GreaterGreater => self.binary(bp.right_binding_power),
+                    // Valid assignments are consumed by their target, so any
+                    // `=` reaching here follows something that can't be assigned
+                    TokenType::Equal => self.error("Invalid assignment target."),
                     TokenType::And => self.and(bp.right_binding_power),
                     TokenType::Or => self.or(bp.right_binding_power),
                     t => panic!(
@@ -883,6 +1722,7 @@ impl Compiler {
         {
             self.error("Invalid assignment target.");
         }
+        self.nesting_depth -= 1;
     }
 
     fn grouping(&mut self, min_binding_power: BindingPower) {
@@ -892,177 +1732,331 @@ impl Compiler {
 
     fn unary(&mut self, min_binding_power: BindingPower) {
         let operator = self.previous().clone();
+        let operator_span = self.span;
         self.expression(min_binding_power);
+        self.span = operator_span;
         match operator.kind {
-            TokenType::Bang => self.emit_opcode(OpCode::Not),
-            TokenType::Minus => self.emit_opcode(OpCode::Negate),
+            TokenType::Bang => self.emit(Op::Not),
+            TokenType::Minus => self.emit(Op::Negate),
+            TokenType::Tilde => self.emit(Op::BitNot),
             _ => {}
         }
     }
 
     fn literal(&mut self) {
         match self.previous().kind {
-            TokenType::False => self.emit_opcode(OpCode::False),
-            TokenType::Nil => self.emit_opcode(OpCode::Nil),
-            TokenType::True => self.emit_opcode(OpCode::True),
+            TokenType::False => self.emit(Op::False),
+            TokenType::Nil => self.emit(Op::Nil),
+            TokenType::True => self.emit(Op::True),
             _ => {}
         }
     }
 
     fn number(&mut self) {
-        let num = self
-            .previous()
-            .lexeme
-            .parse()
-            .expect("ICE: Failed to parse number.");
-        let value = ConstantValue::Number(num);
-        self.emit_constant(value);
+        let lexeme = self.previous().lexeme.clone();
+        if self.integers && self.previous().kind == TokenType::Integer {
+            match number::parse_integer(&lexeme) {
+                Ok(n) => self.emit_constant(ConstantValue::Int(n)),
+                Err(NumberError::TooLarge) => self.error("Integer literal is too large."),
+                Err(error) => self.error(&error.to_string()),
+            }
+            return;
+        }
+        let value = match number::parse_float(&lexeme) {
+            Ok(value) => value,
+            Err(NumberError::TooLarge) if self.number_overflow == NumberOverflow::Infinity => {
+                f64::INFINITY
+            }
+            Err(error) => {
+                self.error(&error.to_string());
+                return;
+            }
+        };
+        self.emit_constant(ConstantValue::Number(value));
     }
 
     fn string(&mut self) {
-        let value = ConstantValue::from(self.previous().lexeme.clone());
+        let value = ConstantValue::from(self.previous().lexeme.as_str());
         self.emit_constant(value);
     }
 
-    fn super_(&mut self, min_binding_power: BindingPower) {
-        if self.class_stack.is_empty() {
-            self.error("Can't use 'super' outside of a class.");
-        } else if !self.current_class().has_super_class {
-            self.error("Can't use 'super' in a class with no superclass.");
-        }
+    fn super_(&mut self) {
+        let superclass = match self.class_stack.last() {
+            None => {
+                self.error("Can't use 'super' outside of a class.");
+                None
+            }
+            Some(Class {
+                in_static: true, ..
+            }) => {
+                self.error("Can't use 'super' in a static method.");
+                None
+            }
+            Some(Class {
+                superclass: None, ..
+            }) => {
+                self.error("Can't use 'super' in a class with no superclass.");
+                None
+            }
+            Some(Class { superclass, .. }) => *superclass,
+        };
 
         self.consume(TokenType::Dot, "Expect '.' after 'super'.");
         self.consume(TokenType::Identifier, "Expect superclass method name.");
         let name_token = self.previous().clone();
+        if is_private_member(&name_token.lexeme) {
+            self.error(&format!(
+                "Can't access private member '{}' through 'super'.",
+                name_token.lexeme
+            ));
+        }
         let name = self.identifier_constant(name_token);
-        self.named_variable(
-            Token {
-                kind: TokenType::This,
-                lexeme: "this".into(),
-                line: self.line,
-            },
-            min_binding_power,
-        );
+        let Some(superclass) = superclass else {
+            return;
+        };
+
+        self.emit_receiver();
         if self.advance_if_eq(TokenType::LeftParen) {
             let arg_count = self.argument_list();
-            self.named_variable(
-                Token {
-                    kind: TokenType::Super,
-                    lexeme: "super".into(),
-                    line: self.line,
-                },
-                min_binding_power,
-            );
-            self.emit_opcode(OpCode::SuperInvoke);
-            self.emit_bytes(name, arg_count);
+            self.emit_get_slot(superclass);
+            self.emit(Op::SuperInvoke(name, arg_count));
         } else {
-            self.named_variable(
-                Token {
-                    kind: TokenType::Super,
-                    lexeme: "super".into(),
-                    line: self.line,
-                },
-                min_binding_power,
-            );
-            self.emit_opcode(OpCode::GetSuper);
-            self.emit_byte(name);
+            self.emit_get_slot(superclass);
+            self.emit(Op::GetSuper(name));
+        }
+    }
+
+    /// `this` always refers to the receiver of the innermost enclosing method.
+    /// Functions nested inside a method capture it as an upvalue like any other
+    /// local, and it can never be assigned to.
+    fn this(&mut self) {
+        match self.class_stack.last() {
+            None => self.error("Can't use 'this' outside of a class."),
+            Some(Class {
+                in_static: true, ..
+            }) => self.error("Can't use 'this' in a static method."),
+            Some(_) => self.emit_receiver(),
         }
     }
 
-    fn this(&mut self, min_binding_power: BindingPower) {
-        if self.class_stack.is_empty() {
-            self.error("Can't use 'this' outside of a class.");
+    /// Emits a read of slot zero of the innermost method being compiled.
+    fn emit_receiver(&mut self) {
+        let Some(context) = self.context_stack.iter().rposition(Context::is_method) else {
+            self.error("Can't use 'this' outside of a method.");
+            return;
+        };
+        self.emit_get_slot(SlotLocation { context, slot: 0 });
+    }
+
+    /// Emits a read of a local from any context on the stack, capturing it as
+    /// an upvalue through every function in between if it isn't our own.
+    fn emit_get_slot(&mut self, location: SlotLocation) {
+        let index = self.context_stack.len() - 1 - location.context;
+        if index == 0 {
+            self.emit(Op::GetLocal(location.slot as u8));
             return;
         }
-        self.variable(min_binding_power);
+        let upvalue = self.capture_slot(location, 0);
+        self.emit(Op::GetUpvalue(upvalue as u8));
+    }
+
+    /// Adds the upvalues needed for the context `index` levels below the top
+    /// of the stack to reach `location`, returning its upvalue index there.
+    fn capture_slot(&mut self, location: SlotLocation, index: usize) -> usize {
+        let enclosing = self.context_stack.len() - 2 - index;
+        if enclosing == location.context {
+            let locals_base = self.context_stack[enclosing].locals_base;
+            let local = &mut self.locals[locals_base + location.slot];
+            local.is_captured = true;
+            let name = local.name.lexeme.clone();
+            return self.add_traced_upvalue(&name, index, location.slot, true);
+        }
+        let upvalue = self.capture_slot(location, index + 1);
+        let local = self.context_stack[location.context].locals_base + location.slot;
+        let name = self.locals[local].name.lexeme.clone();
+        self.add_traced_upvalue(&name, index, upvalue, false)
     }
 
     fn call(&mut self) {
         let arg_count = self.argument_list();
-        self.emit_opcode(OpCode::Call);
-        self.emit_byte(arg_count);
+        self.emit(Op::Call(arg_count));
     }
 
-    fn dot(&mut self) {
+    /// Accesses through `this` use dedicated opcodes, as they are the only
+    /// ones allowed to reach private members.
+    fn dot(&mut self, receiver_is_this: bool) {
         self.consume(TokenType::Identifier, "Expect property name after '.'.");
-        let name = self.identifier_constant(self.previous().clone());
+        let name_token = self.previous().clone();
+        if !receiver_is_this && is_private_member(&name_token.lexeme) {
+            self.error(&format!(
+                "Can't access private member '{}' outside of 'this'.",
+                name_token.lexeme
+            ));
+        }
+        let name = self.identifier_constant(name_token);
         if self.advance_if_eq(TokenType::Equal) {
             self.expression(BindingPower::AssignmentRight);
-            self.emit_opcode(OpCode::SetProperty);
-            self.emit_byte(name);
+            if receiver_is_this {
+                self.emit(Op::SetThisProperty(name));
+            } else {
+                self.emit(Op::SetProperty(name));
+            }
         } else if self.advance_if_eq(TokenType::LeftParen) {
             let arg_count = self.argument_list();
-            self.emit_opcode(OpCode::Invoke);
-            self.emit_bytes(name, arg_count);
+            if receiver_is_this {
+                self.emit(Op::InvokeThis(name, arg_count));
+            } else {
+                self.emit(Op::Invoke(name, arg_count));
+            }
         } else {
-            self.emit_opcode(OpCode::GetProperty);
-            self.emit_byte(name);
+            if receiver_is_this {
+                self.emit(Op::GetThisProperty(name));
+            } else {
+                self.emit(Op::GetProperty(name));
+            }
         }
     }
 
     fn binary(&mut self, min_binding_power: BindingPower) {
         let operator = self.previous().kind;
+        let operator_span = self.span;
         self.expression(min_binding_power);
+        // Point diagnostics at the operator rather than the right operand
+        self.span = operator_span;
 
         match operator {
             TokenType::BangEqual => {
-                self.emit_opcode(OpCode::Equal);
-                self.emit_opcode(OpCode::Not);
+                self.emit(Op::Equal);
+                self.emit(Op::Not);
             }
             TokenType::EqualEqual => {
-                self.emit_opcode(OpCode::Equal);
+                self.emit(Op::Equal);
             }
             TokenType::Greater => {
-                self.emit_opcode(OpCode::Greater);
+                self.emit(Op::Greater);
             }
             TokenType::GreaterEqual => {
-                self.emit_opcode(OpCode::Less);
-                self.emit_opcode(OpCode::Not);
+                self.emit(Op::Less);
+                self.emit(Op::Not);
             }
             TokenType::Less => {
-                self.emit_opcode(OpCode::Less);
+                self.emit(Op::Less);
             }
             TokenType::LessEqual => {
-                self.emit_opcode(OpCode::Greater);
-                self.emit_opcode(OpCode::Not);
+                self.emit(Op::Greater);
+                self.emit(Op::Not);
             }
             TokenType::Plus => {
-                self.emit_opcode(OpCode::Add);
+                self.emit(Op::Add);
             }
             TokenType::Minus => {
-                self.emit_opcode(OpCode::Subtract);
+                self.emit(Op::Subtract);
             }
             TokenType::Star => {
-                self.emit_opcode(OpCode::Multiply);
+                self.emit(Op::Multiply);
             }
             TokenType::Slash => {
-                self.emit_opcode(OpCode::Divide);
+                self.emit(Op::Divide);
+            }
+            TokenType::DotDot => {
+                self.emit(Op::Range);
+            }
+            TokenType::DotDotEqual => {
+                self.emit(Op::RangeInclusive);
+            }
+            TokenType::Ampersand => {
+                self.emit(Op::BitAnd);
+            }
+            TokenType::Pipe => {
+                self.emit(Op::BitOr);
+            }
+            TokenType::Caret => {
+                self.emit(Op::BitXor);
+            }
+            TokenType::LessLess => {
+                self.emit(Op::ShiftLeft);
+            }
+            TokenType::GreaterGreater => {
+                self.emit(Op::ShiftRight);
             }
             _ => {}
         }
     }
 
     fn and(&mut self, min_binding_power: BindingPower) {
-        let end_jump = self.emit_jump(OpCode::JumpIfFalse);
-        self.emit_opcode(OpCode::Pop);
+        let end_jump = self.emit_jump(ForwardJump::JumpIfFalse);
+        self.emit(Op::Pop);
         self.expression(min_binding_power);
         self.patch_jump(end_jump);
     }
 
     fn or(&mut self, min_binding_power: BindingPower) {
-        let else_jump = self.emit_jump(OpCode::JumpIfFalse);
-        let end_jump = self.emit_jump(OpCode::Jump);
+        let else_jump = self.emit_jump(ForwardJump::JumpIfFalse);
+        let end_jump = self.emit_jump(ForwardJump::Jump);
         self.patch_jump(else_jump);
-        self.emit_opcode(OpCode::Pop);
+        self.emit(Op::Pop);
         self.expression(min_binding_power);
         self.patch_jump(end_jump);
     }
 
+    fn index(&mut self) {
+        self.expression(BindingPower::AssignmentRight);
+        self.consume(TokenType::RightBracket, "Expect ']' after index.");
+        if self.advance_if_eq(TokenType::Equal) {
+            self.expression(BindingPower::AssignmentRight);
+            self.emit(Op::SetIndex);
+        } else {
+            self.emit(Op::GetIndex);
+        }
+    }
+
+    fn list(&mut self) {
+        let mut item_count = 0;
+        if self.peek_scanner().kind != TokenType::RightBracket {
+            loop {
+                self.expression(BindingPower::AssignmentRight);
+                if item_count == 255 {
+                    self.error("Can't have more than 255 items in a list literal.");
+                    break;
+                }
+                item_count += 1;
+                if !self.advance_if_eq(TokenType::Comma) {
+                    break;
+                }
+            }
+        }
+
+        self.consume(TokenType::RightBracket, "Expect ']' after list items.");
+        self.emit(Op::BuildList(item_count));
+    }
+
+    /// Compiles the arguments of a call. Named arguments follow the positional
+    /// ones and are announced to the VM with a trailing `NamedArgs`.
     fn argument_list(&mut self) -> u8 {
         let mut arg_count = 0;
+        let mut names: Vec<String> = vec![];
         if self.peek_scanner().kind != TokenType::RightParen {
             loop {
-                self.expression(BindingPower::AssignmentRight);
+                if self.advance_if_eq(TokenType::Identifier) {
+                    let name = self.previous().lexeme.to_string();
+                    if self.advance_if_eq(TokenType::Colon) {
+                        if names.contains(&name) {
+                            self.error(&format!("Argument '{name}' is given more than once."));
+                        }
+                        names.push(name);
+                        self.expression(BindingPower::AssignmentRight);
+                    } else {
+                        if !names.is_empty() {
+                            self.error("Positional arguments can't follow named arguments.");
+                        }
+                        self.expression_from_previous(BindingPower::AssignmentRight);
+                    }
+                } else {
+                    if !names.is_empty() {
+                        self.error_at_current("Positional arguments can't follow named arguments.");
+                    }
+                    self.expression(BindingPower::AssignmentRight);
+                }
                 if arg_count == 255 {
                     self.error("Can't have more than 255 arguments.");
                     break;
@@ -1075,12 +2069,21 @@ impl Compiler {
         }
 
         self.consume(TokenType::RightParen, "Expect ')' after arguments.");
+        if !names.is_empty() {
+            let names: Vec<u8> = names
+                .into_iter()
+                .map(|name| self.make_constant(ConstantValue::from(name)))
+                .collect();
+            self.emit(Op::NamedArgs(&names));
+        }
         arg_count
     }
 }
 
 #[cfg(test)]
 mod test {
+    use crate::{chunk::OpCode, object::ObjString};
+
     use super::*;
 
     #[test]
@@ -1108,6 +2111,28 @@ mod test {
         assert!(chunk.constants.is_empty());
     }
 
+    #[test]
+    fn it_returns_the_last_expression_when_asked() {
+        let constant = OpCode::Constant as u8;
+        let pop = OpCode::Pop as u8;
+        let nil = OpCode::Nil as u8;
+        let ret = OpCode::Return as u8;
+        for source in ["1; 2;", "1; 2"] {
+            let function = Compiler::new(source.into())
+                .with_expression_result()
+                .compile()
+                .expect("Failed to compile");
+            assert_eq!(function.chunk.code, vec![constant, 0, pop, constant, 1, ret, nil, ret]);
+        }
+        // Only a trailing expression at the top level counts
+        let function = Compiler::new("{ 1; }".into())
+            .with_expression_result()
+            .compile()
+            .expect("Failed to compile");
+        assert_eq!(function.chunk.code, vec![constant, 0, pop, nil, ret]);
+        assert!(Compiler::new("1".into()).compile().is_err());
+    }
+
     #[test]
     fn it_compiles_an_empty_block() {
         let source = "{}".into();
@@ -1136,15 +2161,15 @@ mod test {
         let compiler = Compiler::new(source);
         let function = compiler.compile().unwrap();
         let chunk = function.chunk;
-        let empty_function_value = &chunk.constants[1];
-        let ConstantValue::Function(f) = &*empty_function_value else {
+        let empty_function_value = &chunk.constants[0];
+        let ConstantValue::Function(f) = empty_function_value else {
             panic!("Failed to get function from chunk.");
         };
         let empty_function_chunk = &f.chunk;
         let expected_function_codes = [OpCode::Nil as u8, OpCode::Return as u8];
         let expected_codes = [
             OpCode::Closure as u8,
-            1,
+            0,
             OpCode::DefineGlobal as u8,
             0,
             OpCode::Nil as u8,
@@ -1156,17 +2181,19 @@ mod test {
 
         let expected_function_constants = [];
         let expected_constants = [
-            ConstantValue::from("foo"),
-            ConstantValue::from(ObjFunction {
-                arity: 0,
-                upvalue_count: 0,
-                chunk: Chunk {
+            ConstantValue::from(ObjFunction::named(
+                "foo",
+                0,
+                Chunk {
                     code: expected_function_codes.into(),
                     lines: expected_function_lines.into(),
+                    globals: vec![],
+                    global_slots: vec![],
+                    decoded: Default::default(),
+                    debug_info: None,
                     constants: expected_function_constants.clone().into(),
                 },
-                name: Some("foo".into()),
-            }),
+            )),
         ];
 
         assert_eq!(chunk.code.len(), expected_codes.len());
@@ -1202,9 +2229,10 @@ mod test {
             assert_eq!(line, expected_line);
         }
 
+        assert_eq!(chunk.globals, vec![ObjString::from("foo")]);
         assert_eq!(chunk.constants.len(), expected_constants.len());
         for (constant, expected_constant) in chunk.constants.iter().zip(expected_constants.iter()) {
-            assert_eq!(&*constant, expected_constant);
+            assert_eq!(constant, expected_constant);
         }
 
         assert_eq!(
@@ -1407,6 +2435,180 @@ mod test {
         }
     }
 
+    #[test]
+    fn it_compiles_a_list_literal() {
+        let source = "[1, true];[];".into();
+        let compiler = Compiler::new(source);
+        let chunk = compiler.compile().unwrap().chunk;
+        let expected_codes = [
+            OpCode::Constant as u8,
+            0,
+            OpCode::True as u8,
+            OpCode::BuildList as u8,
+            2,
+            OpCode::Pop as u8,
+            OpCode::BuildList as u8,
+            0,
+            OpCode::Pop as u8,
+            OpCode::Nil as u8,
+            OpCode::Return as u8,
+        ];
+        assert_eq!(chunk.code, expected_codes);
+        assert_eq!(chunk.constants, vec![ConstantValue::from(1.0)]);
+    }
+
+    #[test]
+    fn it_compiles_an_index_expression() {
+        let source = "a[0] = a[1];".into();
+        let compiler = Compiler::new(source);
+        let chunk = compiler.compile().unwrap().chunk;
+        let expected_codes = [
+            OpCode::GetGlobal as u8,
+            0,
+            OpCode::Constant as u8,
+            0,
+            OpCode::GetGlobal as u8,
+            0,
+            OpCode::Constant as u8,
+            1,
+            OpCode::GetIndex as u8,
+            OpCode::SetIndex as u8,
+            OpCode::Pop as u8,
+            OpCode::Nil as u8,
+            OpCode::Return as u8,
+        ];
+        assert_eq!(chunk.code, expected_codes);
+    }
+
+    #[test]
+    fn it_compiles_a_range_expression() {
+        let source = "0..1 + 1;0..=1;".into();
+        let compiler = Compiler::new(source);
+        let chunk = compiler.compile().unwrap().chunk;
+        let expected_codes = [
+            OpCode::Constant as u8,
+            0,
+            OpCode::Constant as u8,
+            1,
+            OpCode::Constant as u8,
+            2,
+            OpCode::Add as u8,
+            OpCode::Range as u8,
+            OpCode::Pop as u8,
+            OpCode::Constant as u8,
+            3,
+            OpCode::Constant as u8,
+            4,
+            OpCode::RangeInclusive as u8,
+            OpCode::Pop as u8,
+            OpCode::Nil as u8,
+            OpCode::Return as u8,
+        ];
+        assert_eq!(chunk.code, expected_codes);
+    }
+
+    #[test]
+    fn it_compiles_bitwise_expressions() {
+        // Binds tighter than comparison and looser than arithmetic:
+        // `1 | 2 ^ 3 & 4 << 5 + 6 == 0` groups as
+        // `(1 | (2 ^ (3 & (4 << (5 + 6))))) == 0`
+        let source = "1 | 2 ^ 3 & 4 << 5 + 6 == 0;~1 >> 2;".into();
+        let compiler = Compiler::new(source);
+        let chunk = compiler.compile().unwrap().chunk;
+        let expected_codes = [
+            OpCode::Constant as u8,
+            0,
+            OpCode::Constant as u8,
+            1,
+            OpCode::Constant as u8,
+            2,
+            OpCode::Constant as u8,
+            3,
+            OpCode::Constant as u8,
+            4,
+            OpCode::Constant as u8,
+            5,
+            OpCode::Add as u8,
+            OpCode::ShiftLeft as u8,
+            OpCode::BitAnd as u8,
+            OpCode::BitXor as u8,
+            OpCode::BitOr as u8,
+            OpCode::Constant as u8,
+            6,
+            OpCode::Equal as u8,
+            OpCode::Pop as u8,
+            OpCode::Constant as u8,
+            7,
+            OpCode::BitNot as u8,
+            OpCode::Constant as u8,
+            8,
+            OpCode::ShiftRight as u8,
+            OpCode::Pop as u8,
+            OpCode::Nil as u8,
+            OpCode::Return as u8,
+        ];
+        assert_eq!(chunk.code, expected_codes);
+    }
+
+    #[test]
+    fn it_compiles_number_literal_forms() {
+        let source = "0xFF;0b1010;1_000_000;1_000.000_5;0x7fff_ffff_ffff_ffff;";
+        let constants = Compiler::new(source.into())
+            .compile()
+            .unwrap()
+            .chunk
+            .constants;
+        assert_eq!(
+            constants,
+            vec![
+                ConstantValue::Number(255.0),
+                ConstantValue::Number(10.0),
+                ConstantValue::Number(1_000_000.0),
+                ConstantValue::Number(1_000.000_5),
+                ConstantValue::Number(i64::MAX as f64),
+            ]
+        );
+
+        let constants = Compiler::new(source.into())
+            .with_integers()
+            .compile()
+            .unwrap()
+            .chunk
+            .constants;
+        assert_eq!(
+            constants,
+            vec![
+                ConstantValue::Int(255),
+                ConstantValue::Int(10),
+                ConstantValue::Int(1_000_000),
+                ConstantValue::Number(1_000.000_5),
+                ConstantValue::Int(i64::MAX),
+            ]
+        );
+        assert!(Compiler::new("0x1_0000_0000_0000_0000;".into())
+            .with_integers()
+            .compile()
+            .is_err());
+    }
+
+    #[test]
+    fn it_compiles_overflowing_number_literals_as_configured() {
+        let constants = Compiler::new("1e400;".into())
+            .compile()
+            .unwrap()
+            .chunk
+            .constants;
+        assert_eq!(constants, vec![ConstantValue::Number(f64::INFINITY)]);
+        assert!(Compiler::new("1e400;".into())
+            .with_number_overflow(NumberOverflow::Error)
+            .compile()
+            .is_err());
+        assert!(Compiler::new("1e308;".into())
+            .with_number_overflow(NumberOverflow::Error)
+            .compile()
+            .is_ok());
+    }
+
     #[test]
     fn it_compiles_an_add_expression() {
         let source = "1 + 2;".into();
@@ -1760,6 +2962,10 @@ mod test {
                 OpCode::Return as u8,
             ],
             lines: vec![1; 9],
+            globals: vec![],
+            global_slots: vec![],
+            decoded: Default::default(),
+            debug_info: None,
             constants: vec![1.0.into(), 2.0.into()].into_iter().collect(),
         };
         assert_eq!(chunk, expected_chunk);
@@ -1942,7 +3148,6 @@ mod test {
             OpCode::Return as u8,
         ];
         let expected_lines = [1; 5];
-        let expected_constants = ["a".into()];
 
         assert_eq!(chunk.code.len(), expected_codes.len());
         for (&code, expected_code) in chunk.code.iter().zip(expected_codes) {
@@ -1954,12 +3159,8 @@ mod test {
             assert_eq!(line, expected_line);
         }
 
-        assert_eq!(chunk.constants.len(), 1);
-        for (constant, expected_constant) in
-            chunk.constants.clone().into_iter().zip(expected_constants)
-        {
-            assert_eq!(constant, expected_constant);
-        }
+        assert!(chunk.constants.is_empty());
+        assert_eq!(chunk.globals, vec![ObjString::from("a")]);
     }
 
     #[test]
@@ -1970,14 +3171,14 @@ mod test {
 
         let expected_codes = [
             OpCode::Constant as u8,
-            1,
+            0,
             OpCode::DefineGlobal as u8,
             0,
             OpCode::Nil as u8,
             OpCode::Return as u8,
         ];
         let expected_lines = [1; 6];
-        let expected_constants = ["a".into(), 1.0.into()];
+        let expected_constants = [1.0.into()];
 
         assert_eq!(chunk.code.len(), expected_codes.len());
         for (&code, expected_code) in chunk.code.iter().zip(expected_codes) {
@@ -1989,7 +3190,8 @@ mod test {
             assert_eq!(line, expected_line);
         }
 
-        assert_eq!(chunk.constants.len(), 2);
+        assert_eq!(chunk.globals, vec![ObjString::from("a")]);
+        assert_eq!(chunk.constants.len(), 1);
         for (constant, expected_constant) in
             chunk.constants.clone().into_iter().zip(expected_constants)
         {
@@ -2005,17 +3207,17 @@ mod test {
 
         let expected_codes = [
             OpCode::Constant as u8,
-            1,
+            0,
             OpCode::DefineGlobal as u8,
             0,
             OpCode::GetGlobal as u8,
-            2,
+            0,
             OpCode::Pop as u8,
             OpCode::Nil as u8,
             OpCode::Return as u8,
         ];
         let expected_lines = [1; 9];
-        let expected_constants = ["a".into(), 1.0.into(), "a".into()];
+        let expected_constants = [1.0.into()];
 
         assert_eq!(chunk.code.len(), expected_codes.len());
         for (&code, expected_code) in chunk.code.iter().zip(expected_codes) {
@@ -2027,6 +3229,7 @@ mod test {
             assert_eq!(line, expected_line);
         }
 
+        assert_eq!(chunk.globals, vec![ObjString::from("a")]);
         assert_eq!(chunk.constants.len(), expected_constants.len());
         for (constant, expected_constant) in
             chunk.constants.clone().into_iter().zip(expected_constants)
@@ -2155,23 +3358,23 @@ mod test {
 
         let expected_codes = [
             OpCode::Constant as u8,
-            1,
+            0,
             OpCode::DefineGlobal as u8,
             0,
             OpCode::GetGlobal as u8,
-            3,
+            0,
             OpCode::Constant as u8,
-            4,
+            1,
             OpCode::Add as u8,
             OpCode::SetGlobal as u8,
-            2,
+            0,
             OpCode::Pop as u8,
             OpCode::Nil as u8,
             OpCode::Return as u8,
         ];
 
         let expected_lines = [1; 14];
-        let expected_constants = ["a".into(), 1.0.into(), "a".into(), "a".into(), 1.0.into()];
+        let expected_constants = [1.0.into(), 1.0.into()];
 
         assert_eq!(chunk.code.len(), expected_codes.len());
         for (&code, expected_code) in chunk.code.iter().zip(expected_codes) {
@@ -2183,6 +3386,7 @@ mod test {
             assert_eq!(line, expected_line);
         }
 
+        assert_eq!(chunk.globals, vec![ObjString::from("a")]);
         assert_eq!(chunk.constants.len(), expected_constants.len());
         for (constant, expected_constant) in
             chunk.constants.clone().into_iter().zip(expected_constants)
@@ -2208,20 +3412,24 @@ mod test {
                 OpCode::Return as u8,
             ],
             lines: vec![1; 8],
+            globals: vec![],
+            global_slots: vec![],
+            decoded: Default::default(),
+            debug_info: None,
             constants: vec![],
         };
         let expected_chunk = Chunk {
             code: vec![
                 OpCode::Closure as u8,
-                1,
+                0,
                 OpCode::DefineGlobal as u8,
                 0,
                 OpCode::GetGlobal as u8,
-                2,
+                0,
                 OpCode::Constant as u8,
-                3,
+                1,
                 OpCode::Constant as u8,
-                4,
+                2,
                 OpCode::Call as u8,
                 2,
                 OpCode::Pop as u8,
@@ -2229,15 +3437,15 @@ mod test {
                 OpCode::Return as u8,
             ],
             lines: vec![1; 15],
+            globals: vec!["foo".into()],
+            global_slots: vec![],
+            decoded: Default::default(),
+            debug_info: None,
             constants: vec![
-                "foo".into(),
-                ConstantValue::from(ObjFunction {
-                    arity: 2,
-                    upvalue_count: 0,
-                    chunk: expected_function_chunk,
-                    name: Some("foo".into()),
-                }),
-                "foo".into(),
+                ConstantValue::from(
+                    ObjFunction::named("foo", 2, expected_function_chunk)
+                        .with_parameters(["a", "b"]),
+                ),
                 1.0.into(),
                 2.0.into(),
             ]
@@ -2254,64 +3462,152 @@ mod test {
     }
 
     #[test]
-    fn it_compiles_a_closure() {
-        let source =
-            "fun foo(a, b) { fun bar() { return a + b; } return bar(); } foo(1, 2);".into();
+    fn it_compiles_named_arguments() {
+        let source = "foo(1, b: 2);".into();
         let compiler = Compiler::new(source);
         let chunk = compiler.compile().unwrap().chunk;
-        let expected_bar_chunk = Chunk {
-            code: vec![
-                OpCode::GetUpvalue as u8,
+        assert_eq!(
+            chunk.code,
+            vec![
+                OpCode::GetGlobal as u8,
                 0,
-                OpCode::GetUpvalue as u8,
-                1,
-                OpCode::Add as u8,
-                OpCode::Return as u8,
-                OpCode::Nil as u8,
-                OpCode::Return as u8,
-            ],
-            lines: vec![1; 8],
-            constants: vec![],
-        };
-        let expected_foo_chunk = Chunk {
-            code: vec![
-                OpCode::Closure as u8,
+                OpCode::Constant as u8,
                 0,
+                OpCode::Constant as u8,
                 1,
-                1,
+                OpCode::NamedArgs as u8,
                 1,
                 2,
-                OpCode::GetLocal as u8,
-                3,
                 OpCode::Call as u8,
-                0,
-                OpCode::Return as u8,
+                2,
+                OpCode::Pop as u8,
                 OpCode::Nil as u8,
                 OpCode::Return as u8,
-            ],
-            lines: vec![1; 13],
-            constants: vec![ObjFunction {
-                arity: 0,
-                upvalue_count: 2,
-                chunk: expected_bar_chunk,
-                name: Some("bar".into()),
-            }
-            .into()]
+            ]
+        );
+        assert_eq!(chunk.constants[2], "b".into());
+    }
+
+    #[test]
+    fn it_records_local_names_with_debug_info() {
+        let source = "{ var a = 1; { var b = 2; print b; } } fun f(x) {}";
+        let chunk = Compiler::new(source.into())
+            .with_debug_info(source)
+            .compile()
+            .unwrap()
+            .chunk;
+        let debug_info = chunk.debug_info.expect("Missing debug info");
+        let local = |name: &str, slot, live| LocalName {
+            name: name.into(),
+            slot,
+            live,
+        };
+        assert_eq!(
+            debug_info.locals,
+            vec![local("a", 1, 2..8), local("b", 2, 4..7)]
+        );
+        assert_eq!(
+            debug_info
+                .locals_at(5)
+                .map(|local| &local.name[..])
+                .collect::<Vec<_>>(),
+            vec!["a", "b"]
+        );
+        let ConstantValue::Function(f) = &chunk.constants[2] else {
+            panic!("Expected a function constant");
+        };
+        let f_info = f.chunk.debug_info.as_ref().expect("Missing debug info");
+        assert_eq!(f_info.locals, vec![local("x", 1, 0..2)]);
+    }
+
+    #[test]
+    fn it_compiles_a_source_map() {
+        let source = "print 1 + 2;";
+        let compiler = Compiler::new(source.into()).with_debug_info(source);
+        let chunk = compiler.compile().unwrap().chunk;
+        let debug_info = chunk.debug_info.expect("Missing debug info");
+        assert_eq!(&*debug_info.source, source);
+        assert_eq!(
+            debug_info.spans,
+            vec![
+                (6, 7),
+                (6, 7),
+                (10, 11),
+                (10, 11),
+                (8, 9),
+                (11, 12),
+                (11, 12),
+                (11, 12)
+            ]
+        );
+
+        let compiler = Compiler::new(source.into());
+        assert_eq!(compiler.compile().unwrap().chunk.debug_info, None);
+    }
+
+    #[test]
+    fn it_compiles_a_closure() {
+        let source =
+            "fun foo(a, b) { fun bar() { return a + b; } return bar(); } foo(1, 2);".into();
+        let compiler = Compiler::new(source);
+        let chunk = compiler.compile().unwrap().chunk;
+        let expected_bar_chunk = Chunk {
+            code: vec![
+                OpCode::GetUpvalue as u8,
+                0,
+                OpCode::GetUpvalue as u8,
+                1,
+                OpCode::Add as u8,
+                OpCode::Return as u8,
+                OpCode::Nil as u8,
+                OpCode::Return as u8,
+            ],
+            lines: vec![1; 8],
+            globals: vec![],
+            global_slots: vec![],
+            decoded: Default::default(),
+            debug_info: None,
+            constants: vec![],
+        };
+        let expected_foo_chunk = Chunk {
+            code: vec![
+                OpCode::Closure as u8,
+                0,
+                1,
+                1,
+                1,
+                2,
+                OpCode::GetLocal as u8,
+                3,
+                OpCode::Call as u8,
+                0,
+                OpCode::Return as u8,
+                OpCode::Nil as u8,
+                OpCode::Return as u8,
+            ],
+            lines: vec![1; 13],
+            globals: vec![],
+            global_slots: vec![],
+            decoded: Default::default(),
+            debug_info: None,
+            constants: vec![ObjFunction::named("bar", 0, expected_bar_chunk)
+                .with_upvalues(2)
+                .into()]
             .into_iter()
             .collect(),
         };
         let expected_chunk = Chunk {
             code: vec![
                 OpCode::Closure as u8,
-                1,
+                0,
                 OpCode::DefineGlobal as u8,
                 0,
                 OpCode::GetGlobal as u8,
-                2,
+                0,
                 OpCode::Constant as u8,
-                3,
+                1,
                 OpCode::Constant as u8,
-                4,
+                2,
                 OpCode::Call as u8,
                 2,
                 OpCode::Pop as u8,
@@ -2319,23 +3615,20 @@ mod test {
                 OpCode::Return as u8,
             ],
             lines: vec![1; 15],
+            globals: vec!["foo".into()],
+            global_slots: vec![],
+            decoded: Default::default(),
+            debug_info: None,
             constants: vec![
-                "foo".into(),
-                ObjFunction {
-                    arity: 2,
-                    upvalue_count: 0,
-                    chunk: expected_foo_chunk,
-                    name: Some("foo".into()),
-                }
+                ObjFunction::named("foo", 2, expected_foo_chunk).with_parameters(["a", "b"])
                 .into(),
-                "foo".into(),
                 1.0.into(),
                 2.0.into(),
             ]
             .into_iter()
             .collect(),
         };
-        let ConstantValue::Function(foo) = &chunk.constants[1] else {
+        let ConstantValue::Function(foo) = &chunk.constants[0] else {
             panic!("Failed to read foo chunk.");
         };
 
@@ -2356,60 +3649,85 @@ mod test {
         let expected_chunk = Chunk {
             code: vec![
                 OpCode::Constant as u8,
-                1,
+                0,
                 OpCode::DefineGlobal as u8,
                 0,
                 OpCode::GetGlobal as u8,
-                2,
+                0,
                 OpCode::Constant as u8,
-                3,
+                1,
                 OpCode::Greater as u8,
                 OpCode::JumpIfFalse as u8,
                 0,
                 12,
                 OpCode::Pop as u8,
                 OpCode::GetGlobal as u8,
-                5,
+                0,
                 OpCode::Constant as u8,
-                6,
+                2,
                 OpCode::Add as u8,
                 OpCode::SetGlobal as u8,
-                4,
+                0,
                 OpCode::Pop as u8,
                 OpCode::Jump as u8,
                 0,
                 9,
                 OpCode::Pop as u8,
                 OpCode::GetGlobal as u8,
-                8,
+                0,
                 OpCode::Constant as u8,
-                9,
+                3,
                 OpCode::Subtract as u8,
                 OpCode::SetGlobal as u8,
-                7,
+                0,
                 OpCode::Pop as u8,
                 OpCode::Nil as u8,
                 OpCode::Return as u8,
             ],
             lines: vec![1; 35],
-            constants: vec![
-                "a".into(),
-                0.0.into(),
-                "a".into(),
-                0.0.into(),
-                "a".into(),
-                "a".into(),
-                1.0.into(),
-                "a".into(),
-                "a".into(),
-                1.0.into(),
-            ]
+            globals: vec!["a".into()],
+            global_slots: vec![],
+            decoded: Default::default(),
+            debug_info: None,
+            constants: vec![0.0.into(), 0.0.into(), 1.0.into(), 1.0.into()]
             .into_iter()
             .collect(),
         };
         assert_eq!(chunk, expected_chunk);
     }
 
+    #[test]
+    fn it_compiles_a_for_in_loop() {
+        let source = "for (var x in l) print x;".into();
+        let compiler = Compiler::new(source);
+        let chunk = compiler.compile().unwrap().chunk;
+        let expected_codes = [
+            OpCode::GetGlobal as u8,
+            0,
+            OpCode::IterInit as u8,
+            OpCode::Constant as u8,
+            0,
+            OpCode::ForIn as u8,
+            1,
+            0,
+            7,
+            OpCode::GetLocal as u8,
+            3,
+            OpCode::Print as u8,
+            OpCode::Pop as u8,
+            OpCode::Loop as u8,
+            0,
+            11,
+            OpCode::Pop as u8,
+            OpCode::Pop as u8,
+            OpCode::Nil as u8,
+            OpCode::Return as u8,
+        ];
+        assert_eq!(chunk.code, expected_codes);
+        assert_eq!(chunk.constants, vec![ConstantValue::from(0.0)]);
+        assert_eq!(chunk.globals, vec![ObjString::from("l")]);
+    }
+
     #[test]
     fn it_compiles_a_for_loop() {
         let source = "for (var a = 0; a < 5; a = a + 1) { print \"for loop\"; }".into();
@@ -2454,6 +3772,10 @@ mod test {
                 OpCode::Return as u8,
             ],
             lines: vec![1; 35],
+            globals: vec![],
+            global_slots: vec![],
+            decoded: Default::default(),
+            debug_info: None,
             constants: vec![0.0.into(), 5.0.into(), 1.0.into(), "for loop".into()]
                 .into_iter()
                 .collect(),
@@ -2469,28 +3791,28 @@ mod test {
         let expected_chunk = Chunk {
             code: vec![
                 OpCode::Constant as u8,
-                1,
+                0,
                 OpCode::DefineGlobal as u8,
                 0,
                 OpCode::GetGlobal as u8,
-                2,
+                0,
                 OpCode::Constant as u8,
-                3,
+                1,
                 OpCode::Less as u8,
                 OpCode::JumpIfFalse as u8,
                 0,
                 15,
                 OpCode::Pop as u8,
                 OpCode::Constant as u8,
-                4,
+                2,
                 OpCode::Print as u8,
                 OpCode::GetGlobal as u8,
-                6,
+                0,
                 OpCode::Constant as u8,
-                7,
+                3,
                 OpCode::Add as u8,
                 OpCode::SetGlobal as u8,
-                5,
+                0,
                 OpCode::Pop as u8,
                 OpCode::Loop as u8,
                 0,
@@ -2500,14 +3822,14 @@ mod test {
                 OpCode::Return as u8,
             ],
             lines: vec![1; 30],
+            globals: vec!["a".into()],
+            global_slots: vec![],
+            decoded: Default::default(),
+            debug_info: None,
             constants: vec![
-                "a".into(),
                 0.0.into(),
-                "a".into(),
                 5.0.into(),
                 "while loop".into(),
-                "a".into(),
-                "a".into(),
                 1.0.into(),
             ]
             .into_iter()
@@ -2528,15 +3850,17 @@ mod test {
                 OpCode::DefineGlobal as u8,
                 0,
                 OpCode::GetGlobal as u8,
-                1,
+                0,
                 OpCode::Pop as u8,
                 OpCode::Nil as u8,
                 OpCode::Return as u8,
             ],
             lines: vec![1; 9],
-            constants: vec!["TestClass".into(), "TestClass".into()]
-                .into_iter()
-                .collect(),
+            globals: vec!["TestClass".into()],
+            global_slots: vec![],
+            decoded: Default::default(),
+            debug_info: None,
+            constants: vec!["TestClass".into()],
         };
         assert_eq!(chunk, expected_chunk);
     }
@@ -2550,6 +3874,10 @@ mod test {
         let expected_init_chunk = Chunk {
             code: vec![OpCode::GetLocal as u8, 0, OpCode::Return as u8],
             lines: vec![1; 3],
+            globals: vec![],
+            global_slots: vec![],
+            decoded: Default::default(),
+            debug_info: None,
             constants: vec![],
         };
         let expected_chunk = Chunk {
@@ -2559,26 +3887,24 @@ mod test {
                 OpCode::DefineGlobal as u8,
                 0,
                 OpCode::GetGlobal as u8,
-                1,
+                0,
                 OpCode::Closure as u8,
-                3,
-                OpCode::Method as u8,
                 2,
+                OpCode::Method as u8,
+                1,
                 OpCode::Pop as u8,
                 OpCode::Nil as u8,
                 OpCode::Return as u8,
             ],
             lines: vec![1; 13],
+            globals: vec!["TestClass".into()],
+            global_slots: vec![],
+            decoded: Default::default(),
+            debug_info: None,
             constants: vec![
-                "TestClass".into(),
                 "TestClass".into(),
                 "init".into(),
-                ObjFunction {
-                    arity: 0,
-                    upvalue_count: 0,
-                    chunk: expected_init_chunk,
-                    name: Some("init".into()),
-                }
+                ObjFunction::named("init", 0, expected_init_chunk)
                 .into(),
             ]
             .into_iter()
@@ -2599,19 +3925,19 @@ mod test {
                 0,
                 OpCode::Constant as u8,
                 1,
-                OpCode::SetProperty as u8,
+                OpCode::SetThisProperty as u8,
                 0,
                 OpCode::Pop as u8,
                 OpCode::GetLocal as u8,
                 0,
                 OpCode::GetLocal as u8,
                 0,
-                OpCode::GetProperty as u8,
+                OpCode::GetThisProperty as u8,
                 3,
                 OpCode::Constant as u8,
                 4,
                 OpCode::Multiply as u8,
-                OpCode::SetProperty as u8,
+                OpCode::SetThisProperty as u8,
                 2,
                 OpCode::Pop as u8,
                 OpCode::GetLocal as u8,
@@ -2619,6 +3945,10 @@ mod test {
                 OpCode::Return as u8,
             ],
             lines: vec![1; 22],
+            globals: vec![],
+            global_slots: vec![],
+            decoded: Default::default(),
+            debug_info: None,
             constants: vec!["a".into(), 1.0.into(), "b".into(), "a".into(), 2.0.into()]
                 .into_iter()
                 .collect(),
@@ -2630,32 +3960,30 @@ mod test {
                 OpCode::DefineGlobal as u8,
                 0,
                 OpCode::GetGlobal as u8,
-                1,
+                0,
                 OpCode::Closure as u8,
-                3,
-                OpCode::Method as u8,
                 2,
+                OpCode::Method as u8,
+                1,
                 OpCode::Pop as u8,
                 OpCode::Nil as u8,
                 OpCode::Return as u8,
             ],
             lines: vec![1; 13],
+            globals: vec!["TestClass".into()],
+            global_slots: vec![],
+            decoded: Default::default(),
+            debug_info: None,
             constants: vec![
-                "TestClass".into(),
                 "TestClass".into(),
                 "init".into(),
-                ObjFunction {
-                    arity: 0,
-                    upvalue_count: 0,
-                    chunk: expected_init_chunk,
-                    name: Some("init".into()),
-                }
+                ObjFunction::named("init", 0, expected_init_chunk)
                 .into(),
             ]
             .into_iter()
             .collect(),
         };
-        let ConstantValue::Function(init) = &chunk.constants[3] else {
+        let ConstantValue::Function(init) = &chunk.constants[2] else {
             panic!("Failed to get init chunk");
         };
         println!("{}", init.chunk);
@@ -2676,30 +4004,36 @@ mod test {
                 OpCode::DefineGlobal as u8,
                 0,
                 OpCode::GetGlobal as u8,
-                1,
+                0,
                 OpCode::Closure as u8,
-                3,
-                OpCode::Method as u8,
                 2,
+                OpCode::Method as u8,
+                1,
                 OpCode::Pop as u8,
                 OpCode::Nil as u8,
                 OpCode::Return as u8,
             ],
             lines: vec![1; 13],
+            globals: vec!["TestClass".into()],
+            global_slots: vec![],
+            decoded: Default::default(),
+            debug_info: None,
             constants: vec![
-                "TestClass".into(),
                 "TestClass".into(),
                 "m".into(),
-                ObjFunction {
-                    arity: 0,
-                    upvalue_count: 0,
-                    name: Some("m".into()),
-                    chunk: Chunk {
+                ObjFunction::named(
+                    "m",
+                    0,
+                    Chunk {
                         code: vec![OpCode::Nil as u8, OpCode::Return as u8],
                         lines: vec![1; 2],
+                        globals: vec![],
+                        global_slots: vec![],
+                        decoded: Default::default(),
+                        debug_info: None,
                         constants: vec![],
                     },
-                }
+                )
                 .into(),
             ]
             .into_iter()
@@ -2719,7 +4053,7 @@ mod test {
                 0,
                 OpCode::GetLocal as u8,
                 1,
-                OpCode::SetProperty as u8,
+                OpCode::SetThisProperty as u8,
                 0,
                 OpCode::Pop as u8,
                 OpCode::GetLocal as u8,
@@ -2727,19 +4061,27 @@ mod test {
                 OpCode::Return as u8,
             ],
             lines: vec![1; 10],
+            globals: vec![],
+            global_slots: vec![],
+            decoded: Default::default(),
+            debug_info: None,
             constants: vec!["a".into()].into_iter().collect(),
         };
         let expected_m_chunk = Chunk {
             code: vec![
                 OpCode::GetLocal as u8,
                 0,
-                OpCode::GetProperty as u8,
+                OpCode::GetThisProperty as u8,
                 0,
                 OpCode::Return as u8,
                 OpCode::Nil as u8,
                 OpCode::Return as u8,
             ],
             lines: vec![1; 7],
+            globals: vec![],
+            global_slots: vec![],
+            decoded: Default::default(),
+            debug_info: None,
             constants: vec!["a".into()].into_iter().collect(),
         };
         let expected_chunk = Chunk {
@@ -2749,54 +4091,44 @@ mod test {
                 OpCode::DefineGlobal as u8,
                 0,
                 OpCode::GetGlobal as u8,
-                1,
+                0,
                 OpCode::Closure as u8,
-                3,
-                OpCode::Method as u8,
                 2,
-                OpCode::Closure as u8,
-                5,
                 OpCode::Method as u8,
+                1,
+                OpCode::Closure as u8,
                 4,
+                OpCode::Method as u8,
+                3,
                 OpCode::Pop as u8,
                 OpCode::GetGlobal as u8,
-                7,
+                0,
                 OpCode::Call as u8,
                 0,
                 OpCode::DefineGlobal as u8,
-                6,
+                1,
                 OpCode::GetGlobal as u8,
-                8,
+                1,
                 OpCode::Invoke as u8,
-                9,
+                5,
                 0,
                 OpCode::Pop as u8,
                 OpCode::Nil as u8,
                 OpCode::Return as u8,
             ],
             lines: vec![1; 29],
+            globals: vec!["TestClass".into(), "c".into()],
+            global_slots: vec![],
+            decoded: Default::default(),
+            debug_info: None,
             constants: vec![
-                "TestClass".into(),
                 "TestClass".into(),
                 "init".into(),
-                ObjFunction {
-                    arity: 1,
-                    upvalue_count: 0,
-                    chunk: expected_init_chunk,
-                    name: Some("init".into()),
-                }
+                ObjFunction::named("init", 1, expected_init_chunk).with_parameters(["a"])
                 .into(),
                 "m".into(),
-                ObjFunction {
-                    arity: 0,
-                    upvalue_count: 0,
-                    chunk: expected_m_chunk,
-                    name: Some("m".into()),
-                }
+                ObjFunction::named("m", 0, expected_m_chunk)
                 .into(),
-                "c".into(),
-                "TestClass".into(),
-                "c".into(),
                 "m".into(),
             ]
             .into_iter()
@@ -2817,35 +4149,30 @@ mod test {
                 OpCode::DefineGlobal as u8,
                 0,
                 OpCode::GetGlobal as u8,
-                1,
+                0,
                 OpCode::Pop as u8,
                 OpCode::Class as u8,
-                2,
+                1,
                 OpCode::DefineGlobal as u8,
-                2,
+                1,
                 OpCode::GetGlobal as u8,
-                3,
+                0,
                 OpCode::GetGlobal as u8,
-                4,
+                1,
                 OpCode::Inherit as u8,
                 OpCode::GetGlobal as u8,
-                5,
+                1,
                 OpCode::Pop as u8,
                 OpCode::Pop as u8,
                 OpCode::Nil as u8,
                 OpCode::Return as u8,
             ],
             lines: vec![1; 22],
-            constants: vec![
-                "Parent".into(),
-                "Parent".into(),
-                "Child".into(),
-                "Parent".into(),
-                "Child".into(),
-                "Child".into(),
-            ]
-            .into_iter()
-            .collect(),
+            globals: vec!["Parent".into(), "Child".into()],
+            global_slots: vec![],
+            decoded: Default::default(),
+            debug_info: None,
+            constants: vec!["Parent".into(), "Child".into()],
         };
         assert_eq!(chunk, expected_chunk);
     }
@@ -2867,6 +4194,10 @@ mod test {
                 OpCode::Return as u8,
             ],
             lines: vec![1; 5],
+            globals: vec![],
+            global_slots: vec![],
+            decoded: Default::default(),
+            debug_info: None,
             constants: vec![1.0.into()].into_iter().collect(),
         };
 
@@ -2876,7 +4207,7 @@ mod test {
                 0,
                 OpCode::Constant as u8,
                 1,
-                OpCode::SetProperty as u8,
+                OpCode::SetThisProperty as u8,
                 0,
                 OpCode::Pop as u8,
                 OpCode::GetLocal as u8,
@@ -2884,6 +4215,10 @@ mod test {
                 OpCode::Return as u8,
             ],
             lines: vec![1; 10],
+            globals: vec![],
+            global_slots: vec![],
+            decoded: Default::default(),
+            debug_info: None,
             constants: vec!["a".into(), 2.0.into()].into_iter().collect(),
         };
 
@@ -2898,7 +4233,7 @@ mod test {
                 0,
                 OpCode::GetLocal as u8,
                 0,
-                OpCode::GetProperty as u8,
+                OpCode::GetThisProperty as u8,
                 1,
                 OpCode::Add as u8,
                 OpCode::Print as u8,
@@ -2906,6 +4241,10 @@ mod test {
                 OpCode::Return as u8,
             ],
             lines: vec![1; 15],
+            globals: vec![],
+            global_slots: vec![],
+            decoded: Default::default(),
+            debug_info: None,
             constants: vec!["m".into(), "a".into()].into_iter().collect(),
         };
 
@@ -2916,69 +4255,54 @@ mod test {
                 OpCode::DefineGlobal as u8,
                 0,
                 OpCode::GetGlobal as u8,
-                1,
+                0,
                 OpCode::Closure as u8,
-                3,
-                OpCode::Method as u8,
                 2,
+                OpCode::Method as u8,
+                1,
                 OpCode::Pop as u8,
                 OpCode::Class as u8,
-                4,
+                3,
                 OpCode::DefineGlobal as u8,
-                4,
+                1,
                 OpCode::GetGlobal as u8,
-                5,
+                0,
                 OpCode::GetGlobal as u8,
-                6,
+                1,
                 OpCode::Inherit as u8,
                 OpCode::GetGlobal as u8,
-                7,
+                1,
                 OpCode::Closure as u8,
-                9,
+                5,
                 OpCode::Method as u8,
-                8,
+                4,
                 OpCode::Closure as u8,
-                11,
+                7,
                 1,
                 1,
                 OpCode::Method as u8,
-                10,
+                6,
                 OpCode::Pop as u8,
                 OpCode::CloseUpvalue as u8,
                 OpCode::Nil as u8,
                 OpCode::Return as u8,
             ],
             lines: vec![1; 36],
+            globals: vec!["Parent".into(), "Child".into()],
+            global_slots: vec![],
+            decoded: Default::default(),
+            debug_info: None,
             constants: vec![
-                "Parent".into(),
                 "Parent".into(),
                 "m".into(),
-                ObjFunction {
-                    arity: 0,
-                    upvalue_count: 0,
-                    chunk: expected_super_m_chunk,
-                    name: Some("m".into()),
-                }
+                ObjFunction::named("m", 0, expected_super_m_chunk)
                 .into(),
                 "Child".into(),
-                "Parent".into(),
-                "Child".into(),
-                "Child".into(),
                 "init".into(),
-                ObjFunction {
-                    arity: 0,
-                    upvalue_count: 0,
-                    chunk: expected_init_chunk,
-                    name: Some("init".into()),
-                }
+                ObjFunction::named("init", 0, expected_init_chunk)
                 .into(),
                 "m".into(),
-                ObjFunction {
-                    arity: 0,
-                    upvalue_count: 1,
-                    chunk: expected_m_chunk,
-                    name: Some("m".into()),
-                }
+                ObjFunction::named("m", 0, expected_m_chunk).with_upvalues(1)
                 .into(),
             ]
             .into_iter()
@@ -2987,6 +4311,336 @@ mod test {
         assert_eq!(chunk, expected_chunk);
     }
 
+    #[test]
+    fn it_traces_upvalue_resolution() {
+        let source = "fun outer() { var x = 1; var y = 2; fun middle() { fun inner() { return y + x; } return inner; } return middle; }".into();
+        let compiler = Compiler::new(source).with_upvalue_trace();
+        let (result, diagnostics) = compiler.compile_with_diagnostics();
+        assert!(result.is_ok());
+
+        let trace = diagnostics
+            .upvalues
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            trace,
+            vec![
+                "y: <fn middle> upvalue 0 -> local 2 of <fn outer>",
+                "y: <fn inner> upvalue 0 -> upvalue 0 of <fn middle>",
+                "x: <fn middle> upvalue 1 -> local 1 of <fn outer>",
+                "x: <fn inner> upvalue 1 -> upvalue 1 of <fn middle>",
+            ]
+        );
+        assert_eq!(
+            diagnostics.upvalues[0],
+            UpvalueResolution {
+                name: "y".into(),
+                function: Some("middle".into()),
+                upvalue: 0,
+                enclosing: Some("outer".into()),
+                index: 2,
+                is_local: true,
+            }
+        );
+    }
+
+    #[test]
+    fn it_traces_captured_receivers() {
+        let source = "class A { f() { fun g() { return this; } return g; } }".into();
+        let compiler = Compiler::new(source).with_upvalue_trace();
+        let (_, diagnostics) = compiler.compile_with_diagnostics();
+        assert_eq!(
+            diagnostics.upvalues[0].to_string(),
+            "this: <fn g> upvalue 0 -> local 0 of <fn f>"
+        );
+    }
+
+    #[test]
+    fn it_resolves_names_in_functions_with_many_locals() {
+        let locals: String = (0..10).map(|i| format!("var a{i}; ")).collect();
+        let source = format!(
+            "fun outer() {{ {locals}var x = 1; {{ var x = 2; fun inner() {{ return x + a3; }} }} fun after() {{ return x; }} }}"
+        );
+        let compiler = Compiler::new(source).with_upvalue_trace();
+        let (result, diagnostics) = compiler.compile_with_diagnostics();
+        assert!(result.is_ok());
+        let trace = diagnostics
+            .upvalues
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            trace,
+            vec![
+                "x: <fn inner> upvalue 0 -> local 12 of <fn outer>",
+                "a3: <fn inner> upvalue 1 -> local 4 of <fn outer>",
+                "x: <fn after> upvalue 0 -> local 11 of <fn outer>",
+            ]
+        );
+    }
+
+    #[test]
+    fn it_does_not_trace_upvalues_by_default() {
+        let source = "fun f() { var x; fun g() { return x; } }".into();
+        let (_, diagnostics) = Compiler::new(source).compile_with_diagnostics();
+        assert!(diagnostics.upvalues.is_empty());
+    }
+
+    #[test]
+    fn it_warns_about_shadowed_variables() {
+        let source = "var a;\nfun f(a) {\n  var b;\n  { var b; var c; }\n  fun g() { var b; }\n}\n{ var c; }";
+        let compiler = Compiler::new(source.into()).with_shadowing_warnings();
+        let (result, diagnostics) = compiler.compile_with_diagnostics();
+        assert!(result.is_ok());
+        let warnings = diagnostics
+            .shadowing
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            warnings,
+            vec![
+                "[line 2] Warning: 'a' shadows the global declared on line 1.",
+                "[line 4] Warning: 'b' shadows the local declared on line 3.",
+                "[line 5] Warning: 'b' shadows the local declared on line 3.",
+            ]
+        );
+        assert_eq!(
+            diagnostics.shadowing[1],
+            Shadowing {
+                name: "b".into(),
+                line: 4,
+                span: (35, 36),
+                global: false,
+                shadowed_line: 3,
+                shadowed_span: (24, 25),
+            }
+        );
+    }
+
+    #[test]
+    fn it_skips_debug_blocks_unless_enabled() {
+        let source = "var n = 1;\ndebug { var m = n; { print m; } }\nprint n;";
+        let skipped = Compiler::new(source.into())
+            .compile()
+            .expect("Failed to compile");
+        let plain = Compiler::new("var n = 1;\n\nprint n;".into())
+            .compile()
+            .expect("Failed to compile");
+        assert_eq!(skipped.chunk.code, plain.chunk.code);
+        assert_eq!(skipped.chunk.constants, plain.chunk.constants);
+        assert_eq!(skipped.chunk.globals, plain.chunk.globals);
+
+        let compiled = Compiler::new(source.into())
+            .with_debug_blocks()
+            .compile()
+            .expect("Failed to compile");
+        assert!(compiled.chunk.code.len() > plain.chunk.code.len());
+        assert_eq!(
+            compiled
+                .chunk
+                .code
+                .iter()
+                .filter(|&&byte| byte == OpCode::Print as u8)
+                .count(),
+            2
+        );
+
+        // `debug` stays an ordinary name everywhere but before a block
+        Compiler::new("var debug = 1; debug = debug + 1; debug;".into())
+            .compile()
+            .expect("Failed to compile");
+        assert!(Compiler::new("debug { print 1;".into()).compile().is_err());
+    }
+
+    #[test]
+    fn it_warns_about_redefined_globals() {
+        let source = "var a = 1;\nfun f() { var a; }\nfun a() {}\n{ var a; }\nclass a {}";
+        let compiler = Compiler::new(source.into()).with_redefinition_warnings();
+        let (result, diagnostics) = compiler.compile_with_diagnostics();
+        assert!(result.is_ok());
+        let warnings = diagnostics
+            .redefinitions
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            warnings,
+            vec![
+                "[line 3] Warning: 'a' redefines the global declared on line 1.",
+                "[line 5] Warning: 'a' redefines the global declared on line 1.",
+            ]
+        );
+        assert_eq!(
+            diagnostics.redefinitions[0],
+            Redefinition {
+                name: "a".into(),
+                line: 3,
+                span: (34, 35),
+                previous_line: 1,
+                previous_span: (4, 5),
+            }
+        );
+        assert!(diagnostics.shadowing.is_empty());
+
+        let source = "var a; var a;".into();
+        let (_, diagnostics) = Compiler::new(source).compile_with_diagnostics();
+        assert!(diagnostics.redefinitions.is_empty());
+    }
+
+    #[test]
+    fn it_names_the_function_over_a_limit() {
+        let locals: String = (0..limits::limits().locals)
+            .map(|i| format!("var v{i};"))
+            .collect();
+        let source = format!("fun f() {{ {locals} }}");
+        let (_, diagnostics) = Compiler::new(source.clone()).compile_with_diagnostics();
+        let error = &diagnostics.errors[0];
+        assert_eq!(
+            error.message,
+            "Too many local variables in <fn f> (limit 255)."
+        );
+        // The declaration of the local that didn't fit
+        assert_eq!(&source[error.span.0..error.span.1], "v254");
+
+        let source = "fun f() { var a; var b; var c; }";
+        let compiler = Compiler::new(source.into()).with_max_locals(3);
+        let (_, diagnostics) = compiler.compile_with_diagnostics();
+        assert_eq!(
+            diagnostics.errors[0].to_string(),
+            "[line 1] Error at c: Too many local variables in <fn f> (limit 3)."
+        );
+        assert_eq!(diagnostics.errors[0].span, (28, 29));
+
+        let source = "var a = 1; { var b; var c; fun g() { fun h() { print b + c; } } }";
+        let compiler = Compiler::new(source.into()).with_max_upvalues(1);
+        let (_, diagnostics) = compiler.compile_with_diagnostics();
+        // `g` captures both to pass them on to `h`, so it's over first
+        assert_eq!(
+            diagnostics.errors[0].to_string(),
+            "[line 1] Error at c: Too many closure variables in <fn g> (limit 1)."
+        );
+
+        // Limits can't be raised past what the bytecode can address
+        let compiler = Compiler::new(String::new()).with_max_locals(1000);
+        assert_eq!(compiler.max_locals, limits::limits().locals);
+    }
+
+    #[test]
+    fn it_tells_running_out_of_source_from_other_errors() {
+        for source in ["fun f() {\n  print 1;", "fun f()", "print (1 +", "var s = \"open"] {
+            let (result, diagnostics) = Compiler::new(source.into()).compile_with_diagnostics();
+            assert!(result.is_err());
+            assert_eq!(
+                diagnostics.errors[0].kind,
+                CompileErrorKind::UnexpectedEof,
+                "{source}"
+            );
+        }
+        assert!(Compiler::new("{ print 1;".into()).ends_too_soon());
+
+        let source = "var 1;\nprint (2";
+        let (_, diagnostics) = Compiler::new(source.into()).compile_with_diagnostics();
+        assert_eq!(
+            diagnostics.errors,
+            vec![
+                CompileError {
+                    kind: CompileErrorKind::Invalid,
+                    line: 1,
+                    span: (4, 5),
+                    location: " at 1".into(),
+                    message: "Expect variable name.".into(),
+                    caret: None,
+                },
+                CompileError {
+                    kind: CompileErrorKind::UnexpectedEof,
+                    line: 2,
+                    span: (15, 15),
+                    location: " at end".into(),
+                    message: "Expect ')' after expression.".into(),
+                    caret: None,
+                },
+            ]
+        );
+        assert_eq!(
+            diagnostics.errors[1].to_string(),
+            "[line 2] Error at end: Expect ')' after expression."
+        );
+        assert!(!Compiler::new(source.into()).ends_too_soon());
+    }
+
+    #[test]
+    fn it_leaves_out_statements_after_a_return() {
+        let source = "fun f(n) {\n  if (n) return 1;\n  { return 2; }\n  print 3;\n  var a = 4;\n}";
+        let compiler = Compiler::new(source.into()).with_unreachable_warnings();
+        let (result, diagnostics) = compiler.compile_with_diagnostics();
+        let function = result.expect("Failed to compile");
+        let ConstantValue::Function(f) = &function.chunk.constants[0] else {
+            panic!("Expected a function constant.");
+        };
+        let opcodes: Vec<_> = f
+            .chunk
+            .instructions()
+            .map(|(_, instruction)| instruction.opcode)
+            .collect();
+        // Only the implicit return follows the block's `return 2`
+        assert_eq!(
+            opcodes[opcodes.len() - 4..],
+            [
+                OpCode::Constant,
+                OpCode::Return,
+                OpCode::Nil,
+                OpCode::Return
+            ]
+        );
+        assert_eq!(f.chunk.lines.len(), f.chunk.code.len());
+        assert_eq!(
+            diagnostics.unreachable,
+            vec![Unreachable {
+                line: 4,
+                span: (48, 53)
+            }]
+        );
+        assert_eq!(
+            diagnostics.unreachable[0].to_string(),
+            "[line 4] Warning: Unreachable code after 'return'."
+        );
+
+        // A return that may not run leaves the rest of the block reachable
+        let source = "fun f(n) { while (n) return 1; if (n) { return 2; } print 3; }";
+        let compiler = Compiler::new(source.into()).with_unreachable_warnings();
+        let (result, diagnostics) = compiler.compile_with_diagnostics();
+        assert!(result.is_ok());
+        assert!(diagnostics.unreachable.is_empty());
+
+        // Locals declared in unreachable code are never in scope
+        let source = "fun f() { var a = 1; return; var b = 2; }";
+        let function = Compiler::new(source.into())
+            .with_debug_info(source)
+            .compile()
+            .expect("Failed to compile");
+        let ConstantValue::Function(f) = &function.chunk.constants[0] else {
+            panic!("Expected a function constant.");
+        };
+        let debug_info = f.chunk.debug_info.as_ref().expect("Missing debug info");
+        let names: Vec<_> = debug_info.locals.iter().map(|local| &local.name).collect();
+        assert_eq!(names, ["a"]);
+    }
+
+    #[test]
+    fn it_reports_errors_in_unreachable_statements() {
+        let source = "fun f() { return; print 1 +; }".into();
+        assert_eq!(Compiler::new(source).compile(), Err(Error::Compile));
+    }
+
+    #[test]
+    fn it_does_not_warn_about_shadowing_by_default() {
+        let source = "var a; { var a; }".into();
+        let (_, diagnostics) = Compiler::new(source).compile_with_diagnostics();
+        assert!(diagnostics.shadowing.is_empty());
+    }
+
     #[test]
     fn it_compiles_a_deeply_nested_closure() {
         let source = "var a = 1; fun foo() { var b = 2; fun bar() { var c = 3; fun baz() { return a + b + c; } baz(); return; } bar(); return; } foo();".into();
@@ -3008,7 +4662,11 @@ mod test {
                 OpCode::Return as u8,
             ],
             lines: vec![1; 11],
-            constants: vec!["a".into()].into_iter().collect(),
+            globals: vec!["a".into()],
+            global_slots: vec![],
+            decoded: Default::default(),
+            debug_info: None,
+            constants: vec![],
         };
 
         let expected_bar_chunk = Chunk {
@@ -3032,14 +4690,13 @@ mod test {
                 OpCode::Return as u8,
             ],
             lines: vec![1; 17],
+            globals: vec![],
+            global_slots: vec![],
+            decoded: Default::default(),
+            debug_info: None,
             constants: vec![
                 3.0.into(),
-                ObjFunction {
-                    arity: 0,
-                    upvalue_count: 2,
-                    chunk: expected_baz_chunk,
-                    name: Some("baz".into()),
-                }
+                ObjFunction::named("baz", 0, expected_baz_chunk).with_upvalues(2)
                 .into(),
             ]
             .into_iter()
@@ -3065,14 +4722,13 @@ mod test {
                 OpCode::Return as u8,
             ],
             lines: vec![1; 15],
+            globals: vec![],
+            global_slots: vec![],
+            decoded: Default::default(),
+            debug_info: None,
             constants: vec![
                 2.0.into(),
-                ObjFunction {
-                    arity: 0,
-                    upvalue_count: 1,
-                    chunk: expected_bar_chunk,
-                    name: Some("bar".into()),
-                }
+                ObjFunction::named("bar", 0, expected_bar_chunk).with_upvalues(1)
                 .into(),
             ]
             .into_iter()
@@ -3082,15 +4738,15 @@ mod test {
         let expected_chunk = Chunk {
             code: vec![
                 OpCode::Constant as u8,
-                1,
+                0,
                 OpCode::DefineGlobal as u8,
                 0,
                 OpCode::Closure as u8,
-                3,
+                1,
                 OpCode::DefineGlobal as u8,
-                2,
+                1,
                 OpCode::GetGlobal as u8,
-                4,
+                1,
                 OpCode::Call as u8,
                 0,
                 OpCode::Pop as u8,
@@ -3098,18 +4754,14 @@ mod test {
                 OpCode::Return as u8,
             ],
             lines: vec![1; 15],
+            globals: vec!["a".into(), "foo".into()],
+            global_slots: vec![],
+            decoded: Default::default(),
+            debug_info: None,
             constants: vec![
-                "a".into(),
                 1.0.into(),
-                "foo".into(),
-                ObjFunction {
-                    arity: 0,
-                    upvalue_count: 0,
-                    chunk: expected_foo_chunk,
-                    name: Some("foo".into()),
-                }
+                ObjFunction::named("foo", 0, expected_foo_chunk)
                 .into(),
-                "foo".into(),
             ]
             .into_iter()
             .collect(),
@@ -3117,6 +4769,89 @@ mod test {
         assert_eq!(chunk, expected_chunk);
     }
 
+    #[test]
+    fn it_shares_locals_between_contexts() {
+        let mut compiler = Compiler::new("".into());
+        assert_eq!(compiler.locals.len(), 1);
+
+        compiler.push_context(FunctionType::Function, Some("foo".into()));
+        compiler.begin_scope();
+        compiler.add_local(Token {
+            kind: TokenType::Identifier,
+            lexeme: "a".into(),
+            line: 1,
+        });
+        assert_eq!(compiler.current_context().locals_base, 1);
+        assert_eq!(compiler.locals_range(0), Some(1..3));
+        assert_eq!(compiler.locals_range(1), Some(0..1));
+        assert_eq!(compiler.locals_range(2), None);
+        assert_eq!(compiler.current_locals()[1].name.lexeme, "a");
+
+        let context = compiler.pop_context();
+        assert_eq!(context.locals_base, 1);
+        assert_eq!(compiler.locals.len(), 1);
+        assert_eq!(compiler.current_locals()[0].name.lexeme, "");
+    }
+
+    #[test]
+    fn it_pushes_and_pops_contexts() {
+        let mut compiler = Compiler::new("".into());
+        assert_eq!(compiler.contexts().len(), 1);
+        assert_eq!(compiler.contexts()[0].function_type, FunctionType::Script);
+
+        compiler.push_context(FunctionType::Method, Some("m".into()));
+        compiler.push_context(FunctionType::Function, Some("f".into()));
+        let contexts = compiler.contexts();
+        assert_eq!(contexts.len(), 3);
+        assert!(contexts[1].is_method());
+        assert!(!contexts[2].is_method());
+        assert!(contexts
+            .windows(2)
+            .all(|pair| pair[0].locals_base < pair[1].locals_base));
+
+        let function = compiler.pop_context();
+        assert_eq!(function.function.name.as_deref(), Some("f"));
+        assert_eq!(function.scope_depth, 0);
+        let method = compiler.pop_context();
+        assert_eq!(method.function_type, FunctionType::Method);
+        assert_eq!(compiler.contexts().len(), 1);
+        assert_eq!(compiler.locals.len(), 1);
+    }
+
+    #[test]
+    fn it_captures_super_in_a_closure_inside_a_method() {
+        let source =
+            "class A { m() {} } class B < A { m() { fun f() { return super.m(); } return f; } }"
+                .into();
+        let compiler = Compiler::new(source);
+        let chunk = compiler.compile().unwrap().chunk;
+        let ConstantValue::Function(m) = &chunk.constants[5] else {
+            panic!("Failed to get method from chunk.");
+        };
+        assert_eq!(m.upvalue_count, 1, "m should capture the superclass");
+        assert_eq!(
+            m.chunk.code[..6],
+            [OpCode::Closure as u8, 0, 1, 0, 0, 0],
+            "f should capture the receiver of m and the superclass through m"
+        );
+        let ConstantValue::Function(f) = &m.chunk.constants[0] else {
+            panic!("Failed to get nested function from method.");
+        };
+        assert_eq!(f.upvalue_count, 2);
+        assert_eq!(
+            f.chunk.code[..7],
+            [
+                OpCode::GetUpvalue as u8,
+                0,
+                OpCode::GetUpvalue as u8,
+                1,
+                OpCode::SuperInvoke as u8,
+                0,
+                0
+            ]
+        );
+    }
+
     #[test]
     fn it_handles_a_syntax_error_in_statement() {
         let source = "1 2".into();
@@ -3157,6 +4892,216 @@ mod test {
         assert!(result.is_err_and(|e| { e == Error::Compile }));
     }
 
+    #[test]
+    fn it_handles_an_error_this_in_static_method() {
+        for source in [
+            "class A { class m() { return this; } }",
+            "class A { class m() { fun f() { return this; } } }",
+            "class A {} class B < A { class m() { return super.m; } }",
+            "class A {} class B < A { class m() { super.m(); } }",
+        ] {
+            let result = Compiler::new(source.into()).compile();
+            assert!(result.is_err_and(|e| { e == Error::Compile }), "{source}");
+        }
+    }
+
+    #[test]
+    fn it_compiles_this_in_a_class_nested_in_a_static_method() {
+        let source = "class A { class m() { class B { n() { return this; } } } n() { return this; } }";
+        assert!(Compiler::new(source.into()).compile().is_ok());
+    }
+
+    #[test]
+    fn it_handles_an_error_this_assignment() {
+        let source = "class A { m() { this = 1; } }".into();
+        let compiler = Compiler::new(source);
+        let result = compiler.compile();
+        assert!(result.is_err_and(|e| { e == Error::Compile }));
+    }
+
+    #[test]
+    fn it_compiles_a_data_class() {
+        let source = "class Point(x, y) {}".into();
+        let compiler = Compiler::new(source);
+        let chunk = compiler.compile().unwrap().chunk;
+        let ConstantValue::Function(init) = &chunk.constants[1] else {
+            panic!("Failed to get initializer from chunk.");
+        };
+        assert_eq!(init.arity, 2);
+        assert_eq!(init.name, Some("init".into()));
+        assert_eq!(
+            init.chunk.code,
+            vec![
+                OpCode::GetLocal as u8,
+                0,
+                OpCode::GetLocal as u8,
+                1,
+                OpCode::SetThisProperty as u8,
+                0,
+                OpCode::Pop as u8,
+                OpCode::GetLocal as u8,
+                0,
+                OpCode::GetLocal as u8,
+                2,
+                OpCode::SetThisProperty as u8,
+                1,
+                OpCode::Pop as u8,
+                OpCode::GetLocal as u8,
+                0,
+                OpCode::Return as u8,
+            ]
+        );
+        let ConstantValue::Function(equals) = &chunk.constants[3] else {
+            panic!("Failed to get equals from chunk.");
+        };
+        assert_eq!(equals.arity, 1);
+        assert_eq!(equals.name, Some("equals".into()));
+    }
+
+    #[test]
+    fn it_handles_an_error_invalid_mixin() {
+        let source = "class A with A {}".into();
+        let compiler = Compiler::new(source);
+        let result = compiler.compile();
+        assert!(result.is_err_and(|e| { e == Error::Compile }));
+
+        let source = "class B {} class A with B, B {}".into();
+        let compiler = Compiler::new(source);
+        let result = compiler.compile();
+        assert!(result.is_err_and(|e| { e == Error::Compile }));
+    }
+
+    #[test]
+    fn it_handles_an_error_rest_parameter_not_last() {
+        let source = "fun f(...rest, a) {}".into();
+        let compiler = Compiler::new(source);
+        let result = compiler.compile();
+        assert!(result.is_err_and(|e| { e == Error::Compile }));
+    }
+
+    #[test]
+    fn it_handles_an_error_invalid_named_arguments() {
+        let source = "f(a: 1, 2);".into();
+        let compiler = Compiler::new(source);
+        let result = compiler.compile();
+        assert!(result.is_err_and(|e| { e == Error::Compile }));
+
+        let source = "f(a: 1, a: 2);".into();
+        let compiler = Compiler::new(source);
+        let result = compiler.compile();
+        assert!(result.is_err_and(|e| { e == Error::Compile }));
+    }
+
+    #[test]
+    fn it_handles_an_error_deeply_nested_expression() {
+        let source = format!("print {}1{};", "(".repeat(5000), ")".repeat(5000));
+        let compiler = Compiler::new(source);
+        let result = compiler.compile();
+        assert!(result.is_err_and(|e| { e == Error::Compile }));
+
+        let source = format!("print {}1{};", "-(".repeat(5000), ")".repeat(5000));
+        let compiler = Compiler::new(source);
+        let result = compiler.compile();
+        assert!(result.is_err_and(|e| { e == Error::Compile }));
+
+        let source = format!("{}{};", "f(".repeat(5000), ")".repeat(5000));
+        let compiler = Compiler::new(source);
+        let result = compiler.compile();
+        assert!(result.is_err_and(|e| { e == Error::Compile }));
+    }
+
+    #[test]
+    fn it_compiles_nested_expressions_within_the_limit() {
+        let source = format!("print {}1{};", "(".repeat(250), ")".repeat(250));
+        let compiler = Compiler::new(source);
+        assert!(compiler.compile().is_ok());
+
+        let source = format!("print {}1{};", "(".repeat(10), ")".repeat(10));
+        let compiler = Compiler::new(source.clone()).with_max_nesting_depth(10);
+        let result = compiler.compile();
+        assert!(result.is_err_and(|e| { e == Error::Compile }));
+        let compiler = Compiler::new(source).with_max_nesting_depth(11);
+        assert!(compiler.compile().is_ok());
+    }
+
+    #[test]
+    fn it_handles_an_error_import_without_path() {
+        let source = "import util;".into();
+        let compiler = Compiler::new(source);
+        let result = compiler.compile();
+        assert!(result.is_err_and(|e| { e == Error::Compile }));
+    }
+
+    #[test]
+    fn it_handles_an_error_data_class_declaring_a_generated_method() {
+        for source in [
+            "class P(x, y) { init(z) {} }",
+            "class P(x) { equals(other) { return true; } }",
+        ] {
+            let compiler = Compiler::new(source.into());
+            let result = compiler.compile();
+            assert!(result.is_err_and(|e| { e == Error::Compile }), "{source}");
+        }
+
+        let source = "class P { init(z) {} equals(other) { return true; } }".into();
+        let compiler = Compiler::new(source);
+        assert!(compiler.compile().is_ok());
+    }
+
+    #[test]
+    fn it_handles_an_error_duplicate_data_class_field() {
+        let source = "class Point(x, x) {}".into();
+        let compiler = Compiler::new(source);
+        let result = compiler.compile();
+        assert!(result.is_err_and(|e| { e == Error::Compile }));
+    }
+
+    #[test]
+    fn it_handles_an_error_private_access_outside_this() {
+        let source = "class A { init() { this._x = 1; } } A()._x;".into();
+        let compiler = Compiler::new(source);
+        let result = compiler.compile();
+        assert!(result.is_err_and(|e| { e == Error::Compile }));
+
+        let source = "class A { _m() {} } A()._m();".into();
+        let compiler = Compiler::new(source);
+        let result = compiler.compile();
+        assert!(result.is_err_and(|e| { e == Error::Compile }));
+
+        let source = "class A { _m() {} } class B < A { m() { super._m(); } }".into();
+        let compiler = Compiler::new(source);
+        let result = compiler.compile();
+        assert!(result.is_err_and(|e| { e == Error::Compile }));
+
+        let source = "class A { m() { var a = this; return a._x; } }".into();
+        let compiler = Compiler::new(source);
+        let result = compiler.compile();
+        assert!(result.is_err_and(|e| { e == Error::Compile }));
+    }
+
+    #[test]
+    fn it_captures_this_in_a_nested_function() {
+        let source = "class A { m() { fun f() { return this; } return f; } }".into();
+        let compiler = Compiler::new(source);
+        let chunk = compiler.compile().unwrap().chunk;
+        let ConstantValue::Function(m) = &chunk.constants[2] else {
+            panic!("Failed to get method from chunk.");
+        };
+        assert_eq!(
+            m.chunk.code[..4],
+            [OpCode::Closure as u8, 0, 1, 0],
+            "f should capture the receiver in slot zero of m"
+        );
+        let ConstantValue::Function(f) = &m.chunk.constants[0] else {
+            panic!("Failed to get nested function from method.");
+        };
+        assert_eq!(f.upvalue_count, 1);
+        assert_eq!(
+            f.chunk.code[..3],
+            [OpCode::GetUpvalue as u8, 0, OpCode::Return as u8]
+        );
+    }
+
     #[test]
     fn it_handles_an_error_reassign_local_variable() {
         let source = "{ var a = 0; var a = 1; }".into();
@@ -0,0 +1,110 @@
+//! Number literals, parsed the same way whatever the host's locale.
+//!
+//! A literal is decimal digits with an optional `.` fraction and `e`
+//! exponent, or `0x`/`0b` followed by hex or binary digits. Digits may be
+//! separated by `_`.
+
+use std::fmt::Display;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NumberError {
+    /// The literal isn't a number at all
+    Malformed,
+    /// The literal is too large for its type
+    TooLarge,
+}
+
+impl Display for NumberError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Malformed => write!(f, "Malformed number literal."),
+            Self::TooLarge => write!(f, "Number literal is too large."),
+        }
+    }
+}
+
+/// Splits `0x` and `0b` prefixes off `digits`, returning the radix and what's
+/// left.
+fn split_radix(digits: &str) -> (u32, &str) {
+    match digits.get(..2) {
+        Some("0x" | "0X") => (16, &digits[2..]),
+        Some("0b" | "0B") => (2, &digits[2..]),
+        _ => (10, digits),
+    }
+}
+
+/// Whether `digits` is decimal digits with an optional fraction and exponent.
+fn is_decimal(digits: &str) -> bool {
+    let (mantissa, exponent) = match digits.split_once(['e', 'E']) {
+        Some((mantissa, exponent)) => (mantissa, Some(exponent)),
+        None => (digits, None),
+    };
+    let all_digits = |part: &str| !part.is_empty() && part.bytes().all(|b| b.is_ascii_digit());
+    let mantissa_ok = match mantissa.split_once('.') {
+        Some((whole, fraction)) => all_digits(whole) && all_digits(fraction),
+        None => all_digits(mantissa),
+    };
+    let exponent_ok = exponent.is_none_or(|exponent| {
+        all_digits(exponent.strip_prefix(['+', '-']).unwrap_or(exponent))
+    });
+    mantissa_ok && exponent_ok
+}
+
+/// The value of a number literal, rounded to the nearest float. Literals too
+/// large to be finite are [`NumberError::TooLarge`].
+pub fn parse_float(lexeme: &str) -> Result<f64, NumberError> {
+    let digits = lexeme.replace('_', "");
+    let value = match split_radix(&digits) {
+        (10, digits) if is_decimal(digits) => {
+            digits.parse().map_err(|_| NumberError::Malformed)?
+        }
+        (10, _) => return Err(NumberError::Malformed),
+        (_, "") => return Err(NumberError::Malformed),
+        (radix, digits) => digits.chars().try_fold(0.0, |n, c| {
+            let digit = c.to_digit(radix).ok_or(NumberError::Malformed)?;
+            Ok(n * radix as f64 + digit as f64)
+        })?,
+    };
+    if value.is_infinite() {
+        return Err(NumberError::TooLarge);
+    }
+    Ok(value)
+}
+
+/// The value of an integer literal.
+pub fn parse_integer(lexeme: &str) -> Result<i64, NumberError> {
+    let digits = lexeme.replace('_', "");
+    let (radix, digits) = split_radix(&digits);
+    if digits.is_empty() || !digits.chars().all(|c| c.is_digit(radix)) {
+        return Err(NumberError::Malformed);
+    }
+    i64::from_str_radix(digits, radix).map_err(|_| NumberError::TooLarge)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_parses_number_literals() {
+        assert_eq!(parse_float("1_000.5"), Ok(1000.5));
+        assert_eq!(parse_float("2.5e-3"), Ok(0.0025));
+        assert_eq!(parse_float("1E+2"), Ok(100.0));
+        assert_eq!(parse_float("0xff"), Ok(255.0));
+        assert_eq!(parse_float("0b1010"), Ok(10.0));
+        assert_eq!(parse_integer("0X7f"), Ok(127));
+        assert_eq!(parse_integer("12_345"), Ok(12345));
+    }
+
+    #[test]
+    fn it_rejects_malformed_and_overflowing_literals() {
+        for malformed in ["", "1.", ".5", "1e", "1e+", "inf", "NaN", "0x", "0b12", "1.2.3"] {
+            assert_eq!(parse_float(malformed), Err(NumberError::Malformed), "{malformed}");
+        }
+        assert_eq!(parse_float("1e400"), Err(NumberError::TooLarge));
+        assert_eq!(parse_float(&"9".repeat(400)), Err(NumberError::TooLarge));
+        assert_eq!(parse_float(&format!("0x{}", "f".repeat(300))), Err(NumberError::TooLarge));
+        assert_eq!(parse_integer("9223372036854775808"), Err(NumberError::TooLarge));
+        assert_eq!(parse_integer("0x"), Err(NumberError::Malformed));
+    }
+}
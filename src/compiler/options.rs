@@ -0,0 +1,80 @@
+use super::{limits::LIMITS, DEFAULT_MAX_NESTING_DEPTH};
+use crate::scanner::Keywords;
+
+/// What a float literal too large to be finite, like `1e400`, compiles to.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum NumberOverflow {
+    /// Infinity, as IEEE 754 rounding gives
+    #[default]
+    Infinity,
+    /// A compile error
+    Error,
+}
+
+/// How scripts are compiled. New options may be added, so build these from
+/// [`CompileOptions::default`] and set the fields you need.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct CompileOptions {
+    /// Record source maps and local names, for caret diagnostics and `locals()`
+    pub debug_info: bool,
+    /// How deeply expressions may nest, e.g. through parentheses or call arguments
+    pub max_nesting_depth: usize,
+    /// How many locals a function may have in scope at once, at most
+    /// [`Limits::locals`](super::limits::Limits::locals)
+    pub max_locals: usize,
+    /// How many variables a closure may capture, at most
+    /// [`Limits::upvalues`](super::limits::Limits::upvalues)
+    pub max_upvalues: usize,
+    /// Compile literals like `3` to integers rather than floats
+    pub integers: bool,
+    /// What float literals too large to be finite compile to
+    pub number_overflow: NumberOverflow,
+    /// Record how every captured variable is resolved in the diagnostics
+    pub upvalue_trace: bool,
+    /// Report locals declared over outer variables in the diagnostics
+    pub shadowing_warnings: bool,
+    /// Report globals declared more than once in a script in the diagnostics.
+    /// Redefining is how a REPL session revises code, but in a file it is
+    /// usually a mistake.
+    pub redefinition_warnings: bool,
+    /// Report statements that follow a `return` in the same block in the
+    /// diagnostics. They are left out of the bytecode either way.
+    pub unreachable_warnings: bool,
+    /// Compile the contents of `debug { ... }` blocks rather than skipping them
+    pub debug_blocks: bool,
+    /// The reserved words, including any dialect's aliases
+    pub keywords: Keywords,
+    /// Scan canonical Lox only, ignoring any aliases in `keywords`
+    pub conformance: bool,
+}
+
+impl Default for CompileOptions {
+    fn default() -> Self {
+        Self {
+            debug_info: false,
+            max_nesting_depth: DEFAULT_MAX_NESTING_DEPTH,
+            max_locals: LIMITS.locals,
+            max_upvalues: LIMITS.upvalues,
+            integers: false,
+            number_overflow: NumberOverflow::default(),
+            upvalue_trace: false,
+            shadowing_warnings: false,
+            redefinition_warnings: false,
+            unreachable_warnings: false,
+            debug_blocks: false,
+            keywords: Keywords::canonical(),
+            conformance: false,
+        }
+    }
+}
+
+impl CompileOptions {
+    /// The reserved words scripts are scanned with.
+    pub fn active_keywords(&self) -> Keywords {
+        if self.conformance {
+            return Keywords::canonical();
+        }
+        self.keywords.clone()
+    }
+}
@@ -1,5 +1,51 @@
+use std::fmt::Display;
+
+/// A variable captured by a closure.
 #[derive(Debug, Default)]
 pub struct Upvalue {
+    /// The slot in the enclosing function: a local slot if `is_local`,
+    /// otherwise an index into the enclosing function's own upvalues
     pub index: usize,
+    /// Whether the captured variable is a local of the immediately enclosing function
     pub is_local: bool,
 }
+
+/// How one captured variable was resolved, recorded by
+/// [`Compiler::with_upvalue_trace`](crate::compiler::Compiler::with_upvalue_trace).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UpvalueResolution {
+    /// The captured variable
+    pub name: String,
+    /// The capturing function, `None` for the script
+    pub function: Option<String>,
+    /// The upvalue's index within the capturing function
+    pub upvalue: usize,
+    /// The immediately enclosing function the variable was found in or captured through
+    pub enclosing: Option<String>,
+    /// The slot in the enclosing function, see [`Upvalue::index`]
+    pub index: usize,
+    /// Whether the variable is a local of `enclosing` rather than transitively captured
+    pub is_local: bool,
+}
+
+impl Display for UpvalueResolution {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let kind = if self.is_local { "local" } else { "upvalue" };
+        write!(
+            f,
+            "{}: {} upvalue {} -> {kind} {} of {}",
+            self.name,
+            function_name(self.function.as_deref()),
+            self.upvalue,
+            self.index,
+            function_name(self.enclosing.as_deref())
+        )
+    }
+}
+
+fn function_name(name: Option<&str>) -> String {
+    match name {
+        None => "<script>".into(),
+        Some(name) => format!("<fn {name}>"),
+    }
+}
@@ -0,0 +1,352 @@
+//! Typed bytecode emission.
+//!
+//! The compiler writes instructions as [`Op`]s, each carrying exactly the
+//! operands its opcode takes, so an opcode can't be written without its
+//! operands or an operand without its opcode. Jumps are written through
+//! [`ChunkWriter::emit_jump`] and [`ChunkWriter::emit_loop`], which work out
+//! the encoded distances.
+
+use crate::{
+    chunk::{Chunk, OpCode},
+    compiler::upvalue::Upvalue,
+};
+
+/// An instruction with its operands, for [`ChunkWriter::emit`].
+#[derive(Debug, Clone, Copy)]
+pub enum Op<'a> {
+    /// Pushes the constant at this index
+    Constant(u8),
+    Nil,
+    True,
+    False,
+    Pop,
+    GetLocal(u8),
+    SetLocal(u8),
+    /// Global instructions take an index into the chunk's global names
+    GetGlobal(u8),
+    SetGlobal(u8),
+    DefineGlobal(u8),
+    GetUpvalue(u8),
+    SetUpvalue(u8),
+    /// Property and method instructions take the constant index of the name
+    GetProperty(u8),
+    SetProperty(u8),
+    GetSuper(u8),
+    Equal,
+    Greater,
+    Less,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Not,
+    Negate,
+    Print,
+    /// Calls with this many arguments
+    Call(u8),
+    /// A method name's constant index and an argument count
+    Invoke(u8, u8),
+    SuperInvoke(u8, u8),
+    InvokeThis(u8, u8),
+    /// A function's constant index and the upvalues its closure captures
+    Closure(u8, &'a [Upvalue]),
+    CloseUpvalue,
+    Return,
+    Class(u8),
+    Inherit,
+    Method(u8),
+    StaticMethod(u8),
+    GetThisProperty(u8),
+    SetThisProperty(u8),
+    /// Mixes in this many classes
+    Mixin(u8),
+    /// Builds a list of this many items
+    BuildList(u8),
+    IterInit,
+    Range,
+    RangeInclusive,
+    GetIndex,
+    SetIndex,
+    /// The constant indices of the names of a call's named arguments
+    NamedArgs(&'a [u8]),
+    /// The constant index of the module path
+    Import(u8),
+    BitAnd,
+    BitOr,
+    BitXor,
+    BitNot,
+    ShiftLeft,
+    ShiftRight,
+    /// Whether the top two values are instances of the same class
+    SameClass,
+}
+
+impl Op<'_> {
+    pub fn opcode(&self) -> OpCode {
+        match self {
+            Op::Constant(_) => OpCode::Constant,
+            Op::Nil => OpCode::Nil,
+            Op::True => OpCode::True,
+            Op::False => OpCode::False,
+            Op::Pop => OpCode::Pop,
+            Op::GetLocal(_) => OpCode::GetLocal,
+            Op::SetLocal(_) => OpCode::SetLocal,
+            Op::GetGlobal(_) => OpCode::GetGlobal,
+            Op::SetGlobal(_) => OpCode::SetGlobal,
+            Op::DefineGlobal(_) => OpCode::DefineGlobal,
+            Op::GetUpvalue(_) => OpCode::GetUpvalue,
+            Op::SetUpvalue(_) => OpCode::SetUpvalue,
+            Op::GetProperty(_) => OpCode::GetProperty,
+            Op::SetProperty(_) => OpCode::SetProperty,
+            Op::GetSuper(_) => OpCode::GetSuper,
+            Op::Equal => OpCode::Equal,
+            Op::Greater => OpCode::Greater,
+            Op::Less => OpCode::Less,
+            Op::Add => OpCode::Add,
+            Op::Subtract => OpCode::Subtract,
+            Op::Multiply => OpCode::Multiply,
+            Op::Divide => OpCode::Divide,
+            Op::Not => OpCode::Not,
+            Op::Negate => OpCode::Negate,
+            Op::Print => OpCode::Print,
+            Op::Call(_) => OpCode::Call,
+            Op::Invoke(..) => OpCode::Invoke,
+            Op::SuperInvoke(..) => OpCode::SuperInvoke,
+            Op::InvokeThis(..) => OpCode::InvokeThis,
+            Op::Closure(..) => OpCode::Closure,
+            Op::CloseUpvalue => OpCode::CloseUpvalue,
+            Op::Return => OpCode::Return,
+            Op::Class(_) => OpCode::Class,
+            Op::Inherit => OpCode::Inherit,
+            Op::Method(_) => OpCode::Method,
+            Op::StaticMethod(_) => OpCode::StaticMethod,
+            Op::GetThisProperty(_) => OpCode::GetThisProperty,
+            Op::SetThisProperty(_) => OpCode::SetThisProperty,
+            Op::Mixin(_) => OpCode::Mixin,
+            Op::BuildList(_) => OpCode::BuildList,
+            Op::IterInit => OpCode::IterInit,
+            Op::Range => OpCode::Range,
+            Op::RangeInclusive => OpCode::RangeInclusive,
+            Op::GetIndex => OpCode::GetIndex,
+            Op::SetIndex => OpCode::SetIndex,
+            Op::NamedArgs(_) => OpCode::NamedArgs,
+            Op::Import(_) => OpCode::Import,
+            Op::BitAnd => OpCode::BitAnd,
+            Op::BitOr => OpCode::BitOr,
+            Op::BitXor => OpCode::BitXor,
+            Op::BitNot => OpCode::BitNot,
+            Op::ShiftLeft => OpCode::ShiftLeft,
+            Op::ShiftRight => OpCode::ShiftRight,
+            Op::SameClass => OpCode::SameClass,
+        }
+    }
+}
+
+/// An instruction jumping forward over code not written yet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ForwardJump {
+    Jump,
+    JumpIfFalse,
+    /// Steps the loop over the collection in this slot, jumping out of the
+    /// loop once it's done
+    ForIn(u8),
+}
+
+/// A jump whose distance is written once its target is known, by
+/// [`ChunkWriter::patch_jump`].
+#[derive(Debug, PartialEq, Eq)]
+#[must_use = "A jump goes nowhere until it's patched"]
+pub struct PendingJump {
+    /// The offset of the jump's distance operand
+    offset: usize,
+}
+
+/// Writes instructions to a chunk, attributing them to a line and a source span.
+pub struct ChunkWriter<'a> {
+    chunk: &'a mut Chunk,
+    line: usize,
+    span: (usize, usize),
+}
+
+impl<'a> ChunkWriter<'a> {
+    pub fn new(chunk: &'a mut Chunk, line: usize, span: (usize, usize)) -> Self {
+        Self { chunk, line, span }
+    }
+
+    pub fn emit(&mut self, op: Op) {
+        self.byte(op.opcode() as u8);
+        match op {
+            Op::Constant(operand)
+            | Op::GetLocal(operand)
+            | Op::SetLocal(operand)
+            | Op::GetGlobal(operand)
+            | Op::SetGlobal(operand)
+            | Op::DefineGlobal(operand)
+            | Op::GetUpvalue(operand)
+            | Op::SetUpvalue(operand)
+            | Op::GetProperty(operand)
+            | Op::SetProperty(operand)
+            | Op::GetSuper(operand)
+            | Op::Call(operand)
+            | Op::Class(operand)
+            | Op::Method(operand)
+            | Op::StaticMethod(operand)
+            | Op::GetThisProperty(operand)
+            | Op::SetThisProperty(operand)
+            | Op::Mixin(operand)
+            | Op::BuildList(operand)
+            | Op::Import(operand) => self.byte(operand),
+            Op::Invoke(name, arg_count)
+            | Op::SuperInvoke(name, arg_count)
+            | Op::InvokeThis(name, arg_count) => {
+                self.byte(name);
+                self.byte(arg_count);
+            }
+            Op::Closure(function, upvalues) => {
+                self.byte(function);
+                for upvalue in upvalues {
+                    self.byte(upvalue.is_local as u8);
+                    self.byte(upvalue.index as u8);
+                }
+            }
+            Op::NamedArgs(names) => {
+                self.byte(names.len() as u8);
+                for &name in names {
+                    self.byte(name);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Writes `jump` with a placeholder distance.
+    pub fn emit_jump(&mut self, jump: ForwardJump) -> PendingJump {
+        match jump {
+            ForwardJump::Jump => self.byte(OpCode::Jump as u8),
+            ForwardJump::JumpIfFalse => self.byte(OpCode::JumpIfFalse as u8),
+            ForwardJump::ForIn(slot) => {
+                self.byte(OpCode::ForIn as u8);
+                self.byte(slot);
+            }
+        }
+        let offset = self.chunk.code.len();
+        self.byte(0xff);
+        self.byte(0xff);
+        PendingJump { offset }
+    }
+
+    /// Makes `jump` land on the next instruction written, failing when it's
+    /// too far away to encode.
+    pub fn patch_jump(&mut self, jump: PendingJump) -> Result<(), &'static str> {
+        // -2 to adjust for the jump's own distance operand
+        let distance = self.chunk.code.len() - jump.offset - 2;
+        let [high, low] = (distance as u16).to_be_bytes();
+        self.chunk.code[jump.offset] = high;
+        self.chunk.code[jump.offset + 1] = low;
+        if distance > u16::MAX as usize {
+            return Err("Too much code to jump over.");
+        }
+        Ok(())
+    }
+
+    /// Writes a jump back to `loop_start`, failing when it's too far away to
+    /// encode.
+    pub fn emit_loop(&mut self, loop_start: usize) -> Result<(), &'static str> {
+        self.byte(OpCode::Loop as u8);
+        let distance = self.chunk.code.len() - loop_start + 2;
+        let [high, low] = (distance as u16).to_be_bytes();
+        self.byte(high);
+        self.byte(low);
+        if distance > u16::MAX as usize {
+            return Err("Loop body too large.");
+        }
+        Ok(())
+    }
+
+    fn byte(&mut self, byte: u8) {
+        self.chunk.write_spanned(byte, self.line, self.span);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{object::ObjFunction, value::ConstantValue};
+
+    #[test]
+    fn it_writes_the_operands_each_opcode_takes() {
+        let upvalues = [
+            Upvalue {
+                index: 1,
+                is_local: true,
+            },
+            Upvalue {
+                index: 0,
+                is_local: false,
+            },
+        ];
+        let ops = [
+            Op::Constant(1),
+            Op::Nil,
+            Op::GetGlobal(2),
+            Op::Invoke(3, 4),
+            Op::Closure(0, &upvalues),
+            Op::NamedArgs(&[6, 7, 8]),
+            Op::BuildList(9),
+            Op::Return,
+        ];
+        // A closure's length depends on how many upvalues its function captures
+        let function = ObjFunction::script(Chunk::default()).with_upvalues(upvalues.len());
+        let mut chunk = Chunk {
+            constants: vec![ConstantValue::from(function)],
+            ..Default::default()
+        };
+        let mut writer = ChunkWriter::new(&mut chunk, 1, (0, 0));
+        for op in ops {
+            writer.emit(op);
+        }
+        let decoded: Vec<_> = chunk
+            .instructions()
+            .map(|(_, instruction)| (instruction.opcode, instruction.operands.to_vec()))
+            .collect();
+        assert_eq!(
+            decoded,
+            vec![
+                (OpCode::Constant, vec![1]),
+                (OpCode::Nil, vec![]),
+                (OpCode::GetGlobal, vec![2]),
+                (OpCode::Invoke, vec![3, 4]),
+                (OpCode::Closure, vec![0, 1, 1, 0, 0]),
+                (OpCode::NamedArgs, vec![3, 6, 7, 8]),
+                (OpCode::BuildList, vec![9]),
+                (OpCode::Return, vec![]),
+            ]
+        );
+    }
+
+    #[test]
+    fn it_patches_jumps() {
+        let mut chunk = Chunk::default();
+        let mut writer = ChunkWriter::new(&mut chunk, 1, (0, 0));
+        let loop_start = 0;
+        let exit = writer.emit_jump(ForwardJump::ForIn(1));
+        writer.emit(Op::Pop);
+        writer.emit_loop(loop_start).expect("Failed to write loop");
+        writer.patch_jump(exit).expect("Failed to patch jump");
+        let forward = OpCode::ForIn as u8;
+        let pop = OpCode::Pop as u8;
+        let back = OpCode::Loop as u8;
+        assert_eq!(chunk.code, vec![forward, 1, 0, 4, pop, back, 0, 8]);
+
+        let mut chunk = Chunk::default();
+        let mut writer = ChunkWriter::new(&mut chunk, 1, (0, 0));
+        let jump = writer.emit_jump(ForwardJump::Jump);
+        for _ in 0..=u16::MAX {
+            writer.emit(Op::Nil);
+        }
+        assert_eq!(
+            writer.patch_jump(jump),
+            Err("Too much code to jump over.")
+        );
+    }
+}
@@ -0,0 +1,124 @@
+//! Line coverage for Lox scripts.
+//!
+//! Every line holding code of an interpreted script is instrumented, and the
+//! VM marks a line as hit whenever it dispatches an instruction from it. The
+//! results can be rendered as a gcov-style annotated listing or as LCOV.
+
+use std::fmt::Write;
+
+use crate::{object::obj_function::ObjFunction, value::ConstantValue};
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Coverage {
+    /// Lines that hold at least one instruction, indexed by line number
+    instrumented: Vec<bool>,
+    /// Lines that had at least one instruction executed, indexed by line number
+    hit: Vec<bool>,
+}
+
+impl Coverage {
+    /// Marks every line with code in `function` and its nested functions.
+    pub fn instrument(&mut self, function: &ObjFunction) {
+        for &line in &function.chunk.lines {
+            set(&mut self.instrumented, line);
+        }
+        for constant in &function.chunk.constants {
+            if let ConstantValue::Function(nested) = constant {
+                self.instrument(nested);
+            }
+        }
+    }
+
+    pub fn record(&mut self, line: usize) {
+        set(&mut self.hit, line);
+    }
+
+    pub fn is_instrumented(&self, line: usize) -> bool {
+        self.instrumented.get(line).copied().unwrap_or(false)
+    }
+
+    pub fn is_hit(&self, line: usize) -> bool {
+        self.hit.get(line).copied().unwrap_or(false)
+    }
+
+    /// Annotates each line of `source`: `+` when executed, `#####` when never
+    /// executed and `-` when it holds no code.
+    pub fn listing(&self, source: &str) -> String {
+        let mut listing = String::new();
+        for (index, text) in source.lines().enumerate() {
+            let line = index + 1;
+            let marker = match (self.is_instrumented(line), self.is_hit(line)) {
+                (_, true) => "+",
+                (true, false) => "#####",
+                (false, false) => "-",
+            };
+            writeln!(listing, "{marker:>5}:{line:>5}:{text}").expect("Failed to write listing");
+        }
+        listing
+    }
+
+    /// Renders the coverage as an LCOV tracefile record for `path`.
+    pub fn lcov(&self, path: &str) -> String {
+        let mut lcov = format!("TN:\nSF:{path}\n");
+        let mut found = 0;
+        let mut hit = 0;
+        for line in (0..self.instrumented.len()).filter(|&line| self.is_instrumented(line)) {
+            found += 1;
+            let count = usize::from(self.is_hit(line));
+            hit += count;
+            writeln!(lcov, "DA:{line},{count}").expect("Failed to write LCOV");
+        }
+        writeln!(lcov, "LF:{found}\nLH:{hit}\nend_of_record").expect("Failed to write LCOV");
+        lcov
+    }
+}
+
+fn set(bitmap: &mut Vec<bool>, line: usize) {
+    if bitmap.len() <= line {
+        bitmap.resize(line + 1, false);
+    }
+    bitmap[line] = true;
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::chunk::Chunk;
+
+    fn coverage() -> Coverage {
+        let mut coverage = Coverage::default();
+        let mut function = ObjFunction::script(Chunk::default());
+        function.chunk.write(0, 1);
+        let mut nested = ObjFunction::named("nested", 0, Chunk::default());
+        nested.chunk.write(0, 3);
+        function.chunk.add_constant(nested.into());
+        coverage.instrument(&function);
+        coverage.record(1);
+        coverage
+    }
+
+    #[test]
+    fn it_instruments_nested_functions() {
+        let coverage = coverage();
+        assert!(coverage.is_instrumented(1));
+        assert!(!coverage.is_instrumented(2));
+        assert!(coverage.is_instrumented(3));
+        assert!(coverage.is_hit(1));
+        assert!(!coverage.is_hit(3));
+    }
+
+    #[test]
+    fn it_renders_a_listing() {
+        let listing = coverage().listing("a\n\nb");
+        assert_eq!(listing, "    +:    1:a\n    -:    2:\n#####:    3:b\n");
+    }
+
+    #[test]
+    fn it_renders_lcov() {
+        let lcov = coverage().lcov("test.lox");
+        assert_eq!(
+            lcov,
+            "TN:\nSF:test.lox\nDA:1,1\nDA:3,0\nLF:2\nLH:1\nend_of_record\n"
+        );
+    }
+}
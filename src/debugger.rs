@@ -0,0 +1,59 @@
+//! Pausing scripts to look inside them.
+//!
+//! Scripts pause by calling the `breakpoint()` native. When a debugger is
+//! attached with [`VM::on_breakpoint`](crate::VM::on_breakpoint) it is handed
+//! a [`Breakpoint`] and the script carries on once it returns; otherwise the
+//! call does nothing.
+//!
+//! Tools that want finer control prepare a program with
+//! [`VM::prepare`](crate::VM::prepare) and drive it one instruction at a time
+//! with [`VM::step`](crate::VM::step), which reports each [`StepResult`].
+
+use std::fmt::Display;
+
+use crate::{
+    error::{Error, TraceFrame},
+    value::LoxValue,
+};
+
+/// Where a script paused and what it could see there.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Breakpoint {
+    /// The calls active at the breakpoint, innermost first
+    pub trace: Vec<TraceFrame>,
+    /// The locals in scope at the breakpoint, in slot order, or `None` when
+    /// the script was compiled without debug info
+    pub locals: Option<Vec<(String, LoxValue)>>,
+    /// The classes visible at the breakpoint by name, each listed with its
+    /// methods and superclasses
+    pub classes: Vec<(String, String)>,
+}
+
+impl Breakpoint {
+    /// The line `breakpoint()` was called on.
+    pub fn line(&self) -> usize {
+        self.trace.first().map_or(0, |frame| frame.line)
+    }
+}
+
+/// What running a single instruction with [`VM::step`](crate::VM::step) did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepResult {
+    /// The instruction ran and the program has more to run
+    Executed,
+    /// The instruction called `breakpoint()`
+    Breakpoint,
+    /// The program returned, or no program was prepared
+    Finished,
+    /// The program stopped with an error, which was reported as usual
+    Errored(Error),
+}
+
+impl Display for Breakpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.trace.first() {
+            Some(frame) => write!(f, "Paused at {frame}"),
+            None => write!(f, "Paused"),
+        }
+    }
+}
@@ -0,0 +1,116 @@
+//! Listings of the bytecode scripts compile to, for auditing the compiler.
+//!
+//! A listing covers the script and every function nested in it, each under a
+//! `== name ==` header, in the order they appear in their enclosing chunk.
+
+use std::fmt::Write;
+
+use crate::{chunk::Chunk, object::obj_function::ObjFunction, value::ConstantValue};
+
+/// What a listing shows besides each function's instructions.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DisassemblyOptions {
+    /// List every function's constant table
+    pub constants: bool,
+    /// List which lines every function's instructions came from, as ranges
+    /// of code offsets
+    pub lines: bool,
+}
+
+/// Disassembles `function` and, recursively, the functions nested in it.
+pub(crate) fn disassemble(function: &ObjFunction, options: DisassemblyOptions) -> String {
+    let mut listing = String::new();
+    let mut pending = vec![function];
+    while let Some(function) = pending.pop() {
+        write_function(&mut listing, function, options).expect("Failed to write listing");
+        // Reversed so nested functions are listed in the order they appear
+        pending.extend(function.chunk.constants.iter().rev().filter_map(
+            |constant| match constant {
+                ConstantValue::Function(function) => Some(&**function),
+                _ => None,
+            },
+        ));
+    }
+    listing
+}
+
+fn write_function(
+    listing: &mut String,
+    function: &ObjFunction,
+    options: DisassemblyOptions,
+) -> std::fmt::Result {
+    writeln!(listing, "== {function} ==")?;
+    write!(listing, "{}", function.chunk)?;
+    if options.constants {
+        writeln!(listing, "-- constants --")?;
+        for (index, constant) in function.chunk.constants.iter().enumerate() {
+            writeln!(listing, "{index:4}\t'{constant}'")?;
+        }
+    }
+    if options.lines {
+        writeln!(listing, "-- lines --")?;
+        for (line, start, end) in line_ranges(&function.chunk) {
+            writeln!(listing, "{start:04x}..{end:04x}\tline {line}")?;
+        }
+    }
+    writeln!(listing)
+}
+
+/// Each run of code offsets compiled from the same line, as
+/// `(line, start, end)` with `end` exclusive.
+fn line_ranges(chunk: &Chunk) -> Vec<(usize, usize, usize)> {
+    let mut ranges: Vec<(usize, usize, usize)> = Vec::new();
+    for (offset, &line) in chunk.lines.iter().enumerate() {
+        match ranges.last_mut() {
+            Some((last, _, end)) if *last == line => *end = offset + 1,
+            _ => ranges.push((line, offset, offset + 1)),
+        }
+    }
+    ranges
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::compiler::Compiler;
+
+    fn compile(source: &str) -> ObjFunction {
+        Compiler::new(source.into())
+            .compile()
+            .expect("Failed to compile")
+    }
+
+    #[test]
+    fn it_lists_nested_functions_in_order() {
+        let function = compile("fun a() { fun b() {} }\nfun c() {}");
+        let listing = disassemble(&function, DisassemblyOptions::default());
+        let headers: Vec<_> = listing
+            .lines()
+            .filter(|line| line.starts_with("=="))
+            .collect();
+        assert_eq!(
+            headers,
+            [
+                "== <script> ==",
+                "== <fn a> ==",
+                "== <fn b> ==",
+                "== <fn c> =="
+            ]
+        );
+        assert!(!listing.contains("-- constants --"));
+        assert!(!listing.contains("-- lines --"));
+    }
+
+    #[test]
+    fn it_lists_constants_and_lines() {
+        let function = compile("print 1;\n\nprint \"two\";");
+        let options = DisassemblyOptions {
+            constants: true,
+            lines: true,
+        };
+        let listing = disassemble(&function, options);
+        assert!(listing.contains("-- constants --\n   0\t'1'\n   1\t'two'\n"));
+        assert!(listing.contains("-- lines --\n0000..0003\tline 1\n0003.."));
+        assert!(listing.contains("\tline 3\n"));
+    }
+}
@@ -4,6 +4,8 @@ use std::fmt::Display;
 pub enum Error {
     Runtime,
     Compile,
+    /// The VM hit an internal inconsistency, e.g. from corrupted bytecode
+    InternalFault(&'static str),
 }
 
 impl Display for Error {
@@ -11,8 +13,65 @@ impl Display for Error {
         match self {
             Self::Runtime => f.write_str("Runtime error"),
             Self::Compile => f.write_str("Compile error"),
+            Self::InternalFault(context) => write!(f, "Internal VM fault: {context}"),
         }
     }
 }
 
 impl std::error::Error for Error {}
+
+/// A runtime error as handed to [`VM::on_error`](crate::VM::on_error), with
+/// the same contents the VM would otherwise write to its error output.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuntimeErrorInfo {
+    /// What went wrong, without a trailing newline
+    pub message: String,
+    /// The failing source line with a caret under the offending span, when
+    /// the script was compiled with debug info
+    pub caret: Option<String>,
+    /// The calls active when the error was raised, innermost first
+    pub trace: Vec<TraceFrame>,
+    /// What the flight recorder kept of the events leading up to the error,
+    /// oldest first, when it's enabled
+    pub recent_events: Vec<String>,
+}
+
+/// Heads the events a flight recorder kept, after a runtime error's trace.
+pub(crate) const RECENT_EVENTS_HEADER: &str = "Recent events, oldest first:\n";
+
+/// One call in a [`RuntimeErrorInfo`] stack trace.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceFrame {
+    /// The line the call was executing
+    pub line: usize,
+    /// The function's name, or `None` for the top level of a script
+    pub function: Option<String>,
+}
+
+impl Display for TraceFrame {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.function {
+            Some(name) => write!(f, "[line {}] in {name}", self.line),
+            None => write!(f, "[line {}] in script", self.line),
+        }
+    }
+}
+
+impl Display for RuntimeErrorInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{}", self.message)?;
+        if let Some(caret) = &self.caret {
+            f.write_str(caret)?;
+        }
+        for frame in &self.trace {
+            writeln!(f, "{frame}")?;
+        }
+        if !self.recent_events.is_empty() {
+            f.write_str(RECENT_EVENTS_HEADER)?;
+            for event in &self.recent_events {
+                writeln!(f, "  {event}")?;
+            }
+        }
+        Ok(())
+    }
+}
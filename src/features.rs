@@ -0,0 +1,361 @@
+//! What this implementation of Lox supports.
+//!
+//! Each [`Feature`] is either the language of a chapter of [Crafting
+//! Interpreters](https://craftinginterpreters.com) or an extension to it.
+//! Every feature listed is checked by running an example program in the test
+//! suite, which writes the result to `target/conformance.json`.
+//!
+//! Some extensions are only available once a [`CompileOptions`] field is set.
+//! A [`FeatureSet`] tells which are enabled for a given configuration; scripts
+//! can ask the same of their VM through the `features()` native.
+
+use crate::compiler::CompileOptions;
+
+/// A language feature supported by the VM.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Feature {
+    /// A short identifier, e.g. `"closures"`
+    pub name: &'static str,
+    /// The chapter of Crafting Interpreters specifying it, or `None` for
+    /// extensions to Lox
+    pub chapter: Option<u8>,
+    /// The [`CompileOptions`] field that must be set for scripts to use it,
+    /// or `None` if it is always available
+    pub option: Option<FeatureOption>,
+}
+
+/// A [`CompileOptions`] field that enables some features.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FeatureOption {
+    /// [`CompileOptions::integers`]
+    Integers,
+    /// [`CompileOptions::keywords`], when it renames any keyword
+    Keywords,
+}
+
+impl FeatureOption {
+    /// The name of the field, e.g. `"integers"`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Integers => "integers",
+            Self::Keywords => "keywords",
+        }
+    }
+}
+
+impl Feature {
+    const fn chapter(name: &'static str, chapter: u8) -> Self {
+        Self {
+            name,
+            chapter: Some(chapter),
+            option: None,
+        }
+    }
+
+    const fn extension(name: &'static str) -> Self {
+        Self {
+            name,
+            chapter: None,
+            option: None,
+        }
+    }
+
+    const fn with_option(self, option: FeatureOption) -> Self {
+        Self {
+            option: Some(option),
+            ..self
+        }
+    }
+
+    /// Whether this goes beyond the Lox of Crafting Interpreters.
+    pub fn is_extension(&self) -> bool {
+        self.chapter.is_none()
+    }
+
+    fn is_enabled_by(&self, options: &CompileOptions) -> bool {
+        match self.option {
+            None => true,
+            Some(FeatureOption::Integers) => options.integers,
+            Some(FeatureOption::Keywords) => !options.active_keywords().is_canonical(),
+        }
+    }
+}
+
+const FEATURES: &[Feature] = &[
+    Feature::chapter("expressions", 17),
+    Feature::chapter("values", 18),
+    Feature::chapter("strings", 19),
+    Feature::chapter("global variables", 21),
+    Feature::chapter("local variables", 22),
+    Feature::chapter("control flow", 23),
+    Feature::chapter("functions", 24),
+    Feature::chapter("closures", 25),
+    Feature::chapter("garbage collection", 26),
+    Feature::chapter("classes", 27),
+    Feature::chapter("methods", 28),
+    Feature::chapter("inheritance", 29),
+    Feature::extension("lists"),
+    Feature::extension("indexing"),
+    Feature::extension("ranges"),
+    Feature::extension("for-in loops"),
+    Feature::extension("variadic functions"),
+    Feature::extension("named arguments"),
+    Feature::extension("static methods"),
+    Feature::extension("data classes"),
+    Feature::extension("mixins"),
+    Feature::extension("toString methods"),
+    Feature::extension("modules"),
+    Feature::extension("pattern natives"),
+    Feature::extension("higher-order natives"),
+    Feature::extension("sorting natives"),
+    Feature::extension("copy natives"),
+    Feature::extension("object ids"),
+    Feature::extension("string builders"),
+    Feature::extension("debug blocks"),
+    Feature::extension("integers").with_option(FeatureOption::Integers),
+    Feature::extension("bitwise operators").with_option(FeatureOption::Integers),
+    Feature::extension("keyword aliases").with_option(FeatureOption::Keywords),
+];
+
+/// Every supported feature and whether it is enabled under some
+/// [`CompileOptions`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FeatureSet {
+    features: Vec<(Feature, bool)>,
+}
+
+impl FeatureSet {
+    /// The features enabled when compiling with `options`.
+    pub fn with_options(options: &CompileOptions) -> Self {
+        Self {
+            features: FEATURES
+                .iter()
+                .map(|feature| (*feature, feature.is_enabled_by(options)))
+                .collect(),
+        }
+    }
+
+    /// Every supported feature, chapters of the book first, and whether it
+    /// is enabled.
+    pub fn iter(&self) -> impl Iterator<Item = (&Feature, bool)> {
+        self.features
+            .iter()
+            .map(|(feature, enabled)| (feature, *enabled))
+    }
+
+    /// The extensions to Lox and whether each is enabled.
+    pub fn extensions(&self) -> impl Iterator<Item = (&Feature, bool)> {
+        self.iter().filter(|(feature, _)| feature.is_extension())
+    }
+
+    /// Whether the feature called `name` is supported and enabled.
+    pub fn is_enabled(&self, name: &str) -> bool {
+        self.iter()
+            .any(|(feature, enabled)| enabled && feature.name == name)
+    }
+}
+
+/// The features of a VM with the default options. See
+/// [`VM::features`](crate::VM::features) for those of a configured VM.
+pub fn features() -> FeatureSet {
+    FeatureSet::with_options(&CompileOptions::default())
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        fs,
+        path::{Path, PathBuf},
+    };
+
+    use super::*;
+    use crate::{VmOptions, VM};
+
+    /// A program using `feature` and what it prints.
+    fn example(feature: &Feature) -> (&'static str, &'static str) {
+        match feature.name {
+            "expressions" => ("print (1 + 2) * 3 - -4 / 2;", "11\n"),
+            "values" => ("print !nil == (1 < 2); print 1 == \"1\";", "true\nfalse\n"),
+            "strings" => ("print \"con\" + \"cat\" == \"concat\";", "true\n"),
+            "global variables" => ("var a = 1; a = a + 1; print a;", "2\n"),
+            "local variables" => ("var a = 1; { var a = 2; print a; } print a;", "2\n1\n"),
+            "control flow" => (
+                "for (var i = 0; i < 3; i = i + 1) if (i != 1 and true or false) print i;",
+                "0\n2\n",
+            ),
+            "functions" => ("fun add(a, b) { return a + b; } print add(1, 2);", "3\n"),
+            "closures" => (
+                "fun counter() { var n = 0; fun next() { n = n + 1; return n; } return next; }
+                var next = counter(); next(); print next();",
+                "2\n",
+            ),
+            "garbage collection" => (
+                "var s = \"\"; for (var i = 0; i < 1000; i = i + 1) s = \"garbage\" + \"!\"; print s;",
+                "garbage!\n",
+            ),
+            "classes" => ("class A {} var a = A(); a.x = 1; print a.x; print A;", "1\nA\n"),
+            "methods" => (
+                "class A { init(x) { this.x = x; } get() { return this.x; } } print A(3).get();",
+                "3\n",
+            ),
+            "inheritance" => (
+                "class A { name() { return \"a\"; } }
+                 class B < A { name() { return super.name() + \"b\"; } } print B().name();",
+                "ab\n",
+            ),
+            "lists" => ("print [1, \"two\", [nil]];", "[1, two, [nil]]\n"),
+            "indexing" => (
+                "var l = [1, 2, 3]; l[1] = 5; print l[1]; print \"hello\"[1..3];",
+                "5\nel\n",
+            ),
+            "ranges" => ("print contains(1..=10, 10); print 1..10;", "true\n1..10\n"),
+            "for-in loops" => ("for (var c in \"hi\") print c;", "h\ni\n"),
+            "variadic functions" => ("fun f(a, ...rest) { print rest; } f(1, 2, 3);", "[2, 3]\n"),
+            "named arguments" => ("fun f(a, b) { print a - b; } f(b: 1, a: 3);", "2\n"),
+            "static methods" => ("class A { class make() { return 1; } } print A.make();", "1\n"),
+            "data classes" => (
+                "class P(x, y) {} print P(1, 2).y; print P(1, 2).equals(P(1, 2));",
+                "2\ntrue\n",
+            ),
+            "mixins" => (
+                "class M { hi() { return \"hi\"; } } class A with M {} print A().hi();",
+                "hi\n",
+            ),
+            "toString methods" => (
+                "class A { toString() { return \"an A\"; } } print A();",
+                "an A\n",
+            ),
+            "modules" => ("import \"shared\"; import \"shared\"; print shared;", "1\n"),
+            "pattern natives" => ("print match(\"order 66\", \"[0-9]+\");", "66\n"),
+            "higher-order natives" => (
+                "fun double(x) { return x * 2; } print map([1, 2], double);",
+                "[2, 4]\n",
+            ),
+            "sorting natives" => ("print sort([3, 1, 2]);", "[1, 2, 3]\n"),
+            "copy natives" => (
+                "var a = [[1]]; var b = deepCopy(a); b[0][0] = 2; print a; print same(copy(a)[0], a[0]);",
+                "[[1]]\ntrue\n",
+            ),
+            "object ids" => ("class A {} print id(A) == id(A); print id(A()) == id(A());", "true\nfalse\n"),
+            "string builders" => (
+                "var b = stringBuilder(); append(append(b, \"a\"), \"b\"); print toString(b);",
+                "ab\n",
+            ),
+            "debug blocks" => (
+                "var debug = \"on\"; debug { print \"checked\"; } print debug;",
+                "on\n",
+            ),
+            "integers" => ("print 7 / 2; print 6 / 2;", "3.500000\n3\n"),
+            "bitwise operators" => ("print 12 & 10; print 1 << 4;", "8\n16\n"),
+            "keyword aliases" => ("function f() { return 1; } print f();", "1\n"),
+            name => panic!("No example for feature '{name}'."),
+        }
+    }
+
+    /// Runs the example of `feature` with its option enabled, returning
+    /// whether it printed what it should.
+    fn verify(feature: &Feature, module_dir: &Path) -> bool {
+        let mut options = VmOptions::default();
+        options.module_paths.push(module_dir.to_path_buf());
+        match feature.option {
+            Some(FeatureOption::Integers) => options.compile.integers = true,
+            Some(FeatureOption::Keywords) => {
+                options.compile.keywords.alias("function", "fun");
+            }
+            None => {}
+        }
+        let mut vm = VM::with_options(Vec::new(), Vec::new(), options);
+        let (source, expected) = example(feature);
+        let (result, out, _) = vm.interpret_captured(source);
+        result.is_ok() && out == expected
+    }
+
+    /// The matrix of `(feature, enabled by default, verified)` as JSON.
+    fn json(features: &[(Feature, bool, bool)]) -> String {
+        let entries = features
+            .iter()
+            .map(|(feature, default, verified)| {
+                let chapter = feature
+                    .chapter
+                    .map_or("null".to_string(), |chapter| chapter.to_string());
+                let option = feature
+                    .option
+                    .map_or("null".to_string(), |option| format!("\"{}\"", option.name()));
+                format!(
+                    "{{\"name\":\"{}\",\"chapter\":{chapter},\"extension\":{},\"option\":{option},\"default\":{default},\"verified\":{verified}}}",
+                    feature.name,
+                    feature.is_extension()
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(",");
+        format!(
+            "{{\"version\":\"{}\",\"features\":[{entries}]}}\n",
+            env!("CARGO_PKG_VERSION")
+        )
+    }
+
+    #[test]
+    fn it_lists_chapters_before_extensions() {
+        let first_extension = FEATURES.iter().position(Feature::is_extension).unwrap();
+        assert!(FEATURES[first_extension..]
+            .iter()
+            .all(Feature::is_extension));
+        let chapters: Vec<_> = FEATURES.iter().filter_map(|f| f.chapter).collect();
+        assert!(chapters.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    #[test]
+    fn it_enables_extensions_by_option() {
+        let defaults = features();
+        assert_eq!(defaults.iter().count(), FEATURES.len());
+        assert!(defaults.is_enabled("closures"));
+        assert!(defaults.is_enabled("lists"));
+        assert!(!defaults.is_enabled("integers"));
+        assert!(!defaults.is_enabled("keyword aliases"));
+        assert!(!defaults.is_enabled("missing"));
+        assert!(defaults
+            .extensions()
+            .all(|(feature, _)| feature.is_extension()));
+
+        let mut options = CompileOptions {
+            integers: true,
+            ..Default::default()
+        };
+        options.keywords.alias("function", "fun");
+        let configured = FeatureSet::with_options(&options);
+        assert!(configured.is_enabled("integers"));
+        assert!(configured.is_enabled("bitwise operators"));
+        assert!(configured.is_enabled("keyword aliases"));
+        options.conformance = true;
+        assert!(!FeatureSet::with_options(&options).is_enabled("keyword aliases"));
+    }
+
+    #[test]
+    fn it_writes_the_conformance_matrix() {
+        let module_dir =
+            std::env::temp_dir().join(format!("loxide-conformance-{}", std::process::id()));
+        fs::create_dir_all(&module_dir).expect("Failed to create directory");
+        fs::write(module_dir.join("shared.lox"), "var shared = 1;")
+            .expect("Failed to write module");
+        let results: Vec<_> = features()
+            .iter()
+            .map(|(feature, default)| (*feature, default, verify(feature, &module_dir)))
+            .collect();
+        fs::remove_dir_all(&module_dir).expect("Failed to clean up");
+
+        let target = std::env::var_os("CARGO_TARGET_DIR")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("target"));
+        fs::create_dir_all(&target).expect("Failed to create target directory");
+        fs::write(target.join("conformance.json"), json(&results))
+            .expect("Failed to write the conformance matrix");
+
+        let failed: Vec<_> = results
+            .iter()
+            .filter(|(_, _, verified)| !verified)
+            .map(|(feature, _, _)| feature.name)
+            .collect();
+        assert!(failed.is_empty(), "Unsupported features: {failed:?}");
+    }
+}
@@ -0,0 +1,270 @@
+//! Heap dumps, for finding out what keeps objects alive.
+//!
+//! A dump is the graph of every object reachable from the VM's roots: the
+//! value stack, open upvalues and globals. Each object is listed with its
+//! kind, heap size and the objects it references, so a structure that should
+//! have been collected can be traced back to the root pinning it.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    io::{self, Write},
+};
+
+use crate::{
+    object::{store::for_each_reference, ObjectId, Store},
+    value::RuntimeValue,
+};
+
+/// How long a string's contents may be before its label is cut short.
+const MAX_LABEL_CHARS: usize = 32;
+
+/// How [`VM::dump_heap`](crate::VM::dump_heap) writes the object graph.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum HeapFormat {
+    /// A Graphviz digraph, with roots as boxes pointing at their objects
+    Dot,
+    /// `{"roots": [{"root", "object"}], "objects": [{"id", "kind", "size",
+    /// "label", "references"}]}`, objects referring to each other by id
+    Json,
+}
+
+struct HeapObject {
+    kind: &'static str,
+    size: usize,
+    label: String,
+    references: Vec<usize>,
+}
+
+/// The live objects of a store, numbered in the order they are reached
+/// walking breadth first from the roots.
+pub(crate) struct HeapGraph {
+    /// Each root's description and the object it holds
+    roots: Vec<(String, usize)>,
+    objects: Vec<HeapObject>,
+}
+
+impl HeapGraph {
+    pub(crate) fn of(store: &Store) -> Self {
+        let mut roots = Vec::new();
+        for (slot, value) in store.value_stack.iter().enumerate() {
+            roots.push((format!("stack {slot}"), *value));
+        }
+        for (slot, upvalue) in &store.open_upvalues {
+            roots.push((format!("open upvalue {slot}"), (*upvalue).into()));
+        }
+        for (name, value) in store.globals.entries() {
+            roots.push((format!("global {name}"), *value));
+        }
+
+        let mut ids = HashMap::<ObjectId, usize>::new();
+        let mut values = Vec::new();
+        let mut queue = VecDeque::new();
+        let mut id_of = |value: RuntimeValue, queue: &mut VecDeque<RuntimeValue>| {
+            Some(*ids.entry(value.id()?).or_insert_with(|| {
+                values.push(value);
+                queue.push_back(value);
+                values.len() - 1
+            }))
+        };
+        let roots = roots
+            .into_iter()
+            .filter_map(|(root, value)| Some((root, id_of(value, &mut queue)?)))
+            .collect();
+        let mut references = Vec::new();
+        while let Some(value) = queue.pop_front() {
+            let mut targets = Vec::new();
+            for_each_reference(value, |reference| {
+                targets.extend(id_of(reference, &mut queue))
+            });
+            references.push(targets);
+        }
+
+        let objects = values
+            .into_iter()
+            .zip(references)
+            .map(|(value, references)| HeapObject {
+                kind: kind(&value).expect("ICE: Only objects are numbered."),
+                size: value.heap_size().unwrap_or_default(),
+                label: label(&value),
+                references,
+            })
+            .collect();
+        Self { roots, objects }
+    }
+
+    pub(crate) fn write(&self, writer: &mut impl Write, format: HeapFormat) -> io::Result<()> {
+        match format {
+            HeapFormat::Dot => self.write_dot(writer),
+            HeapFormat::Json => self.write_json(writer),
+        }
+    }
+
+    fn write_dot(&self, writer: &mut impl Write) -> io::Result<()> {
+        writeln!(writer, "digraph heap {{")?;
+        for (root, id) in &self.roots {
+            writeln!(writer, "  {} [shape=box];", dot_string(root))?;
+            writeln!(writer, "  {} -> {id};", dot_string(root))?;
+        }
+        for (id, object) in self.objects.iter().enumerate() {
+            let label = format!("{} {}\n{} bytes", object.kind, object.label, object.size);
+            writeln!(writer, "  {id} [label={}];", dot_string(&label))?;
+            for reference in &object.references {
+                writeln!(writer, "  {id} -> {reference};")?;
+            }
+        }
+        writeln!(writer, "}}")
+    }
+
+    fn write_json(&self, writer: &mut impl Write) -> io::Result<()> {
+        write!(writer, "{{\"roots\":[")?;
+        for (i, (root, id)) in self.roots.iter().enumerate() {
+            let separator = if i == 0 { "" } else { "," };
+            write!(
+                writer,
+                "{separator}{{\"root\":{},\"object\":{id}}}",
+                json_string(root)
+            )?;
+        }
+        write!(writer, "],\"objects\":[")?;
+        for (id, object) in self.objects.iter().enumerate() {
+            let separator = if id == 0 { "" } else { "," };
+            let references = object
+                .references
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(",");
+            write!(
+                writer,
+                "{separator}{{\"id\":{id},\"kind\":\"{}\",\"size\":{},\"label\":{},\"references\":[{references}]}}",
+                object.kind,
+                object.size,
+                json_string(&object.label)
+            )?;
+        }
+        writeln!(writer, "]}}")
+    }
+}
+
+/// The kind of heap object `value` is, or `None` if it isn't one.
+fn kind(value: &RuntimeValue) -> Option<&'static str> {
+    Some(match value {
+        RuntimeValue::BoundMethod(_) => "bound method",
+        RuntimeValue::Class(_) => "class",
+        RuntimeValue::Closure(_) => "closure",
+        RuntimeValue::Function(_) => "function",
+        RuntimeValue::Instance(_) => "instance",
+        RuntimeValue::List(_) => "list",
+        RuntimeValue::Native(_) => "native",
+        RuntimeValue::Range(_) => "range",
+        RuntimeValue::String(_) => "string",
+        RuntimeValue::StringBuilder(_) => "string builder",
+        RuntimeValue::Upvalue(_) => "upvalue",
+        RuntimeValue::Bool(_)
+        | RuntimeValue::Number(_)
+        | RuntimeValue::Int(_)
+        | RuntimeValue::Nil => return None,
+    })
+}
+
+/// A short description of `value`. Lists only give their length, since
+/// their elements are objects of their own.
+fn label(value: &RuntimeValue) -> String {
+    match value {
+        RuntimeValue::List(list) => format!("of {}", list.items.len()),
+        RuntimeValue::String(string) if string.chars.chars().count() > MAX_LABEL_CHARS => {
+            let prefix: String = string.chars.chars().take(MAX_LABEL_CHARS).collect();
+            format!("\"{prefix}...\"")
+        }
+        RuntimeValue::String(string) => format!("\"{}\"", string.chars),
+        value => value.to_string(),
+    }
+}
+
+fn dot_string(s: &str) -> String {
+    let escaped = s
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n");
+    format!("\"{escaped}\"")
+}
+
+pub(crate) fn json_string(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len() + 2);
+    escaped.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            c if c.is_control() => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::object::{HeapSize, ObjList, ObjString};
+
+    fn graph() -> (Store, HeapGraph) {
+        let mut store = Store::default();
+        let string = store.insert_string("a \"quoted\"\nstring".into());
+        let list = store.insert_list(ObjList {
+            items: vec![string.into(), RuntimeValue::Nil, string.into()],
+        });
+        store.globals.insert(ObjString::from("xs"), list.into());
+        store.value_stack.push(RuntimeValue::Number(1.0));
+        store.insert_string("garbage".into());
+        let graph = HeapGraph::of(&store);
+        (store, graph)
+    }
+
+    #[test]
+    fn it_lists_reachable_objects() {
+        let (_, graph) = graph();
+        assert_eq!(graph.roots, vec![("global xs".to_string(), 0)]);
+        assert_eq!(graph.objects.len(), 2);
+        assert_eq!(graph.objects[0].kind, "list");
+        assert_eq!(graph.objects[0].label, "of 3");
+        assert_eq!(graph.objects[0].references, vec![1, 1]);
+        assert_eq!(graph.objects[1].kind, "string");
+        assert!(graph.objects[1].references.is_empty());
+    }
+
+    #[test]
+    fn it_writes_dot() {
+        let (_, graph) = graph();
+        let mut out = Vec::new();
+        graph.write(&mut out, HeapFormat::Dot).unwrap();
+        let dot = String::from_utf8(out).unwrap();
+        assert!(dot
+            .starts_with("digraph heap {\n  \"global xs\" [shape=box];\n  \"global xs\" -> 0;\n"));
+        assert!(dot.contains("  0 -> 1;\n  0 -> 1;\n"));
+        assert!(dot.contains(r#"[label="string \"a \"quoted\"\nstring\"\n"#));
+        assert!(dot.ends_with("}\n"));
+    }
+
+    #[test]
+    fn it_writes_json() {
+        let (store, graph) = graph();
+        let mut out = Vec::new();
+        graph.write(&mut out, HeapFormat::Json).unwrap();
+        let json = String::from_utf8(out).unwrap();
+        let RuntimeValue::List(list) = store.globals.get(&"xs".into()).copied().unwrap() else {
+            panic!("Expected a list");
+        };
+        assert!(
+            json.starts_with("{\"roots\":[{\"root\":\"global xs\",\"object\":0}],\"objects\":[")
+        );
+        assert!(json.contains(&format!(
+            "{{\"id\":0,\"kind\":\"list\",\"size\":{},\"label\":\"of 3\",\"references\":[1,1]}}",
+            ObjList::size(&list)
+        )));
+        assert!(json.contains(r#""label":"\"a \"quoted\"\nstring\"""#));
+        assert!(json.ends_with("]}\n"));
+    }
+}
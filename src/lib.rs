@@ -1,10 +1,73 @@
-pub mod call_frame;
-pub mod chunk;
-pub mod compiler;
+//! A bytecode interpreter for Lox.
+//!
+//! Embed it by creating a [`VM`], optionally set up by [`VmOptions`], and
+//! passing it source code:
+//!
+//! ```
+//! use loxide::VM;
+//!
+//! let mut vm = VM::new(Vec::new(), Vec::new());
+//! vm.interpret("print 1 + 2;").unwrap();
+//! ```
+//!
+//! Everything re-exported here is the stable interface. The compiler, object
+//! store and value representation change between releases; they are only
+//! public with the `internals` feature, for tooling that accepts that churn.
+
+/// Declares modules that are public only with the `internals` feature.
+macro_rules! internal_modules {
+    ($($name:ident),* $(,)?) => {
+        $(
+            #[cfg(feature = "internals")]
+            pub mod $name;
+            #[cfg(not(feature = "internals"))]
+            mod $name;
+        )*
+    };
+}
+
+internal_modules!(
+    arena, call_frame, chunk, compiler, native, object, regex, scanner, table, token, value,
+    verifier
+);
+
+pub mod coverage;
+pub mod debugger;
+pub mod disassembler;
 pub mod error;
-pub mod object;
-pub mod scanner;
-pub mod table;
-pub mod token;
-pub mod value;
+pub mod features;
+pub mod heap;
+pub mod manifest;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+pub mod module;
+pub mod program;
+pub mod repl;
+pub mod stats;
+pub mod trace;
+pub mod visualizer;
 pub mod vm;
+
+pub use compiler::{
+    diagnostics::{
+        CompileError, CompileErrorKind, Diagnostics, Redefinition, Shadowing, Unreachable,
+    },
+    limits::{limits, Limits},
+    upvalue::UpvalueResolution,
+    CompileOptions, NumberOverflow,
+};
+pub use coverage::Coverage;
+pub use debugger::{Breakpoint, StepResult};
+pub use disassembler::DisassemblyOptions;
+pub use error::{Error, RuntimeErrorInfo, TraceFrame};
+pub use features::{features, Feature, FeatureOption, FeatureSet};
+pub use heap::HeapFormat;
+pub use module::{FileResolver, ModuleResolver, Source};
+pub use object::HeapSize;
+pub use program::Program;
+pub use scanner::Keywords;
+pub use stats::Stats;
+pub use trace::{JsonTrace, TextTrace, TraceEvent, TraceSink};
+pub use value::LoxValue;
+pub use visualizer::StateFormat;
+pub use vm::{OutputBuffering, VmOptions, VmState, VM};
@@ -1,35 +1,381 @@
-use loxide::{error::Error, vm::VM};
+use loxide::{
+    manifest::{self, Manifest},
+    repl::{self, complete, Entry, DISABLE_BRACKETED_PASTE, ENABLE_BRACKETED_PASTE, RC_FILE},
+    DisassemblyOptions, Error, HeapFormat, JsonTrace, OutputBuffering, StateFormat, StepResult,
+    TextTrace, VM,
+};
 use std::{
     env, fs,
-    io::{stderr, stdin, stdout, Write},
+    io::{stderr, stdin, stdout, IsTerminal, Write},
+    path::{Path, PathBuf},
+    process,
+    time::Instant,
 };
 
-fn repl(mut vm: VM) {
+fn run_repl(mut vm: VM, preludes: &[PathBuf]) {
+    for path in preludes {
+        let loaded = fs::read_to_string(path)
+            .map_err(|e| e.to_string())
+            .and_then(|source| repl::load(&mut vm, path, &source).map_err(|e| e.to_string()));
+        if let Err(e) = loaded {
+            eprintln!("{}: {e}", path.display());
+        }
+    }
+    // Ask the terminal to mark pastes, so a pasted block runs as one entry
+    let bracketed_paste = stdin().is_terminal() && stdout().is_terminal();
+    if bracketed_paste {
+        print!("{ENABLE_BRACKETED_PASTE}");
+    }
+    let mut entry = Entry::default();
     loop {
         let mut line = String::new();
-        print!("> ");
+        print!("{}", if entry.is_empty() { "> " } else { "... " });
         let _ = stdout().flush();
-        stdin().read_line(&mut line).expect("Malformed input.");
-        if let Err(e) = vm.interpret(&line) {
-            eprintln!("{e}")
+        let read = stdin().read_line(&mut line).expect("Malformed input.");
+        let source = if read == 0 {
+            // End of input runs whatever was left unfinished
+            match entry.finish() {
+                Some(source) => source,
+                None => break,
+            }
+        } else {
+            if entry.is_empty() {
+                // Without a line editor, a line ending in a tab asks for completions
+                if let Some(partial) = line.trim_end_matches(['\r', '\n']).strip_suffix('\t') {
+                    println!("{}", complete(&vm, partial).join("  "));
+                    continue;
+                }
+                if let Some(name) = line.trim().strip_prefix(":info ") {
+                    match vm.class_info(name.trim()) {
+                        Some(info) => println!("{info}"),
+                        None => eprintln!("No class named '{}'.", name.trim()),
+                    }
+                    continue;
+                }
+            }
+            match entry.push(&vm, &line) {
+                Some(source) => source,
+                None => continue,
+            }
+        };
+        if let Err(e) = vm.interpret(&source) {
+            eprintln!("{e}");
+            // Keep the session's globals but drop whatever the error left behind
+            vm.reset(false);
+        }
+    }
+    if bracketed_paste {
+        print!("{DISABLE_BRACKETED_PASTE}");
+        let _ = stdout().flush();
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum CoverageFormat {
+    Listing,
+    Lcov,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum TraceFormat {
+    Text,
+    Json,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct RunOptions {
+    coverage: Option<CoverageFormat>,
+    stats: bool,
+    integers: bool,
+    heap_dump: Option<HeapFormat>,
+    full_backtrace: bool,
+    debug_blocks: bool,
+    debugger: bool,
+    visualize: Option<StateFormat>,
+    trace: Option<TraceFormat>,
+    /// How many of the latest events to report with a runtime error
+    flight_recorder: Option<usize>,
+}
+
+/// How many events `--flight-recorder` keeps when not told.
+const DEFAULT_FLIGHT_RECORDER_EVENTS: usize = 32;
+
+const USAGE: &str = "Usage: loxide [repl [--load path]...]\n       loxide [run [--coverage[=listing|lcov]] [--stats] [--integers] [--heap-dump[=dot|json]] [--full-backtrace] [--debug-blocks] [--debugger] [--visualize[=text|json]] [--trace[=text|json]] [--flight-recorder[=n]]] path\n       loxide dis [--constants] [--lines] [--integers] path\n       loxide init [path]\n\nA path of - reads the script from stdin. Setting LOXIDE_FULL_BACKTRACE also lists\nevery frame of runtime errors.";
+
+/// The scripts to run before the first prompt: the user's rc file, if there
+/// is one, then each `--load path` in order.
+fn parse_preludes(arguments: &[String]) -> Option<Vec<PathBuf>> {
+    let mut preludes: Vec<PathBuf> = env::var_os("HOME")
+        .map(|home| Path::new(&home).join(RC_FILE))
+        .filter(|rc| rc.is_file())
+        .into_iter()
+        .collect();
+    let mut arguments = arguments.iter();
+    while let Some(argument) = arguments.next() {
+        match argument.strip_prefix("--load=") {
+            Some(path) => preludes.push(path.into()),
+            None if argument == "--load" => preludes.push(arguments.next()?.into()),
+            None => return None,
         }
     }
+    Some(preludes)
 }
 
-fn run_file(path: &str, mut vm: VM) -> Result<(), Error> {
-    let source = fs::read_to_string(path).expect("Failed to read file.");
-    vm.interpret(&source)
+/// Finds the script to run for `path`: the file itself, with imports resolved
+/// next to it, or the entry of the project in the directory `path`.
+fn load_target(path: &str, vm: &mut VM) -> PathBuf {
+    let path = Path::new(path);
+    if !path.is_dir() {
+        vm.add_module_path(path.parent().unwrap_or(Path::new(".")));
+        return path.into();
+    }
+    match Manifest::load(path) {
+        Ok(manifest) => {
+            for module_path in manifest.paths {
+                vm.add_module_path(module_path);
+            }
+            manifest.entry
+        }
+        Err(e) => {
+            eprintln!("{e}");
+            process::exit(1);
+        }
+    }
+}
+
+/// Runs the script at `path`, or streams it from stdin when `path` is `-`.
+fn run_file(path: &str, mut vm: VM, options: RunOptions) -> Result<(), Error> {
+    let script = if path == "-" {
+        vm.add_module_path(".");
+        None
+    } else {
+        let path = load_target(path, &mut vm);
+        let source = fs::read_to_string(&path).expect("Failed to read file.");
+        Some((path, source))
+    };
+    if options.coverage.is_some() {
+        vm.enable_coverage();
+    }
+    vm.set_integers(options.integers);
+    vm.set_debug_blocks(options.debug_blocks);
+    if options.full_backtrace {
+        vm.set_full_backtrace(true);
+    }
+    if options.debugger {
+        attach_debugger(&mut vm);
+    }
+    // Like the other reports, the trace goes to stderr
+    match options.trace {
+        Some(TraceFormat::Text) => vm.set_trace_sink(TextTrace::new(stderr())),
+        Some(TraceFormat::Json) => vm.set_trace_sink(JsonTrace::new(stderr())),
+        None => {}
+    }
+    vm.set_flight_recorder(options.flight_recorder);
+    // Output nobody watches line by line is written in blocks, which is much
+    // faster, unless it's to be seen between the states of a visualized run
+    if !stdout().is_terminal() && options.visualize.is_none() {
+        vm.set_output_buffering(OutputBuffering::Block(8 * 1024));
+    }
+    // Unlike in the REPL, a global declared twice in a file is likely a mistake
+    vm.set_redefinition_warnings(true);
+    vm.set_unreachable_warnings(true);
+    // Shown before the script runs, so they don't trail its output
+    vm.on_compiled(|diagnostics| {
+        for warning in &diagnostics.redefinitions {
+            eprintln!("{warning}");
+        }
+        for warning in &diagnostics.unreachable {
+            eprintln!("{warning}");
+        }
+    });
+    let start = Instant::now();
+    let result = match (&script, options.visualize) {
+        (Some((_, source)), Some(format)) => visualize(&mut vm, source, format),
+        (None, Some(format)) => {
+            let source = std::io::read_to_string(stdin()).expect("Failed to read stdin.");
+            visualize(&mut vm, &source, format)
+        }
+        (Some((path, source)), None) => vm.interpret_module(path, source),
+        (None, None) => vm.interpret_reader(stdin()),
+    };
+    let elapsed = start.elapsed();
+    // Reports go to stderr to keep them apart from the script's own output
+    if let (Some(format), Some(report)) = (options.coverage, vm.coverage()) {
+        match (format, &script) {
+            (CoverageFormat::Listing, Some((_, source))) => eprint!("{}", report.listing(source)),
+            // A script streamed from stdin isn't kept around to be listed
+            (_, Some((path, _))) => eprint!("{}", report.lcov(&path.to_string_lossy())),
+            (_, None) => eprint!("{}", report.lcov(path)),
+        }
+    }
+    if options.stats {
+        eprint!("wall time: {elapsed:?}\n{}", vm.stats());
+    }
+    if let Some(format) = options.heap_dump {
+        vm.dump_heap(stderr(), format)
+            .expect("Failed to write heap dump.");
+    }
+    result
+}
+
+/// Runs `source` an instruction at a time, writing the machine's state to
+/// stderr before each one.
+fn visualize(vm: &mut VM, source: &str, format: StateFormat) -> Result<(), Error> {
+    let program = vm.compile(source)?;
+    vm.prepare(&program)?;
+    for step in 1.. {
+        if format == StateFormat::Text {
+            eprintln!("========== STEP {step} ==========");
+        }
+        vm.dump_state(stderr(), format)
+            .expect("Failed to write state.");
+        match vm.step() {
+            StepResult::Executed | StepResult::Breakpoint => {}
+            StepResult::Finished => break,
+            StepResult::Errored(error) => return Err(error),
+        }
+    }
+    Ok(())
+}
+
+/// Pauses the script at each `breakpoint()` to take commands from stdin.
+fn attach_debugger(vm: &mut VM) {
+    // Locals are only known to code compiled with debug info
+    vm.set_debug_info(true);
+    vm.on_breakpoint(|breakpoint| {
+        eprintln!("{breakpoint}");
+        loop {
+            eprint!("(debug) ");
+            let mut command = String::new();
+            if stdin().read_line(&mut command).unwrap_or(0) == 0 {
+                return;
+            }
+            let command = command.trim();
+            if let Some(name) = command
+                .strip_prefix("info ")
+                .or_else(|| command.strip_prefix("i "))
+            {
+                match breakpoint
+                    .classes
+                    .iter()
+                    .find(|(class, _)| class == name.trim())
+                {
+                    Some((_, info)) => eprintln!("{info}"),
+                    None => eprintln!("No class named '{}'.", name.trim()),
+                }
+                continue;
+            }
+            match command {
+                "" | "c" | "continue" => return,
+                "l" | "locals" => match &breakpoint.locals {
+                    Some(locals) => {
+                        for (name, value) in locals {
+                            eprintln!("{name} = {value}");
+                        }
+                    }
+                    None => eprintln!("Locals are unknown without debug info."),
+                },
+                "bt" | "backtrace" => {
+                    for frame in &breakpoint.trace {
+                        eprintln!("{frame}");
+                    }
+                }
+                _ => eprintln!(
+                    "Commands: locals (l), backtrace (bt), info (i) <class>, continue (c)"
+                ),
+            }
+        }
+    });
+}
+
+fn parse_options(arguments: &[String]) -> Option<RunOptions> {
+    let mut options = RunOptions::default();
+    for argument in arguments {
+        match argument.as_str() {
+            "--coverage" | "--coverage=listing" => options.coverage = Some(CoverageFormat::Listing),
+            "--coverage=lcov" => options.coverage = Some(CoverageFormat::Lcov),
+            "--stats" => options.stats = true,
+            "--integers" => options.integers = true,
+            "--heap-dump" | "--heap-dump=dot" => options.heap_dump = Some(HeapFormat::Dot),
+            "--heap-dump=json" => options.heap_dump = Some(HeapFormat::Json),
+            "--full-backtrace" => options.full_backtrace = true,
+            "--debug-blocks" => options.debug_blocks = true,
+            "--debugger" => options.debugger = true,
+            "--visualize" | "--visualize=text" => options.visualize = Some(StateFormat::Text),
+            "--visualize=json" => options.visualize = Some(StateFormat::Json),
+            "--trace" | "--trace=text" => options.trace = Some(TraceFormat::Text),
+            "--trace=json" => options.trace = Some(TraceFormat::Json),
+            "--flight-recorder" => options.flight_recorder = Some(DEFAULT_FLIGHT_RECORDER_EVENTS),
+            flag if flag.starts_with("--flight-recorder=") => {
+                options.flight_recorder = Some(flag["--flight-recorder=".len()..].parse().ok()?)
+            }
+            _ => return None,
+        }
+    }
+    Some(options)
+}
+
+/// Lays out a new project in the directory `path`.
+fn init_project(path: &str) {
+    match manifest::scaffold(Path::new(path)) {
+        Ok(files) => {
+            println!("Created a project in {path}:");
+            for file in files {
+                println!("  {}", file.strip_prefix(path).unwrap_or(&file).display());
+            }
+            println!("Run it with: loxide {path}");
+        }
+        Err(e) => {
+            eprintln!("{e}");
+            process::exit(1);
+        }
+    }
+}
+
+/// Prints the bytecode the script at `path` compiles to.
+fn disassemble_file(path: &str, mut vm: VM, arguments: &[String]) -> Result<(), Error> {
+    let mut options = DisassemblyOptions::default();
+    for argument in arguments {
+        match argument.as_str() {
+            "--constants" => options.constants = true,
+            "--lines" => options.lines = true,
+            "--integers" => vm.set_integers(true),
+            _ => {
+                eprintln!("{USAGE}");
+                return Ok(());
+            }
+        }
+    }
+    let source = if path == "-" {
+        std::io::read_to_string(stdin()).expect("Failed to read stdin.")
+    } else {
+        fs::read_to_string(path).expect("Failed to read file.")
+    };
+    print!("{}", vm.disassemble(&source, options)?);
+    Ok(())
 }
 
 fn main() -> Result<(), Error> {
-    let vm = VM::new(stdout(), stderr());
+    let mut vm = VM::new(stdout(), stderr());
+    if env::var_os("LOXIDE_FULL_BACKTRACE").is_some_and(|value| !value.is_empty() && value != "0") {
+        vm.set_full_backtrace(true);
+    }
     let args: Vec<String> = env::args().collect();
-    if args.len() == 1 {
-        repl(vm);
-    } else if args.len() == 2 {
-        run_file(&args[1], vm)?;
-    } else {
-        eprintln!("Usage: loxide [path]");
+    match &args[1..] {
+        [] => run_repl(vm, &parse_preludes(&[]).unwrap_or_default()),
+        [command, options @ ..] if command == "repl" => match parse_preludes(options) {
+            Some(preludes) => run_repl(vm, &preludes),
+            None => eprintln!("{USAGE}"),
+        },
+        [command] if command == "init" => init_project("."),
+        [command, path] if command == "init" => init_project(path),
+        [path] => run_file(path, vm, RunOptions::default())?,
+        [command, options @ .., path] if command == "dis" => disassemble_file(path, vm, options)?,
+        [command, options @ .., path] if command == "run" => match parse_options(options) {
+            Some(options) => run_file(path, vm, options)?,
+            None => eprintln!("{USAGE}"),
+        },
+        _ => eprintln!("{USAGE}"),
     }
 
     Ok(())
@@ -0,0 +1,275 @@
+//! Project manifests, read from a `lox.toml` at the root of a project.
+//!
+//! The manifest is a small subset of TOML:
+//!
+//! ```toml
+//! [project]
+//! entry = "src/main.lox"
+//! paths = ["src", "lib"]
+//! ```
+//!
+//! `entry` names the script to run and `paths` the directories searched for
+//! imported modules, both relative to the project root. Without `paths`, the
+//! project root itself is searched.
+//!
+//! [`scaffold`] lays out a new project, as `loxide init` does.
+
+use std::{
+    fmt::Display,
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+pub const MANIFEST_FILE: &str = "lox.toml";
+
+/// The files of a new project, relative to its root: a manifest, an entry
+/// script importing a module from `src`, and an example test of the module.
+pub const SCAFFOLD: &[(&str, &str)] = &[
+    (
+        MANIFEST_FILE,
+        "[project]\nentry = \"src/main.lox\"\npaths = [\"src\"]\n",
+    ),
+    (
+        "src/main.lox",
+        "// Modules are found in the manifest's paths\nimport \"greeting\";\n\nprint greet(\"world\");\n",
+    ),
+    (
+        "src/greeting.lox",
+        "fun greet(name) {\n  return \"Hello, \" + name + \"!\";\n}\n",
+    ),
+    (
+        "tests/test_greeting.lox",
+        "// Run with: loxide run tests/test_greeting.lox\nimport \"../src/greeting\";\n\nif (greet(\"Lox\") == \"Hello, Lox!\") {\n  print \"ok - greet\";\n} else {\n  print \"FAIL - greet\";\n}\n",
+    ),
+];
+
+/// Writes the [`SCAFFOLD`] of a new project to `root`, creating it if needed,
+/// and returns the paths written. Fails without writing anything when `root`
+/// already has any of the files.
+pub fn scaffold(root: &Path) -> io::Result<Vec<PathBuf>> {
+    let paths: Vec<_> = SCAFFOLD.iter().map(|(file, _)| root.join(file)).collect();
+    if let Some(existing) = paths.iter().find(|path| path.exists()) {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} already exists.", existing.display()),
+        ));
+    }
+    for (path, (_, contents)) in paths.iter().zip(SCAFFOLD) {
+        if let Some(directory) = path.parent() {
+            fs::create_dir_all(directory)?;
+        }
+        fs::File::create_new(path)?.write_all(contents.as_bytes())?;
+    }
+    Ok(paths)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Manifest {
+    pub entry: PathBuf,
+    pub paths: Vec<PathBuf>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ManifestError {
+    /// The offending line, or zero for problems with the manifest as a whole
+    pub line: usize,
+    pub message: String,
+}
+
+impl Display for ManifestError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.line == 0 {
+            write!(f, "{MANIFEST_FILE}: {}", self.message)
+        } else {
+            write!(f, "{MANIFEST_FILE}:{}: {}", self.line, self.message)
+        }
+    }
+}
+
+impl std::error::Error for ManifestError {}
+
+impl Manifest {
+    /// Reads the manifest of the project in `root`, resolving its paths
+    /// against `root`.
+    pub fn load(root: &Path) -> Result<Self, ManifestError> {
+        let text = fs::read_to_string(root.join(MANIFEST_FILE)).map_err(|e| ManifestError {
+            line: 0,
+            message: e.to_string(),
+        })?;
+        let manifest = Self::parse(&text)?;
+        Ok(Self {
+            entry: root.join(manifest.entry),
+            paths: manifest.paths.iter().map(|path| root.join(path)).collect(),
+        })
+    }
+
+    pub fn parse(text: &str) -> Result<Self, ManifestError> {
+        let mut entry = None;
+        let mut paths = None;
+        let mut in_project = false;
+        for (index, line) in text.lines().enumerate() {
+            let line_number = index + 1;
+            let error = |message: &str| ManifestError {
+                line: line_number,
+                message: message.into(),
+            };
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+            if let Some(section) = line.strip_prefix('[') {
+                let Some(section) = section.strip_suffix(']') else {
+                    return Err(error("Expect ']' after section name."));
+                };
+                in_project = section.trim() == "project";
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=') else {
+                return Err(error("Expect '=' after key."));
+            };
+            if !in_project {
+                continue;
+            }
+            match key.trim() {
+                "entry" => {
+                    entry = Some(
+                        parse_string(value.trim())
+                            .ok_or_else(|| error("Expect a string for 'entry'."))?,
+                    )
+                }
+                "paths" => {
+                    paths = Some(
+                        parse_strings(value.trim())
+                            .ok_or_else(|| error("Expect a list of strings for 'paths'."))?,
+                    )
+                }
+                key => return Err(error(&format!("Unknown key '{key}'."))),
+            }
+        }
+
+        let Some(entry) = entry else {
+            return Err(ManifestError {
+                line: 0,
+                message: "Missing 'entry' in [project].".into(),
+            });
+        };
+        Ok(Self {
+            entry: entry.into(),
+            paths: paths
+                .unwrap_or_else(|| vec![".".into()])
+                .into_iter()
+                .map(PathBuf::from)
+                .collect(),
+        })
+    }
+}
+
+/// Drops a trailing `#` comment, leaving any `#` inside strings alone.
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => in_string = !in_string,
+            '#' if !in_string => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+fn parse_string(value: &str) -> Option<String> {
+    let value = value.strip_prefix('"')?.strip_suffix('"')?;
+    (!value.contains('"')).then(|| value.to_string())
+}
+
+fn parse_strings(value: &str) -> Option<Vec<String>> {
+    let items = value.strip_prefix('[')?.strip_suffix(']')?.trim();
+    items
+        .split(',')
+        .map(str::trim)
+        // Allow a trailing comma
+        .filter(|item| !item.is_empty())
+        .map(parse_string)
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::VM;
+
+    #[test]
+    fn it_parses_a_manifest() {
+        let text = "# A project\n[project]\nentry = \"src/main.lox\" # run this\npaths = [\"src\", \"lib\",]\n\n[other]\nname = 1\n";
+        assert_eq!(
+            Manifest::parse(text),
+            Ok(Manifest {
+                entry: "src/main.lox".into(),
+                paths: vec!["src".into(), "lib".into()],
+            })
+        );
+    }
+
+    #[test]
+    fn it_defaults_to_searching_the_project_root() {
+        let manifest = Manifest::parse("[project]\nentry = \"main.lox\"").unwrap();
+        assert_eq!(manifest.paths, vec![PathBuf::from(".")]);
+    }
+
+    #[test]
+    fn it_scaffolds_a_project_that_runs() {
+        let root = std::env::temp_dir().join(format!("loxide-init-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let written = scaffold(&root).expect("Failed to scaffold project");
+        assert_eq!(written.len(), SCAFFOLD.len());
+        assert!(written.iter().all(|path| path.is_file()));
+
+        let manifest = Manifest::load(&root).expect("Failed to load manifest");
+        assert_eq!(manifest.entry, root.join("src/main.lox"));
+        let mut vm = VM::new(Vec::new(), Vec::new());
+        for path in manifest.paths {
+            vm.add_module_path(path);
+        }
+        let entry = fs::read_to_string(&manifest.entry).unwrap();
+        assert!(vm.interpret_module(&manifest.entry, &entry).is_ok());
+        let test = root.join("tests/test_greeting.lox");
+        let source = fs::read_to_string(&test).unwrap();
+        assert!(VM::new(Vec::new(), Vec::new())
+            .interpret_module(&test, &source)
+            .is_ok());
+
+        let error = scaffold(&root).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::AlreadyExists);
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn it_reports_manifest_errors() {
+        let error = |text| Manifest::parse(text).unwrap_err().to_string();
+        assert_eq!(
+            error("[project]\nentry = main.lox"),
+            "lox.toml:2: Expect a string for 'entry'."
+        );
+        assert_eq!(
+            error("[project]\nentry = \"main.lox\"\npaths = \"src\""),
+            "lox.toml:3: Expect a list of strings for 'paths'."
+        );
+        assert_eq!(
+            error("[project\n"),
+            "lox.toml:1: Expect ']' after section name."
+        );
+        assert_eq!(
+            error("[project]\nentry"),
+            "lox.toml:2: Expect '=' after key."
+        );
+        assert_eq!(
+            error("[project]\nmain = \"a\""),
+            "lox.toml:2: Unknown key 'main'."
+        );
+        assert_eq!(
+            error("[project]\n"),
+            "lox.toml: Missing 'entry' in [project]."
+        );
+    }
+}
@@ -0,0 +1,89 @@
+//! Per-opcode execution statistics, collected when the `metrics` feature is enabled.
+//!
+//! Every heap allocation and object clone is attributed to the most recently
+//! dispatched opcode, which makes it possible to assert that hot handlers stay
+//! allocation free.
+
+use crate::chunk::OpCode;
+
+const OPCODE_SLOTS: usize = u8::MAX as usize + 1;
+
+#[derive(Debug, Clone)]
+pub struct Metrics {
+    current: Option<OpCode>,
+    executions: [usize; OPCODE_SLOTS],
+    allocations: [usize; OPCODE_SLOTS],
+    clones: [usize; OPCODE_SLOTS],
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self {
+            current: None,
+            executions: [0; OPCODE_SLOTS],
+            allocations: [0; OPCODE_SLOTS],
+            clones: [0; OPCODE_SLOTS],
+        }
+    }
+}
+
+impl Metrics {
+    /// Marks the start of `op`, attributing subsequent events to it.
+    pub fn begin(&mut self, op: OpCode) {
+        self.current = Some(op);
+        self.executions[op as usize] += 1;
+    }
+
+    pub fn record_allocation(&mut self) {
+        if let Some(op) = self.current {
+            self.allocations[op as usize] += 1;
+        }
+    }
+
+    pub fn record_clone(&mut self) {
+        if let Some(op) = self.current {
+            self.clones[op as usize] += 1;
+        }
+    }
+
+    /// How many times `op` was dispatched.
+    pub fn executions(&self, op: OpCode) -> usize {
+        self.executions[op as usize]
+    }
+
+    /// How many heap allocations happened while executing `op`.
+    pub fn allocations(&self, op: OpCode) -> usize {
+        self.allocations[op as usize]
+    }
+
+    /// How many object payloads were cloned while executing `op`.
+    pub fn clones(&self, op: OpCode) -> usize {
+        self.clones[op as usize]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_attributes_events_to_the_current_opcode() {
+        let mut metrics = Metrics::default();
+        metrics.record_allocation();
+        assert_eq!(metrics.allocations(OpCode::Add), 0);
+
+        metrics.begin(OpCode::Add);
+        metrics.record_allocation();
+        metrics.begin(OpCode::Constant);
+        metrics.record_clone();
+        metrics.record_allocation();
+        metrics.begin(OpCode::Add);
+
+        assert_eq!(metrics.executions(OpCode::Add), 2);
+        assert_eq!(metrics.allocations(OpCode::Add), 1);
+        assert_eq!(metrics.clones(OpCode::Add), 0);
+        assert_eq!(metrics.executions(OpCode::Constant), 1);
+        assert_eq!(metrics.allocations(OpCode::Constant), 1);
+        assert_eq!(metrics.clones(OpCode::Constant), 1);
+    }
+}
@@ -0,0 +1,106 @@
+//! Where imported modules come from.
+//!
+//! `import "name";` hands the name to the VM's [`ModuleResolver`], which
+//! finds the module's source. By default that's a [`FileResolver`] searching
+//! the VM's module paths, but an embedder can serve modules from memory, an
+//! archive or a database with [`VM::set_module_resolver`](crate::VM::set_module_resolver).
+
+use std::{
+    fmt::{self, Debug},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::error::Error;
+
+/// A module's source and the path naming it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Source {
+    /// Identifies the module, so it runs once however it's imported.
+    /// Relative imports from the module are resolved next to it, and cycle
+    /// errors show its file name.
+    pub path: PathBuf,
+    pub text: String,
+}
+
+/// Finds the modules scripts import.
+///
+/// Specs starting with `./` or `../` are joined onto the directory of the
+/// importing module's [`Source::path`] before they're resolved, so
+/// `import "./util";` in `lib/main` asks for `lib/util`. Other specs are
+/// passed as written.
+pub trait ModuleResolver {
+    /// The module `spec` names. Failing reports it as not found.
+    fn resolve(&mut self, spec: &str) -> Result<Source, Error>;
+}
+
+impl<F: FnMut(&str) -> Result<Source, Error>> ModuleResolver for F {
+    fn resolve(&mut self, spec: &str) -> Result<Source, Error> {
+        self(spec)
+    }
+}
+
+impl Debug for dyn ModuleResolver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ModuleResolver")
+    }
+}
+
+/// Resolves modules to `.lox` files, the extension implied when a spec has
+/// none. Relative and absolute specs are paths, others are looked up in the
+/// module paths in order.
+#[derive(Debug, Clone, Default)]
+pub struct FileResolver {
+    module_paths: Vec<PathBuf>,
+}
+
+impl FileResolver {
+    pub fn new(module_paths: Vec<PathBuf>) -> Self {
+        Self { module_paths }
+    }
+}
+
+impl ModuleResolver for FileResolver {
+    fn resolve(&mut self, spec: &str) -> Result<Source, Error> {
+        resolve_file(spec, &self.module_paths)
+    }
+}
+
+/// Resolves `spec` as [`FileResolver`] does, searching `module_paths`.
+pub(crate) fn resolve_file(spec: &str, module_paths: &[PathBuf]) -> Result<Source, Error> {
+    let path = find_file(spec, module_paths).ok_or(Error::Runtime)?;
+    let text = fs::read_to_string(&path).map_err(|_| Error::Runtime)?;
+    Ok(Source {
+        path: absolute(&path),
+        text,
+    })
+}
+
+/// The file `spec` names, searching `module_paths` unless it's a path.
+pub(crate) fn find_file(spec: &str, module_paths: &[PathBuf]) -> Option<PathBuf> {
+    let mut relative = PathBuf::from(spec);
+    if relative.extension().is_none() {
+        relative.set_extension("lox");
+    }
+    if is_relative(spec) || relative.is_absolute() {
+        Some(relative).filter(|path| path.is_file())
+    } else {
+        module_paths
+            .iter()
+            .map(|directory| directory.join(&relative))
+            .find(|path| path.is_file())
+    }
+}
+
+/// Whether `spec` names a module next to the one importing it.
+pub(crate) fn is_relative(spec: &str) -> bool {
+    spec.starts_with("./") || spec.starts_with("../")
+}
+
+/// `path` made absolute, with symbolic links resolved when it exists, so
+/// each file has one name.
+pub(crate) fn absolute(path: &Path) -> PathBuf {
+    path.canonicalize()
+        .or_else(|_| std::path::absolute(path))
+        .unwrap_or_else(|_| path.into())
+}
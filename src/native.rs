@@ -0,0 +1,524 @@
+//! The native functions defined in every VM's globals.
+
+use std::{
+    cmp::Ordering,
+    collections::HashMap,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    error::Error,
+    object::{obj_native::NativeContext, ObjList, ObjString, ObjStringBuilder, Pointer},
+    regex::Regex,
+    value::RuntimeValue,
+};
+
+pub fn clock(
+    _context: &mut dyn NativeContext,
+    _args: &[RuntimeValue],
+) -> Result<RuntimeValue, Error> {
+    Ok(SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("IVME: Failed to get system time")
+        .as_secs_f64()
+        .into())
+}
+
+/// Returns the caller's local variables as a list of `[name, value]` pairs,
+/// innermost last. Only available in code compiled with debug info.
+pub fn locals(
+    context: &mut dyn NativeContext,
+    _args: &[RuntimeValue],
+) -> Result<RuntimeValue, Error> {
+    let Some(locals) = context.caller_locals() else {
+        return Err(context.error("locals() requires debug info.\n".into()));
+    };
+    let mut result = context.new_list();
+    for (name, value) in locals {
+        let scope = context.open_scope();
+        let mut pair = context.new_list();
+        let name = context.new_string(name);
+        pair.items.extend([name.into(), value]);
+        result.items.push(pair.into());
+        context.close_scope(scope);
+    }
+    Ok(result.into())
+}
+
+/// Whether both arguments are the same value. Objects, including closures,
+/// are only the same as themselves: evaluating a function declaration twice
+/// creates two distinct closures.
+pub fn same(
+    _context: &mut dyn NativeContext,
+    args: &[RuntimeValue],
+) -> Result<RuntimeValue, Error> {
+    Ok((args[0] == args[1]).into())
+}
+
+/// Returns how many fields the instance `value` has.
+pub fn field_count(
+    context: &mut dyn NativeContext,
+    args: &[RuntimeValue],
+) -> Result<RuntimeValue, Error> {
+    let RuntimeValue::Instance(instance) = args[0] else {
+        return Err(context.error("fieldCount() expects an instance as its first argument.\n".into()));
+    };
+    Ok(RuntimeValue::Number(instance.fields.entries().count() as f64))
+}
+
+/// Returns how many bytes `value` takes up: for objects, what the object
+/// owns in the heap without the objects it refers to, and for booleans,
+/// numbers and `nil` the size of the value itself.
+pub fn size_of(
+    _context: &mut dyn NativeContext,
+    args: &[RuntimeValue],
+) -> Result<RuntimeValue, Error> {
+    let size = args[0]
+        .heap_size()
+        .unwrap_or(std::mem::size_of::<RuntimeValue>());
+    Ok(RuntimeValue::Number(size as f64))
+}
+
+/// Returns a number identifying the object `value`. It stays the same for as
+/// long as the object lives, so objects such as classes can key lookups.
+pub fn id(context: &mut dyn NativeContext, args: &[RuntimeValue]) -> Result<RuntimeValue, Error> {
+    let Some(id) = args[0].id() else {
+        return Err(context.error("id() expects an object as its first argument.\n".into()));
+    };
+    Ok(RuntimeValue::Number(id.get() as f64))
+}
+
+/// Calls `fn` with each item of `list`.
+pub fn for_each(
+    context: &mut dyn NativeContext,
+    args: &[RuntimeValue],
+) -> Result<RuntimeValue, Error> {
+    let list = list_argument(context, "forEach", args[0])?;
+    let mut index = 0;
+    // The callback may grow or shrink the list, so re-check the length on every step
+    while let Some(&item) = list.items.get(index) {
+        context.call(args[1], &[item])?;
+        index += 1;
+    }
+    Ok(RuntimeValue::Nil)
+}
+
+/// Returns a new list holding the result of calling `fn` with each item of `list`.
+pub fn map(context: &mut dyn NativeContext, args: &[RuntimeValue]) -> Result<RuntimeValue, Error> {
+    let list = list_argument(context, "map", args[0])?;
+    let mut result = context.new_list();
+    let mut index = 0;
+    while let Some(&item) = list.items.get(index) {
+        let value = context.call(args[1], &[item])?;
+        result.items.push(value);
+        index += 1;
+    }
+    Ok(result.into())
+}
+
+/// Returns a new list holding the items of `list` for which `fn` returns a truthy value.
+pub fn filter(
+    context: &mut dyn NativeContext,
+    args: &[RuntimeValue],
+) -> Result<RuntimeValue, Error> {
+    let list = list_argument(context, "filter", args[0])?;
+    let mut result = context.new_list();
+    let mut index = 0;
+    while let Some(&item) = list.items.get(index) {
+        if !context.call(args[1], &[item])?.is_falsey() {
+            result.items.push(item);
+        }
+        index += 1;
+    }
+    Ok(result.into())
+}
+
+/// Returns a new list holding the items of `list` in ascending order. Items
+/// must be all numbers or all strings. Equal items keep their order.
+pub fn sort(context: &mut dyn NativeContext, args: &[RuntimeValue]) -> Result<RuntimeValue, Error> {
+    let list = list_argument(context, "sort", args[0])?;
+    sorted(context, list, &mut |context, a, b| {
+        match (a, b) {
+            (RuntimeValue::String(a), RuntimeValue::String(b)) => return Ok(a.chars.cmp(&b.chars)),
+            (RuntimeValue::Int(a), RuntimeValue::Int(b)) => return Ok(a.cmp(&b)),
+            _ => {}
+        }
+        match (f64::try_from(a), f64::try_from(b)) {
+            (Ok(a), Ok(b)) => Ok(a.partial_cmp(&b).unwrap_or(Ordering::Equal)),
+            _ => {
+                Err(context.error("sort() can only compare two numbers or two strings.\n".into()))
+            }
+        }
+    })
+}
+
+/// Returns a new list holding the items of `list` ordered by `comparator`,
+/// which is called with two items and returns a negative number when the
+/// first goes before the second, a positive number when it goes after and
+/// zero when either order will do. Equal items keep their order.
+pub fn sort_by(
+    context: &mut dyn NativeContext,
+    args: &[RuntimeValue],
+) -> Result<RuntimeValue, Error> {
+    let list = list_argument(context, "sortBy", args[0])?;
+    let comparator = args[1];
+    sorted(context, list, &mut |context, a, b| {
+        let Ok(order) = f64::try_from(context.call(comparator, &[a, b])?) else {
+            return Err(context.error("sortBy() expects the comparator to return a number.\n".into()));
+        };
+        Ok(order.partial_cmp(&0.0).unwrap_or(Ordering::Equal))
+    })
+}
+
+type Comparator<'a> =
+    dyn FnMut(&mut dyn NativeContext, RuntimeValue, RuntimeValue) -> Result<Ordering, Error> + 'a;
+
+/// Sorts a copy of `list` into a new list, which holds every item for the
+/// whole sort so they stay reachable while `compare` runs Lox code.
+fn sorted(
+    context: &mut dyn NativeContext,
+    list: Pointer<ObjList>,
+    compare: &mut Comparator,
+) -> Result<RuntimeValue, Error> {
+    let mut result = context.new_list();
+    result.items.clone_from(&list.items);
+    let mut items = list.items.clone();
+    merge_sort(context, &mut items, compare)?;
+    result.items = items;
+    Ok(result.into())
+}
+
+/// A stable merge sort that stops at the first failed comparison. Unlike
+/// `slice::sort_by`, it doesn't panic when `compare` isn't a total order.
+fn merge_sort(
+    context: &mut dyn NativeContext,
+    items: &mut Vec<RuntimeValue>,
+    compare: &mut Comparator,
+) -> Result<(), Error> {
+    if items.len() <= 1 {
+        return Ok(());
+    }
+    let mut right = items.split_off(items.len() / 2);
+    merge_sort(context, items, compare)?;
+    merge_sort(context, &mut right, compare)?;
+
+    let mut left = std::mem::take(items).into_iter().peekable();
+    let mut right = right.into_iter().peekable();
+    while let (Some(&a), Some(&b)) = (left.peek(), right.peek()) {
+        // Only take from the right when strictly smaller, keeping the sort stable
+        if compare(context, b, a)? == Ordering::Less {
+            items.push(b);
+            right.next();
+        } else {
+            items.push(a);
+            left.next();
+        }
+    }
+    items.extend(left);
+    items.extend(right);
+    Ok(())
+}
+
+/// Returns a copy of a list, instance or string builder holding the same
+/// items, fields or text. Other values, including functions and classes, are
+/// returned as they are.
+pub fn copy(context: &mut dyn NativeContext, args: &[RuntimeValue]) -> Result<RuntimeValue, Error> {
+    Ok(match args[0] {
+        RuntimeValue::List(list) => {
+            let mut copy = context.new_list();
+            copy.items.clone_from(&list.items);
+            copy.into()
+        }
+        RuntimeValue::Instance(instance) => {
+            let mut copy = context.new_instance(instance.class);
+            for (name, &value) in instance.fields.entries() {
+                copy.fields.insert(name.clone(), value);
+            }
+            copy.into()
+        }
+        RuntimeValue::StringBuilder(builder) => {
+            let mut copy = context.new_string_builder();
+            copy.chars.clone_from(&builder.chars);
+            copy.into()
+        }
+        value => value,
+    })
+}
+
+/// Like `copy`, but also copies the lists and instances inside the value.
+/// Each is copied once, so shared references and cycles carry over to the
+/// copy instead of recursing forever.
+pub fn deep_copy(
+    context: &mut dyn NativeContext,
+    args: &[RuntimeValue],
+) -> Result<RuntimeValue, Error> {
+    let mut copies = HashMap::new();
+    // Copies whose contents are still to be filled in, worked through with
+    // a stack rather than recursion so long chains of lists can't overflow
+    let mut pending = Vec::new();
+    let copy = copy_once(context, args[0], &mut copies, &mut pending);
+    while let Some((original, copy)) = pending.pop() {
+        // Every copy made for an item is stored in `copy` right away, which
+        // keeps it reachable once the step's handles are released
+        let scope = context.open_scope();
+        match (original, copy) {
+            (RuntimeValue::List(original), RuntimeValue::List(mut copy)) => {
+                for index in 0..original.items.len() {
+                    let item = copy_once(context, original.items[index], &mut copies, &mut pending);
+                    copy.items.push(item);
+                }
+            }
+            (RuntimeValue::Instance(original), RuntimeValue::Instance(mut copy)) => {
+                let fields: Vec<_> = original
+                    .fields
+                    .entries()
+                    .map(|(name, &value)| (name.clone(), value))
+                    .collect();
+                for (name, value) in fields {
+                    let value = copy_once(context, value, &mut copies, &mut pending);
+                    copy.fields.insert(name, value);
+                }
+            }
+            _ => unreachable!("Only lists and instances are queued for copying."),
+        }
+        context.close_scope(scope);
+    }
+    Ok(copy)
+}
+
+/// The copy of `value`, allocating an empty one and queueing it to be filled
+/// in when `value` is a list or instance seen for the first time.
+fn copy_once(
+    context: &mut dyn NativeContext,
+    value: RuntimeValue,
+    copies: &mut HashMap<RuntimeValue, RuntimeValue>,
+    pending: &mut Vec<(RuntimeValue, RuntimeValue)>,
+) -> RuntimeValue {
+    if let Some(&copy) = copies.get(&value) {
+        return copy;
+    }
+    let copy = match value {
+        RuntimeValue::List(_) => context.new_list().into(),
+        RuntimeValue::Instance(instance) => context.new_instance(instance.class).into(),
+        // A builder holds no values, so its copy is complete right away
+        RuntimeValue::StringBuilder(builder) => {
+            let mut copy = context.new_string_builder();
+            copy.chars.clone_from(&builder.chars);
+            copies.insert(value, copy.into());
+            return copy.into();
+        }
+        value => return value,
+    };
+    copies.insert(value, copy);
+    pending.push((value, copy));
+    copy
+}
+
+/// Whether `collection` holds `value`. Ranges only hold the numbers they step through.
+pub fn contains(
+    context: &mut dyn NativeContext,
+    args: &[RuntimeValue],
+) -> Result<RuntimeValue, Error> {
+    match (args[0], args[1]) {
+        (RuntimeValue::Range(range), RuntimeValue::Number(n)) => Ok(range.contains(n).into()),
+        (RuntimeValue::Range(range), RuntimeValue::Int(n)) => Ok(range.contains(n as f64).into()),
+        (RuntimeValue::Range(_), _) => Ok(false.into()),
+        (RuntimeValue::List(list), value) => {
+            Ok(list.items.iter().any(|item| item.lox_eq(&value)).into())
+        }
+        _ => {
+            Err(context.error(
+                "contains() expects a list or a range as its first argument.\n".into(),
+            ))
+        }
+    }
+}
+
+/// Returns the leftmost part of `s` matching `pattern`, or `nil` if there is none.
+pub fn match_(
+    context: &mut dyn NativeContext,
+    args: &[RuntimeValue],
+) -> Result<RuntimeValue, Error> {
+    let string = string_argument(context, "match", args[0])?;
+    let regex = pattern_argument(context, "match", args[1])?;
+    match regex.find(&string.chars) {
+        Some(range) => Ok(context.new_string(string.chars[range].into()).into()),
+        None => Ok(RuntimeValue::Nil),
+    }
+}
+
+/// Returns `s` with every match of `pattern` replaced by `replacement`.
+pub fn replace(
+    context: &mut dyn NativeContext,
+    args: &[RuntimeValue],
+) -> Result<RuntimeValue, Error> {
+    let string = string_argument(context, "replace", args[0])?;
+    let regex = pattern_argument(context, "replace", args[1])?;
+    let RuntimeValue::String(replacement) = args[2] else {
+        return Err(context.error("replace() expects a string as its third argument.\n".into()));
+    };
+    let result = regex.replace_all(&string.chars, &replacement.chars);
+    Ok(context.new_string(result).into())
+}
+
+/// Returns a new, empty string builder, which `append` adds to and
+/// `toString` reads out. Building a string piece by piece this way takes
+/// time linear in its length, where repeated `+` copies it over and over.
+pub fn string_builder(
+    context: &mut dyn NativeContext,
+    _args: &[RuntimeValue],
+) -> Result<RuntimeValue, Error> {
+    Ok(context.new_string_builder().into())
+}
+
+/// Adds the string `piece` to the end of `builder`, returning `builder`.
+pub fn append(
+    context: &mut dyn NativeContext,
+    args: &[RuntimeValue],
+) -> Result<RuntimeValue, Error> {
+    let mut builder = builder_argument(context, "append", args[0])?;
+    let RuntimeValue::String(piece) = args[1] else {
+        return Err(context.error("append() expects a string as its second argument.\n".into()));
+    };
+    builder.chars.push_str(&piece.chars);
+    Ok(builder.into())
+}
+
+/// Returns the string `builder` has built so far.
+pub fn to_string(
+    context: &mut dyn NativeContext,
+    args: &[RuntimeValue],
+) -> Result<RuntimeValue, Error> {
+    let builder = builder_argument(context, "toString", args[0])?;
+    let chars = builder.chars.clone();
+    Ok(context.new_string(chars).into())
+}
+
+/// Writes out everything printed so far that the VM's output buffering is
+/// holding back.
+pub fn flush_out(
+    context: &mut dyn NativeContext,
+    _args: &[RuntimeValue],
+) -> Result<RuntimeValue, Error> {
+    context.flush_output();
+    Ok(RuntimeValue::Nil)
+}
+
+/// Pauses in the debugger attached to the VM, or does nothing when none is.
+pub fn breakpoint(
+    context: &mut dyn NativeContext,
+    _args: &[RuntimeValue],
+) -> Result<RuntimeValue, Error> {
+    context.breakpoint();
+    Ok(RuntimeValue::Nil)
+}
+
+/// Stops the script with a runtime error carrying `message`, reported with
+/// the line and backtrace of the call as the VM's own errors are.
+pub fn error(context: &mut dyn NativeContext, args: &[RuntimeValue]) -> Result<RuntimeValue, Error> {
+    let RuntimeValue::String(message) = args[0] else {
+        return Err(context.error("error() expects a string as its first argument.\n".into()));
+    };
+    Err(context.error(format!("{}\n", message.chars)))
+}
+
+/// Runs another file into the globals, returning whether it ran. Files that
+/// already ran, whether loaded or imported, are skipped.
+pub fn load(context: &mut dyn NativeContext, args: &[RuntimeValue]) -> Result<RuntimeValue, Error> {
+    let RuntimeValue::String(path) = args[0] else {
+        return Err(context.error("load() expects a string as its first argument.\n".into()));
+    };
+    let path = path.chars.to_string();
+    Ok(RuntimeValue::Bool(context.load(&path)?))
+}
+
+/// Runs Lox source, returning the value of its last statement when that's an
+/// expression, or nil. The optional second argument, false when left out,
+/// isolates it from the caller's globals, so that only the natives are
+/// defined while it runs and whatever it defines is dropped afterwards.
+pub fn eval(context: &mut dyn NativeContext, args: &[RuntimeValue]) -> Result<RuntimeValue, Error> {
+    let RuntimeValue::String(source) = args[0] else {
+        return Err(context.error("eval() expects a string as its first argument.\n".into()));
+    };
+    let isolated = match args.get(1) {
+        None => false,
+        Some(RuntimeValue::Bool(isolated)) => *isolated,
+        Some(_) => {
+            return Err(context.error("eval() expects a boolean as its second argument.\n".into()))
+        }
+    };
+    let source = source.chars.to_string();
+    context.eval(&source, isolated)
+}
+
+/// Returns the extensions to Lox as a list of `[name, enabled]` pairs, so
+/// scripts can check what the VM was configured to accept.
+pub fn features(
+    context: &mut dyn NativeContext,
+    _args: &[RuntimeValue],
+) -> Result<RuntimeValue, Error> {
+    let features = context.features();
+    let mut result = context.new_list();
+    for (feature, enabled) in features.extensions() {
+        let scope = context.open_scope();
+        let mut pair = context.new_list();
+        let name = context.new_string(feature.name.to_string());
+        pair.items.extend([name.into(), RuntimeValue::Bool(enabled)]);
+        result.items.push(pair.into());
+        context.close_scope(scope);
+    }
+    Ok(result.into())
+}
+
+fn string_argument(
+    context: &mut dyn NativeContext,
+    native: &str,
+    value: RuntimeValue,
+) -> Result<Pointer<ObjString>, Error> {
+    let RuntimeValue::String(string) = value else {
+        return Err(context.error(format!(
+            "{native}() expects a string as its first argument.\n"
+        )));
+    };
+    Ok(string)
+}
+
+fn pattern_argument(
+    context: &mut dyn NativeContext,
+    native: &str,
+    value: RuntimeValue,
+) -> Result<Regex, Error> {
+    let RuntimeValue::String(pattern) = value else {
+        return Err(context.error(format!(
+            "{native}() expects a string pattern as its second argument.\n"
+        )));
+    };
+    Regex::new(&pattern.chars)
+        .map_err(|e| context.error(format!("Invalid pattern '{}': {e}\n", pattern.chars)))
+}
+
+fn builder_argument(
+    context: &mut dyn NativeContext,
+    native: &str,
+    value: RuntimeValue,
+) -> Result<Pointer<ObjStringBuilder>, Error> {
+    let RuntimeValue::StringBuilder(builder) = value else {
+        return Err(context.error(format!(
+            "{native}() expects a string builder as its first argument.\n"
+        )));
+    };
+    Ok(builder)
+}
+
+fn list_argument(
+    context: &mut dyn NativeContext,
+    native: &str,
+    value: RuntimeValue,
+) -> Result<Pointer<ObjList>, Error> {
+    let RuntimeValue::List(list) = value else {
+        return Err(context.error(format!(
+            "{native}() expects a list as its first argument.\n"
+        )));
+    };
+    Ok(list)
+}
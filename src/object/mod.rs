@@ -3,11 +3,16 @@ pub mod obj_class;
 pub mod obj_closure;
 pub mod obj_function;
 pub mod obj_instance;
+pub mod obj_list;
 pub mod obj_native;
+pub mod obj_range;
 pub mod obj_string;
+pub mod obj_string_builder;
 pub mod obj_upvalue;
 pub mod object_store;
 pub mod store;
+#[cfg(feature = "track-allocations")]
+pub mod tracking;
 
 use std::hash::Hasher;
 
@@ -16,10 +21,13 @@ pub use obj_class::ObjClass;
 pub use obj_closure::ObjClosure;
 pub use obj_function::ObjFunction;
 pub use obj_instance::ObjInstance;
+pub use obj_list::ObjList;
 pub use obj_native::ObjNative;
+pub use obj_range::ObjRange;
 pub use obj_string::ObjString;
+pub use obj_string_builder::ObjStringBuilder;
 pub use obj_upvalue::ObjUpvalue;
-pub use object_store::{ObjectStore, Pointer};
+pub use object_store::{ObjectId, ObjectStore, Pointer};
 pub use store::Store;
 
 pub trait HeapSize {
@@ -28,6 +36,7 @@ pub trait HeapSize {
 }
 
 #[derive(Debug, Default, PartialEq, Eq)]
+#[cfg_attr(not(feature = "internals"), allow(dead_code))]
 pub struct ObjStringHasher(u64);
 
 impl Hasher for ObjStringHasher {
@@ -1,12 +1,134 @@
 use crate::{object::ObjString, table::Table};
-use std::fmt::Display;
+use std::{
+    fmt::{Display, Formatter, Result},
+    ops::Deref,
+    rc::Rc,
+};
 
 use super::{HeapSize, ObjClosure, Pointer};
 
 #[derive(Debug)]
 pub struct ObjClass {
     pub name: Pointer<ObjString>,
-    pub methods: Table<Pointer<ObjClosure>>,
+    pub methods: MethodTable,
+    /// Methods called on the class itself, which have no receiver
+    pub statics: MethodTable,
+    /// The class this one inherits from, whose methods were copied into it
+    pub superclass: Option<Pointer<ObjClass>>,
+}
+
+/// A class's methods by name. A subclass starts out sharing its superclass's
+/// table, and only copies it when it defines a method of its own, so classes
+/// that inherit without overriding anything cost no more than an empty one.
+#[derive(Debug, Default, Clone)]
+pub struct MethodTable(Rc<Table<Pointer<ObjClosure>>>);
+
+impl MethodTable {
+    /// Sets the method `name`, copying the table first if it's shared.
+    pub fn insert(&mut self, name: ObjString, method: Pointer<ObjClosure>) -> bool {
+        Rc::make_mut(&mut self.0).insert(name, method)
+    }
+
+    /// Takes on the methods of `superclass`'s table. An empty table, as a new
+    /// subclass's is, just shares it.
+    pub fn inherit(&mut self, superclass: &MethodTable) {
+        if self.is_empty() {
+            *self = superclass.clone();
+            return;
+        }
+        let table = Rc::make_mut(&mut self.0);
+        for (name, &method) in superclass.entries() {
+            table.insert(name.clone(), method);
+        }
+    }
+
+    /// Whether another class shares this table.
+    pub fn is_shared(&self) -> bool {
+        Rc::strong_count(&self.0) > 1
+    }
+}
+
+impl Deref for MethodTable {
+    type Target = Table<Pointer<ObjClosure>>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+/// Whether a field or method name is private, i.e. only accessible through `this`.
+pub fn is_private_member(name: &str) -> bool {
+    name.starts_with('_')
+}
+
+impl ObjClass {
+    /// The methods the class defines or overrides itself, sorted by name.
+    /// Methods inherited unchanged from the superclass are left out.
+    pub fn own_methods(&self) -> Vec<(&ObjString, Pointer<ObjClosure>)> {
+        self.own(&self.methods, |superclass| &superclass.methods)
+    }
+
+    /// The static methods the class defines or overrides itself, sorted by name.
+    pub fn own_statics(&self) -> Vec<(&ObjString, Pointer<ObjClosure>)> {
+        self.own(&self.statics, |superclass| &superclass.statics)
+    }
+
+    fn own<'a>(
+        &'a self,
+        table: &'a Table<Pointer<ObjClosure>>,
+        inherited: impl Fn(&ObjClass) -> &Table<Pointer<ObjClosure>>,
+    ) -> Vec<(&'a ObjString, Pointer<ObjClosure>)> {
+        let mut methods: Vec<_> = table
+            .entries()
+            .filter(|(name, method)| {
+                self.superclass
+                    .as_deref()
+                    .and_then(|superclass| inherited(superclass).get(name))
+                    != Some(method)
+            })
+            .map(|(name, &method)| (name, method))
+            .collect();
+        methods.sort_by(|(a, _), (b, _)| a.chars.cmp(&b.chars));
+        methods
+    }
+
+    /// Displays the class with the methods it defines, then each of its
+    /// superclasses in the same way.
+    pub fn verbose(&self) -> VerboseClass<'_> {
+        VerboseClass(self)
+    }
+}
+
+/// The display of [`ObjClass::verbose`], e.g.
+///
+/// ```text
+/// class Dog < Animal
+///   bark/0
+///   static create/1
+/// class Animal
+///   init/1
+/// ```
+pub struct VerboseClass<'a>(&'a ObjClass);
+
+impl Display for VerboseClass<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        let class = self.0;
+        write!(f, "class {}", class.name)?;
+        if let Some(superclass) = &class.superclass {
+            write!(f, " < {}", superclass.name)?;
+        }
+        let statics = class.own_statics();
+        let methods = class.own_methods().into_iter().map(|method| ("", method));
+        for (kind, (name, method)) in methods.chain(statics.into_iter().map(|m| ("static ", m))) {
+            let function = &method.function;
+            let rest = if function.variadic { "+" } else { "" };
+            write!(f, "\n  {kind}{name}/{}{rest}", function.arity)?;
+        }
+        match &class.superclass {
+            Some(superclass) => write!(f, "\n{}", superclass.verbose()),
+            None => Ok(()),
+        }
+    }
 }
 
 impl PartialEq for ObjClass {
@@ -17,12 +139,15 @@ impl PartialEq for ObjClass {
 
 impl HeapSize for ObjClass {
     fn size(&self) -> usize {
-        size_of::<Pointer<ObjString>>() + self.methods.size()
+        size_of::<Pointer<ObjString>>()
+            + self.methods.size()
+            + self.statics.size()
+            + size_of::<Option<Pointer<ObjClass>>>()
     }
 }
 
 impl Display for ObjClass {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
         write!(f, "{}", self.name)
     }
 }
@@ -16,8 +16,38 @@ impl HeapSize for ObjClosure {
     }
 }
 
+/// The alternate form (`{:#}`), used when tracing, also shows the arity and
+/// the number of captured upvalues.
 impl Display for ObjClosure {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.function)
+        match (&self.function.name, f.alternate()) {
+            (Some(name), true) => write!(
+                f,
+                "<fn {name} arity={} upvalues={}>",
+                self.function.arity,
+                self.upvalues.len()
+            ),
+            _ => write!(f, "{}", self.function),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{chunk::Chunk, object::Store};
+
+    use super::*;
+
+    #[test]
+    fn it_displays_a_closure() {
+        let mut store = Store::default();
+        let function = store.insert_function(ObjFunction::named("f", 2, Chunk::default()));
+        let upvalue = store.insert_upvalue(ObjUpvalue::Open { location: 0 });
+        let closure = ObjClosure {
+            function,
+            upvalues: vec![upvalue],
+        };
+        assert_eq!(format!("{closure}"), "<fn f>");
+        assert_eq!(format!("{closure:#}"), "<fn f arity=2 upvalues=1>");
     }
 }
@@ -3,25 +3,88 @@ use std::fmt::Display;
 
 use super::HeapSize;
 
-#[derive(Clone, Debug, Default, PartialEq)]
+/// A compiled function. Its chunk must end every path with a `Return`, which
+/// the VM verifies before running it, and `parameters` is either empty or
+/// names each of the `arity` fixed parameters.
+#[derive(Clone, Debug, PartialEq)]
 pub struct ObjFunction {
     pub arity: usize,
+    /// Whether extra arguments are collected into a trailing rest parameter
+    pub variadic: bool,
+    /// The names of the fixed parameters, used to resolve named arguments
+    pub parameters: Vec<String>,
     pub upvalue_count: usize,
     pub chunk: Chunk,
     pub name: Option<String>,
 }
 
+impl ObjFunction {
+    /// The top level of a script running `chunk`. Scripts have no name,
+    /// take no parameters and capture nothing.
+    pub fn script(chunk: Chunk) -> Self {
+        Self {
+            arity: 0,
+            variadic: false,
+            parameters: Vec::new(),
+            upvalue_count: 0,
+            chunk,
+            name: None,
+        }
+    }
+
+    /// The function `name` running `chunk`, taking `arity` parameters that
+    /// can only be passed by position until they're given names.
+    pub fn named(name: impl Into<String>, arity: usize, chunk: Chunk) -> Self {
+        Self {
+            arity,
+            name: Some(name.into()),
+            ..Self::script(chunk)
+        }
+    }
+
+    /// Names the parameters, in order, so they can be passed by name.
+    ///
+    /// # Panics
+    /// If there isn't exactly one name for each parameter.
+    pub fn with_parameters(mut self, names: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.parameters = names.into_iter().map(Into::into).collect();
+        assert_eq!(
+            self.parameters.len(),
+            self.arity,
+            "Every parameter needs exactly one name."
+        );
+        self
+    }
+
+    /// Has the function capture `count` upvalues when its closure is created.
+    pub fn with_upvalues(mut self, count: usize) -> Self {
+        self.upvalue_count = count;
+        self
+    }
+}
+
 impl HeapSize for ObjFunction {
     fn size(&self) -> usize {
         size_of::<usize>() * 2
+            + self.parameters.iter().map(String::len).sum::<usize>()
             + self.chunk.code.len()
             + self.chunk.lines.len() * size_of::<usize>()
+            + self.chunk.globals.iter().map(|name| name.chars.len()).sum::<usize>()
+            + self.chunk.global_slots.len() * size_of::<usize>()
+            + self
+                .chunk
+                .debug_info
+                .as_ref()
+                .map_or(0, |info| info.spans.len() * size_of::<(usize, usize)>())
             + self
                 .chunk
                 .constants
                 .iter()
                 .map(|x| match x {
+                    ConstantValue::Nil => 0,
+                    ConstantValue::Bool(_) => size_of::<bool>(),
                     ConstantValue::Number(_) => size_of::<f64>(),
+                    ConstantValue::Int(_) => size_of::<i64>(),
                     ConstantValue::String(s) => s.chars.len(),
                     ConstantValue::Function(obj_function) => obj_function.size(),
                 })
@@ -37,3 +100,30 @@ impl Display for ObjFunction {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_builds_functions() {
+        let script = ObjFunction::script(Chunk::default());
+        assert_eq!(script.to_string(), "<script>");
+        assert_eq!((script.arity, script.upvalue_count), (0, 0));
+
+        let function = ObjFunction::named("f", 2, Chunk::default())
+            .with_parameters(["a", "b"])
+            .with_upvalues(1);
+        assert_eq!(function.to_string(), "<fn f>");
+        assert_eq!(function.arity, 2);
+        assert_eq!(function.parameters, vec!["a", "b"]);
+        assert_eq!(function.upvalue_count, 1);
+        assert!(!function.variadic);
+    }
+
+    #[test]
+    #[should_panic(expected = "Every parameter needs exactly one name.")]
+    fn it_rejects_parameter_names_that_do_not_match_the_arity() {
+        let _ = ObjFunction::named("f", 2, Chunk::default()).with_parameters(["a"]);
+    }
+}
@@ -0,0 +1,95 @@
+use std::fmt::Display;
+
+use crate::value::RuntimeValue;
+
+use super::HeapSize;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ObjList {
+    pub items: Vec<RuntimeValue>,
+}
+
+impl HeapSize for ObjList {
+    fn size(&self) -> usize {
+        size_of_val(self) + self.items.capacity() * size_of::<RuntimeValue>()
+    }
+}
+
+/// Lists nested deeper than this print as `[...]`, as a list inside itself does.
+pub const MAX_PRINT_DEPTH: usize = 32;
+
+impl ObjList {
+    /// Writes the list, with `enclosing` holding the lists it's nested in.
+    fn write_nested(
+        &self,
+        f: &mut std::fmt::Formatter<'_>,
+        enclosing: &mut Vec<*const ObjList>,
+    ) -> std::fmt::Result {
+        if enclosing.len() >= MAX_PRINT_DEPTH || enclosing.contains(&(self as *const _)) {
+            return write!(f, "[...]");
+        }
+        enclosing.push(self);
+        write!(f, "[")?;
+        for (i, item) in self.items.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            match item {
+                RuntimeValue::List(list) => list.write_nested(f, enclosing)?,
+                item => write!(f, "{item}")?,
+            }
+        }
+        enclosing.pop();
+        write!(f, "]")
+    }
+}
+
+impl Display for ObjList {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.write_nested(f, &mut Vec::new())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::object::Store;
+
+    #[test]
+    fn it_displays_a_list() {
+        let list = ObjList {
+            items: vec![1.0.into(), true.into(), RuntimeValue::Nil],
+        };
+        assert_eq!(format!("{list}"), "[1, true, nil]");
+        assert_eq!(format!("{}", ObjList::default()), "[]");
+    }
+
+    #[test]
+    fn it_cuts_off_cycles_and_deep_nesting() {
+        let mut store = Store::default();
+        let mut list = store.insert_list(ObjList {
+            items: vec![1.0.into()],
+        });
+        let itself = list.into();
+        list.items.push(itself);
+        let shared = store.insert_list(ObjList::default());
+        list.items.push(
+            store
+                .insert_list(ObjList {
+                    items: vec![shared.into(), shared.into()],
+                })
+                .into(),
+        );
+        assert_eq!(format!("{}", *list), "[1, [...], [[], []]]");
+
+        let mut deep = store.insert_list(ObjList::default());
+        for _ in 0..MAX_PRINT_DEPTH {
+            deep = store.insert_list(ObjList {
+                items: vec![deep.into()],
+            });
+        }
+        let printed = format!("{}", *deep);
+        assert!(printed.starts_with(&"[".repeat(MAX_PRINT_DEPTH)));
+        assert!(printed.contains("[[...]]"));
+    }
+}
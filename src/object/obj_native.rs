@@ -1,13 +1,99 @@
-use crate::value::RuntimeValue;
+use crate::{error::Error, features::FeatureSet, value::RuntimeValue};
 use std::fmt::{Debug, Display};
 
-use super::HeapSize;
+use super::{HeapSize, ObjClass, ObjInstance, ObjList, ObjString, ObjStringBuilder, Pointer};
 
-pub type NativeFn = fn(&[RuntimeValue]) -> RuntimeValue;
+/// The services the VM offers to native functions.
+///
+/// A native's arguments stay reachable for the whole call. Anything it
+/// allocates through the context, or passes to [`NativeContext::root`], is a
+/// handle that stays reachable until the native returns or the
+/// [`HandleScope`] it was made in is closed. Any other object a native holds
+/// may be freed by the next allocation or call, so it must be rooted or
+/// stored in something reachable first.
+pub trait NativeContext {
+    /// Calls `callee` with `args`, running it to completion before returning its result.
+    fn call(&mut self, callee: RuntimeValue, args: &[RuntimeValue]) -> Result<RuntimeValue, Error>;
+    /// Allocates an empty list that stays reachable until the native returns.
+    fn new_list(&mut self) -> Pointer<ObjList>;
+    /// Allocates an instance of `class` with no fields that stays reachable
+    /// until the native returns.
+    fn new_instance(&mut self, class: Pointer<ObjClass>) -> Pointer<ObjInstance>;
+    /// Allocates a string that stays reachable until the native returns.
+    fn new_string(&mut self, chars: String) -> Pointer<ObjString>;
+    /// Allocates an empty string builder that stays reachable until the
+    /// native returns.
+    fn new_string_builder(&mut self) -> Pointer<ObjStringBuilder>;
+    /// Keeps `value` reachable until the native returns, such as the result
+    /// of a call it holds on to while allocating.
+    fn root(&mut self, value: RuntimeValue);
+    /// Starts a scope that handles made from now on belong to.
+    fn open_scope(&self) -> HandleScope;
+    /// Releases every handle made since `scope` was opened, along with the
+    /// scopes opened inside it. Objects allocated in the scope must be
+    /// stored in something reachable before it closes to outlive it.
+    fn close_scope(&mut self, scope: HandleScope);
+    /// The named locals in scope where the native was called from, in slot
+    /// order, or `None` when the calling code was compiled without debug info.
+    fn caller_locals(&self) -> Option<Vec<(String, RuntimeValue)>>;
+    /// The features enabled for scripts compiled by the VM.
+    fn features(&self) -> FeatureSet;
+    /// Writes out whatever printed output the VM is holding back.
+    fn flush_output(&mut self);
+    /// Runs the file at `path` into the globals unless it ran before,
+    /// returning whether it ran.
+    fn load(&mut self, path: &str) -> Result<bool, Error>;
+    /// Compiles and runs `source`, returning the value of its last statement
+    /// when that's an expression. Isolated source sees only the natives.
+    fn eval(&mut self, source: &str, isolated: bool) -> Result<RuntimeValue, Error>;
+    /// Pauses in the debugger attached to the VM, if any, at the place the
+    /// native was called from.
+    fn breakpoint(&mut self);
+    /// Reports a runtime error and unwinds the VM. Natives should return
+    /// `Err(Error::Runtime)` right after calling this.
+    fn runtime_error(&mut self, message: String);
+    /// Reports a runtime error, returning the error for the native to return.
+    fn error(&mut self, message: String) -> Error {
+        self.runtime_error(message);
+        Error::Runtime
+    }
+}
+
+/// Marks where a native's handles stood when the scope was opened, so that
+/// natives allocating in a loop can release each step's handles.
+#[derive(Debug, PartialEq, Eq)]
+#[must_use = "A scope releases nothing until it's closed"]
+pub struct HandleScope(pub(crate) usize);
 
-#[derive(Clone, Copy, PartialEq)]
+pub type NativeFn = fn(&mut dyn NativeContext, &[RuntimeValue]) -> Result<RuntimeValue, Error>;
+
+/// What a native runs when called.
+#[derive(Clone, Copy, Debug)]
+pub enum NativeFunction {
+    /// One of the VM's own natives, working on runtime values directly
+    Builtin(NativeFn),
+    /// The index of a function the embedder registered with the VM
+    Host(usize),
+}
+
+#[derive(Clone, Copy)]
 pub struct ObjNative {
-    pub function: NativeFn,
+    pub arity: usize,
+    /// How many of the last `arity` arguments callers may leave out
+    pub optional: usize,
+    pub function: NativeFunction,
+}
+
+impl PartialEq for ObjNative {
+    fn eq(&self, other: &Self) -> bool {
+        self.arity == other.arity
+            && self.optional == other.optional
+            && match (self.function, other.function) {
+                (NativeFunction::Builtin(a), NativeFunction::Builtin(b)) => std::ptr::fn_addr_eq(a, b),
+                (NativeFunction::Host(a), NativeFunction::Host(b)) => a == b,
+                _ => false,
+            }
+    }
 }
 
 impl HeapSize for ObjNative {
@@ -18,7 +104,11 @@ impl HeapSize for ObjNative {
 
 impl Debug for ObjNative {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "ObjNative {{ function: <native fn>}}",)
+        write!(
+            f,
+            "ObjNative {{ arity: {}, function: <native fn>}}",
+            self.arity
+        )
     }
 }
 
@@ -0,0 +1,97 @@
+use std::fmt::Display;
+
+use super::HeapSize;
+
+/// A range of numbers stepping by one, as produced by `start..end` or `start..=end`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ObjRange {
+    pub start: f64,
+    pub end: f64,
+    pub inclusive: bool,
+}
+
+impl ObjRange {
+    /// The `index`th number of the range, if the range reaches that far.
+    pub fn get(&self, index: usize) -> Option<f64> {
+        let value = self.start + index as f64;
+        let in_range = if self.inclusive {
+            value <= self.end
+        } else {
+            value < self.end
+        };
+        in_range.then_some(value)
+    }
+
+    pub fn contains(&self, value: f64) -> bool {
+        let offset = value - self.start;
+        offset >= 0.0 && offset.fract() == 0.0 && self.get(offset as usize).is_some()
+    }
+}
+
+impl HeapSize for ObjRange {
+    fn size(&self) -> usize {
+        size_of_val(self)
+    }
+}
+
+impl Display for ObjRange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.inclusive {
+            write!(f, "{}..={}", self.start, self.end)
+        } else {
+            write!(f, "{}..{}", self.start, self.end)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_steps_through_a_range() {
+        let range = ObjRange {
+            start: 1.0,
+            end: 3.0,
+            inclusive: false,
+        };
+        assert_eq!(range.get(0), Some(1.0));
+        assert_eq!(range.get(1), Some(2.0));
+        assert_eq!(range.get(2), None);
+        let range = ObjRange {
+            inclusive: true,
+            ..range
+        };
+        assert_eq!(range.get(2), Some(3.0));
+        assert_eq!(range.get(3), None);
+    }
+
+    #[test]
+    fn it_checks_membership() {
+        let range = ObjRange {
+            start: 0.0,
+            end: 10.0,
+            inclusive: false,
+        };
+        assert!(range.contains(0.0));
+        assert!(range.contains(9.0));
+        assert!(!range.contains(10.0));
+        assert!(!range.contains(-1.0));
+        assert!(!range.contains(2.5));
+    }
+
+    #[test]
+    fn it_displays_a_range() {
+        let range = ObjRange {
+            start: 1.0,
+            end: 10.0,
+            inclusive: false,
+        };
+        assert_eq!(format!("{range}"), "1..10");
+        let range = ObjRange {
+            inclusive: true,
+            ..range
+        };
+        assert_eq!(format!("{range}"), "1..=10");
+    }
+}
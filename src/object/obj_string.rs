@@ -1,13 +1,161 @@
-use std::{fmt::Display, hash::Hash};
+use std::{
+    fmt::{Debug, Display},
+    hash::Hash,
+    ops::Deref,
+};
 
 use super::HeapSize;
 
 #[derive(Clone, Debug, Default)]
 pub struct ObjString {
-    pub chars: String,
+    pub chars: SmallString,
     pub hash: u32,
 }
 
+/// How many bytes a [`SmallString`] holds without allocating. Most identifiers
+/// and short pieces of text fit.
+pub const INLINE_CAPACITY: usize = 22;
+
+/// Immutable text that is stored inline when it is short enough, so short
+/// strings don't need an allocation of their own. Derefs to `str`.
+///
+/// Only built from `str`s, so its bytes are always valid UTF-8.
+#[derive(Clone)]
+pub struct SmallString(Repr);
+
+#[derive(Clone)]
+enum Repr {
+    Inline {
+        len: u8,
+        bytes: [u8; INLINE_CAPACITY],
+    },
+    Heap(Box<str>),
+}
+
+impl SmallString {
+    pub fn as_str(&self) -> &str {
+        match &self.0 {
+            // Inline bytes are only ever copied whole from a `str`, and the
+            // representation is private so nothing else can write them
+            Repr::Inline { len, bytes } => unsafe {
+                str::from_utf8_unchecked(&bytes[..*len as usize])
+            },
+            Repr::Heap(chars) => chars,
+        }
+    }
+
+    /// `a` followed by `b`, only allocating if the result is too long to be
+    /// stored inline.
+    pub fn concat(a: &str, b: &str) -> Self {
+        let len = a.len() + b.len();
+        if len > INLINE_CAPACITY {
+            let mut chars = String::with_capacity(len);
+            chars.push_str(a);
+            chars.push_str(b);
+            return Self(Repr::Heap(chars.into_boxed_str()));
+        }
+        let mut bytes = [0; INLINE_CAPACITY];
+        bytes[..a.len()].copy_from_slice(a.as_bytes());
+        bytes[a.len()..len].copy_from_slice(b.as_bytes());
+        Self(Repr::Inline {
+            len: len as u8,
+            bytes,
+        })
+    }
+
+    /// The bytes allocated outside the string itself.
+    pub fn heap_size(&self) -> usize {
+        match &self.0 {
+            Repr::Inline { .. } => 0,
+            Repr::Heap(chars) => chars.len(),
+        }
+    }
+}
+
+impl Default for SmallString {
+    fn default() -> Self {
+        Self(Repr::Inline {
+            len: 0,
+            bytes: [0; INLINE_CAPACITY],
+        })
+    }
+}
+
+impl Deref for SmallString {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+impl From<&str> for SmallString {
+    fn from(value: &str) -> Self {
+        if value.len() > INLINE_CAPACITY {
+            return Self(Repr::Heap(value.into()));
+        }
+        let mut bytes = [0; INLINE_CAPACITY];
+        bytes[..value.len()].copy_from_slice(value.as_bytes());
+        Self(Repr::Inline {
+            len: value.len() as u8,
+            bytes,
+        })
+    }
+}
+
+impl From<String> for SmallString {
+    fn from(value: String) -> Self {
+        if value.len() > INLINE_CAPACITY {
+            return Self(Repr::Heap(value.into_boxed_str()));
+        }
+        value.as_str().into()
+    }
+}
+
+impl PartialEq for SmallString {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
+
+impl Eq for SmallString {}
+
+impl PartialEq<str> for SmallString {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl PartialEq<&str> for SmallString {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+impl PartialOrd for SmallString {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for SmallString {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.as_str().cmp(other.as_str())
+    }
+}
+
+impl Debug for SmallString {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl Display for SmallString {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
 impl Hash for ObjString {
     fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
         state.write_u32(self.hash);
@@ -26,7 +174,7 @@ impl Eq for ObjString {}
 
 impl HeapSize for ObjString {
     fn size(&self) -> usize {
-        self.chars.len() + size_of::<String>() + size_of::<u32>()
+        self.chars.heap_size() + size_of::<SmallString>() + size_of::<u32>()
     }
 }
 
@@ -43,7 +191,17 @@ impl From<&str> for ObjString {
 impl From<String> for ObjString {
     fn from(value: String) -> Self {
         let hash = hash_str(&value);
-        Self { chars: value, hash }
+        Self {
+            chars: value.into(),
+            hash,
+        }
+    }
+}
+
+impl From<SmallString> for ObjString {
+    fn from(chars: SmallString) -> Self {
+        let hash = hash_str(&chars);
+        Self { chars, hash }
     }
 }
 
@@ -53,7 +211,7 @@ impl Display for ObjString {
     }
 }
 
-fn hash_str(value: &str) -> u32 {
+pub(crate) fn hash_str(value: &str) -> u32 {
     let mut hash = 2166136261u32;
     for c in value.chars() {
         hash ^= c as u32;
@@ -62,3 +220,37 @@ fn hash_str(value: &str) -> u32 {
 
     hash
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_stores_short_strings_inline() {
+        let short = ObjString::from("a".repeat(INLINE_CAPACITY));
+        assert!(matches!(short.chars.0, Repr::Inline { .. }));
+        assert_eq!(short.chars.heap_size(), 0);
+        assert_eq!(short.chars, "a".repeat(INLINE_CAPACITY).as_str());
+
+        let long = ObjString::from("a".repeat(INLINE_CAPACITY + 1));
+        assert!(matches!(long.chars.0, Repr::Heap(_)));
+        assert_eq!(long.size(), short.size() + INLINE_CAPACITY + 1);
+        assert_eq!(size_of::<SmallString>(), size_of::<String>());
+    }
+
+    #[test]
+    fn it_hashes_and_compares_by_contents() {
+        let inline = ObjString::from("héllo");
+        let owned = ObjString::from(String::from("héllo"));
+        assert_eq!(inline, owned);
+        assert_eq!(inline.hash, owned.hash);
+        assert_eq!(&*inline.chars, "héllo");
+        assert!(ObjString::from("abc").chars < ObjString::from("abd").chars);
+        let short = SmallString::concat("key", "name");
+        assert!(matches!(short.0, Repr::Inline { .. }));
+        assert_eq!(ObjString::from(short), ObjString::from("keyname"));
+        let long = SmallString::concat(&"a".repeat(INLINE_CAPACITY), "b");
+        assert!(matches!(long.0, Repr::Heap(_)));
+        assert_eq!(long.len(), INLINE_CAPACITY + 1);
+    }
+}
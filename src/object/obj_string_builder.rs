@@ -0,0 +1,22 @@
+use std::fmt::Display;
+
+use super::HeapSize;
+
+/// A string under construction, which `append` grows in place so building a
+/// string piece by piece takes time linear in its length.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ObjStringBuilder {
+    pub chars: String,
+}
+
+impl HeapSize for ObjStringBuilder {
+    fn size(&self) -> usize {
+        size_of_val(self) + self.chars.capacity()
+    }
+}
+
+impl Display for ObjStringBuilder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<string builder>")
+    }
+}
@@ -7,11 +7,13 @@ use std::{
     ptr::NonNull,
 };
 
+#[cfg(feature = "track-allocations")]
+use super::tracking;
 use crate::{error::Error, value::RuntimeValue};
 
 use super::{
-    HeapSize, ObjBoundMethod, ObjClass, ObjClosure, ObjFunction, ObjInstance, ObjNative, ObjString,
-    ObjUpvalue,
+    HeapSize, ObjBoundMethod, ObjClass, ObjClosure, ObjFunction, ObjInstance, ObjList, ObjNative,
+    ObjRange, ObjString, ObjStringBuilder, ObjUpvalue,
 };
 
 #[derive(Default)]
@@ -30,6 +32,24 @@ impl Hasher for PointerHasher {
 #[derive(Debug)]
 pub struct Pointer<T>(NonNull<T>);
 
+/// The identity of a heap object, for keying objects by what they are rather
+/// than what they hold. Objects are pinned in their store and never move, so
+/// an object's id stays the same for as long as it lives.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ObjectId(usize);
+
+impl ObjectId {
+    pub fn get(self) -> usize {
+        self.0
+    }
+}
+
+impl<T> Pointer<T> {
+    pub fn id(&self) -> ObjectId {
+        ObjectId(self.0.as_ptr() as usize)
+    }
+}
+
 impl<T> Default for Pointer<T> {
     fn default() -> Self {
         Self(NonNull::dangling())
@@ -46,49 +66,67 @@ impl<T> Copy for Pointer<T> {}
 
 impl Display for Pointer<ObjBoundMethod> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", unsafe { self.0.as_ref() })
+        write!(f, "{}", &**self)
     }
 }
 
 impl Display for Pointer<ObjClass> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", unsafe { self.0.as_ref() })
+        write!(f, "{}", &**self)
     }
 }
 
 impl Display for Pointer<ObjClosure> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", unsafe { self.0.as_ref() })
+        Display::fmt(&**self, f)
     }
 }
 
 impl Display for Pointer<ObjFunction> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", unsafe { self.0.as_ref() })
+        write!(f, "{}", &**self)
     }
 }
 
 impl Display for Pointer<ObjInstance> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", unsafe { self.0.as_ref() })
+        write!(f, "{}", &**self)
+    }
+}
+
+impl Display for Pointer<ObjList> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", &**self)
     }
 }
 
 impl Display for Pointer<ObjNative> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", unsafe { self.0.as_ref() })
+        write!(f, "{}", &**self)
+    }
+}
+
+impl Display for Pointer<ObjRange> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", &**self)
     }
 }
 
 impl Display for Pointer<ObjString> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", unsafe { self.0.as_ref() })
+        write!(f, "{}", &**self)
+    }
+}
+
+impl Display for Pointer<ObjStringBuilder> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", &**self)
     }
 }
 
 impl Display for Pointer<ObjUpvalue> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", unsafe { self.0.as_ref() })
+        write!(f, "{}", &**self)
     }
 }
 
@@ -147,6 +185,17 @@ impl TryFrom<RuntimeValue> for Pointer<ObjInstance> {
     }
 }
 
+impl TryFrom<RuntimeValue> for Pointer<ObjList> {
+    type Error = Error;
+
+    fn try_from(value: RuntimeValue) -> Result<Self, Self::Error> {
+        match value {
+            RuntimeValue::List(pointer) => Ok(pointer),
+            _ => Err(Error::Runtime),
+        }
+    }
+}
+
 impl TryFrom<RuntimeValue> for Pointer<ObjNative> {
     type Error = Error;
 
@@ -158,6 +207,17 @@ impl TryFrom<RuntimeValue> for Pointer<ObjNative> {
     }
 }
 
+impl TryFrom<RuntimeValue> for Pointer<ObjRange> {
+    type Error = Error;
+
+    fn try_from(value: RuntimeValue) -> Result<Self, Self::Error> {
+        match value {
+            RuntimeValue::Range(pointer) => Ok(pointer),
+            _ => Err(Error::Runtime),
+        }
+    }
+}
+
 impl TryFrom<RuntimeValue> for Pointer<ObjString> {
     type Error = Error;
 
@@ -169,16 +229,31 @@ impl TryFrom<RuntimeValue> for Pointer<ObjString> {
     }
 }
 
+impl TryFrom<RuntimeValue> for Pointer<ObjStringBuilder> {
+    type Error = Error;
+
+    fn try_from(value: RuntimeValue) -> Result<Self, Self::Error> {
+        match value {
+            RuntimeValue::StringBuilder(pointer) => Ok(pointer),
+            _ => Err(Error::Runtime),
+        }
+    }
+}
+
 impl<T> Deref for Pointer<T> {
     type Target = T;
 
     fn deref(&self) -> &Self::Target {
+        #[cfg(feature = "track-allocations")]
+        tracking::accessed(self.id().get());
         unsafe { self.0.as_ref() }
     }
 }
 
 impl<T> DerefMut for Pointer<T> {
     fn deref_mut(&mut self) -> &mut Self::Target {
+        #[cfg(feature = "track-allocations")]
+        tracking::accessed(self.id().get());
         unsafe { self.0.as_mut() }
     }
 }
@@ -193,7 +268,7 @@ impl<T> Eq for Pointer<T> {}
 
 impl<T> Hash for Pointer<T> {
     fn hash<H: Hasher>(&self, state: &mut H) {
-        self.0.as_ptr().hash(state)
+        self.id().hash(state)
     }
 }
 
@@ -206,6 +281,10 @@ impl<T> HeapSize for Pointer<T> {
 #[derive(Debug)]
 pub struct ObjectStore<T> {
     map: HashMap<NonNull<T>, Pin<Box<T>>, BuildHasherDefault<PointerHasher>>,
+    /// Freed objects, kept so pointers that outlive them still read valid
+    /// memory and can be caught
+    #[cfg(feature = "track-allocations")]
+    quarantine: Vec<Pin<Box<T>>>,
 }
 
 impl<T: Debug + HeapSize> ObjectStore<T> {
@@ -213,6 +292,8 @@ impl<T: Debug + HeapSize> ObjectStore<T> {
         let value_box = Box::pin(value);
         let value_ptr = NonNull::from(&*value_box);
         self.map.insert(value_ptr, value_box);
+        #[cfg(feature = "track-allocations")]
+        tracking::allocated::<T>(value_ptr.as_ptr() as usize);
         Pointer(value_ptr)
     }
 
@@ -220,13 +301,20 @@ impl<T: Debug + HeapSize> ObjectStore<T> {
         let Some(o) = self.map.remove(&key.0) else {
             return 0;
         };
-        o.size()
+        let size = o.size();
+        #[cfg(feature = "track-allocations")]
+        {
+            tracking::freed(key.id().get());
+            self.quarantine.push(o);
+        }
+        size
     }
 
     pub fn keys(&self) -> Vec<Pointer<T>> {
         self.map.keys().map(|x| Pointer(*x)).collect()
     }
 
+    #[cfg_attr(not(feature = "internals"), allow(dead_code))]
     pub fn contains_key(&self, key: &Pointer<T>) -> bool {
         self.map.contains_key(&key.0)
     }
@@ -236,13 +324,43 @@ impl<T> Default for ObjectStore<T> {
     fn default() -> Self {
         Self {
             map: HashMap::<NonNull<T>, Pin<Box<T>>, BuildHasherDefault<PointerHasher>>::default(),
+            #[cfg(feature = "track-allocations")]
+            quarantine: Vec::new(),
         }
     }
 }
 
+#[cfg(feature = "track-allocations")]
+impl<T> Drop for ObjectStore<T> {
+    fn drop(&mut self) {
+        let live = self.map.keys().map(|pointer| pointer.as_ptr() as usize);
+        let freed = self.quarantine.iter().map(|o| &**o as *const T as usize);
+        tracking::forget(live.chain(freed));
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::object::obj_string::SmallString;
+
+    #[test]
+    fn it_identifies_objects_for_as_long_as_they_live() {
+        let mut strings = ObjectStore::<ObjString>::default();
+        let a = strings.insert("same".into());
+        let b = strings.insert("same".into());
+        assert_eq!(*a, *b);
+        assert_ne!(a.id(), b.id());
+        assert_eq!(RuntimeValue::from(a).id(), Some(a.id()));
+        assert_eq!(RuntimeValue::Number(1.0).id(), None);
+        // Growing the store doesn't move what is already in it
+        for i in 0..1000 {
+            strings.insert(i.to_string().into());
+        }
+        assert!(strings.contains_key(&a));
+        assert_eq!(a.chars, "same");
+        assert_eq!(a.id(), RuntimeValue::from(a).id().unwrap());
+    }
 
     #[test]
     fn it_inserts_and_retrieves_strings() {
@@ -259,7 +377,7 @@ mod test {
         let value = "test string value".into();
         let mut value_ref = value_store.insert(value);
         {
-            value_ref.chars += " mutated";
+            *value_ref = "test string value mutated".into();
         }
         let retrieved_value = &value_ref.chars;
         assert_eq!(retrieved_value, "test string value mutated");
@@ -273,9 +391,10 @@ mod test {
         let freed_bytes = value_store.free(value_ref);
         let retrieved_value = value_store.map.get(&value_ref.0);
         assert!(retrieved_value.is_none());
+        // Short enough to be stored inline
         assert_eq!(
             freed_bytes,
-            "test string value".to_owned().len() + size_of::<String>() + size_of::<u32>()
+            size_of::<SmallString>() + size_of::<u32>()
         );
     }
 }
@@ -2,13 +2,23 @@ use std::{
     array,
     collections::{BTreeMap, HashSet},
     fmt::Debug,
+    time::Instant,
 };
 
-use crate::{call_frame::CallFrame, table::Table, value::RuntimeValue, vm::MAX_FRAMES};
+#[cfg(feature = "metrics")]
+use crate::metrics::Metrics;
+use crate::{
+    call_frame::CallFrame,
+    stats::{Collection, ObjectCounts, Stats},
+    table::{Globals, Table},
+    trace::{TraceEvent, Tracer},
+    value::{ConstantValue, RuntimeValue},
+    vm::MAX_FRAMES,
+};
 
 use super::{
-    HeapSize, ObjBoundMethod, ObjClass, ObjClosure, ObjFunction, ObjInstance, ObjNative, ObjString,
-    ObjUpvalue, ObjectStore, Pointer,
+    HeapSize, ObjBoundMethod, ObjClass, ObjClosure, ObjFunction, ObjInstance, ObjList, ObjNative,
+    ObjRange, ObjString, ObjStringBuilder, ObjUpvalue, ObjectId, ObjectStore, Pointer,
 };
 
 const GC_HEAP_GROW_FACTOR: usize = 2;
@@ -21,16 +31,27 @@ pub struct Store {
     pub closure_store: ObjectStore<ObjClosure>,
     pub function_store: ObjectStore<ObjFunction>,
     pub instance_store: ObjectStore<ObjInstance>,
+    pub list_store: ObjectStore<ObjList>,
     pub native_store: ObjectStore<ObjNative>,
+    pub range_store: ObjectStore<ObjRange>,
     pub string_store: ObjectStore<ObjString>,
+    pub string_builder_store: ObjectStore<ObjStringBuilder>,
     pub upvalue_store: ObjectStore<ObjUpvalue>,
     pub value_stack: Vec<RuntimeValue>,
     pub frame_stack: [CallFrame; MAX_FRAMES],
     pub frame_stack_top: usize,
     pub open_upvalues: BTreeMap<usize, Pointer<ObjUpvalue>>,
-    pub globals: Table<RuntimeValue>,
+    pub globals: Globals,
+    /// Every live string, so equal strings share one object. Entries are weak:
+    /// they don't keep strings alive and are dropped when a string is swept.
+    strings: Table<Pointer<ObjString>>,
+    #[cfg(feature = "metrics")]
+    pub metrics: Metrics,
+    pub stats: Stats,
+    /// Where trace events go, to stderr as text in the `debug` build
+    pub(crate) tracer: Tracer,
     bytes_allocated: usize,
-    next_gc: usize,
+    pub(crate) next_gc: usize,
 }
 
 impl Default for Store {
@@ -41,15 +62,23 @@ impl Default for Store {
             closure_store: ObjectStore::<ObjClosure>::default(),
             function_store: ObjectStore::<ObjFunction>::default(),
             instance_store: ObjectStore::<ObjInstance>::default(),
+            list_store: ObjectStore::<ObjList>::default(),
             native_store: ObjectStore::<ObjNative>::default(),
+            range_store: ObjectStore::<ObjRange>::default(),
             string_store: ObjectStore::<ObjString>::default(),
+            string_builder_store: ObjectStore::<ObjStringBuilder>::default(),
             upvalue_store: ObjectStore::<ObjUpvalue>::default(),
-            globals: Table::default(),
+            globals: Globals::default(),
+            strings: Table::default(),
             value_stack: Vec::with_capacity(MAX_STACK_SIZE),
             frame_stack: array::from_fn(|_| CallFrame::default()),
             frame_stack_top: 0,
             open_upvalues: BTreeMap::default(),
             next_gc: 1024 * 1024,
+            #[cfg(feature = "metrics")]
+            metrics: Metrics::default(),
+            stats: Stats::default(),
+            tracer: Tracer::default(),
             bytes_allocated: 0,
         }
     }
@@ -57,89 +86,175 @@ impl Default for Store {
 
 impl Store {
     pub fn insert_bound_method(&mut self, bound_method: ObjBoundMethod) -> Pointer<ObjBoundMethod> {
-        self.bytes_allocated += bound_method.size();
-        self.collect_garbage();
-        self.bound_method_store.insert(bound_method)
+        let size = bound_method.size();
+        self.stats.allocations.bound_methods += 1;
+        let pointer = self.bound_method_store.insert(bound_method);
+        self.allocate("bound method", size, pointer.into());
+        pointer
     }
 
     pub fn insert_class(&mut self, class: ObjClass) -> Pointer<ObjClass> {
-        self.bytes_allocated += class.size();
-        self.collect_garbage();
-        self.class_store.insert(class)
+        let size = class.size();
+        self.stats.allocations.classes += 1;
+        let pointer = self.class_store.insert(class);
+        self.allocate("class", size, pointer.into());
+        pointer
     }
 
     pub fn insert_closure(&mut self, closure: ObjClosure) -> Pointer<ObjClosure> {
-        self.bytes_allocated += closure.size();
-        self.collect_garbage();
-        self.closure_store.insert(closure)
+        let size = closure.size();
+        self.stats.allocations.closures += 1;
+        let pointer = self.closure_store.insert(closure);
+        self.allocate("closure", size, pointer.into());
+        pointer
     }
 
     pub fn insert_function(&mut self, function: ObjFunction) -> Pointer<ObjFunction> {
-        self.bytes_allocated += function.size();
-        self.collect_garbage();
-        self.function_store.insert(function)
+        let size = function.size();
+        self.stats.allocations.functions += 1;
+        let pointer = self.function_store.insert(function);
+        self.allocate("function", size, pointer.into());
+        pointer
     }
 
     pub fn insert_instance(&mut self, instance: ObjInstance) -> Pointer<ObjInstance> {
-        self.bytes_allocated += instance.size();
-        self.collect_garbage();
-        self.instance_store.insert(instance)
+        let size = instance.size();
+        self.stats.allocations.instances += 1;
+        let pointer = self.instance_store.insert(instance);
+        self.allocate("instance", size, pointer.into());
+        pointer
+    }
+
+    pub fn insert_list(&mut self, list: ObjList) -> Pointer<ObjList> {
+        let size = list.size();
+        self.stats.allocations.lists += 1;
+        let pointer = self.list_store.insert(list);
+        self.allocate("list", size, pointer.into());
+        pointer
     }
 
     pub fn insert_native(&mut self, native: ObjNative) -> Pointer<ObjNative> {
-        self.bytes_allocated += native.size();
-        self.collect_garbage();
-        self.native_store.insert(native)
+        let size = native.size();
+        self.stats.allocations.natives += 1;
+        let pointer = self.native_store.insert(native);
+        self.allocate("native", size, pointer.into());
+        pointer
+    }
+
+    pub fn insert_range(&mut self, range: ObjRange) -> Pointer<ObjRange> {
+        let size = range.size();
+        self.stats.allocations.ranges += 1;
+        let pointer = self.range_store.insert(range);
+        self.allocate("range", size, pointer.into());
+        pointer
     }
 
+    /// Returns the interned copy of `string`, allocating it only when new.
     pub fn insert_string(&mut self, string: ObjString) -> Pointer<ObjString> {
-        self.bytes_allocated += string.size();
-        self.collect_garbage();
-        self.string_store.insert(string)
+        if let Some(key) = self.strings.find_string(&string.chars, string.hash) {
+            if let Some(&interned) = self.strings.get(key) {
+                return interned;
+            }
+        }
+        let size = string.size();
+        self.stats.allocations.strings += 1;
+        let pointer = self.string_store.insert(string.clone());
+        self.strings.insert(string, pointer);
+        self.allocate("string", size, pointer.into());
+        pointer
+    }
+
+    pub fn insert_string_builder(
+        &mut self,
+        builder: ObjStringBuilder,
+    ) -> Pointer<ObjStringBuilder> {
+        let size = builder.size();
+        self.stats.allocations.string_builders += 1;
+        let pointer = self.string_builder_store.insert(builder);
+        self.allocate("string builder", size, pointer.into());
+        pointer
     }
 
     pub fn insert_upvalue(&mut self, upvalue: ObjUpvalue) -> Pointer<ObjUpvalue> {
-        self.bytes_allocated += upvalue.size();
+        let size = upvalue.size();
+        self.stats.allocations.upvalues += 1;
+        let pointer = self.upvalue_store.insert(upvalue);
+        self.allocate("upvalue", size, pointer.into());
+        pointer
+    }
+
+    /// Materializes a chunk constant as a runtime value. The constant stays
+    /// owned by its chunk; strings and functions are copied onto the heap.
+    pub fn load_constant(&mut self, constant: &ConstantValue) -> RuntimeValue {
+        match constant {
+            ConstantValue::Nil => RuntimeValue::Nil,
+            ConstantValue::Bool(b) => RuntimeValue::Bool(*b),
+            ConstantValue::Number(n) => RuntimeValue::Number(*n),
+            ConstantValue::Int(n) => RuntimeValue::Int(*n),
+            ConstantValue::String(s) => {
+                #[cfg(feature = "metrics")]
+                self.metrics.record_clone();
+                self.insert_string(s.clone()).into()
+            }
+            ConstantValue::Function(f) => {
+                #[cfg(feature = "metrics")]
+                self.metrics.record_clone();
+                self.insert_function(*f.clone()).into()
+            }
+        }
+    }
+
+    /// Charges the heap for `object`, just inserted, and collects garbage if
+    /// it's grown enough. The object is kept on the stack through the
+    /// collection, as nothing else can reach it yet, and neither can the
+    /// objects it references, which the caller may have just allocated too.
+    fn allocate(&mut self, kind: &'static str, size: usize, object: RuntimeValue) {
+        #[cfg(feature = "metrics")]
+        self.metrics.record_allocation();
+        self.bytes_allocated += size;
+        self.tracer.emit(TraceEvent::Alloc { kind, size });
+        self.value_stack.push(object);
         self.collect_garbage();
-        self.upvalue_store.insert(upvalue)
+        self.value_stack.pop();
     }
 
     fn collect_garbage(&mut self) {
         if self.bytes_allocated <= self.next_gc {
             return;
         }
-        #[cfg(feature = "debug")]
-        {
-            println!("-- gc begin");
-        }
-        #[cfg(feature = "debug")]
         let before = self.bytes_allocated;
+        self.tracer.emit(TraceEvent::GcStart {
+            bytes_allocated: before,
+        });
 
-        #[allow(clippy::mutable_key_type)]
-        let mut reachable_objects = HashSet::<RuntimeValue>::new();
+        let mut collection = Collection::default();
+        let mut reachable_objects = HashSet::<ObjectId>::new();
         let mut tracing_stack = Vec::<RuntimeValue>::new();
+        let start = Instant::now();
         self.mark_roots(&mut reachable_objects, &mut tracing_stack);
-        self.trace_references(&mut reachable_objects, tracing_stack);
-        self.sweep(reachable_objects);
+        let roots_marked = Instant::now();
+        let live = self.trace_references(&mut reachable_objects, tracing_stack, &mut collection.visited);
+        let traced = Instant::now();
+        self.sweep(reachable_objects, &mut collection.freed);
+        // Objects grow after they're charged for, as classes gain methods and
+        // instances fields, so the heap is measured afresh from what survived
+        self.bytes_allocated = live;
+        collection.mark_roots = roots_marked - start;
+        collection.trace_references = traced - roots_marked;
+        collection.sweep = traced.elapsed();
+        self.stats.gc.record(collection);
         self.next_gc = self.bytes_allocated * GC_HEAP_GROW_FACTOR;
 
-        #[cfg(feature = "debug")]
-        {
-            println!("-- gc end");
-            println!(
-                "collected {} bytes (from {} to {}) next at {}",
-                before - self.bytes_allocated,
-                before,
-                self.bytes_allocated,
-                self.next_gc
-            );
-        }
+        self.tracer.emit(TraceEvent::GcEnd {
+            before,
+            after: self.bytes_allocated,
+            next_gc: self.next_gc,
+        });
     }
 
-    #[allow(clippy::mutable_key_type)]
     fn mark_roots(
         &self,
-        reachable_objects: &mut HashSet<RuntimeValue>,
+        reachable_objects: &mut HashSet<ObjectId>,
         tracing_stack: &mut Vec<RuntimeValue>,
     ) {
         for value in &self.value_stack {
@@ -153,123 +268,150 @@ impl Store {
             mark_value(*upvalue, reachable_objects, tracing_stack);
         }
 
-        for value in self.globals.values() {
+        for value in self.globals.roots() {
             mark_value(*value, reachable_objects, tracing_stack);
         }
     }
 
-    #[allow(clippy::mutable_key_type)]
+    /// Marks everything reachable from the marked objects on `tracing_stack`,
+    /// counting each object in `visited` as it is traced. Returns the size of
+    /// everything marked.
     fn trace_references(
         &self,
-        reachable_objects: &mut HashSet<RuntimeValue>,
+        reachable_objects: &mut HashSet<ObjectId>,
         mut tracing_stack: Vec<RuntimeValue>,
-    ) {
+        visited: &mut ObjectCounts,
+    ) -> usize {
+        let mut live = 0;
         while let Some(value) = tracing_stack.pop() {
-            match value {
-                RuntimeValue::BoundMethod(pointer) => {
-                    let receiver = pointer.receiver;
-                    mark_value(receiver, reachable_objects, &mut tracing_stack);
-                    let method = pointer.method;
-                    mark_value(method, reachable_objects, &mut tracing_stack);
-                }
-                RuntimeValue::Class(pointer) => {
-                    let name = pointer.name;
-                    mark_value(name, reachable_objects, &mut tracing_stack);
-                    for method in pointer.methods.values() {
-                        mark_value(*method, reachable_objects, &mut tracing_stack);
-                    }
-                }
-                RuntimeValue::Closure(pointer) => {
-                    let function = pointer.function;
-                    mark_value(function, reachable_objects, &mut tracing_stack);
-
-                    for upvalue in pointer.upvalues.iter() {
-                        mark_value(*upvalue, reachable_objects, &mut tracing_stack);
-                    }
-                }
-                RuntimeValue::Instance(pointer) => {
-                    let class = pointer.class;
-                    mark_value(class, reachable_objects, &mut tracing_stack);
-                    for field in pointer.fields.values() {
-                        mark_value(*field, reachable_objects, &mut tracing_stack);
-                    }
-                }
-                RuntimeValue::Upvalue(pointer) => {
-                    if let ObjUpvalue::Closed { value } = &*pointer {
-                        mark_value(*value, reachable_objects, &mut tracing_stack);
-                    }
-                }
-                _ => continue,
+            visited.count(&value);
+            live += value.heap_size().unwrap_or_default();
+            for_each_reference(value, |reference| {
+                mark_value(reference, reachable_objects, &mut tracing_stack)
+            });
+        }
+        live
+    }
+
+    /// Frees every unreachable object, counting them by store in `freed`.
+    fn sweep(&mut self, reachable_objects: HashSet<ObjectId>, freed: &mut ObjectCounts) {
+        for string in self.string_store.keys() {
+            if !reachable_objects.contains(&string.id()) {
+                self.strings.remove(&string);
             }
         }
+        let reachable = &reachable_objects;
+        sweep_store(&mut self.bound_method_store, reachable, &mut freed.bound_methods);
+        sweep_store(&mut self.class_store, reachable, &mut freed.classes);
+        sweep_store(&mut self.closure_store, reachable, &mut freed.closures);
+        sweep_store(&mut self.function_store, reachable, &mut freed.functions);
+        sweep_store(&mut self.instance_store, reachable, &mut freed.instances);
+        sweep_store(&mut self.list_store, reachable, &mut freed.lists);
+        sweep_store(&mut self.native_store, reachable, &mut freed.natives);
+        sweep_store(&mut self.range_store, reachable, &mut freed.ranges);
+        sweep_store(&mut self.string_store, reachable, &mut freed.strings);
+        sweep_store(
+            &mut self.string_builder_store,
+            reachable,
+            &mut freed.string_builders,
+        );
+        sweep_store(&mut self.upvalue_store, reachable, &mut freed.upvalues);
     }
+}
 
-    #[allow(clippy::mutable_key_type)]
-    fn sweep(&mut self, reachable_objects: HashSet<RuntimeValue>) {
-        self.bytes_allocated -= sweep_store(&mut self.bound_method_store, &reachable_objects)
-            + sweep_store(&mut self.class_store, &reachable_objects)
-            + sweep_store(&mut self.closure_store, &reachable_objects)
-            + sweep_store(&mut self.function_store, &reachable_objects)
-            + sweep_store(&mut self.instance_store, &reachable_objects)
-            + sweep_store(&mut self.native_store, &reachable_objects)
-            + sweep_store(&mut self.string_store, &reachable_objects)
-            + sweep_store(&mut self.upvalue_store, &reachable_objects);
+/// Calls `visit` with every object `value` holds a reference to.
+pub fn for_each_reference(value: RuntimeValue, mut visit: impl FnMut(RuntimeValue)) {
+    match value {
+        RuntimeValue::BoundMethod(pointer) => {
+            visit(pointer.receiver);
+            visit(pointer.method.into());
+        }
+        RuntimeValue::Class(pointer) => {
+            visit(pointer.name.into());
+            if let Some(superclass) = pointer.superclass {
+                visit(superclass.into());
+            }
+            let statics = pointer.statics.values();
+            for method in pointer.methods.values().into_iter().chain(statics) {
+                visit((*method).into());
+            }
+        }
+        RuntimeValue::Closure(pointer) => {
+            visit(pointer.function.into());
+            for upvalue in pointer.upvalues.iter() {
+                visit((*upvalue).into());
+            }
+        }
+        RuntimeValue::Instance(pointer) => {
+            visit(pointer.class.into());
+            for field in pointer.fields.values() {
+                visit(*field);
+            }
+        }
+        RuntimeValue::List(pointer) => {
+            for item in pointer.items.iter() {
+                visit(*item);
+            }
+        }
+        RuntimeValue::Upvalue(pointer) => {
+            if let ObjUpvalue::Closed { value } = &*pointer {
+                visit(*value);
+            }
+        }
+        _ => {}
     }
 }
 
-#[allow(clippy::mutable_key_type)]
+/// Marks `value` reachable and queues it for tracing, unless it isn't an
+/// object or was marked already.
 fn mark_value(
     value: impl Into<RuntimeValue>,
-    reachable_objects: &mut HashSet<RuntimeValue>,
+    reachable_objects: &mut HashSet<ObjectId>,
     tracing_stack: &mut Vec<RuntimeValue>,
 ) {
     let rv = value.into();
-    if reachable_objects.contains(&rv) {
-        return;
+    if rv.id().is_some_and(|id| reachable_objects.insert(id)) {
+        tracing_stack.push(rv);
     }
-    reachable_objects.insert(rv);
-    tracing_stack.push(rv);
 }
 
-#[allow(clippy::mutable_key_type)]
+/// Frees the objects of `store` that aren't reachable, adding how many to
+/// `freed`.
 fn sweep_store<T: Debug + HeapSize>(
     store: &mut ObjectStore<T>,
-    reachable_objects: &HashSet<RuntimeValue>,
-) -> usize
-where
-    RuntimeValue: From<Pointer<T>>,
-{
-    let mut bytes_freed = 0;
+    reachable_objects: &HashSet<ObjectId>,
+    freed: &mut usize,
+) {
     let mut objects_to_free = Vec::new();
     let keys = store.keys();
     for key in keys {
-        if !reachable_objects.contains(&key.into()) {
+        if !reachable_objects.contains(&key.id()) {
             objects_to_free.push(key);
         }
     }
 
+    *freed += objects_to_free.len();
     for key in objects_to_free {
-        bytes_freed += store.free(key);
+        store.free(key);
     }
-    bytes_freed
 }
 
 #[cfg(test)]
 mod test {
-    use crate::{chunk::Chunk, table::Table};
+    use crate::{chunk::Chunk, object::obj_class::MethodTable, table::Table};
 
     use super::*;
 
     #[test]
     fn it_runs_the_garbage_collector_strings() {
         let mut store = Store::default();
-        let string: ObjString = "test string".into();
-        let string_size = string.size();
+        let string_size = ObjString::from("test string 00").size();
         let mut allocated_size = 0;
         let mut next_gc = 128;
         store.next_gc = next_gc;
-        for _ in 0..100 {
-            let pointer = store.insert_string(string.clone());
+        for i in 0..100 {
+            // Distinct strings, as equal ones are interned
+            let pointer = store.insert_string(format!("test string {i:02}").into());
             allocated_size += string_size;
             if allocated_size > next_gc {
                 allocated_size = string_size;
@@ -281,6 +423,58 @@ mod test {
         }
     }
 
+    #[test]
+    fn it_loads_constants_onto_the_heap() {
+        let mut store = Store::default();
+        assert_eq!(
+            store.load_constant(&ConstantValue::Number(1.5)),
+            RuntimeValue::Number(1.5)
+        );
+
+        let constant = ConstantValue::from("a");
+        let RuntimeValue::String(string) = store.load_constant(&constant) else {
+            panic!("Expected a string");
+        };
+        assert_eq!(string.chars, "a");
+        assert_eq!(store.load_constant(&constant), string.into());
+
+        let function = ObjFunction::named("f", 0, Chunk::default());
+        let RuntimeValue::Function(pointer) = store.load_constant(&function.clone().into()) else {
+            panic!("Expected a function");
+        };
+        assert_eq!(*pointer, function);
+    }
+
+    #[test]
+    fn it_interns_strings() {
+        let mut store = Store::default();
+        let first = store.insert_string("interned".into());
+        let allocated = store.bytes_allocated;
+        let second = store.insert_string("interned".into());
+        let other = store.insert_string("other".into());
+        assert_eq!(first, second);
+        assert_ne!(first, other);
+        assert_eq!(
+            store.bytes_allocated,
+            allocated + ObjString::from("other").size()
+        );
+    }
+
+    #[test]
+    fn it_forgets_swept_strings() {
+        let mut store = Store::default();
+        let pointer = store.insert_string("swept".into());
+        store.next_gc = 0;
+        store.collect_garbage();
+        assert!(!store.string_store.contains_key(&pointer));
+        let hash = ObjString::from("swept").hash;
+        assert!(store.strings.find_string("swept", hash).is_none());
+
+        let pointer = store.insert_string("swept".into());
+        assert!(store.string_store.contains_key(&pointer));
+        assert_eq!(store.insert_string("swept".into()), pointer);
+    }
+
     #[test]
     fn it_preserves_values_on_the_stack() {
         let mut store = Store::default();
@@ -295,6 +489,25 @@ mod test {
         assert!(!store.string_store.contains_key(&pointer_to_remove));
     }
 
+    #[test]
+    fn it_records_each_collection() {
+        let mut store = Store::default();
+        let pointer = store.insert_string("kept".into());
+        store.insert_string("freed".into());
+        store.insert_list(ObjList::default());
+        store.value_stack.push(pointer.into());
+        store.next_gc = 0;
+        store.collect_garbage();
+        let gc = &store.stats.gc;
+        assert_eq!(gc.collections, 1);
+        let last = gc.last.as_ref().expect("Expected a collection");
+        assert_eq!(last.visited.total(), 1);
+        assert_eq!(last.visited.strings, 1);
+        assert_eq!(last.freed.strings, 1);
+        assert_eq!(last.freed.lists, 1);
+        assert_eq!(&gc.total, last);
+    }
+
     #[test]
     fn it_preserves_globals() {
         let mut store = Store::default();
@@ -306,6 +519,17 @@ mod test {
         assert!(store.string_store.contains_key(&pointer));
     }
 
+    #[test]
+    fn it_preserves_hidden_globals() {
+        let mut store = Store::default();
+        let pointer = store.insert_string("should be preserved".into());
+        store.globals.insert("a".into(), pointer.into());
+        store.globals.isolate(|_| false);
+        store.next_gc = 0;
+        store.collect_garbage();
+        assert!(store.string_store.contains_key(&pointer));
+    }
+
     #[test]
     fn it_preserves_upvalues() {
         let mut store = Store::default();
@@ -322,10 +546,61 @@ mod test {
         assert!(store.upvalue_store.contains_key(&upvalue_pointer));
     }
 
+    #[test]
+    fn it_preserves_what_a_new_object_references() {
+        let mut store = Store::default();
+        let name = store.insert_string("A".into());
+        // The class's allocation collects before anything else can reach it
+        store.next_gc = 0;
+        let class = store.insert_class(ObjClass {
+            name,
+            methods: MethodTable::default(),
+            statics: MethodTable::default(),
+            superclass: None,
+        });
+        assert!(store.class_store.contains_key(&class));
+        assert!(store.string_store.contains_key(&name));
+        assert_eq!(store.bytes_allocated, ObjClass::size(&class) + ObjString::size(&name));
+    }
+
+    #[test]
+    fn it_measures_objects_that_grew_since_they_were_allocated() {
+        let mut store = Store::default();
+        let name = store.insert_string("A".into());
+        let mut class = store.insert_class(ObjClass {
+            name,
+            methods: MethodTable::default(),
+            statics: MethodTable::default(),
+            superclass: None,
+        });
+        let function = store.insert_function(ObjFunction::script(Chunk::default()));
+        let method = store.insert_closure(ObjClosure {
+            function,
+            upvalues: Vec::new(),
+        });
+        for i in 0..32 {
+            class.methods.insert(format!("m{i}").into(), method);
+        }
+        store.value_stack.push(class.into());
+        store.next_gc = 0;
+        store.collect_garbage();
+        let live = ObjClass::size(&class)
+            + ObjString::size(&name)
+            + ObjClosure::size(&method)
+            + ObjFunction::size(&function);
+        assert_eq!(store.bytes_allocated, live);
+
+        // Freeing the grown class must not take more than the heap holds
+        store.value_stack.clear();
+        store.next_gc = 0;
+        store.collect_garbage();
+        assert_eq!(store.bytes_allocated, 0);
+    }
+
     #[test]
     fn it_preserves_call_frame_values() {
         let mut store = Store::default();
-        let function = ObjFunction::default();
+        let function = ObjFunction::script(Chunk::default());
         let function_pointer = store.insert_function(function);
         let closure = ObjClosure {
             function: function_pointer,
@@ -336,7 +611,6 @@ mod test {
             closure: closure_pointer,
             chunk: &function_pointer.chunk as *const Chunk,
             ip: 0,
-            slots: 0,
             start_stack_index: 0,
         };
         store.frame_stack_top += 1;
@@ -349,7 +623,7 @@ mod test {
     #[test]
     fn it_traces_bound_methods() {
         let mut store = Store::default();
-        let function = ObjFunction::default();
+        let function = ObjFunction::script(Chunk::default());
         let function_pointer = store.insert_function(function);
         let closure = ObjClosure {
             function: function_pointer,
@@ -378,18 +652,20 @@ mod test {
 
         let class_name = "TestClass".into();
         let class_name_pointer = store.insert_string(class_name);
-        let function = ObjFunction::default();
+        let function = ObjFunction::script(Chunk::default());
         let function_pointer = store.insert_function(function);
         let closure = ObjClosure {
             function: function_pointer,
             upvalues: Vec::new(),
         };
         let closure_pointer = store.insert_closure(closure);
-        let mut methods = Table::default();
+        let mut methods = MethodTable::default();
         methods.insert(init_string, closure_pointer);
         let class = ObjClass {
             name: class_name_pointer,
             methods,
+            statics: MethodTable::default(),
+            superclass: None,
         };
         let class_pointer = store.insert_class(class);
         store
@@ -406,7 +682,7 @@ mod test {
     #[test]
     fn it_traces_closures() {
         let mut store = Store::default();
-        let function = ObjFunction::default();
+        let function = ObjFunction::script(Chunk::default());
         let function_pointer = store.insert_function(function);
         let upvalue = ObjUpvalue::Open { location: 2 };
         let upvalue_pointer = store.insert_upvalue(upvalue);
@@ -431,18 +707,20 @@ mod test {
         let init_string = ObjString::from("init");
         let class_name = "TestClass".into();
         let class_name_pointer = store.insert_string(class_name);
-        let function = ObjFunction::default();
+        let function = ObjFunction::script(Chunk::default());
         let function_pointer = store.insert_function(function);
         let closure = ObjClosure {
             function: function_pointer,
             upvalues: Vec::new(),
         };
         let closure_pointer = store.insert_closure(closure);
-        let mut methods = Table::default();
+        let mut methods = MethodTable::default();
         methods.insert(init_string, closure_pointer);
         let class = ObjClass {
             name: class_name_pointer,
             methods,
+            statics: MethodTable::default(),
+            superclass: None,
         };
         let class_pointer = store.insert_class(class);
         let mut fields = Table::default();
@@ -464,4 +742,20 @@ mod test {
         assert!(store.class_store.contains_key(&class_pointer));
         assert!(store.instance_store.contains_key(&instance_pointer));
     }
+
+    #[test]
+    fn it_traces_lists() {
+        let mut store = Store::default();
+        let string = "should be preserved".into();
+        let string_pointer = store.insert_string(string);
+        let list = ObjList {
+            items: vec![string_pointer.into()],
+        };
+        let list_pointer = store.insert_list(list);
+        store.globals.insert("list".into(), list_pointer.into());
+        store.next_gc = 0;
+        store.collect_garbage();
+        assert!(store.string_store.contains_key(&string_pointer));
+        assert!(store.list_store.contains_key(&list_pointer));
+    }
 }
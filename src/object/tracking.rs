@@ -0,0 +1,197 @@
+//! Dangling pointer detection, built with the `track-allocations` feature.
+//!
+//! Every object remembers the instruction that allocated it. The collector
+//! quarantines the objects it frees rather than dropping them, so a pointer
+//! that outlived its object still reads valid memory, and the access is noted
+//! against the instruction that made it. The VM checks for such an access
+//! after each instruction and stops with a fault naming where the object was
+//! allocated, freed and accessed, rather than carrying on through freed
+//! memory.
+
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fmt::{self, Display},
+    rc::Rc,
+};
+
+use super::ObjFunction;
+
+/// An instruction the machine executed: which, in which function and on
+/// which line.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Site {
+    pub instruction: &'static str,
+    pub function: Rc<str>,
+    pub line: usize,
+}
+
+/// Displays as `by OP_CLASS in <fn f> on line 3`, or `outside any
+/// instruction` for what the host did before or between runs.
+impl Display for Site {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.instruction.is_empty() {
+            return write!(f, "outside any instruction");
+        }
+        write!(
+            f,
+            "by {} in {} on line {}",
+            self.instruction, self.function, self.line
+        )
+    }
+}
+
+/// An access through a pointer to an object the collector had freed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DanglingAccess {
+    /// The type of the object, e.g. `ObjClass`
+    pub kind: &'static str,
+    pub allocated: Site,
+    /// The instruction whose allocation collected the object
+    pub freed: Site,
+    pub accessed: Site,
+}
+
+impl Display for DanglingAccess {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Dangling pointer to {} allocated {}, freed {}, accessed {}.",
+            self.kind, self.allocated, self.freed, self.accessed
+        )
+    }
+}
+
+#[derive(Debug)]
+struct Object {
+    kind: &'static str,
+    allocated: Site,
+    freed: Option<Site>,
+}
+
+#[derive(Debug, Default)]
+struct Tracker {
+    site: Site,
+    /// The address of the function `site` is in, so its name is only
+    /// formatted when the machine enters another function
+    function: usize,
+    /// Objects by address, live or quarantined
+    objects: HashMap<usize, Object>,
+    /// The first dangling access since the last check
+    dangling: Option<DanglingAccess>,
+}
+
+thread_local! {
+    static TRACKER: RefCell<Tracker> = RefCell::default();
+}
+
+/// Notes that `instruction` on `line` of `function` is executing.
+pub fn enter(instruction: &'static str, line: usize, function: &ObjFunction) {
+    let address = function as *const ObjFunction as usize;
+    let entered = TRACKER.with_borrow_mut(|tracker| {
+        tracker.site.instruction = instruction;
+        tracker.site.line = line;
+        std::mem::replace(&mut tracker.function, address) != address
+    });
+    if entered {
+        let name = Rc::from(function.to_string());
+        TRACKER.with_borrow_mut(|tracker| tracker.site.function = name);
+    }
+}
+
+/// Notes that no instruction is executing, as a run has ended.
+pub fn leave() {
+    TRACKER.with_borrow_mut(|tracker| tracker.site.instruction = "");
+}
+
+/// Notes that the object at `address`, of type `T`, was just allocated.
+pub fn allocated<T>(address: usize) {
+    let kind = std::any::type_name::<T>().rsplit("::").next().unwrap_or_default();
+    TRACKER.with_borrow_mut(|tracker| {
+        let allocated = tracker.site.clone();
+        tracker.objects.insert(
+            address,
+            Object {
+                kind,
+                allocated,
+                freed: None,
+            },
+        );
+    });
+}
+
+/// Notes that the collector freed the object at `address`.
+pub fn freed(address: usize) {
+    TRACKER.with_borrow_mut(|tracker| {
+        let site = tracker.site.clone();
+        if let Some(object) = tracker.objects.get_mut(&address) {
+            object.freed = Some(site);
+        }
+    });
+}
+
+/// Notes that the object at `address` was read or written, remembering the
+/// access if the object was freed.
+pub fn accessed(address: usize) {
+    TRACKER.with_borrow_mut(|tracker| {
+        if tracker.dangling.is_some() {
+            return;
+        }
+        let Some(object) = tracker.objects.get(&address) else {
+            return;
+        };
+        let Some(freed) = &object.freed else {
+            return;
+        };
+        tracker.dangling = Some(DanglingAccess {
+            kind: object.kind,
+            allocated: object.allocated.clone(),
+            freed: freed.clone(),
+            accessed: tracker.site.clone(),
+        });
+    });
+}
+
+/// Forgets the objects at `addresses`, as their memory is given back and
+/// may be reused.
+pub fn forget(addresses: impl Iterator<Item = usize>) {
+    // The tracker may be gone already if a store outlives its thread's locals
+    let _ = TRACKER.try_with(|tracker| {
+        let mut tracker = tracker.borrow_mut();
+        for address in addresses {
+            tracker.objects.remove(&address);
+        }
+    });
+}
+
+/// The first dangling access since the last call, if any.
+pub fn take_dangling() -> Option<DanglingAccess> {
+    TRACKER.with_borrow_mut(|tracker| tracker.dangling.take())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::object::{ObjString, ObjectStore};
+
+    #[test]
+    fn it_notes_the_first_access_to_a_freed_object() {
+        let mut strings = ObjectStore::<ObjString>::default();
+        let live = strings.insert("live".into());
+        let freed = strings.insert("freed".into());
+        strings.free(freed);
+        assert_eq!(live.chars, "live");
+        assert_eq!(take_dangling(), None);
+
+        // Quarantined, so still readable
+        assert_eq!(freed.chars, "freed");
+        let access = take_dangling().expect("Expected a dangling access");
+        assert_eq!(access.kind, "ObjString");
+        assert_eq!(access.accessed, Site::default());
+        assert_eq!(
+            access.to_string(),
+            "Dangling pointer to ObjString allocated outside any instruction, freed outside any instruction, accessed outside any instruction."
+        );
+        assert_eq!(take_dangling(), None);
+    }
+}
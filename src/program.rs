@@ -0,0 +1,24 @@
+//! Scripts compiled ahead of time, to be run later.
+
+use crate::{
+    compiler::{CompileOptions, Compiler},
+    error::Error,
+    object::ObjFunction,
+};
+
+/// A compiled script. Compiling needs no VM, so a program can be compiled on
+/// one thread and run on another, as many times and on as many VMs as
+/// needed. Each run sees the globals of the VM running it.
+#[derive(Debug, Clone)]
+pub struct Program {
+    pub(crate) function: ObjFunction,
+}
+
+impl Program {
+    /// Compiles `source` as configured by `options`. Compile errors are
+    /// reported on stderr.
+    pub fn compile(source: &str, options: &CompileOptions) -> Result<Self, Error> {
+        let function = Compiler::with_options(source, options).compile()?;
+        Ok(Self { function })
+    }
+}
@@ -0,0 +1,325 @@
+//! A small backtracking regular expression engine backing the `match` and
+//! `replace` natives.
+//!
+//! Supported syntax: literal characters, `.`, the escapes `\d`, `\w` and
+//! `\s`, character classes such as `[a-z_]` or `[^0-9]`, the quantifiers
+//! `*`, `+` and `?`, and the anchors `^` and `$`. Any other escaped
+//! character matches itself.
+
+use std::{fmt::Display, ops::Range};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegexError(String);
+
+impl Display for RegexError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for RegexError {}
+
+#[derive(Debug, Clone, PartialEq)]
+enum ClassItem {
+    Char(char),
+    Range(char, char),
+    Atom(Atom),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Atom {
+    Any,
+    Char(char),
+    Digit,
+    Word,
+    Space,
+    Class {
+        items: Vec<ClassItem>,
+        negated: bool,
+    },
+    End,
+}
+
+impl Atom {
+    fn matches(&self, c: char) -> bool {
+        match self {
+            Atom::Any => true,
+            Atom::Char(expected) => c == *expected,
+            Atom::Digit => c.is_ascii_digit(),
+            Atom::Word => c.is_alphanumeric() || c == '_',
+            Atom::Space => c.is_whitespace(),
+            Atom::Class { items, negated } => {
+                let found = items.iter().any(|item| match item {
+                    ClassItem::Char(expected) => c == *expected,
+                    ClassItem::Range(low, high) => (*low..=*high).contains(&c),
+                    ClassItem::Atom(atom) => atom.matches(c),
+                });
+                found != *negated
+            }
+            Atom::End => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Quantifier {
+    One,
+    ZeroOrOne,
+    ZeroOrMore,
+    OneOrMore,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Node {
+    atom: Atom,
+    quantifier: Quantifier,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Regex {
+    nodes: Vec<Node>,
+    anchored: bool,
+}
+
+impl Regex {
+    pub fn new(pattern: &str) -> Result<Self, RegexError> {
+        let mut chars = pattern.chars().peekable();
+        let anchored = chars.next_if_eq(&'^').is_some();
+        let mut nodes: Vec<Node> = vec![];
+        while let Some(c) = chars.next() {
+            let atom = match c {
+                '.' => Atom::Any,
+                '$' if chars.peek().is_none() => Atom::End,
+                '\\' => {
+                    let escaped = chars
+                        .next()
+                        .ok_or_else(|| RegexError("Trailing backslash.".into()))?;
+                    escape(escaped)
+                }
+                '[' => {
+                    let negated = chars.next_if_eq(&'^').is_some();
+                    let mut items = vec![];
+                    loop {
+                        let c = chars
+                            .next()
+                            .ok_or_else(|| RegexError("Unterminated character class.".into()))?;
+                        let item = match c {
+                            ']' if !items.is_empty() => break,
+                            '\\' => {
+                                let escaped = chars.next().ok_or_else(|| {
+                                    RegexError("Unterminated character class.".into())
+                                })?;
+                                match escape(escaped) {
+                                    Atom::Char(c) => ClassItem::Char(c),
+                                    atom => ClassItem::Atom(atom),
+                                }
+                            }
+                            low => match chars.next_if_eq(&'-') {
+                                Some(_) => match chars.next_if(|&high| high != ']') {
+                                    Some(high) if low <= high => ClassItem::Range(low, high),
+                                    Some(high) => {
+                                        return Err(RegexError(format!(
+                                            "Invalid range '{low}-{high}' in character class."
+                                        )))
+                                    }
+                                    None => {
+                                        // A trailing '-' is literal
+                                        items.push(ClassItem::Char(low));
+                                        ClassItem::Char('-')
+                                    }
+                                },
+                                None => ClassItem::Char(low),
+                            },
+                        };
+                        items.push(item);
+                    }
+                    Atom::Class { items, negated }
+                }
+                '*' | '+' | '?' => {
+                    return Err(RegexError(format!("Nothing to repeat before '{c}'.")));
+                }
+                c => Atom::Char(c),
+            };
+            let quantifier = match chars.next_if(|&c| c == '*' || c == '+' || c == '?') {
+                Some('*') => Quantifier::ZeroOrMore,
+                Some('+') => Quantifier::OneOrMore,
+                Some('?') => Quantifier::ZeroOrOne,
+                _ => Quantifier::One,
+            };
+            if atom == Atom::End && quantifier != Quantifier::One {
+                return Err(RegexError("Can't repeat the '$' anchor.".into()));
+            }
+            nodes.push(Node { atom, quantifier });
+        }
+        Ok(Self { nodes, anchored })
+    }
+
+    /// The byte range of the leftmost match in `text`.
+    pub fn find(&self, text: &str) -> Option<Range<usize>> {
+        let chars: Vec<char> = text.chars().collect();
+        self.find_chars(&chars, 0)
+            .map(|range| byte_offset(text, range.start)..byte_offset(text, range.end))
+    }
+
+    /// Replaces every non-overlapping match in `text` with `replacement`.
+    pub fn replace_all(&self, text: &str, replacement: &str) -> String {
+        let chars: Vec<char> = text.chars().collect();
+        let mut result = String::new();
+        let mut position = 0;
+        while let Some(range) = self.find_chars(&chars, position) {
+            result.extend(&chars[position..range.start]);
+            result.push_str(replacement);
+            if range.is_empty() {
+                // Step past empty matches so they aren't found again
+                if let Some(&c) = chars.get(range.end) {
+                    result.push(c);
+                }
+                position = range.end + 1;
+            } else {
+                position = range.end;
+            }
+            if position > chars.len() || self.anchored {
+                break;
+            }
+        }
+        if position < chars.len() {
+            result.extend(&chars[position..]);
+        }
+        result
+    }
+
+    fn find_chars(&self, chars: &[char], from: usize) -> Option<Range<usize>> {
+        if self.anchored {
+            if from != 0 {
+                return None;
+            }
+            return match_here(&self.nodes, chars, 0).map(|end| 0..end);
+        }
+        (from..=chars.len())
+            .find_map(|start| match_here(&self.nodes, chars, start).map(|end| start..end))
+    }
+}
+
+fn escape(c: char) -> Atom {
+    match c {
+        'd' => Atom::Digit,
+        'w' => Atom::Word,
+        's' => Atom::Space,
+        c => Atom::Char(c),
+    }
+}
+
+fn byte_offset(text: &str, char_index: usize) -> usize {
+    text.char_indices()
+        .nth(char_index)
+        .map_or(text.len(), |(offset, _)| offset)
+}
+
+/// Matches `nodes` against `chars` starting at `position`, returning where the match ends.
+fn match_here(nodes: &[Node], chars: &[char], position: usize) -> Option<usize> {
+    let Some((node, rest)) = nodes.split_first() else {
+        return Some(position);
+    };
+    if node.atom == Atom::End {
+        return (position == chars.len()).then_some(position);
+    }
+    let matches_at = |i: usize| chars.get(i).is_some_and(|&c| node.atom.matches(c));
+    let (min, max) = match node.quantifier {
+        Quantifier::One => (1, 1),
+        Quantifier::ZeroOrOne => (0, 1),
+        Quantifier::ZeroOrMore => (0, usize::MAX),
+        Quantifier::OneOrMore => (1, usize::MAX),
+    };
+    let mut count = 0;
+    while count < max && matches_at(position + count) {
+        count += 1;
+    }
+    if count < min {
+        return None;
+    }
+    // Quantifiers are greedy, so give back characters one at a time
+    (min..=count)
+        .rev()
+        .find_map(|taken| match_here(rest, chars, position + taken))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn find<'a>(pattern: &str, text: &'a str) -> Option<&'a str> {
+        let regex = Regex::new(pattern).expect("Failed to parse pattern");
+        regex.find(text).map(|range| &text[range])
+    }
+
+    #[test]
+    fn it_matches_literals() {
+        assert_eq!(find("abc", "xxabcxx"), Some("abc"));
+        assert_eq!(find("abd", "xxabcxx"), None);
+        assert_eq!(find("", "abc"), Some(""));
+    }
+
+    #[test]
+    fn it_matches_any_and_escapes() {
+        assert_eq!(find("a.c", "abc"), Some("abc"));
+        assert_eq!(find(r"a\.c", "abc a.c"), Some("a.c"));
+        assert_eq!(find(r"\d\d", "a12b"), Some("12"));
+        assert_eq!(find(r"\w+", "  hi_there!"), Some("hi_there"));
+        assert_eq!(find(r"\s", "a b"), Some(" "));
+    }
+
+    #[test]
+    fn it_matches_classes() {
+        assert_eq!(find("[a-c]+", "xxbcaxx"), Some("bca"));
+        assert_eq!(find("[^a-c]+", "abxyc"), Some("xy"));
+        assert_eq!(find(r"[\d_]+", "a1_2b"), Some("1_2"));
+        assert_eq!(find("[a-]+", "x-a-x"), Some("-a-"));
+        assert_eq!(find("[]]", "a]"), Some("]"));
+    }
+
+    #[test]
+    fn it_matches_quantifiers() {
+        assert_eq!(find("ab*c", "ac"), Some("ac"));
+        assert_eq!(find("ab*c", "abbbc"), Some("abbbc"));
+        assert_eq!(find("ab+c", "ac"), None);
+        assert_eq!(find("ab+c", "abbc"), Some("abbc"));
+        assert_eq!(find("colou?r", "color"), Some("color"));
+        assert_eq!(find("colou?r", "colour"), Some("colour"));
+        assert_eq!(find("a.*b", "a1b2b3"), Some("a1b2b"));
+    }
+
+    #[test]
+    fn it_matches_anchors() {
+        assert_eq!(find("^ab", "abab"), Some("ab"));
+        assert_eq!(find("^b", "ab"), None);
+        assert_eq!(find("b$", "abab"), Some("b"));
+        assert_eq!(find("a$", "ab"), None);
+        assert_eq!(find("^$", ""), Some(""));
+        assert_eq!(find("a^", "a^"), Some("a^"));
+    }
+
+    #[test]
+    fn it_matches_multibyte_text() {
+        assert_eq!(find("é+", "caféé!"), Some("éé"));
+        assert_eq!(find("f.!", "fé!"), Some("fé!"));
+    }
+
+    #[test]
+    fn it_replaces_all_matches() {
+        let regex = Regex::new(r"\d+").unwrap();
+        assert_eq!(regex.replace_all("a1b22c", "#"), "a#b#c");
+        let regex = Regex::new("x*").unwrap();
+        assert_eq!(regex.replace_all("ab", "-"), "-a-b-");
+        let regex = Regex::new("^a").unwrap();
+        assert_eq!(regex.replace_all("aaa", "b"), "baa");
+    }
+
+    #[test]
+    fn it_rejects_invalid_patterns() {
+        assert!(Regex::new("*a").is_err());
+        assert!(Regex::new("a**").is_err());
+        assert!(Regex::new("[abc").is_err());
+        assert!(Regex::new("[z-a]").is_err());
+        assert!(Regex::new("a\\").is_err());
+    }
+}
@@ -0,0 +1,281 @@
+//! Support for the interactive prompt.
+
+use std::{io::Write, path::Path};
+
+use crate::{error::Error, vm::VM};
+
+/// The personal prelude loaded from the home directory when a session starts.
+pub const RC_FILE: &str = ".loxiderc.lox";
+
+/// Asks the terminal to mark what's pasted with [`PASTE_START`] and
+/// [`PASTE_END`].
+pub const ENABLE_BRACKETED_PASTE: &str = "\x1b[?2004h";
+pub const DISABLE_BRACKETED_PASTE: &str = "\x1b[?2004l";
+pub const PASTE_START: &str = "\x1b[200~";
+pub const PASTE_END: &str = "\x1b[201~";
+
+/// The lines read at the prompt for one entry, gathered until they make
+/// something to run, so definitions typed or pasted over several lines run
+/// whole rather than failing at their first line.
+///
+/// An entry is complete once it compiles, or fails to for a reason other than
+/// ending too soon. A blank line ends an entry regardless, so a mistake that
+/// leaves it open can still be run and reported. Pasted text, when the
+/// terminal marks it, is gathered to the end of the paste first.
+#[derive(Debug, Default)]
+pub struct Entry {
+    source: String,
+    /// Whether a marked paste has started but not yet ended
+    pasting: bool,
+}
+
+impl Entry {
+    /// Whether no lines have been read for the entry yet.
+    pub fn is_empty(&self) -> bool {
+        self.source.is_empty()
+    }
+
+    /// Adds `line` to the entry, returning its source once it's complete.
+    pub fn push<Out: Write, EOut: Write>(
+        &mut self,
+        vm: &VM<Out, EOut>,
+        line: &str,
+    ) -> Option<String> {
+        let mut line = line.to_string();
+        if line.contains(PASTE_START) {
+            line = line.replace(PASTE_START, "");
+            self.pasting = true;
+        }
+        if line.contains(PASTE_END) {
+            line = line.replace(PASTE_END, "");
+            self.pasting = false;
+        }
+        let blank = line.trim().is_empty();
+        self.source.push_str(&line);
+        if self.pasting {
+            return None;
+        }
+        if self.source.trim().is_empty() {
+            self.source.clear();
+            return None;
+        }
+        if !blank && vm.is_incomplete(&self.source) {
+            return None;
+        }
+        Some(std::mem::take(&mut self.source))
+    }
+
+    /// Ends the entry early, as when input runs out, returning what was read
+    /// of it.
+    pub fn finish(&mut self) -> Option<String> {
+        self.pasting = false;
+        let source = std::mem::take(&mut self.source);
+        (!source.trim().is_empty()).then_some(source)
+    }
+}
+
+/// Runs the script `source`, read from `path`, as part of the session so
+/// what it defines stays available at the prompt. A failing script keeps
+/// the globals it defined before the error.
+pub fn load<Out: Write, EOut: Write>(
+    vm: &mut VM<Out, EOut>,
+    path: &Path,
+    source: &str,
+) -> Result<(), Error> {
+    let result = vm.interpret_module(path, source);
+    if result.is_err() {
+        vm.reset(false);
+    }
+    result
+}
+
+/// The words that could finish the identifier at the end of `line`, sorted.
+///
+/// After a `.`, these are the members of the global before it, if that global
+/// holds a class or an instance. Otherwise they are keywords and globals.
+pub fn complete<Out: Write, EOut: Write>(vm: &VM<Out, EOut>, line: &str) -> Vec<String> {
+    let (before, prefix) = line.split_at(word_start(line));
+    let mut candidates = match before.strip_suffix('.') {
+        Some(receiver) => {
+            let (_, receiver) = receiver.split_at(word_start(receiver));
+            vm.global_members(receiver)
+        }
+        None => vm
+            .keywords()
+            .words()
+            .map(str::to_string)
+            .chain(vm.globals().map(|(name, _)| name.to_string()))
+            .collect(),
+    };
+    candidates.retain(|candidate| candidate.starts_with(prefix));
+    candidates.sort();
+    candidates.dedup();
+    candidates
+}
+
+/// Where the identifier ending `line` starts.
+fn word_start(line: &str) -> usize {
+    line.char_indices()
+        .rev()
+        .take_while(|&(_, c)| c.is_alphanumeric() || c == '_')
+        .last()
+        .map_or(line.len(), |(i, _)| i)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn vm() -> VM<Vec<u8>, Vec<u8>> {
+        let mut vm = VM::new(Vec::new(), Vec::new());
+        vm.interpret(
+            "class Point { init() { this.x = 0; } length() {} } var p = Point(); var printed = 1;",
+        )
+        .expect("Failed to run program");
+        vm
+    }
+
+    #[test]
+    fn it_loads_scripts_into_the_session() {
+        let mut vm = VM::new(Vec::new(), Vec::new());
+        load(
+            &mut vm,
+            Path::new("prelude.lox"),
+            "fun double(x) { return 2 * x; }",
+        )
+        .expect("Failed to load script");
+        assert_eq!(
+            load(
+                &mut vm,
+                Path::new("broken.lox"),
+                "var before = double(2); nil();"
+            ),
+            Err(Error::Runtime)
+        );
+        vm.interpret("var after = double(before);")
+            .expect("Failed to run program");
+        assert_eq!(vm.global("after"), Some(8.0.into()));
+        assert!(vm.modules().any(|module| module.ends_with("prelude.lox")));
+    }
+
+    /// Pushes each line, returning the entries completed along the way.
+    fn entries(vm: &VM<Vec<u8>, Vec<u8>>, entry: &mut Entry, lines: &[&str]) -> Vec<String> {
+        lines
+            .iter()
+            .filter_map(|line| entry.push(vm, line))
+            .collect()
+    }
+
+    #[test]
+    fn it_gathers_definitions_typed_over_several_lines() {
+        let mut vm = VM::new(Vec::new(), Vec::new());
+        let mut entry = Entry::default();
+        let class = [
+            "class Counter {\n",
+            "  init() { this.count = 0; }\n",
+            "  add(n) {\n",
+            "    this.count = this.count + n;\n",
+            "    return this;\n",
+            "  }\n",
+            "}\n",
+        ];
+        assert!(entries(&vm, &mut entry, &class[..6]).is_empty());
+        assert!(!entry.is_empty());
+        let completed = entries(&vm, &mut entry, &class[6..]);
+        assert_eq!(completed, vec![class.concat()]);
+        assert!(entry.is_empty());
+        vm.interpret(&completed[0]).expect("Failed to run program");
+
+        let completed = entries(
+            &vm,
+            &mut entry,
+            &[
+                "var total = Counter()\n",
+                "  .add(2)\n",
+                "  .add(3).count;\n",
+                "print 1;\n",
+            ],
+        );
+        assert_eq!(
+            completed,
+            vec![
+                "var total = Counter()\n  .add(2)\n  .add(3).count;\n",
+                "print 1;\n"
+            ]
+        );
+        vm.interpret(&completed[0]).expect("Failed to run program");
+        assert_eq!(vm.global("total"), Some(5.0.into()));
+
+        // A string left open runs on to the next line
+        let completed = entries(&vm, &mut entry, &["var s = \"a\n", "b\";\n"]);
+        assert_eq!(completed, vec!["var s = \"a\nb\";\n"]);
+
+        // The body's brace may come on the line after the parameters
+        let completed = entries(&vm, &mut entry, &["fun f()\n", "{ print 1; }\n"]);
+        assert_eq!(completed, vec!["fun f()\n{ print 1; }\n"]);
+    }
+
+    #[test]
+    fn it_runs_entries_with_errors_before_they_end() {
+        let vm = VM::new(Vec::new(), Vec::new());
+        let mut entry = Entry::default();
+        // Nothing could follow that makes these compile
+        assert_eq!(entry.push(&vm, "print );\n").as_deref(), Some("print );\n"));
+        assert_eq!(entry.push(&vm, "class {\n").as_deref(), Some("class {\n"));
+        // A blank line gives up on an entry left open
+        assert_eq!(entry.push(&vm, "fun f() {\n"), None);
+        assert_eq!(entry.push(&vm, "\n").as_deref(), Some("fun f() {\n\n"));
+        assert_eq!(entry.push(&vm, "  \n"), None);
+        assert!(entry.is_empty());
+        assert_eq!(entry.push(&vm, "print\n"), None);
+        assert_eq!(entry.finish().as_deref(), Some("print\n"));
+        assert_eq!(entry.finish(), None);
+    }
+
+    #[test]
+    fn it_gathers_bracketed_pastes_whole() {
+        let mut vm = VM::new(Vec::new(), Vec::new());
+        let mut entry = Entry::default();
+        let pasted = [
+            "\x1b[200~fun greet(name) {\n",
+            "  return \"hi \" + name;\n",
+            "}\n",
+            "\n",
+            "var greeting = greet(\"bob\");\n",
+            "\x1b[201~\n",
+        ];
+        let completed = entries(&vm, &mut entry, &pasted);
+        assert_eq!(
+            completed,
+            vec!["fun greet(name) {\n  return \"hi \" + name;\n}\n\nvar greeting = greet(\"bob\");\n\n"]
+        );
+        vm.interpret(&completed[0]).expect("Failed to run program");
+        assert_eq!(vm.global("greeting"), Some("hi bob".into()));
+
+        // A paste ending partway through a line waits for the rest of it
+        let completed = entries(
+            &vm,
+            &mut entry,
+            &["\x1b[200~print greet(\x1b[201~\"al\");\n"],
+        );
+        assert_eq!(completed, vec!["print greet(\"al\");\n"]);
+    }
+
+    #[test]
+    fn it_completes_keywords_and_globals() {
+        let vm = vm();
+        assert_eq!(complete(&vm, "pri"), vec!["print", "printed"]);
+        assert_eq!(complete(&vm, "var x = Po"), vec!["Point"]);
+        assert_eq!(complete(&vm, "print cl"), vec!["class", "clock"]);
+        assert!(complete(&vm, "zzz").is_empty());
+    }
+
+    #[test]
+    fn it_completes_members_after_a_dot() {
+        let vm = vm();
+        assert_eq!(complete(&vm, "p."), vec!["init", "length", "x"]);
+        assert_eq!(complete(&vm, "print p.le"), vec!["length"]);
+        assert_eq!(complete(&vm, "Point.i"), vec!["init"]);
+        assert!(complete(&vm, "printed.").is_empty());
+    }
+}
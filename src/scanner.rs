@@ -1,28 +1,257 @@
-use crate::token::{Token, TokenType};
+use std::{
+    collections::HashMap,
+    fmt::{self, Debug},
+    io::{ErrorKind, Read},
+    str,
+};
 
-#[derive(Debug, Clone)]
+use crate::{
+    arena::{Arena, Lexeme},
+    token::{ScanError, Token, TokenType},
+};
+
+/// Every reserved word of canonical Lox and the token it scans to, in
+/// alphabetical order.
+pub const KEYWORDS: &[(&str, TokenType)] = &[
+    ("and", TokenType::And),
+    ("class", TokenType::Class),
+    ("else", TokenType::Else),
+    ("false", TokenType::False),
+    ("for", TokenType::For),
+    ("fun", TokenType::Fun),
+    ("if", TokenType::If),
+    ("import", TokenType::Import),
+    ("in", TokenType::In),
+    ("nil", TokenType::Nil),
+    ("or", TokenType::Or),
+    ("print", TokenType::Print),
+    ("return", TokenType::Return),
+    ("super", TokenType::Super),
+    ("this", TokenType::This),
+    ("true", TokenType::True),
+    ("var", TokenType::Var),
+    ("while", TokenType::While),
+    ("with", TokenType::With),
+];
+
+/// The reserved words a scanner recognizes. Dialects, e.g. for teaching, can
+/// alias keywords, so `function` scans the same as `fun`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Keywords {
+    words: HashMap<String, TokenType>,
+}
+
+impl Keywords {
+    /// The keywords of canonical Lox, without aliases.
+    pub fn canonical() -> Self {
+        Self {
+            words: KEYWORDS
+                .iter()
+                .map(|&(word, kind)| (word.to_string(), kind))
+                .collect(),
+        }
+    }
+
+    /// Makes `alias` scan as the canonical `keyword`. Returns false without
+    /// aliasing if `keyword` isn't canonical, or `alias` is already reserved or
+    /// couldn't scan as an identifier.
+    pub fn alias(&mut self, alias: &str, keyword: &str) -> bool {
+        let Some(&(_, kind)) = KEYWORDS.iter().find(|&&(word, _)| word == keyword) else {
+            return false;
+        };
+        let mut chars = alias.chars();
+        let scans = chars.next().is_some_and(|c| c.is_alphabetic() || c == '_')
+            && chars.all(|c| c.is_alphanumeric() || c == '_');
+        if !scans || self.words.contains_key(alias) {
+            return false;
+        }
+        self.words.insert(alias.into(), kind);
+        true
+    }
+
+    /// Whether these are just the keywords of canonical Lox.
+    pub fn is_canonical(&self) -> bool {
+        self.words.len() == KEYWORDS.len()
+    }
+
+    /// Every reserved word, aliases included, in no particular order.
+    pub fn words(&self) -> impl Iterator<Item = &str> {
+        self.words.keys().map(String::as_str)
+    }
+
+    fn kind(&self, word: &str) -> TokenType {
+        self.words.get(word).copied().unwrap_or(TokenType::Identifier)
+    }
+}
+
+impl Default for Keywords {
+    fn default() -> Self {
+        Self::canonical()
+    }
+}
+
+/// How many bytes past the scanning position a cursor keeps buffered, enough
+/// for the few characters of lookahead the scanner needs.
+const LOOKAHEAD: usize = 16;
+/// How many bytes a cursor reads from its reader at a time.
+const READ_SIZE: usize = 8 * 1024;
+
+/// The source text seen by a scanner. A cursor over a reader only buffers
+/// the text around the scanning position, topping it up as the scanner moves.
+struct Cursor {
+    buffer: String,
+    /// The byte offset in the source at which `buffer` starts
+    offset: usize,
+    reader: Option<Box<dyn Read>>,
+    /// Bytes read that don't yet form a whole character
+    pending: Vec<u8>,
+    /// Why the reader stopped early, if it failed
+    error: Option<String>,
+}
+
+impl Cursor {
+    fn new(source: String) -> Self {
+        Self {
+            buffer: source,
+            offset: 0,
+            reader: None,
+            pending: vec![],
+            error: None,
+        }
+    }
+
+    fn from_reader(reader: impl Read + 'static) -> Self {
+        Self {
+            reader: Some(Box::new(reader)),
+            ..Self::new(String::new())
+        }
+    }
+
+    /// The source from byte `index` on, as far as it's buffered.
+    fn rest(&mut self, index: usize) -> &str {
+        if self.buffer.len() + self.offset - index < LOOKAHEAD {
+            self.fill(index);
+        }
+        &self.buffer[index - self.offset..]
+    }
+
+    /// Drops the text before `index` and reads until `LOOKAHEAD` bytes past it
+    /// are buffered or the reader runs out.
+    fn fill(&mut self, index: usize) {
+        if self.reader.is_none() {
+            return;
+        }
+        self.buffer.drain(..index - self.offset);
+        self.offset = index;
+        let mut bytes = [0; READ_SIZE];
+        while let Some(reader) = self.reader.as_mut() {
+            if self.buffer.len() >= LOOKAHEAD {
+                return;
+            }
+            match reader.read(&mut bytes) {
+                Ok(0) => {
+                    self.buffer
+                        .push_str(&String::from_utf8_lossy(&self.pending));
+                    self.pending.clear();
+                    self.reader = None;
+                    return;
+                }
+                Ok(read) => {
+                    self.pending.extend_from_slice(&bytes[..read]);
+                    self.decode_pending();
+                }
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => {
+                    self.error = Some(e.to_string());
+                    self.reader = None;
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Moves the whole characters read so far into the buffer, replacing
+    /// invalid UTF-8 with U+FFFD.
+    fn decode_pending(&mut self) {
+        loop {
+            let (valid, invalid) = match str::from_utf8(&self.pending) {
+                Ok(text) => (text.len(), None),
+                Err(e) => (e.valid_up_to(), e.error_len()),
+            };
+            self.buffer
+                .push_str(&String::from_utf8_lossy(&self.pending[..valid]));
+            let Some(invalid) = invalid else {
+                // Anything left is the start of a character still being read
+                self.pending.drain(..valid);
+                return;
+            };
+            self.buffer.push(char::REPLACEMENT_CHARACTER);
+            self.pending.drain(..valid + invalid);
+        }
+    }
+}
+
+impl Debug for Cursor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Cursor")
+            .field("buffer", &self.buffer)
+            .field("offset", &self.offset)
+            .field("reading", &self.reader.is_some())
+            .field("error", &self.error)
+            .finish()
+    }
+}
+
+#[derive(Debug)]
 pub struct Scanner {
     pub line: usize,
-    source: String,
+    source: Cursor,
     current_index: usize,
+    keywords: Keywords,
+    arena: Arena,
 }
 
 impl Scanner {
     pub fn new(source: String) -> Self {
         Self {
             line: 1,
-            source,
+            source: Cursor::new(source),
             current_index: 0,
+            keywords: Keywords::canonical(),
+            arena: Arena::default(),
         }
     }
 
+    /// A scanner that reads `reader` as it goes instead of needing the whole
+    /// source up front.
+    pub fn from_reader(reader: impl Read + 'static) -> Self {
+        Self {
+            line: 1,
+            source: Cursor::from_reader(reader),
+            current_index: 0,
+            keywords: Keywords::canonical(),
+            arena: Arena::default(),
+        }
+    }
+
+    /// Scans the reserved words of `keywords` rather than canonical Lox's.
+    pub fn with_keywords(mut self, keywords: Keywords) -> Self {
+        self.keywords = keywords;
+        self
+    }
+
+    fn rest(&mut self) -> &str {
+        self.source.rest(self.current_index)
+    }
+
     fn iter_peek(&mut self) -> Option<char> {
-        self.source[self.current_index..].chars().next()
+        self.rest().chars().next()
     }
 
     fn iter_next(&mut self) -> Option<char> {
-        self.current_index += 1;
-        self.source[self.current_index - 1..].chars().next()
+        let c = self.iter_peek()?;
+        self.current_index += c.len_utf8();
+        Some(c)
     }
 
     fn next_if_eq(&mut self, c: char) -> Option<char> {
@@ -33,80 +262,141 @@ impl Scanner {
     }
 
     fn identifier(&mut self) -> Option<Token> {
-        let mut lexeme_builder = vec![];
-
         while let Some(c) = self.iter_peek() {
             if !c.is_alphanumeric() && c != '_' {
                 break;
             }
-            lexeme_builder.push(c);
+            self.arena.scratch().push(c);
             self.iter_next();
         }
 
-        let lexeme: String = lexeme_builder.into_iter().collect();
-        let kind = {
-            match lexeme.as_str() {
-                "and" => TokenType::And,
-                "class" => TokenType::Class,
-                "else" => TokenType::Else,
-                "false" => TokenType::False,
-                "for" => TokenType::For,
-                "fun" => TokenType::Fun,
-                "if" => TokenType::If,
-                "nil" => TokenType::Nil,
-                "or" => TokenType::Or,
-                "print" => TokenType::Print,
-                "return" => TokenType::Return,
-                "super" => TokenType::Super,
-                "this" => TokenType::This,
-                "true" => TokenType::True,
-                "var" => TokenType::Var,
-                "while" => TokenType::While,
-                _ => TokenType::Identifier,
-            }
-        };
+        let kind = self.keywords.kind(self.arena.scratch());
         Some(Token {
             kind,
             line: self.line,
-            lexeme,
+            lexeme: self.arena.alloc_scratch(),
         })
     }
 
     fn number(&mut self) -> Option<Token> {
-        let mut lexeme_builder = vec![];
-
-        while let Some(c) = self.iter_peek() {
-            if !c.is_ascii_digit() {
-                break;
-            }
-            lexeme_builder.push(c);
-            self.iter_next();
+        match (self.iter_peek(), self.peek_next()) {
+            (Some('0'), Some('x' | 'X')) => return Some(self.radix_number(16)),
+            (Some('0'), Some('b' | 'B')) => return Some(self.radix_number(2)),
+            _ => {}
         }
 
+        self.digits();
+        let mut kind = TokenType::Integer;
         let peek_next = self.peek_next().take_if(|x| x.is_ascii_digit());
         let next = self.iter_peek();
         if next == Some('.') && peek_next.is_some() {
-            lexeme_builder.push(next?);
+            kind = TokenType::Number;
+            self.arena.scratch().push(next?);
             self.iter_next(); // Consume the '.'
-            while let Some(c) = self.iter_peek() {
-                if !c.is_ascii_digit() {
-                    break;
-                }
-                lexeme_builder.push(c);
-                self.iter_next();
-            }
+            self.digits();
+        }
+        if self.exponent() {
+            kind = TokenType::Number;
         }
 
-        let lexeme: String = lexeme_builder.into_iter().collect();
+        let separated = self
+            .arena
+            .scratch()
+            .split(['.', 'e', 'E'])
+            .all(separated_digits);
+        let lexeme = self.arena.alloc_scratch();
+        if !separated {
+            return Some(self.error_token(ScanError::MisplacedSeparator, lexeme));
+        }
         Some(Token {
-            kind: TokenType::Number,
+            kind,
             line: self.line,
             lexeme,
         })
     }
 
+    /// Scans digits into the scratch buffer, allowing `_` separators.
+    fn digits(&mut self) {
+        while let Some(c) = self.iter_peek() {
+            if !c.is_ascii_digit() && c != '_' {
+                break;
+            }
+            self.arena.scratch().push(c);
+            self.iter_next();
+        }
+    }
+
+    /// Scans an exponent such as `e9` or `E-3` into the scratch buffer.
+    /// Without digits after it, the `e` is left to scan as an identifier.
+    fn exponent(&mut self) -> bool {
+        let mut chars = self.rest().chars();
+        let Some(e) = chars.next().filter(|c| matches!(c, 'e' | 'E')) else {
+            return false;
+        };
+        let sign = chars.clone().next().filter(|c| matches!(c, '+' | '-'));
+        if sign.is_some() {
+            chars.next();
+        }
+        if chars.next().filter(char::is_ascii_digit).is_none() {
+            return false;
+        }
+
+        for c in [Some(e), sign].into_iter().flatten() {
+            self.arena.scratch().push(c);
+            self.iter_next();
+        }
+        self.digits();
+        true
+    }
+
+    /// Scans an integer literal with a `0x` or `0b` prefix. The whole
+    /// alphanumeric run is consumed so a bad digit doesn't leave the rest of
+    /// the literal behind as an identifier.
+    fn radix_number(&mut self, radix: u32) -> Token {
+        for _ in 0..2 {
+            if let Some(c) = self.iter_next() {
+                self.arena.scratch().push(c);
+            }
+        }
+        let prefix_len = self.arena.scratch().len();
+        while let Some(c) = self.iter_peek() {
+            if !c.is_ascii_alphanumeric() && c != '_' {
+                break;
+            }
+            self.arena.scratch().push(c);
+            self.iter_next();
+        }
+
+        let digits = &self.arena.scratch()[prefix_len..];
+        let error = if let Some(digit) = digits.chars().find(|&c| c != '_' && !c.is_digit(radix)) {
+            Some(ScanError::InvalidDigit { digit, radix })
+        } else if !digits.contains(|c: char| c != '_') {
+            Some(ScanError::MissingDigits)
+        } else if !separated_digits(digits) {
+            Some(ScanError::MisplacedSeparator)
+        } else {
+            None
+        };
+        let lexeme = self.arena.alloc_scratch();
+        if let Some(error) = error {
+            return self.error_token(error, lexeme);
+        }
+        Token {
+            kind: TokenType::Integer,
+            line: self.line,
+            lexeme,
+        }
+    }
+
+    fn error_token(&self, error: ScanError, lexeme: Lexeme) -> Token {
+        Token {
+            kind: TokenType::Error(error),
+            lexeme,
+            line: self.line,
+        }
+    }
+
     fn string(&mut self) -> Option<Token> {
-        let mut lexeme_builder = vec![];
         while let Some(c) = self.iter_peek() {
             if c == '"' {
                 break;
@@ -116,25 +406,22 @@ impl Scanner {
                 self.line += 1;
             }
 
-            lexeme_builder.push(c);
+            self.arena.scratch().push(c);
             self.iter_next();
         }
 
         if self.is_at_end() {
-            return Some(Token {
-                kind: TokenType::Error,
-                lexeme: "Unterminated string.".into(),
-                line: self.line,
-            });
+            self.arena.scratch().insert(0, '"');
+            let lexeme = self.arena.alloc_scratch();
+            return Some(self.error_token(ScanError::UnterminatedString, lexeme));
         }
 
         // Consume closing quote
         self.iter_next();
 
-        let lexeme = lexeme_builder.into_iter().collect();
         Some(Token {
             kind: TokenType::String,
-            lexeme,
+            lexeme: self.arena.alloc_scratch(),
             line: self.line,
         })
     }
@@ -144,11 +431,9 @@ impl Scanner {
     }
 
     fn peek_next(&mut self) -> Option<char> {
-        self.iter_peek()?;
-        let mut next_iter = self.source[self.current_index..].chars().peekable();
-        next_iter.next();
-        let next_c = next_iter.peek()?;
-        Some(*next_c)
+        let mut chars = self.rest().chars();
+        chars.next()?;
+        chars.next()
     }
 
     fn skip_whitespace(&mut self) {
@@ -162,13 +447,9 @@ impl Scanner {
                     self.line += 1;
                     self.iter_next();
                 }
-                Some('/') => {
-                    if self.peek_next() == Some('/') {
-                        while self.iter_peek() != Some('\n') && !self.is_at_end() {
-                            self.iter_next();
-                        }
-                    } else {
-                        return;
+                Some('/') if self.peek_next() == Some('/') => {
+                    while self.iter_peek() != Some('\n') && !self.is_at_end() {
+                        self.iter_next();
                     }
                 }
                 _ => return,
@@ -177,20 +458,60 @@ impl Scanner {
     }
 }
 
+/// Whether `c` is whitespace or could begin a token.
+fn starts_token(c: char) -> bool {
+    c.is_whitespace()
+        || c.is_alphabetic()
+        || c.is_ascii_digit()
+        || "_\"(){}[];:,.-+/*&|^~!=<>".contains(c)
+}
+
+/// Whether `_` only appears between digits.
+fn separated_digits(digits: &str) -> bool {
+    !digits.starts_with('_') && !digits.ends_with('_') && !digits.contains("__")
+}
+
+/// Pairs every token with the `(start, end)` byte span it covers in the source.
+#[derive(Debug)]
+pub struct SpannedTokens {
+    scanner: Scanner,
+}
+
+impl Scanner {
+    pub fn spanned(self) -> SpannedTokens {
+        SpannedTokens { scanner: self }
+    }
+}
+
+impl Iterator for SpannedTokens {
+    type Item = (Token, (usize, usize));
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.scanner.skip_whitespace();
+        let start = self.scanner.current_index;
+        let token = self.scanner.next()?;
+        Some((token, (start, self.scanner.current_index)))
+    }
+}
+
 impl Iterator for Scanner {
     type Item = Token;
 
     fn next(&mut self) -> Option<Self::Item> {
         self.skip_whitespace();
         if self.is_at_end() {
+            // The source stops where a failing reader gave out
+            if let Some(error) = self.source.error.take() {
+                return Some(self.error_token(ScanError::ReadFailed, error.into()));
+            }
             return Some(Token {
                 kind: TokenType::Eof,
-                lexeme: "".into(),
+                lexeme: self.arena.alloc(""),
                 line: self.line,
             });
         };
         let c = self.iter_peek().unwrap();
-        if c.is_alphabetic() {
+        if c.is_alphabetic() || c == '_' {
             return self.identifier();
         }
         if c.is_ascii_digit() {
@@ -198,8 +519,8 @@ impl Iterator for Scanner {
         }
 
         let mut token = Token {
-            kind: TokenType::Error,
-            lexeme: c.to_string(),
+            kind: TokenType::Error(ScanError::UnexpectedCharacter),
+            lexeme: self.arena.alloc(c.encode_utf8(&mut [0; 4])),
             line: self.line,
         };
 
@@ -208,16 +529,38 @@ impl Iterator for Scanner {
             ')' => TokenType::RightParen,
             '{' => TokenType::LeftBrace,
             '}' => TokenType::RightBrace,
+            '[' => TokenType::LeftBracket,
+            ']' => TokenType::RightBracket,
             ';' => TokenType::Semicolon,
+            ':' => TokenType::Colon,
             ',' => TokenType::Comma,
-            '.' => TokenType::Dot,
+            '.' => {
+                if self.next_if_eq('.').is_some() {
+                    if self.next_if_eq('=').is_some() {
+                        token.lexeme = self.arena.alloc("..=");
+                        TokenType::DotDotEqual
+                    } else if self.next_if_eq('.').is_some() {
+                        token.lexeme = self.arena.alloc("...");
+                        TokenType::DotDotDot
+                    } else {
+                        token.lexeme = self.arena.alloc("..");
+                        TokenType::DotDot
+                    }
+                } else {
+                    TokenType::Dot
+                }
+            }
             '-' => TokenType::Minus,
             '+' => TokenType::Plus,
             '/' => TokenType::Slash,
             '*' => TokenType::Star,
+            '&' => TokenType::Ampersand,
+            '|' => TokenType::Pipe,
+            '^' => TokenType::Caret,
+            '~' => TokenType::Tilde,
             '!' => {
                 if self.next_if_eq('=').is_some() {
-                    token.lexeme = "!=".into();
+                    token.lexeme = self.arena.alloc("!=");
                     TokenType::BangEqual
                 } else {
                     TokenType::Bang
@@ -225,7 +568,7 @@ impl Iterator for Scanner {
             }
             '=' => {
                 if self.next_if_eq('=').is_some() {
-                    token.lexeme = "==".into();
+                    token.lexeme = self.arena.alloc("==");
                     TokenType::EqualEqual
                 } else {
                     TokenType::Equal
@@ -233,16 +576,22 @@ impl Iterator for Scanner {
             }
             '<' => {
                 if self.next_if_eq('=').is_some() {
-                    token.lexeme = "<=".into();
+                    token.lexeme = self.arena.alloc("<=");
                     TokenType::LessEqual
+                } else if self.next_if_eq('<').is_some() {
+                    token.lexeme = self.arena.alloc("<<");
+                    TokenType::LessLess
                 } else {
                     TokenType::Less
                 }
             }
             '>' => {
                 if self.next_if_eq('=').is_some() {
-                    token.lexeme = ">=".into();
+                    token.lexeme = self.arena.alloc(">=");
                     TokenType::GreaterEqual
+                } else if self.next_if_eq('>').is_some() {
+                    token.lexeme = self.arena.alloc(">>");
+                    TokenType::GreaterGreater
                 } else {
                     TokenType::Greater
                 }
@@ -252,8 +601,14 @@ impl Iterator for Scanner {
                 token.kind
             }
             _ => {
-                token.lexeme = format!("Unexpected character '{}'", token.lexeme);
-                TokenType::Error
+                // Take the whole run of stray characters so it's reported once
+                self.arena.scratch().push(c);
+                while let Some(c) = self.iter_peek().filter(|&c| !starts_token(c)) {
+                    self.arena.scratch().push(c);
+                    self.iter_next();
+                }
+                token.lexeme = self.arena.alloc_scratch();
+                TokenType::Error(ScanError::UnexpectedCharacter)
             }
         };
         Some(token)
@@ -264,6 +619,43 @@ impl Iterator for Scanner {
 mod test {
     use super::*;
 
+    #[test]
+    fn it_scans_every_keyword() {
+        for &(keyword, kind) in KEYWORDS {
+            let token = Scanner::new(keyword.into()).next().unwrap();
+            assert_eq!(token.kind, kind, "{keyword}");
+        }
+        assert!(KEYWORDS.is_sorted_by_key(|&(keyword, _)| keyword));
+    }
+
+    #[test]
+    fn it_scans_keyword_aliases() {
+        let mut keywords = Keywords::canonical();
+        assert!(keywords.alias("function", "fun"));
+        assert!(keywords.alias("let", "var"));
+        assert!(!keywords.alias("def", "function"));
+        assert!(!keywords.alias("var", "fun"));
+        assert!(!keywords.alias("2fun", "fun"));
+        assert!(!keywords.is_canonical());
+        let kinds: Vec<_> = Scanner::new("function fun let var def".into())
+            .with_keywords(keywords)
+            .map(|token| token.kind)
+            .take(5)
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                TokenType::Fun,
+                TokenType::Fun,
+                TokenType::Var,
+                TokenType::Var,
+                TokenType::Identifier
+            ]
+        );
+        let token = Scanner::new("function".into()).next().unwrap();
+        assert_eq!(token.kind, TokenType::Identifier);
+    }
+
     #[test]
     fn it_scans_end_of_file() {
         let source = "";
@@ -296,7 +688,7 @@ mod test {
 
     #[test]
     fn it_scans_an_identifier() {
-        let source = "identifier\nidentifier1234\nidentifier_1234";
+        let source = "identifier\nidentifier1234\nidentifier_1234\n_identifier";
         let mut scanner = Scanner::new(source.into());
         let token = scanner.next().unwrap();
         assert_eq!(
@@ -325,6 +717,15 @@ mod test {
                 lexeme: "identifier_1234".into()
             }
         );
+        let token = scanner.next().unwrap();
+        assert_eq!(
+            token,
+            Token {
+                kind: TokenType::Identifier,
+                line: 4,
+                lexeme: "_identifier".into()
+            }
+        );
     }
 
     #[test]
@@ -345,16 +746,98 @@ mod test {
         assert_eq!(
             token,
             Token {
-                kind: TokenType::Number,
+                kind: TokenType::Integer,
                 line: 2,
                 lexeme: "54321".into()
             }
         );
     }
 
+    #[test]
+    fn it_scans_prefixed_and_separated_numbers() {
+        let source = "0xFF 0B1010 1_000_000 1_000.000_5 0x_ff";
+        let tokens: Vec<_> = Scanner::new(source.into())
+            .take_while(|token| token.kind != TokenType::Eof)
+            .map(|token| (token.kind, token.lexeme))
+            .collect();
+        assert_eq!(
+            tokens,
+            vec![
+                (TokenType::Integer, "0xFF".into()),
+                (TokenType::Integer, "0B1010".into()),
+                (TokenType::Integer, "1_000_000".into()),
+                (TokenType::Number, "1_000.000_5".into()),
+                (
+                    TokenType::Error(ScanError::MisplacedSeparator),
+                    "0x_ff".into()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn it_scans_scientific_notation() {
+        let source = "1e9 2.5e-3 6E+2 1_000e1_0 1e x.e1";
+        let tokens: Vec<_> = Scanner::new(source.into())
+            .take_while(|token| token.kind != TokenType::Eof)
+            .map(|token| (token.kind, token.lexeme))
+            .collect();
+        assert_eq!(
+            tokens,
+            vec![
+                (TokenType::Number, "1e9".into()),
+                (TokenType::Number, "2.5e-3".into()),
+                (TokenType::Number, "6E+2".into()),
+                (TokenType::Number, "1_000e1_0".into()),
+                (TokenType::Integer, "1".into()),
+                (TokenType::Identifier, "e".into()),
+                (TokenType::Identifier, "x".into()),
+                (TokenType::Dot, ".".into()),
+                (TokenType::Identifier, "e1".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn it_scans_malformed_numbers() {
+        let hex_g = ScanError::InvalidDigit {
+            digit: 'G',
+            radix: 16,
+        };
+        let binary_2 = ScanError::InvalidDigit {
+            digit: '2',
+            radix: 2,
+        };
+        for (source, error, message) in [
+            ("0x", ScanError::MissingDigits, "Expect digits after '0x'."),
+            ("0b_", ScanError::MissingDigits, "Expect digits after '0b'."),
+            ("0xFG", hex_g, "Invalid digit 'G' in hex literal."),
+            ("0b102", binary_2, "Invalid digit '2' in binary literal."),
+            ("1__0", ScanError::MisplacedSeparator, "Invalid '_' in number literal."),
+            ("1_", ScanError::MisplacedSeparator, "Invalid '_' in number literal."),
+            ("1_.5", ScanError::MisplacedSeparator, "Invalid '_' in number literal."),
+            ("1.5_", ScanError::MisplacedSeparator, "Invalid '_' in number literal."),
+            ("1e5_", ScanError::MisplacedSeparator, "Invalid '_' in number literal."),
+        ] {
+            let mut scanner = Scanner::new(source.into());
+            let token = scanner.next().unwrap();
+            assert_eq!(
+                token,
+                Token {
+                    kind: TokenType::Error(error),
+                    lexeme: source.into(),
+                    line: 1,
+                },
+                "{source}"
+            );
+            assert_eq!(error.message(&token.lexeme), message, "{source}");
+            assert_eq!(scanner.next().unwrap().kind, TokenType::Eof, "{source}");
+        }
+    }
+
     #[test]
     fn it_scans_single_characters() {
-        let source = "(){};,.-+/*! = < > $";
+        let source = "(){}[];:,.-+/*&|^~! = < > $";
         let mut scanner = Scanner::new(source.into());
         let expected_tokens = vec![
             Token {
@@ -377,11 +860,26 @@ mod test {
                 lexeme: "}".into(),
                 line: 1,
             },
+            Token {
+                kind: TokenType::LeftBracket,
+                lexeme: "[".into(),
+                line: 1,
+            },
+            Token {
+                kind: TokenType::RightBracket,
+                lexeme: "]".into(),
+                line: 1,
+            },
             Token {
                 kind: TokenType::Semicolon,
                 lexeme: ";".into(),
                 line: 1,
             },
+            Token {
+                kind: TokenType::Colon,
+                lexeme: ":".into(),
+                line: 1,
+            },
             Token {
                 kind: TokenType::Comma,
                 lexeme: ",".into(),
@@ -412,6 +910,26 @@ mod test {
                 lexeme: "*".into(),
                 line: 1,
             },
+            Token {
+                kind: TokenType::Ampersand,
+                lexeme: "&".into(),
+                line: 1,
+            },
+            Token {
+                kind: TokenType::Pipe,
+                lexeme: "|".into(),
+                line: 1,
+            },
+            Token {
+                kind: TokenType::Caret,
+                lexeme: "^".into(),
+                line: 1,
+            },
+            Token {
+                kind: TokenType::Tilde,
+                lexeme: "~".into(),
+                line: 1,
+            },
             Token {
                 kind: TokenType::Bang,
                 lexeme: "!".into(),
@@ -433,8 +951,8 @@ mod test {
                 line: 1,
             },
             Token {
-                kind: TokenType::Error,
-                lexeme: "Unexpected character '$'".into(),
+                kind: TokenType::Error(ScanError::UnexpectedCharacter),
+                lexeme: "$".into(),
                 line: 1,
             },
         ];
@@ -446,7 +964,7 @@ mod test {
 
     #[test]
     fn it_scans_double_tokens() {
-        let source = "== <= >= !=";
+        let source = "== <= >= != .. ..= ... << >>";
         let mut scanner = Scanner::new(source.into());
         let expected_tokens = vec![
             Token {
@@ -469,6 +987,31 @@ mod test {
                 lexeme: "!=".into(),
                 line: 1,
             },
+            Token {
+                kind: TokenType::DotDot,
+                lexeme: "..".into(),
+                line: 1,
+            },
+            Token {
+                kind: TokenType::DotDotEqual,
+                lexeme: "..=".into(),
+                line: 1,
+            },
+            Token {
+                kind: TokenType::DotDotDot,
+                lexeme: "...".into(),
+                line: 1,
+            },
+            Token {
+                kind: TokenType::LessLess,
+                lexeme: "<<".into(),
+                line: 1,
+            },
+            Token {
+                kind: TokenType::GreaterGreater,
+                lexeme: ">>".into(),
+                line: 1,
+            },
         ];
 
         for expected_token in expected_tokens {
@@ -500,13 +1043,39 @@ mod test {
         assert_eq!(
             token,
             Token {
-                kind: TokenType::Error,
-                lexeme: "Unterminated string.".into(),
+                kind: TokenType::Error(ScanError::UnterminatedString),
+                lexeme: source.into(),
                 line: 1
             }
         );
     }
 
+    #[test]
+    fn it_spans_runs_of_unexpected_characters() {
+        let source = "a $@é# b ✓";
+        let tokens: Vec<_> = Scanner::new(source.into())
+            .spanned()
+            .take_while(|(token, _)| token.kind != TokenType::Eof)
+            .map(|(token, span)| (token.kind, token.lexeme, span))
+            .collect();
+        let unexpected = TokenType::Error(ScanError::UnexpectedCharacter);
+        assert_eq!(
+            tokens,
+            vec![
+                (TokenType::Identifier, "a".into(), (0, 1)),
+                (unexpected, "$@".into(), (2, 4)),
+                (TokenType::Identifier, "é".into(), (4, 6)),
+                (unexpected, "#".into(), (6, 7)),
+                (TokenType::Identifier, "b".into(), (8, 9)),
+                (unexpected, "✓".into(), (10, 13)),
+            ]
+        );
+        assert_eq!(
+            ScanError::UnexpectedCharacter.message("$@"),
+            "Unexpected characters '$@'"
+        );
+    }
+
     #[test]
     fn it_scans_a_boolean() {
         let source = "true false";
@@ -548,7 +1117,7 @@ mod test {
 
     #[test]
     fn it_scans_a_keyword() {
-        let source = "and class else for fun if or print return super this var while";
+        let source = "and class else for fun if or print return super this var while with in";
         let mut scanner = Scanner::new(source.into());
         let expected_tokens = [
             Token {
@@ -616,10 +1185,94 @@ mod test {
                 lexeme: "while".into(),
                 line: 1,
             },
+            Token {
+                kind: TokenType::With,
+                lexeme: "with".into(),
+                line: 1,
+            },
+            Token {
+                kind: TokenType::In,
+                lexeme: "in".into(),
+                line: 1,
+            },
         ];
 
         for token in expected_tokens {
             assert_eq!(scanner.next().unwrap(), token);
         }
     }
+
+    /// Hands out its bytes one at a time, then fails if `error` is set.
+    struct Trickle {
+        bytes: std::collections::VecDeque<u8>,
+        error: bool,
+    }
+
+    impl Trickle {
+        fn new(source: &[u8], error: bool) -> Self {
+            Self {
+                bytes: source.iter().copied().collect(),
+                error,
+            }
+        }
+    }
+
+    impl Read for Trickle {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            match self.bytes.pop_front() {
+                Some(byte) => {
+                    buf[0] = byte;
+                    Ok(1)
+                }
+                None if self.error => Err(std::io::Error::other("disk on fire")),
+                None => Ok(0),
+            }
+        }
+    }
+
+    fn scan_all(scanner: Scanner) -> Vec<(Token, (usize, usize))> {
+        let mut tokens = vec![];
+        for (token, span) in scanner.spanned() {
+            let kind = token.kind;
+            tokens.push((token, span));
+            if kind == TokenType::Eof {
+                break;
+            }
+        }
+        tokens
+    }
+
+    #[test]
+    fn it_scans_a_reader_like_a_string() {
+        let source = "var größe = 0x1F + 2.5e3; // ünïcode\nprint \"naïve ✓\" ..= größe;";
+        let from_string = scan_all(Scanner::new(source.into()));
+        let from_reader = scan_all(Scanner::from_reader(Trickle::new(source.as_bytes(), false)));
+        assert_eq!(from_reader, from_string);
+        assert_eq!(from_string[1].0.lexeme, "größe");
+        assert_eq!(from_string[1].1, (4, 11));
+    }
+
+    #[test]
+    fn it_replaces_invalid_utf8_from_a_reader() {
+        let tokens = scan_all(Scanner::from_reader(Trickle::new(b"\"a\xFFb\"", false)));
+        assert_eq!(tokens[0].0.kind, TokenType::String);
+        assert_eq!(tokens[0].0.lexeme, "a\u{FFFD}b");
+    }
+
+    #[test]
+    fn it_reports_read_errors() {
+        let tokens = scan_all(Scanner::from_reader(Trickle::new(b"print 1;", true)));
+        let kinds: Vec<_> = tokens.iter().map(|(token, _)| token.kind).collect();
+        assert_eq!(
+            kinds,
+            [
+                TokenType::Print,
+                TokenType::Integer,
+                TokenType::Semicolon,
+                TokenType::Error(ScanError::ReadFailed),
+                TokenType::Eof
+            ]
+        );
+        assert_eq!(tokens[3].0.lexeme, "disk on fire");
+    }
 }
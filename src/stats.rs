@@ -0,0 +1,230 @@
+//! Counters describing how much work the VM did, cheap enough to always collect.
+
+use std::{fmt::Display, ops::AddAssign, time::Duration};
+
+use crate::value::RuntimeValue;
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Stats {
+    /// Instructions dispatched by the run loop
+    pub instructions: u64,
+    /// The most values the stack ever held at once
+    pub peak_stack_depth: usize,
+    /// Heap objects allocated. Interned strings are only counted when new.
+    pub allocations: ObjectCounts,
+    pub gc: GcStats,
+}
+
+/// Heap objects, by kind.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ObjectCounts {
+    pub bound_methods: usize,
+    pub classes: usize,
+    pub closures: usize,
+    pub functions: usize,
+    pub instances: usize,
+    pub lists: usize,
+    pub natives: usize,
+    pub ranges: usize,
+    pub strings: usize,
+    pub string_builders: usize,
+    pub upvalues: usize,
+}
+
+impl ObjectCounts {
+    /// Counts `value` under its kind, if it is a heap object.
+    pub fn count(&mut self, value: &RuntimeValue) {
+        match value {
+            RuntimeValue::BoundMethod(_) => self.bound_methods += 1,
+            RuntimeValue::Class(_) => self.classes += 1,
+            RuntimeValue::Closure(_) => self.closures += 1,
+            RuntimeValue::Function(_) => self.functions += 1,
+            RuntimeValue::Instance(_) => self.instances += 1,
+            RuntimeValue::List(_) => self.lists += 1,
+            RuntimeValue::Native(_) => self.natives += 1,
+            RuntimeValue::Range(_) => self.ranges += 1,
+            RuntimeValue::String(_) => self.strings += 1,
+            RuntimeValue::StringBuilder(_) => self.string_builders += 1,
+            RuntimeValue::Upvalue(_) => self.upvalues += 1,
+            RuntimeValue::Bool(_)
+            | RuntimeValue::Number(_)
+            | RuntimeValue::Int(_)
+            | RuntimeValue::Nil => {}
+        }
+    }
+
+    /// Each kind with its count, in alphabetical order.
+    pub fn kinds(&self) -> [(&'static str, usize); 11] {
+        [
+            ("bound methods", self.bound_methods),
+            ("classes", self.classes),
+            ("closures", self.closures),
+            ("functions", self.functions),
+            ("instances", self.instances),
+            ("lists", self.lists),
+            ("natives", self.natives),
+            ("ranges", self.ranges),
+            ("string builders", self.string_builders),
+            ("strings", self.strings),
+            ("upvalues", self.upvalues),
+        ]
+    }
+
+    pub fn total(&self) -> usize {
+        self.bound_methods
+            + self.classes
+            + self.closures
+            + self.functions
+            + self.instances
+            + self.lists
+            + self.natives
+            + self.ranges
+            + self.strings
+            + self.string_builders
+            + self.upvalues
+    }
+}
+
+impl AddAssign<&ObjectCounts> for ObjectCounts {
+    fn add_assign(&mut self, other: &ObjectCounts) {
+        self.bound_methods += other.bound_methods;
+        self.classes += other.classes;
+        self.closures += other.closures;
+        self.functions += other.functions;
+        self.instances += other.instances;
+        self.lists += other.lists;
+        self.natives += other.natives;
+        self.ranges += other.ranges;
+        self.strings += other.strings;
+        self.string_builders += other.string_builders;
+        self.upvalues += other.upvalues;
+    }
+}
+
+/// The work done by one garbage collection, broken down by phase.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Collection {
+    /// Time spent marking the stack, frames, open upvalues and globals
+    pub mark_roots: Duration,
+    /// Time spent marking everything reachable from the roots
+    pub trace_references: Duration,
+    /// Time spent freeing unreachable objects
+    pub sweep: Duration,
+    /// Objects found reachable, by store
+    pub visited: ObjectCounts,
+    /// Objects freed, by store
+    pub freed: ObjectCounts,
+}
+
+impl AddAssign<&Collection> for Collection {
+    fn add_assign(&mut self, other: &Collection) {
+        self.mark_roots += other.mark_roots;
+        self.trace_references += other.trace_references;
+        self.sweep += other.sweep;
+        self.visited += &other.visited;
+        self.freed += &other.freed;
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct GcStats {
+    /// How many times the garbage collector ran
+    pub collections: usize,
+    /// The sum of every collection
+    pub total: Collection,
+    /// The most recent collection
+    pub last: Option<Collection>,
+}
+
+impl GcStats {
+    pub fn record(&mut self, collection: Collection) {
+        self.collections += 1;
+        self.total += &collection;
+        self.last = Some(collection);
+    }
+}
+
+fn write_counts(f: &mut std::fmt::Formatter<'_>, counts: &ObjectCounts) -> std::fmt::Result {
+    for (kind, count) in counts.kinds() {
+        writeln!(f, "  {kind}: {count}")?;
+    }
+    Ok(())
+}
+
+impl Display for Stats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "instructions executed: {}", self.instructions)?;
+        writeln!(f, "peak stack depth: {}", self.peak_stack_depth)?;
+        writeln!(f, "gc collections: {}", self.gc.collections)?;
+        writeln!(f, "allocations: {}", self.allocations.total())?;
+        write_counts(f, &self.allocations)?;
+        if self.gc.collections == 0 {
+            return Ok(());
+        }
+        let total = &self.gc.total;
+        writeln!(f, "gc mark roots: {:?}", total.mark_roots)?;
+        writeln!(f, "gc trace references: {:?}", total.trace_references)?;
+        writeln!(f, "gc sweep: {:?}", total.sweep)?;
+        writeln!(f, "gc objects visited: {}", total.visited.total())?;
+        write_counts(f, &total.visited)?;
+        writeln!(f, "gc objects freed: {}", total.freed.total())?;
+        write_counts(f, &total.freed)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn it_displays_a_report() {
+        let mut stats = Stats {
+            instructions: 10,
+            peak_stack_depth: 3,
+            allocations: ObjectCounts {
+                strings: 2,
+                closures: 1,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let report = stats.to_string();
+        assert!(report.starts_with(
+            "instructions executed: 10\npeak stack depth: 3\ngc collections: 0\nallocations: 3\n"
+        ));
+        assert!(report.contains("  closures: 1\n"));
+        assert!(report.ends_with("  strings: 2\n  upvalues: 0\n"));
+
+        stats.gc.record(Collection {
+            sweep: Duration::from_micros(5),
+            freed: ObjectCounts {
+                lists: 4,
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        let report = stats.to_string();
+        assert!(report.contains("gc collections: 1\n"));
+        assert!(report.contains("gc sweep: 5µs\ngc objects visited: 0\n"));
+        assert!(report.contains("gc objects freed: 4\n  bound methods: 0\n"));
+    }
+
+    #[test]
+    fn it_sums_collections() {
+        let mut gc = GcStats::default();
+        for freed in [1, 2] {
+            gc.record(Collection {
+                mark_roots: Duration::from_millis(1),
+                freed: ObjectCounts {
+                    strings: freed,
+                    ..Default::default()
+                },
+                ..Default::default()
+            });
+        }
+        assert_eq!(gc.collections, 2);
+        assert_eq!(gc.total.mark_roots, Duration::from_millis(2));
+        assert_eq!(gc.total.freed.strings, 3);
+        assert_eq!(gc.last.map(|last| last.freed.strings), Some(2));
+    }
+}
@@ -0,0 +1,190 @@
+use std::collections::HashMap;
+
+use crate::{
+    object::{ObjFunction, ObjString},
+    value::{ConstantValue, RuntimeValue},
+};
+
+/// The global variables of a VM. Each name gets a slot the first time a chunk
+/// using it is linked, so running code indexes globals without hashing names.
+#[derive(Debug, Default)]
+pub struct Globals {
+    slots: HashMap<ObjString, usize>,
+    names: Vec<ObjString>,
+    values: Vec<Option<RuntimeValue>>,
+    /// The values hidden by each [`Globals::isolate`] still in effect,
+    /// outermost first
+    hidden: Vec<Vec<Option<RuntimeValue>>>,
+}
+
+impl Globals {
+    /// The slot of `name`, assigning a new undefined one if it has none yet.
+    pub fn slot(&mut self, name: &ObjString) -> usize {
+        if let Some(&slot) = self.slots.get(name) {
+            return slot;
+        }
+        let slot = self.names.len();
+        self.slots.insert(name.clone(), slot);
+        self.names.push(name.clone());
+        self.values.push(None);
+        slot
+    }
+
+    /// Resolves the global name table of `function`, and of every function
+    /// nested in it, into slots.
+    pub fn link(&mut self, function: &mut ObjFunction) {
+        let mut pending = vec![function];
+        while let Some(ObjFunction { chunk, .. }) = pending.pop() {
+            chunk.global_slots = chunk.globals.iter().map(|name| self.slot(name)).collect();
+            pending.extend(chunk.constants.iter_mut().filter_map(|constant| {
+                match constant {
+                    ConstantValue::Function(function) => Some(&mut **function),
+                    _ => None,
+                }
+            }));
+        }
+    }
+
+    pub fn name(&self, slot: usize) -> &ObjString {
+        &self.names[slot]
+    }
+
+    /// The value in `slot`, if it has been defined.
+    pub fn get_slot(&self, slot: usize) -> Option<RuntimeValue> {
+        self.values[slot]
+    }
+
+    pub fn define_slot(&mut self, slot: usize, value: RuntimeValue) {
+        self.values[slot] = Some(value);
+    }
+
+    /// Assigns to `slot`, returning false without assigning if it is undefined.
+    pub fn set_slot(&mut self, slot: usize, value: RuntimeValue) -> bool {
+        match &mut self.values[slot] {
+            Some(current) => {
+                *current = value;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn get(&self, name: &ObjString) -> Option<&RuntimeValue> {
+        let &slot = self.slots.get(name)?;
+        self.values[slot].as_ref()
+    }
+
+    /// Defines `name`, returning true if it was not defined before.
+    pub fn insert(&mut self, name: ObjString, value: RuntimeValue) -> bool {
+        let slot = self.slot(&name);
+        self.values[slot].replace(value).is_none()
+    }
+
+    /// Undefines every global. Slots stay assigned, so linked chunks stay valid.
+    pub fn clear(&mut self) {
+        self.values.fill(None);
+    }
+
+    /// Hides every global but those `keep` accepts until [`Globals::restore`],
+    /// for running code that must neither see nor change them. Slots stay
+    /// assigned, so linked chunks stay valid.
+    pub fn isolate(&mut self, keep: impl Fn(&RuntimeValue) -> bool) {
+        let kept = self
+            .values
+            .iter()
+            .map(|value| value.filter(|value| keep(value)))
+            .collect();
+        self.hidden.push(std::mem::replace(&mut self.values, kept));
+    }
+
+    /// Brings back the globals hidden by the last [`Globals::isolate`],
+    /// undefining those defined since.
+    pub fn restore(&mut self) {
+        let Some(mut values) = self.hidden.pop() else {
+            return;
+        };
+        values.resize(self.values.len(), None);
+        self.values = values;
+    }
+
+    /// The defined globals, in the order their names were first linked.
+    pub fn entries(&self) -> impl Iterator<Item = (&ObjString, &RuntimeValue)> {
+        self.names
+            .iter()
+            .zip(&self.values)
+            .filter_map(|(name, value)| Some((name, value.as_ref()?)))
+    }
+
+    /// The values of the globals along with those hidden by
+    /// [`Globals::isolate`], which must outlive the isolation.
+    pub fn roots(&self) -> impl Iterator<Item = &RuntimeValue> {
+        self.values.iter().chain(self.hidden.iter().flatten()).flatten()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::chunk::Chunk;
+
+    use super::*;
+
+    #[test]
+    fn it_assigns_each_name_one_slot() {
+        let mut globals = Globals::default();
+        let a = globals.slot(&"a".into());
+        let b = globals.slot(&"b".into());
+        assert_ne!(a, b);
+        assert_eq!(globals.slot(&"a".into()), a);
+        assert_eq!(globals.name(b), &ObjString::from("b"));
+        assert_eq!(globals.get_slot(a), None);
+        assert!(!globals.set_slot(a, RuntimeValue::Nil));
+        globals.define_slot(a, RuntimeValue::Bool(true));
+        assert_eq!(globals.get(&"a".into()), Some(&RuntimeValue::Bool(true)));
+        assert!(globals.set_slot(a, RuntimeValue::Nil));
+        assert_eq!(globals.entries().count(), 1);
+        globals.clear();
+        assert_eq!(globals.get_slot(a), None);
+        assert_eq!(globals.slot(&"a".into()), a);
+    }
+
+    #[test]
+    fn it_hides_globals_while_isolated() {
+        let mut globals = Globals::default();
+        globals.insert("a".into(), RuntimeValue::Nil);
+        globals.insert("b".into(), RuntimeValue::Bool(true));
+        globals.isolate(|value| *value == RuntimeValue::Nil);
+        assert_eq!(globals.get(&"a".into()), Some(&RuntimeValue::Nil));
+        assert_eq!(globals.get(&"b".into()), None);
+        globals.insert("b".into(), RuntimeValue::Int(1));
+        globals.insert("c".into(), RuntimeValue::Int(2));
+        assert_eq!(globals.roots().count(), 5);
+        globals.restore();
+        assert_eq!(globals.get(&"b".into()), Some(&RuntimeValue::Bool(true)));
+        assert_eq!(globals.get(&"c".into()), None);
+        assert_eq!(globals.roots().count(), 2);
+    }
+
+    #[test]
+    fn it_links_nested_functions() {
+        let inner = ObjFunction::named(
+            "inner",
+            0,
+            Chunk {
+                globals: vec!["b".into(), "a".into()],
+                ..Default::default()
+            },
+        );
+        let mut outer = ObjFunction::script(Chunk {
+            constants: vec![inner.into()],
+            globals: vec!["a".into()],
+            ..Default::default()
+        });
+        let mut globals = Globals::default();
+        globals.link(&mut outer);
+        assert_eq!(outer.chunk.global_slots, vec![0]);
+        let ConstantValue::Function(inner) = &outer.chunk.constants[0] else {
+            panic!("Expected a function constant.");
+        };
+        assert_eq!(inner.chunk.global_slots, vec![1, 0]);
+    }
+}
@@ -1,16 +1,36 @@
-use std::{fmt::Debug, mem::swap, slice::Iter};
+use std::{
+    collections::hash_map::RandomState,
+    fmt::Debug,
+    hash::BuildHasher,
+    mem::swap,
+    slice::Iter,
+};
 
 use crate::{
     object::{HeapSize, ObjString},
     value::RuntimeValue,
 };
 
+pub mod globals;
+
+pub use globals::Globals;
+
 pub const MAX_TABLE_LOAD: f32 = 0.75;
 
-#[derive(Debug)]
+/// How far past its home bucket an insert may have to probe before the table
+/// stops trusting string hashes and rehashes with a keyed secondary hash.
+/// String hashes of similar keys cluster, so this leaves room for the runs
+/// hundreds of thousands of ordinary keys reach by chance.
+pub const MAX_PROBE_LENGTH: usize = 256;
+
+#[derive(Debug, Clone)]
 pub struct Table<T: Clone + Debug + HeapSize = RuntimeValue> {
     count: usize,
     entries: Vec<Option<TableEntry<T>>>,
+    /// Set once keys collided badly enough to exceed [`MAX_PROBE_LENGTH`].
+    /// String hashes are predictable, so keys chosen to collide under them
+    /// would otherwise make every insert and lookup linear in the table size.
+    secondary: Option<RandomState>,
 }
 
 impl<T: Clone + Debug + HeapSize> Default for Table<T> {
@@ -18,7 +38,29 @@ impl<T: Clone + Debug + HeapSize> Default for Table<T> {
         Self {
             count: 0,
             entries: vec![None; 8],
+            secondary: None,
+        }
+    }
+}
+
+/// How far live keys sit from the bucket their hash points at.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ProbeStats {
+    /// Live keys in the table
+    pub keys: usize,
+    /// The longest distance of any key from its home bucket
+    pub max: usize,
+    /// The sum of every key's distance from its home bucket
+    pub total: usize,
+}
+
+impl ProbeStats {
+    /// The average distance of a key from its home bucket.
+    pub fn mean(&self) -> f64 {
+        if self.keys == 0 {
+            return 0.0;
         }
+        self.total as f64 / self.keys as f64
     }
 }
 
@@ -42,7 +84,7 @@ impl<T: Clone + Debug + HeapSize> Table<T> {
             return None;
         }
 
-        let index = find_entry_index(&self.entries, key);
+        let (index, _) = find_entry_index(&self.entries, self.home(key), key);
         match &self.entries[index] {
             Some(e) => match &e.key {
                 Some(_) => match &e.value {
@@ -60,7 +102,7 @@ impl<T: Clone + Debug + HeapSize> Table<T> {
             return None;
         }
 
-        let index = find_entry_index(&self.entries, key);
+        let (index, _) = find_entry_index(&self.entries, self.home(key), key);
         match self.entries[index].as_mut() {
             Some(e) => match &e.key {
                 Some(_) => match &mut e.value {
@@ -78,7 +120,7 @@ impl<T: Clone + Debug + HeapSize> Table<T> {
             self.adjust_capacity();
         }
 
-        let index = find_entry_index(&self.entries, &key);
+        let (index, probes) = find_entry_index(&self.entries, self.home(&key), &key);
         let mut is_new_key = false;
         match &mut self.entries[index] {
             Some(e) => {
@@ -98,6 +140,11 @@ impl<T: Clone + Debug + HeapSize> Table<T> {
             }
         }
 
+        if probes > MAX_PROBE_LENGTH && self.secondary.is_none() {
+            self.secondary = Some(RandomState::new());
+            self.rehash(self.entries.len());
+        }
+
         is_new_key
     }
 
@@ -106,7 +153,7 @@ impl<T: Clone + Debug + HeapSize> Table<T> {
             return false;
         }
 
-        let index = find_entry_index(&self.entries, key);
+        let (index, _) = find_entry_index(&self.entries, self.home(key), key);
         match &mut self.entries[index] {
             Some(e) => {
                 if e.key.is_none() {
@@ -120,32 +167,52 @@ impl<T: Clone + Debug + HeapSize> Table<T> {
         }
     }
 
+    /// Finds the key equal to `chars` without building an `ObjString` first.
+    /// Tombstones are probed past, as the key may have been inserted after them.
     pub fn find_string(&self, chars: &str, hash: u32) -> Option<&ObjString> {
         if self.count == 0 {
             return None;
         }
 
-        let mut index = hash as usize & (self.entries.len() - 1);
-        loop {
+        let hash_home = match &self.secondary {
+            Some(state) => state.hash_one(chars) as usize,
+            None => hash as usize,
+        };
+        let mut index = hash_home & (self.entries.len() - 1);
+        // The table is never full, but bound the probe so corruption can't hang us
+        for _ in 0..self.entries.len() {
             match &self.entries[index] {
-                Some(e) => {
-                    let Some(ref key) = e.key else {
-                        continue;
-                    };
-                    if key.chars.len() == chars.len() && key.hash == hash && key.chars == chars {
+                Some(TableEntry { key: Some(key), .. }) => {
+                    if key.hash == hash && key.chars == chars {
                         return Some(key);
                     }
                 }
+                Some(_) => {}
                 None => return None,
             }
             index = (index + 1) & (self.entries.len() - 1);
         }
+        None
+    }
+
+    /// Whether the table has no live keys. Removed keys leave tombstones
+    /// counted as entries, so this looks at the entries themselves.
+    pub fn is_empty(&self) -> bool {
+        self.entries().next().is_none()
     }
 
     pub fn iter(&self) -> Iter<'_, Option<TableEntry<T>>> {
         self.entries.iter()
     }
 
+    /// The live key-value pairs, in no particular order.
+    pub fn entries(&self) -> impl Iterator<Item = (&ObjString, &T)> {
+        self.entries.iter().flatten().filter_map(|entry| {
+            let key = entry.key.as_ref()?;
+            Some((key, entry.value.as_ref()?))
+        })
+    }
+
     pub fn values(&self) -> Vec<&T> {
         self.entries
             .iter()
@@ -156,8 +223,44 @@ impl<T: Clone + Debug + HeapSize> Table<T> {
             .collect()
     }
 
+    /// How far live keys sit from their home buckets.
+    pub fn probe_stats(&self) -> ProbeStats {
+        let mask = self.entries.len() - 1;
+        let mut stats = ProbeStats::default();
+        for (index, entry) in self.entries.iter().enumerate() {
+            let Some(TableEntry { key: Some(key), .. }) = entry else {
+                continue;
+            };
+            let distance = index.wrapping_sub(self.home(key)) & mask;
+            stats.keys += 1;
+            stats.max = stats.max.max(distance);
+            stats.total += distance;
+        }
+        stats
+    }
+
+    /// Whether keys are placed by the keyed secondary hash rather than their
+    /// own string hash.
+    pub fn uses_secondary_hash(&self) -> bool {
+        self.secondary.is_some()
+    }
+
+    /// The hash placing `key`, before it is reduced to a bucket.
+    fn home(&self, key: &ObjString) -> usize {
+        match &self.secondary {
+            Some(state) => state.hash_one(key.chars.as_str()) as usize,
+            None => key.hash as usize,
+        }
+    }
+
     fn adjust_capacity(&mut self) {
-        let mut entries = vec![None; self.entries.len() * 2];
+        self.rehash(self.entries.len() * 2);
+    }
+
+    /// Moves the live entries into `capacity` fresh buckets, dropping
+    /// tombstones.
+    fn rehash(&mut self, capacity: usize) {
+        let mut entries = vec![None; capacity];
         swap(&mut self.entries, &mut entries);
         self.count = 0;
         for entry in entries {
@@ -166,7 +269,7 @@ impl<T: Clone + Debug + HeapSize> Table<T> {
                     let Some(ref key) = e.key else {
                         continue;
                     };
-                    let index = find_entry_index(&self.entries, key);
+                    let (index, _) = find_entry_index(&self.entries, self.home(key), key);
                     self.entries[index] = Some(e);
                     self.count += 1;
                 }
@@ -196,29 +299,34 @@ impl<T: Clone + Debug + HeapSize> HeapSize for TableEntry<T> {
     }
 }
 
+/// The bucket holding `key`, or the one it should be inserted into, along
+/// with how many buckets past `home` the probe went.
 fn find_entry_index<T: Clone + Debug + HeapSize>(
-    entries: &Vec<Option<TableEntry<T>>>,
+    entries: &[Option<TableEntry<T>>],
+    home: usize,
     key: &ObjString,
-) -> usize {
-    let mut index = (key.hash as usize) & (entries.len() - 1);
+) -> (usize, usize) {
+    let mut index = home & (entries.len() - 1);
     let mut tombstone = None;
+    let mut probes = 0;
     loop {
         match &entries[index] {
             Some(entry) => match &entry.key {
                 Some(k) => {
                     if k == key {
-                        return index;
+                        return (index, probes);
                     }
                 }
                 None => tombstone = Some(index),
             },
             None => match tombstone {
-                Some(t) => return t,
-                None => return index,
+                Some(t) => return (t, probes),
+                None => return (index, probes),
             },
         }
 
-        index = (index + 1) & (entries.capacity() - 1);
+        index = (index + 1) & (entries.len() - 1);
+        probes += 1;
     }
 }
 
@@ -330,6 +438,21 @@ mod test {
         assert_eq!(table.count, 128);
     }
 
+    #[test]
+    fn it_iterates_live_entries() {
+        let mut table = Table::default();
+        for key in ["a", "b", "c"] {
+            table.insert(key.into(), RuntimeValue::Bool(true));
+        }
+        table.remove(&("b".into()));
+        let mut keys = table
+            .entries()
+            .map(|(key, _)| key.chars.clone())
+            .collect::<Vec<_>>();
+        keys.sort();
+        assert_eq!(keys, vec!["a", "c"]);
+    }
+
     #[test]
     fn it_removes_non_existent_value() {
         let mut table = Table::default();
@@ -339,6 +462,129 @@ mod test {
         assert!(!table.remove(&("test".into())));
     }
 
+    #[test]
+    fn it_finds_a_string_key_past_tombstones() {
+        let mut table = Table::default();
+        // Fill a probe sequence, then remove its head to leave a tombstone
+        for i in 0..5 {
+            assert!(table.insert(format!("{i}").into(), RuntimeValue::Nil));
+        }
+        let keys: Vec<ObjString> = (0..5).map(|i| format!("{i}").into()).collect();
+        for key in &keys {
+            assert!(table.remove(key));
+        }
+        assert!(table.insert("kept".into(), RuntimeValue::Nil));
+        let string = ObjString::from("kept");
+        let key = table
+            .find_string(&string.chars, string.hash)
+            .expect("Failed to find string");
+        assert_eq!(key, &string);
+        assert!(table
+            .find_string("missing", ObjString::from("missing").hash)
+            .is_none());
+    }
+
+    #[test]
+    fn it_finds_colliding_string_keys() {
+        let mut table = Table::default();
+        let first = ObjString::from("first");
+        // Pick a second key landing in the same bucket as the first
+        let second = (0..)
+            .map(|i| ObjString::from(format!("second{i}")))
+            .find(|s| s.hash & 7 == first.hash & 7)
+            .expect("Failed to find a colliding key");
+        assert!(table.insert(first.clone(), RuntimeValue::Nil));
+        assert!(table.insert(second.clone(), RuntimeValue::Bool(true)));
+        assert!(table.remove(&first));
+        let key = table
+            .find_string(&second.chars, second.hash)
+            .expect("Failed to find string");
+        assert_eq!(key, &second);
+        assert!(table.find_string(&first.chars, first.hash).is_none());
+    }
+
+    #[test]
+    fn it_terminates_on_a_table_of_tombstones() {
+        let mut table = Table::default();
+        for i in 0..5 {
+            let key: ObjString = format!("{i}").into();
+            assert!(table.insert(key.clone(), RuntimeValue::Nil));
+            assert!(table.remove(&key));
+        }
+        assert!(table
+            .find_string("absent", ObjString::from("absent").hash)
+            .is_none());
+    }
+
+    /// `2^rounds` distinct keys sharing one full string hash. Each round picks
+    /// two pairs of characters driving the hash from the current state to the
+    /// same next state, so every way of picking one of each pair collides.
+    fn colliding_keys(rounds: u32) -> Vec<String> {
+        let step = |state: u32, c: char| (state ^ c as u32).wrapping_mul(16777619);
+        let mut state = ObjString::from("").hash;
+        let mut pairs = Vec::new();
+        for _ in 0..rounds {
+            // Two first characters whose hashes agree above the low 20 bits,
+            // which are all a second character can flip
+            let mut seen = std::collections::HashMap::new();
+            let (a, other_a) = ('\u{4e00}'..)
+                .find_map(|a| seen.insert(step(state, a) >> 20, a).map(|other| (other, a)))
+                .expect("Failed to find first characters");
+            let difference = step(state, a) ^ step(state, other_a);
+            let (b, other_b) = ('a'..)
+                .find_map(|b| Some((b, char::from_u32(b as u32 ^ difference)?)))
+                .expect("Failed to find second characters");
+            state = step(step(state, a), b);
+            pairs.push(([a, b], [other_a, other_b]));
+        }
+        (0..1usize << rounds)
+            .map(|picks| {
+                pairs
+                    .iter()
+                    .enumerate()
+                    .flat_map(|(i, (a, b))| if picks >> i & 1 == 0 { *a } else { *b })
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn it_rehashes_keys_with_colliding_hashes() {
+        let keys: Vec<ObjString> = colliding_keys(10).into_iter().map(Into::into).collect();
+        assert!(keys.iter().all(|key| key.hash == keys[0].hash));
+
+        let mut table = Table::default();
+        for (i, key) in keys.iter().enumerate() {
+            assert!(table.insert(key.clone(), RuntimeValue::Number(i as f64)));
+            // Without the secondary hash every key lands in one cluster, so
+            // the probes for the last key would cover the whole table
+            assert!(table.probe_stats().max <= MAX_PROBE_LENGTH);
+        }
+        assert!(table.uses_secondary_hash());
+        let stats = table.probe_stats();
+        assert_eq!(stats.keys, keys.len());
+        assert!(stats.mean() < 4.0);
+
+        for (i, key) in keys.iter().enumerate() {
+            assert_eq!(table.get(key), Some(&RuntimeValue::Number(i as f64)));
+            assert_eq!(table.find_string(&key.chars, key.hash), Some(key));
+        }
+        assert!(table.remove(&keys[0]));
+        assert!(table.get(&keys[0]).is_none());
+        assert!(table.find_string(&keys[0].chars, keys[0].hash).is_none());
+    }
+
+    #[test]
+    fn it_keeps_string_hashes_for_ordinary_keys() {
+        let mut table = Table::default();
+        for i in 0..4096 {
+            assert!(table.insert(format!("key{i}").into(), RuntimeValue::Nil));
+        }
+        assert!(!table.uses_secondary_hash());
+        assert_eq!(table.probe_stats().keys, 4096);
+        assert!(table.probe_stats().max <= MAX_PROBE_LENGTH);
+    }
+
     #[test]
     fn it_finds_a_string_key() {
         let mut table = Table::default();
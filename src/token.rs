@@ -1,7 +1,9 @@
+use crate::arena::Lexeme;
+
 #[derive(Debug, Default, Clone, Eq, PartialEq)]
 pub struct Token {
     pub kind: TokenType,
-    pub lexeme: String,
+    pub lexeme: Lexeme,
     pub line: usize,
 }
 
@@ -12,13 +14,23 @@ pub enum TokenType {
     RightParen,
     LeftBrace,
     RightBrace,
+    LeftBracket,
+    RightBracket,
+    Colon,
     Comma,
     Dot,
+    DotDot,
+    DotDotEqual,
+    DotDotDot,
     Minus,
     Plus,
     Semicolon,
     Slash,
     Star,
+    Ampersand,
+    Pipe,
+    Caret,
+    Tilde,
     // One or two character tokens
     Bang,
     BangEqual,
@@ -26,12 +38,16 @@ pub enum TokenType {
     EqualEqual,
     Greater,
     GreaterEqual,
+    GreaterGreater,
     Less,
     LessEqual,
+    LessLess,
     // Literals
     Identifier,
     String,
     Number,
+    /// A number literal without a fractional part
+    Integer,
     // Keywords
     And,
     Class,
@@ -40,6 +56,8 @@ pub enum TokenType {
     For,
     Fun,
     If,
+    Import,
+    In,
     #[default]
     Nil,
     Or,
@@ -50,6 +68,48 @@ pub enum TokenType {
     True,
     Var,
     While,
-    Error,
+    With,
+    /// Text the scanner couldn't make a token of, which is the lexeme
+    Error(ScanError),
     Eof,
 }
+
+/// Why the scanner rejected the text of an error token.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ScanError {
+    /// A run of characters that can't start any token
+    UnexpectedCharacter,
+    /// A string without its closing quote, running to the end of the source
+    UnterminatedString,
+    /// A `_` in a number that isn't between two digits
+    MisplacedSeparator,
+    /// A digit that doesn't belong in a literal of this radix
+    InvalidDigit { digit: char, radix: u32 },
+    /// A `0x` or `0b` prefix without digits
+    MissingDigits,
+    /// The source couldn't be read to the end. With no offending text, the
+    /// lexeme is the reader's error instead.
+    ReadFailed,
+}
+
+impl ScanError {
+    /// The message reported for an error token with `lexeme`.
+    pub fn message(self, lexeme: &str) -> String {
+        match self {
+            Self::UnexpectedCharacter if lexeme.chars().count() > 1 => {
+                format!("Unexpected characters '{lexeme}'")
+            }
+            Self::UnexpectedCharacter => format!("Unexpected character '{lexeme}'"),
+            Self::UnterminatedString => "Unterminated string.".into(),
+            Self::MisplacedSeparator => "Invalid '_' in number literal.".into(),
+            Self::InvalidDigit { digit, radix } => {
+                let name = if radix == 2 { "binary" } else { "hex" };
+                format!("Invalid digit '{digit}' in {name} literal.")
+            }
+            Self::MissingDigits => {
+                format!("Expect digits after '{}'.", lexeme.get(..2).unwrap_or(lexeme))
+            }
+            Self::ReadFailed => format!("Failed to read source: {lexeme}."),
+        }
+    }
+}
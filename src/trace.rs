@@ -0,0 +1,470 @@
+//! Events describing what the machine does as it runs, for tracing tools.
+//!
+//! A [`TraceSink`] registered with [`VM::set_trace_sink`](crate::VM::set_trace_sink)
+//! receives a [`TraceEvent`] for every script compiled, instruction executed,
+//! call, return, allocation and garbage collection. [`TextTrace`] renders the
+//! events as the `debug` build always has, and [`JsonTrace`] as one JSON
+//! object per line for tools to read.
+//!
+//! A VM can also keep just its latest events in a flight recorder, set up with
+//! [`VM::set_flight_recorder`](crate::VM::set_flight_recorder), and report
+//! them with the next runtime error.
+
+use std::{
+    collections::VecDeque,
+    fmt::{self, Debug, Display, Write as _},
+    io::{stderr, Write},
+};
+
+use crate::{heap::json_string, value::RuntimeValue};
+
+/// Something the machine did, passed to a [`TraceSink`].
+#[derive(Debug, Clone, Copy)]
+pub enum TraceEvent<'a> {
+    /// A script is about to be compiled
+    Compile,
+    /// A function of a script about to run, disassembled. Each function
+    /// nested in the script follows its own event.
+    Code {
+        function: &'a str,
+        disassembly: &'a str,
+    },
+    /// An instruction is about to execute
+    InstructionExecuted {
+        instruction: &'static str,
+        /// The instruction's offset in its chunk
+        offset: usize,
+        line: usize,
+        /// How many call frames are active
+        depth: usize,
+        stack: TraceStack<'a>,
+    },
+    /// A closure was called, pushing a frame at `depth`
+    Call {
+        function: &'a str,
+        arg_count: usize,
+        depth: usize,
+    },
+    /// A function returned, popping its frame at `depth`
+    Return { function: &'a str, depth: usize },
+    /// An object of `kind` was allocated
+    Alloc { kind: &'static str, size: usize },
+    /// A garbage collection is starting
+    GcStart { bytes_allocated: usize },
+    /// A garbage collection finished, shrinking the heap from `before` bytes
+    /// to `after`. The heap is measured afresh, so `after` can exceed
+    /// `before` when objects grew since they were allocated.
+    GcEnd {
+        before: usize,
+        after: usize,
+        next_gc: usize,
+    },
+}
+
+/// Renders the event on one line, as the flight recorder keeps it.
+/// Disassembly is left out.
+impl Display for TraceEvent<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            TraceEvent::Compile => write!(f, "compile"),
+            TraceEvent::Code { function, .. } => write!(f, "code of {function}"),
+            TraceEvent::InstructionExecuted {
+                instruction,
+                line,
+                stack,
+                ..
+            } => {
+                write!(f, "{instruction} on line {line}")?;
+                if !stack.is_empty() {
+                    write!(f, " with {stack}")?;
+                }
+                Ok(())
+            }
+            TraceEvent::Call {
+                function,
+                arg_count,
+                depth,
+            } => write!(
+                f,
+                "call {function} with {arg_count} arguments at depth {depth}"
+            ),
+            TraceEvent::Return { function, depth } => {
+                write!(f, "return from {function} at depth {depth}")
+            }
+            TraceEvent::Alloc { kind, size } => write!(f, "alloc {kind} of {size} bytes"),
+            TraceEvent::GcStart { bytes_allocated } => {
+                write!(f, "gc start at {bytes_allocated} bytes")
+            }
+            TraceEvent::GcEnd {
+                before,
+                after,
+                next_gc,
+            } => write!(
+                f,
+                "gc end from {before} to {after} bytes, next at {next_gc}"
+            ),
+        }
+    }
+}
+
+/// The value stack at an instruction, bottom first.
+#[derive(Clone, Copy)]
+pub struct TraceStack<'a>(pub(crate) &'a [RuntimeValue]);
+
+impl<'a> TraceStack<'a> {
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Each value as the trace shows it.
+    pub fn values(&self) -> impl Iterator<Item = String> + 'a {
+        self.0.iter().map(|value| format!("{value:#}"))
+    }
+}
+
+impl Debug for TraceStack<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.values()).finish()
+    }
+}
+
+/// Renders the stack as `[ a ][ b ]`.
+impl Display for TraceStack<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for value in self.0 {
+            write!(f, "[ {value:#} ]")?;
+        }
+        Ok(())
+    }
+}
+
+/// Receives the events of a traced VM.
+pub trait TraceSink {
+    fn event(&mut self, event: &TraceEvent);
+}
+
+impl<F: FnMut(&TraceEvent)> TraceSink for F {
+    fn event(&mut self, event: &TraceEvent) {
+        self(event)
+    }
+}
+
+/// Writes events as the `debug` build's trace: disassembled scripts, the
+/// stack before each instruction and garbage collections. Calls, returns and
+/// allocations aren't shown.
+pub struct TextTrace<W: Write>(W);
+
+impl<W: Write> TextTrace<W> {
+    pub fn new(out: W) -> Self {
+        Self(out)
+    }
+}
+
+impl<W: Write> TraceSink for TextTrace<W> {
+    fn event(&mut self, event: &TraceEvent) {
+        let out = &mut self.0;
+        let _ = match *event {
+            TraceEvent::Compile => writeln!(out, "========== CODE =========="),
+            TraceEvent::Code {
+                function,
+                disassembly,
+            } => writeln!(out, "== {function} ==\n{disassembly}"),
+            TraceEvent::InstructionExecuted {
+                instruction, stack, ..
+            } => writeln!(out, "\n{stack}\n{instruction}"),
+            TraceEvent::GcStart { .. } => writeln!(out, "-- gc begin"),
+            TraceEvent::GcEnd {
+                before,
+                after,
+                next_gc,
+            } => writeln!(
+                out,
+                "-- gc end\ncollected {} bytes (from {before} to {after}) next at {next_gc}",
+                before.saturating_sub(after)
+            ),
+            TraceEvent::Call { .. } | TraceEvent::Return { .. } | TraceEvent::Alloc { .. } => {
+                Ok(())
+            }
+        };
+    }
+}
+
+/// Writes each event as a JSON object on its own line, named by its
+/// `"event"`: `compile`, `code`, `instruction`, `call`, `return`, `alloc`,
+/// `gc_start` or `gc_end`. The other keys are the event's fields.
+pub struct JsonTrace<W: Write>(W);
+
+impl<W: Write> JsonTrace<W> {
+    pub fn new(out: W) -> Self {
+        Self(out)
+    }
+}
+
+impl<W: Write> TraceSink for JsonTrace<W> {
+    fn event(&mut self, event: &TraceEvent) {
+        let out = &mut self.0;
+        let _ = match *event {
+            TraceEvent::Compile => writeln!(out, r#"{{"event":"compile"}}"#),
+            TraceEvent::Code {
+                function,
+                disassembly,
+            } => writeln!(
+                out,
+                r#"{{"event":"code","function":{},"disassembly":{}}}"#,
+                json_string(function),
+                json_string(disassembly)
+            ),
+            TraceEvent::InstructionExecuted {
+                instruction,
+                offset,
+                line,
+                depth,
+                stack,
+            } => {
+                let stack = stack
+                    .values()
+                    .map(|value| json_string(&value))
+                    .collect::<Vec<_>>()
+                    .join(",");
+                writeln!(
+                    out,
+                    r#"{{"event":"instruction","instruction":"{instruction}","offset":{offset},"line":{line},"depth":{depth},"stack":[{stack}]}}"#
+                )
+            }
+            TraceEvent::Call {
+                function,
+                arg_count,
+                depth,
+            } => writeln!(
+                out,
+                r#"{{"event":"call","function":{},"arg_count":{arg_count},"depth":{depth}}}"#,
+                json_string(function)
+            ),
+            TraceEvent::Return { function, depth } => writeln!(
+                out,
+                r#"{{"event":"return","function":{},"depth":{depth}}}"#,
+                json_string(function)
+            ),
+            TraceEvent::Alloc { kind, size } => {
+                writeln!(out, r#"{{"event":"alloc","kind":"{kind}","size":{size}}}"#)
+            }
+            TraceEvent::GcStart { bytes_allocated } => writeln!(
+                out,
+                r#"{{"event":"gc_start","bytes_allocated":{bytes_allocated}}}"#
+            ),
+            TraceEvent::GcEnd {
+                before,
+                after,
+                next_gc,
+            } => writeln!(
+                out,
+                r#"{{"event":"gc_end","before":{before},"after":{after},"next_gc":{next_gc}}}"#
+            ),
+        };
+    }
+}
+
+/// The latest events, rendered a line each and oldest first, up to a fixed
+/// number.
+#[derive(Debug)]
+pub(crate) struct FlightRecorder {
+    events: VecDeque<String>,
+    capacity: usize,
+}
+
+impl FlightRecorder {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            events: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    /// Keeps `event`, dropping the oldest event once full. Disassembly is
+    /// too long to be worth keeping.
+    fn record(&mut self, event: &TraceEvent) {
+        if self.capacity == 0 || matches!(event, TraceEvent::Code { .. }) {
+            return;
+        }
+        // Reuse the oldest event's buffer rather than allocating a new one
+        let mut line = match self.events.len() == self.capacity {
+            true => self.events.pop_front().unwrap_or_default(),
+            false => String::new(),
+        };
+        line.clear();
+        let _ = write!(line, "{event}");
+        self.events.push_back(line);
+    }
+
+    /// Every event kept, oldest first, leaving the recorder empty.
+    pub(crate) fn take(&mut self) -> Vec<String> {
+        self.events.drain(..).collect()
+    }
+}
+
+/// The sink a VM's events go to and the flight recorder keeping them, if
+/// any.
+pub(crate) struct Tracer {
+    sink: Option<Box<dyn TraceSink>>,
+    pub(crate) recorder: Option<FlightRecorder>,
+}
+
+/// The `debug` build traces to stderr as text from the start.
+impl Default for Tracer {
+    fn default() -> Self {
+        let mut tracer = Self {
+            sink: None,
+            recorder: None,
+        };
+        if cfg!(feature = "debug") {
+            tracer.set_sink(TextTrace::new(stderr()));
+        }
+        tracer
+    }
+}
+
+impl Tracer {
+    /// Sends events to `sink`, replacing the sink before.
+    pub(crate) fn set_sink(&mut self, sink: impl TraceSink + 'static) {
+        self.sink = Some(Box::new(sink));
+    }
+
+    /// Whether events are wanted, so the ones costly to build can be skipped.
+    pub(crate) fn is_active(&self) -> bool {
+        self.sink.is_some() || self.recorder.is_some()
+    }
+
+    /// Whether disassembly is wanted, which only a sink shows.
+    pub(crate) fn wants_code(&self) -> bool {
+        self.sink.is_some()
+    }
+
+    pub(crate) fn emit(&mut self, event: TraceEvent) {
+        if let Some(sink) = self.sink.as_mut() {
+            sink.event(&event);
+        }
+        if let Some(recorder) = self.recorder.as_mut() {
+            recorder.record(&event);
+        }
+    }
+}
+
+impl Debug for Tracer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Tracer")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn render(sink: &mut dyn TraceSink) {
+        let stack = [RuntimeValue::Number(1.0), RuntimeValue::Nil];
+        let events = [
+            TraceEvent::Compile,
+            TraceEvent::Code {
+                function: "<script>",
+                disassembly: "0000    1 OP_NIL",
+            },
+            TraceEvent::InstructionExecuted {
+                instruction: "OP_ADD",
+                offset: 3,
+                line: 2,
+                depth: 1,
+                stack: TraceStack(&stack),
+            },
+            TraceEvent::Call {
+                function: "<fn f>",
+                arg_count: 2,
+                depth: 2,
+            },
+            TraceEvent::Alloc {
+                kind: "string",
+                size: 40,
+            },
+            TraceEvent::GcStart {
+                bytes_allocated: 100,
+            },
+            TraceEvent::GcEnd {
+                before: 100,
+                after: 60,
+                next_gc: 120,
+            },
+        ];
+        for event in &events {
+            sink.event(event);
+        }
+    }
+
+    #[test]
+    fn it_renders_events_as_text() {
+        let mut out = vec![];
+        render(&mut TextTrace::new(&mut out));
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "========== CODE ==========\n== <script> ==\n0000    1 OP_NIL\n\n[ 1 ][ nil ]\nOP_ADD\n-- gc begin\n-- gc end\ncollected 40 bytes (from 100 to 60) next at 120\n"
+        );
+
+        // Objects that grew since they were charged for can outweigh the garbage
+        let mut out = vec![];
+        TextTrace::new(&mut out).event(&TraceEvent::GcEnd {
+            before: 100,
+            after: 120,
+            next_gc: 240,
+        });
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "-- gc end\ncollected 0 bytes (from 100 to 120) next at 240\n"
+        );
+    }
+
+    #[test]
+    fn it_keeps_the_latest_events_in_a_flight_recorder() {
+        let mut recorder = FlightRecorder::new(3);
+        render(&mut |event: &TraceEvent| recorder.record(event));
+        assert_eq!(
+            recorder.take(),
+            vec![
+                "alloc string of 40 bytes",
+                "gc start at 100 bytes",
+                "gc end from 100 to 60 bytes, next at 120",
+            ]
+        );
+        assert!(recorder.take().is_empty());
+
+        let stack = [RuntimeValue::Number(1.0)];
+        recorder.record(&TraceEvent::InstructionExecuted {
+            instruction: "OP_NEGATE",
+            offset: 0,
+            line: 4,
+            depth: 1,
+            stack: TraceStack(&stack),
+        });
+        assert_eq!(recorder.take(), vec!["OP_NEGATE on line 4 with [ 1 ]"]);
+    }
+
+    #[test]
+    fn it_renders_events_as_json_lines() {
+        let mut out = vec![];
+        render(&mut JsonTrace::new(&mut out));
+        let out = String::from_utf8(out).unwrap();
+        let lines: Vec<_> = out.lines().collect();
+        assert_eq!(
+            lines,
+            vec![
+                r#"{"event":"compile"}"#,
+                r#"{"event":"code","function":"<script>","disassembly":"0000    1 OP_NIL"}"#,
+                r#"{"event":"instruction","instruction":"OP_ADD","offset":3,"line":2,"depth":1,"stack":["1","nil"]}"#,
+                r#"{"event":"call","function":"<fn f>","arg_count":2,"depth":2}"#,
+                r#"{"event":"alloc","kind":"string","size":40}"#,
+                r#"{"event":"gc_start","bytes_allocated":100}"#,
+                r#"{"event":"gc_end","before":100,"after":60,"next_gc":120}"#,
+            ]
+        );
+    }
+}
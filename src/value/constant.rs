@@ -1,24 +1,37 @@
 use std::fmt::Display;
 
-use crate::{
-    error::Error,
-    object::{ObjFunction, ObjString},
-    value::RuntimeValue,
-};
+use crate::object::{ObjFunction, ObjString};
+
+use super::fmt_number;
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum ConstantValue {
+    Nil,
+    Bool(bool),
     Number(f64),
+    Int(i64),
     String(ObjString),
     Function(Box<ObjFunction>),
 }
 
+impl From<bool> for ConstantValue {
+    fn from(value: bool) -> Self {
+        Self::Bool(value)
+    }
+}
+
 impl From<f64> for ConstantValue {
     fn from(value: f64) -> Self {
         Self::Number(value)
     }
 }
 
+impl From<i64> for ConstantValue {
+    fn from(value: i64) -> Self {
+        Self::Int(value)
+    }
+}
+
 impl From<String> for ConstantValue {
     fn from(value: String) -> Self {
         Self::String(value.into())
@@ -37,21 +50,13 @@ impl From<ObjFunction> for ConstantValue {
     }
 }
 
-impl TryFrom<RuntimeValue> for ConstantValue {
-    type Error = Error;
-
-    fn try_from(value: RuntimeValue) -> Result<Self, Self::Error> {
-        match value {
-            RuntimeValue::Number(n) => Ok(Self::Number(n)),
-            _ => Err(Error::Runtime),
-        }
-    }
-}
-
 impl Display for ConstantValue {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Self::Number(n) => write!(f, "{n}"),
+            Self::Nil => write!(f, "nil"),
+            Self::Bool(b) => write!(f, "{b}"),
+            Self::Number(n) => fmt_number(*n, f),
+            Self::Int(n) => write!(f, "{n}"),
             Self::String(s) => write!(f, "{s}"),
             Self::Function(fun) => write!(f, "{fun}"),
         }
@@ -0,0 +1,223 @@
+use std::fmt::Display;
+
+use crate::object::{ObjList, ObjRange, Pointer};
+
+use super::{fmt_number, RuntimeValue};
+
+/// An owned snapshot of a runtime value, safe to keep after the VM that
+/// produced it has collected the underlying objects or been dropped.
+#[derive(Clone, Debug, PartialEq)]
+pub enum LoxValue {
+    Nil,
+    Bool(bool),
+    Number(f64),
+    Int(i64),
+    String(String),
+    List(Vec<LoxValue>),
+    Range {
+        start: f64,
+        end: f64,
+        inclusive: bool,
+    },
+    /// Functions, classes, instances and other objects a host can't take
+    /// apart, kept as their printed form
+    Object(String),
+}
+
+impl LoxValue {
+    fn from_runtime(value: RuntimeValue, enclosing: &mut Vec<Pointer<ObjList>>) -> Self {
+        match value {
+            RuntimeValue::Nil => Self::Nil,
+            RuntimeValue::Bool(b) => Self::Bool(b),
+            RuntimeValue::Number(n) => Self::Number(n),
+            RuntimeValue::Int(n) => Self::Int(n),
+            RuntimeValue::String(s) => Self::String(s.chars.to_string()),
+            RuntimeValue::Range(r) => Self::Range {
+                start: r.start,
+                end: r.end,
+                inclusive: r.inclusive,
+            },
+            RuntimeValue::List(list) if enclosing.contains(&list) => Self::Object("[...]".into()),
+            RuntimeValue::List(list) => {
+                enclosing.push(list);
+                let items = list
+                    .items
+                    .iter()
+                    .map(|&item| Self::from_runtime(item, enclosing))
+                    .collect();
+                enclosing.pop();
+                Self::List(items)
+            }
+            RuntimeValue::BoundMethod(_)
+            | RuntimeValue::Class(_)
+            | RuntimeValue::Closure(_)
+            | RuntimeValue::Function(_)
+            | RuntimeValue::Instance(_)
+            | RuntimeValue::Native(_)
+            | RuntimeValue::StringBuilder(_)
+            | RuntimeValue::Upvalue(_) => Self::Object(value.to_string()),
+        }
+    }
+}
+
+/// Copies `value` out of the heap. The objects it points to must still be
+/// alive, which holds for anything reachable from the VM's globals or stack.
+/// A list that contains itself is cut off with `[...]`.
+impl From<RuntimeValue> for LoxValue {
+    fn from(value: RuntimeValue) -> Self {
+        Self::from_runtime(value, &mut Vec::new())
+    }
+}
+
+impl From<bool> for LoxValue {
+    fn from(value: bool) -> Self {
+        Self::Bool(value)
+    }
+}
+
+impl From<f64> for LoxValue {
+    fn from(value: f64) -> Self {
+        Self::Number(value)
+    }
+}
+
+impl From<&str> for LoxValue {
+    fn from(value: &str) -> Self {
+        Self::String(value.to_string())
+    }
+}
+
+impl Display for LoxValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Nil => write!(f, "nil"),
+            Self::Bool(b) => write!(f, "{b}"),
+            Self::Number(n) => fmt_number(*n, f),
+            Self::Int(n) => write!(f, "{n}"),
+            Self::String(s) => write!(f, "{s}"),
+            Self::List(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{item}")?;
+                }
+                write!(f, "]")
+            }
+            Self::Range {
+                start,
+                end,
+                inclusive,
+            } => Display::fmt(
+                &ObjRange {
+                    start: *start,
+                    end: *end,
+                    inclusive: *inclusive,
+                },
+                f,
+            ),
+            Self::Object(s) => write!(f, "{s}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        chunk::Chunk,
+        object::{
+            obj_class::MethodTable, obj_native::NativeFunction, ObjBoundMethod, ObjClass, ObjClosure, ObjFunction,
+            ObjInstance, ObjNative, ObjUpvalue, Store,
+        },
+        table::Table,
+    };
+
+    use super::*;
+
+    #[test]
+    fn it_converts_every_runtime_value() {
+        let mut store = Store::default();
+        let function = store.insert_function(ObjFunction::named("f", 0, Chunk::default()));
+        let closure = store.insert_closure(ObjClosure {
+            function,
+            upvalues: Vec::new(),
+        });
+        let name = store.insert_string("A".into());
+        let class = store.insert_class(ObjClass {
+            name,
+            methods: MethodTable::default(),
+            statics: MethodTable::default(),
+            superclass: None,
+        });
+        let instance = store.insert_instance(ObjInstance {
+            class,
+            fields: Table::default(),
+        });
+        let bound_method = store.insert_bound_method(ObjBoundMethod {
+            receiver: instance.into(),
+            method: closure,
+        });
+        let native = store.insert_native(ObjNative {
+            arity: 0,
+            optional: 0,
+            function: NativeFunction::Builtin(|_, _| Ok(RuntimeValue::Nil)),
+        });
+        let upvalue = store.insert_upvalue(ObjUpvalue::Open { location: 0 });
+        let range = store.insert_range(ObjRange {
+            start: 1.0,
+            end: 3.0,
+            inclusive: false,
+        });
+        let string = store.insert_string("s".into());
+
+        let cases: [(RuntimeValue, LoxValue); 14] = [
+            (RuntimeValue::Nil, LoxValue::Nil),
+            (true.into(), LoxValue::Bool(true)),
+            (2.5.into(), LoxValue::Number(2.5)),
+            (3i64.into(), LoxValue::Int(3)),
+            (string.into(), LoxValue::String("s".into())),
+            (
+                range.into(),
+                LoxValue::Range {
+                    start: 1.0,
+                    end: 3.0,
+                    inclusive: false,
+                },
+            ),
+            (function.into(), LoxValue::Object("<fn f>".into())),
+            (closure.into(), LoxValue::Object("<fn f>".into())),
+            (class.into(), LoxValue::Object("A".into())),
+            (instance.into(), LoxValue::Object("A instance".into())),
+            (bound_method.into(), LoxValue::Object("<fn f>".into())),
+            (native.into(), LoxValue::Object("<native fn>".into())),
+            (upvalue.into(), LoxValue::Object("upvalue".into())),
+            (
+                store
+                    .insert_list(ObjList {
+                        items: vec![1.0.into(), string.into()],
+                    })
+                    .into(),
+                LoxValue::List(vec![1.0.into(), "s".into()]),
+            ),
+        ];
+        for (runtime, expected) in cases {
+            assert_eq!(LoxValue::from(runtime), expected);
+            assert_eq!(LoxValue::from(runtime).to_string(), runtime.to_string());
+        }
+    }
+
+    #[test]
+    fn it_cuts_off_cyclic_lists() {
+        let mut store = Store::default();
+        let mut list = store.insert_list(ObjList::default());
+        let outer = store.insert_list(ObjList {
+            items: vec![list.into()],
+        });
+        list.items.push(outer.into());
+        assert_eq!(
+            LoxValue::from(RuntimeValue::from(outer)),
+            LoxValue::List(vec![LoxValue::List(vec![LoxValue::Object("[...]".into())])])
+        );
+    }
+}
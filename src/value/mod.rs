@@ -1,5 +1,33 @@
+//! Values in their three forms, one per owner:
+//!
+//! - [`ConstantValue`]: literals and functions owned by a compiled chunk. They
+//!   become runtime values through [`Store::load_constant`], which copies any
+//!   payload onto the heap.
+//! - [`RuntimeValue`]: a `Copy` handle into the [`Store`]. Its objects live
+//!   only as long as the garbage collector can reach them.
+//! - [`LoxValue`]: an owned snapshot for hosts, made with `LoxValue::from`
+//!   while the runtime value is still reachable.
+//!
+//! [`Store`]: crate::object::Store
+//! [`Store::load_constant`]: crate::object::Store::load_constant
+
 pub mod constant;
+pub mod lox;
 pub mod runtime;
 
 pub use constant::ConstantValue;
+pub use lox::LoxValue;
 pub use runtime::RuntimeValue;
+
+use std::fmt;
+
+/// Writes `n` as Rust does, except that very large or very small numbers
+/// get an exponent rather than hundreds of digits, e.g. `1e-9` or `1.5e300`.
+pub(crate) fn fmt_number(n: f64, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let magnitude = n.abs();
+    if n.is_finite() && n != 0.0 && !(1e-6..1e21).contains(&magnitude) {
+        write!(f, "{n:e}")
+    } else {
+        write!(f, "{n}")
+    }
+}
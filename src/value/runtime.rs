@@ -3,30 +3,95 @@ use std::{fmt::Display, hash::Hash};
 use crate::{
     error::Error,
     object::{
-        HeapSize, ObjBoundMethod, ObjClass, ObjClosure, ObjFunction, ObjInstance, ObjNative,
-        ObjString, ObjUpvalue, Pointer,
+        HeapSize, ObjBoundMethod, ObjClass, ObjClosure, ObjFunction, ObjInstance, ObjList,
+        ObjNative, ObjRange, ObjString, ObjStringBuilder, ObjUpvalue, ObjectId, Pointer,
     },
 };
 
-use super::constant::ConstantValue;
+use super::fmt_number;
 
 #[derive(Copy, Clone, Debug, Default, PartialEq)]
 pub enum RuntimeValue {
     Bool(bool),
     Number(f64),
+    /// An integer, only produced when integers are enabled
+    Int(i64),
     BoundMethod(Pointer<ObjBoundMethod>),
     Class(Pointer<ObjClass>),
     Closure(Pointer<ObjClosure>),
     Function(Pointer<ObjFunction>),
     Instance(Pointer<ObjInstance>),
+    List(Pointer<ObjList>),
     Native(Pointer<ObjNative>),
+    Range(Pointer<ObjRange>),
     String(Pointer<ObjString>),
+    StringBuilder(Pointer<ObjStringBuilder>),
     Upvalue(Pointer<ObjUpvalue>),
     #[default]
     Nil,
 }
 
 impl RuntimeValue {
+    /// Lox equality, under which integers equal the floats of the same value.
+    pub fn lox_eq(&self, other: &Self) -> bool {
+        match (*self, *other) {
+            (Self::Int(a), Self::Number(b)) | (Self::Number(b), Self::Int(a)) => a as f64 == b,
+            (a, b) => a == b,
+        }
+    }
+
+    /// The value as an integer, if it is one or is a float with no
+    /// fractional part that fits in an `i64`.
+    pub fn as_integer(&self) -> Option<i64> {
+        match *self {
+            Self::Int(n) => Some(n),
+            Self::Number(n) if n.fract() == 0.0 && n >= i64::MIN as f64 && n < i64::MAX as f64 => {
+                Some(n as i64)
+            }
+            _ => None,
+        }
+    }
+
+    /// The identity of the object this value refers to, or `None` for
+    /// booleans, numbers and `nil`, which have none.
+    pub fn id(&self) -> Option<ObjectId> {
+        Some(match self {
+            Self::BoundMethod(pointer) => pointer.id(),
+            Self::Class(pointer) => pointer.id(),
+            Self::Closure(pointer) => pointer.id(),
+            Self::Function(pointer) => pointer.id(),
+            Self::Instance(pointer) => pointer.id(),
+            Self::List(pointer) => pointer.id(),
+            Self::Native(pointer) => pointer.id(),
+            Self::Range(pointer) => pointer.id(),
+            Self::String(pointer) => pointer.id(),
+            Self::StringBuilder(pointer) => pointer.id(),
+            Self::Upvalue(pointer) => pointer.id(),
+            Self::Bool(_) | Self::Number(_) | Self::Int(_) | Self::Nil => return None,
+        })
+    }
+
+    /// The bytes the object this value refers to owns in the heap, not
+    /// counting the objects it refers to in turn, or `None` for booleans,
+    /// numbers and `nil`, which are held in the value itself.
+    pub fn heap_size(&self) -> Option<usize> {
+        // Sized through the objects, as a pointer's own size is only its slot
+        Some(match self {
+            Self::BoundMethod(pointer) => ObjBoundMethod::size(pointer),
+            Self::Class(pointer) => ObjClass::size(pointer),
+            Self::Closure(pointer) => ObjClosure::size(pointer),
+            Self::Function(pointer) => ObjFunction::size(pointer),
+            Self::Instance(pointer) => ObjInstance::size(pointer),
+            Self::List(pointer) => ObjList::size(pointer),
+            Self::Native(pointer) => ObjNative::size(pointer),
+            Self::Range(pointer) => ObjRange::size(pointer),
+            Self::String(pointer) => ObjString::size(pointer),
+            Self::StringBuilder(pointer) => ObjStringBuilder::size(pointer),
+            Self::Upvalue(pointer) => ObjUpvalue::size(pointer),
+            Self::Bool(_) | Self::Number(_) | Self::Int(_) | Self::Nil => return None,
+        })
+    }
+
     pub fn is_falsey(&self) -> bool {
         match self {
             Self::Nil => true,
@@ -42,20 +107,17 @@ impl HeapSize for RuntimeValue {
     }
 }
 
+/// Numbers hash by their bits and objects by their identity, so values can key
+/// maps and sets under `==` without looking inside objects.
 impl Hash for RuntimeValue {
     fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(self).hash(state);
         match self {
             RuntimeValue::Bool(b) => b.hash(state),
             RuntimeValue::Number(n) => n.to_bits().hash(state),
-            RuntimeValue::BoundMethod(pointer) => pointer.hash(state),
-            RuntimeValue::Class(pointer) => pointer.hash(state),
-            RuntimeValue::Closure(pointer) => pointer.hash(state),
-            RuntimeValue::Function(pointer) => pointer.hash(state),
-            RuntimeValue::Instance(pointer) => pointer.hash(state),
-            RuntimeValue::Native(pointer) => pointer.hash(state),
-            RuntimeValue::String(pointer) => pointer.hash(state),
-            RuntimeValue::Upvalue(pointer) => pointer.hash(state),
-            RuntimeValue::Nil => 0.hash(state),
+            RuntimeValue::Int(n) => n.hash(state),
+            RuntimeValue::Nil => {}
+            value => value.id().hash(state),
         }
     }
 }
@@ -66,14 +128,18 @@ impl Display for RuntimeValue {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             RuntimeValue::Bool(b) => write!(f, "{b}"),
-            RuntimeValue::Number(n) => write!(f, "{n}"),
+            RuntimeValue::Number(n) => fmt_number(*n, f),
+            RuntimeValue::Int(n) => write!(f, "{n}"),
             RuntimeValue::BoundMethod(pointer) => write!(f, "{pointer}"),
             RuntimeValue::Class(pointer) => write!(f, "{pointer}"),
-            RuntimeValue::Closure(pointer) => write!(f, "{pointer}"),
+            RuntimeValue::Closure(pointer) => Display::fmt(pointer, f),
             RuntimeValue::Function(pointer) => write!(f, "{pointer}"),
             RuntimeValue::Instance(pointer) => write!(f, "{pointer}"),
+            RuntimeValue::List(pointer) => write!(f, "{pointer}"),
             RuntimeValue::Native(pointer) => write!(f, "{pointer}"),
+            RuntimeValue::Range(pointer) => write!(f, "{pointer}"),
             RuntimeValue::String(pointer) => write!(f, "{pointer}"),
+            RuntimeValue::StringBuilder(pointer) => write!(f, "{pointer}"),
             RuntimeValue::Upvalue(pointer) => write!(f, "{pointer}"),
             RuntimeValue::Nil => write!(f, "nil"),
         }
@@ -86,6 +152,7 @@ impl TryFrom<RuntimeValue> for usize {
     fn try_from(value: RuntimeValue) -> Result<Self, Self::Error> {
         match value {
             RuntimeValue::Number(n) => Ok(n as usize),
+            RuntimeValue::Int(n) => Ok(n as usize),
             _ => Err(Error::Runtime),
         }
     }
@@ -97,6 +164,7 @@ impl TryFrom<RuntimeValue> for f64 {
     fn try_from(value: RuntimeValue) -> Result<Self, Self::Error> {
         match value {
             RuntimeValue::Number(n) => Ok(n),
+            RuntimeValue::Int(n) => Ok(n as f64),
             _ => Err(Error::Runtime),
         }
     }
@@ -114,6 +182,12 @@ impl From<f64> for RuntimeValue {
     }
 }
 
+impl From<i64> for RuntimeValue {
+    fn from(value: i64) -> Self {
+        Self::Int(value)
+    }
+}
+
 impl From<usize> for RuntimeValue {
     fn from(value: usize) -> Self {
         Self::Number(value as f64)
@@ -150,31 +224,38 @@ impl From<Pointer<ObjInstance>> for RuntimeValue {
     }
 }
 
+impl From<Pointer<ObjList>> for RuntimeValue {
+    fn from(value: Pointer<ObjList>) -> Self {
+        Self::List(value)
+    }
+}
+
 impl From<Pointer<ObjNative>> for RuntimeValue {
     fn from(value: Pointer<ObjNative>) -> Self {
         Self::Native(value)
     }
 }
 
+impl From<Pointer<ObjRange>> for RuntimeValue {
+    fn from(value: Pointer<ObjRange>) -> Self {
+        Self::Range(value)
+    }
+}
+
 impl From<Pointer<ObjString>> for RuntimeValue {
     fn from(value: Pointer<ObjString>) -> Self {
         Self::String(value)
     }
 }
 
-impl From<Pointer<ObjUpvalue>> for RuntimeValue {
-    fn from(value: Pointer<ObjUpvalue>) -> Self {
-        Self::Upvalue(value)
+impl From<Pointer<ObjStringBuilder>> for RuntimeValue {
+    fn from(value: Pointer<ObjStringBuilder>) -> Self {
+        Self::StringBuilder(value)
     }
 }
 
-impl TryFrom<ConstantValue> for RuntimeValue {
-    type Error = Error;
-
-    fn try_from(value: ConstantValue) -> Result<Self, Error> {
-        match value {
-            ConstantValue::Number(n) => Ok(Self::Number(n)),
-            _ => Err(Error::Runtime),
-        }
+impl From<Pointer<ObjUpvalue>> for RuntimeValue {
+    fn from(value: Pointer<ObjUpvalue>) -> Self {
+        Self::Upvalue(value)
     }
 }
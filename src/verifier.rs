@@ -0,0 +1,225 @@
+//! Checks bytecode once before it runs, so the run loop can trust it.
+//!
+//! Verification follows every path through a chunk from its first byte,
+//! checking that each instruction reached decodes, that its operands index
+//! into the chunk's tables, and that control never leaves the chunk. Bytes no
+//! path reaches are never looked at. The opcodes found along the way are kept
+//! as [`Chunk::decoded`], which the run loop dispatches on instead of decoding
+//! each byte again.
+
+use crate::{
+    chunk::{Instruction, OpCode},
+    object::ObjFunction,
+    value::ConstantValue,
+};
+
+/// Verifies `function` and every function nested in it, filling in their
+/// decoded instructions. Chunks verified before are skipped.
+pub fn verify(function: &ObjFunction) -> Result<(), &'static str> {
+    let mut pending = vec![function];
+    while let Some(function) = pending.pop() {
+        if function.chunk.decoded.get().is_none() {
+            let decoded = decode(function)?;
+            // A chunk is only verified once, before its first run
+            let _ = function.chunk.decoded.set(decoded);
+        }
+        pending.extend(function.chunk.constants.iter().filter_map(
+            |constant| match constant {
+                ConstantValue::Function(function) => Some(&**function),
+                _ => None,
+            },
+        ));
+    }
+    Ok(())
+}
+
+/// The opcode at each offset of `function`'s chunk an instruction reachable
+/// from the start begins at, with `OpCode::Unknown` everywhere else.
+fn decode(function: &ObjFunction) -> Result<Box<[OpCode]>, &'static str> {
+    let chunk = &function.chunk;
+    let mut decoded = vec![OpCode::Unknown; chunk.code.len()].into_boxed_slice();
+    let mut visited = vec![false; chunk.code.len()];
+    let mut pending = vec![0];
+    while let Some(offset) = pending.pop() {
+        let Some(instruction) = chunk.instruction_at(offset) else {
+            return Err("Instruction pointer out of range.");
+        };
+        if std::mem::replace(&mut visited[offset], true) {
+            continue;
+        }
+        if instruction.opcode == OpCode::Unknown {
+            // A known opcode only fails to decode when its operands run off the end
+            return match OpCode::try_from(chunk.code[offset]) {
+                Ok(opcode) if opcode != OpCode::Unknown => Err("Instruction pointer out of range."),
+                _ => Err("Unknown opcode."),
+            };
+        }
+        check_operands(function, &instruction)?;
+        decoded[offset] = instruction.opcode;
+
+        let next = offset + instruction.size();
+        let target = |backward: bool, distance: usize| {
+            let target = if backward {
+                next.checked_sub(distance)
+            } else {
+                next.checked_add(distance)
+            };
+            target
+                .filter(|&target| target < chunk.code.len())
+                .ok_or("Jump out of range.")
+        };
+        match instruction.opcode {
+            OpCode::Return => {}
+            OpCode::Jump => pending.push(target(false, instruction.operand(0))?),
+            OpCode::Loop => pending.push(target(true, instruction.operand(0))?),
+            OpCode::JumpIfFalse => {
+                pending.push(target(false, instruction.operand(0))?);
+                pending.push(next);
+            }
+            OpCode::ForIn => {
+                pending.push(target(false, instruction.operand(1))?);
+                pending.push(next);
+            }
+            _ => pending.push(next),
+        }
+    }
+    Ok(decoded)
+}
+
+/// Checks that the constants, globals and upvalues `instruction` names exist
+/// and that constants have the type it expects.
+fn check_operands(function: &ObjFunction, instruction: &Instruction) -> Result<(), &'static str> {
+    let chunk = &function.chunk;
+    let constant = |index: usize| {
+        chunk
+            .constants
+            .get(index)
+            .ok_or("Constant index out of range.")
+    };
+    let name = |index: usize| match constant(index)? {
+        ConstantValue::String(_) => Ok(()),
+        _ => Err("Unexpected constant value."),
+    };
+    match instruction.opcode {
+        OpCode::Constant => {
+            constant(instruction.operand(0))?;
+        }
+        OpCode::GetProperty
+        | OpCode::SetProperty
+        | OpCode::GetSuper
+        | OpCode::GetThisProperty
+        | OpCode::SetThisProperty
+        | OpCode::Class
+        | OpCode::Method
+        | OpCode::StaticMethod
+        | OpCode::Invoke
+        | OpCode::SuperInvoke
+        | OpCode::InvokeThis
+        | OpCode::Import => name(instruction.operand(0))?,
+        OpCode::NamedArgs => {
+            for &index in &instruction.operands[1..] {
+                name(index as usize)?;
+            }
+        }
+        OpCode::GetGlobal | OpCode::SetGlobal | OpCode::DefineGlobal
+            if instruction.operand(0) >= chunk.globals.len() =>
+        {
+            return Err("Global index out of range.");
+        }
+        OpCode::GetUpvalue | OpCode::SetUpvalue
+            if instruction.operand(0) >= function.upvalue_count =>
+        {
+            return Err("Upvalue index out of range.");
+        }
+        OpCode::Closure => {
+            let ConstantValue::Function(_) = constant(instruction.operands[0] as usize)? else {
+                return Err("Unexpected constant value.");
+            };
+            for pair in instruction.operands[1..].chunks(2) {
+                let (is_local, index) = (pair[0] != 0, pair[1] as usize);
+                if !is_local && index >= function.upvalue_count {
+                    return Err("Upvalue index out of range.");
+                }
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{chunk::Chunk, compiler::Compiler};
+
+    fn function(code: &[u8], constants: Vec<ConstantValue>) -> ObjFunction {
+        let mut chunk = Chunk {
+            constants,
+            ..Default::default()
+        };
+        for &byte in code {
+            chunk.write(byte, 1);
+        }
+        ObjFunction::script(chunk)
+    }
+
+    #[test]
+    fn it_decodes_reachable_instructions() {
+        let jump = OpCode::Jump as u8;
+        let loop_ = OpCode::Loop as u8;
+        let nil = OpCode::Nil as u8;
+        let ret = OpCode::Return as u8;
+        // The jump skips the byte that would start a loop over the rest
+        let function = function(&[jump, 0, 1, loop_, nil, ret], vec![]);
+        verify(&function).expect("Failed to verify");
+        assert_eq!(
+            &**function.chunk.decoded.get().expect("Expected decoded code"),
+            &[
+                OpCode::Jump,
+                OpCode::Unknown,
+                OpCode::Unknown,
+                OpCode::Unknown,
+                OpCode::Nil,
+                OpCode::Return
+            ]
+        );
+    }
+
+    #[test]
+    fn it_verifies_nested_functions() {
+        let function = Compiler::new("fun f(a) { fun g() { return a; } return g; }".into())
+            .compile()
+            .expect("Failed to compile");
+        verify(&function).expect("Failed to verify");
+        let ConstantValue::Function(f) = &function.chunk.constants[0] else {
+            panic!("Expected a function constant.");
+        };
+        assert!(f.chunk.decoded.get().is_some());
+    }
+
+    #[test]
+    fn it_rejects_malformed_code() {
+        let constant = OpCode::Constant as u8;
+        let get_property = OpCode::GetProperty as u8;
+        let get_global = OpCode::GetGlobal as u8;
+        let get_upvalue = OpCode::GetUpvalue as u8;
+        let jump = OpCode::Jump as u8;
+        let nil = OpCode::Nil as u8;
+        let ret = OpCode::Return as u8;
+        let cases: [(&[u8], &str); 8] = [
+            (&[nil], "Instruction pointer out of range."),
+            (&[constant], "Instruction pointer out of range."),
+            (&[200, ret], "Unknown opcode."),
+            (&[jump, 0, 9, ret], "Jump out of range."),
+            (&[constant, 1, ret], "Constant index out of range."),
+            (&[get_property, 0, ret], "Unexpected constant value."),
+            (&[get_global, 0, ret], "Global index out of range."),
+            (&[get_upvalue, 0, ret], "Upvalue index out of range."),
+        ];
+        for (code, error) in cases {
+            let function = function(code, vec![ConstantValue::Number(1.0)]);
+            assert_eq!(verify(&function), Err(error), "{code:?}");
+            assert!(function.chunk.decoded.get().is_none());
+        }
+    }
+}
@@ -0,0 +1,241 @@
+//! Snapshots of the machine, for watching it run a program step by step.
+//!
+//! A snapshot shows what the debug build traces as it runs, but on demand:
+//! the value stack, the active call frames, the globals scripts defined and
+//! the code of the innermost frame with a marker at the instruction it runs
+//! next. Taking one between calls to [`VM::step`](crate::VM::step) shows the
+//! effect of every instruction.
+
+use std::io::{self, Write};
+
+use crate::{heap::json_string, object::Store, value::RuntimeValue};
+
+/// How [`VM::dump_state`](crate::VM::dump_state) writes a snapshot.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum StateFormat {
+    /// Sections headed `-- stack --`, `-- frames --`, `-- globals --` and
+    /// `-- code --`, the next instruction marked with `->`
+    #[default]
+    Text,
+    /// `{"stack": [value], "frames": [{"function", "line", "ip", "base"}],
+    /// "globals": [{"name", "value"}], "code": [line], "ip"}` on one line,
+    /// frames innermost first and `ip` indexing the code's offsets
+    Json,
+}
+
+struct FrameState {
+    /// `None` for the top level of a script
+    function: Option<String>,
+    line: usize,
+    ip: usize,
+    /// The stack slot the frame's locals start at
+    base: usize,
+}
+
+/// What the machine looks like between two instructions.
+pub(crate) struct MachineState {
+    stack: Vec<String>,
+    /// Innermost first
+    frames: Vec<FrameState>,
+    globals: Vec<(String, String)>,
+    /// The disassembly of the innermost frame's chunk, one line per entry
+    code: Vec<String>,
+}
+
+impl MachineState {
+    pub(crate) fn of(store: &Store) -> Self {
+        let active = &store.frame_stack[..store.frame_stack_top];
+        let frames = active
+            .iter()
+            .rev()
+            .enumerate()
+            .map(|(depth, frame)| {
+                let chunk = unsafe { &*frame.chunk };
+                // Callers have moved past the call they're waiting on
+                let offset = if depth == 0 {
+                    frame.ip
+                } else {
+                    frame.ip.saturating_sub(1)
+                };
+                FrameState {
+                    function: frame.closure.function.name.clone(),
+                    line: chunk.lines.get(offset).copied().unwrap_or_default(),
+                    ip: frame.ip,
+                    base: frame.start_stack_index,
+                }
+            })
+            .collect();
+        let code = match active.last() {
+            Some(frame) => unsafe { &*frame.chunk }
+                .to_string()
+                .lines()
+                .map(String::from)
+                .collect(),
+            None => Vec::new(),
+        };
+        Self {
+            stack: store
+                .value_stack
+                .iter()
+                .map(|value| format!("{value:#}"))
+                .collect(),
+            frames,
+            // Natives are the same in every VM, so they're left out
+            globals: store
+                .globals
+                .entries()
+                .filter(|(_, value)| !matches!(value, RuntimeValue::Native(_)))
+                .map(|(name, value)| (name.to_string(), format!("{value:#}")))
+                .collect(),
+            code,
+        }
+    }
+
+    pub(crate) fn write(&self, writer: &mut impl Write, format: StateFormat) -> io::Result<()> {
+        match format {
+            StateFormat::Text => self.write_text(writer),
+            StateFormat::Json => self.write_json(writer),
+        }
+    }
+
+    fn write_text(&self, writer: &mut impl Write) -> io::Result<()> {
+        writeln!(writer, "-- stack --")?;
+        for value in &self.stack {
+            write!(writer, "[ {value} ]")?;
+        }
+        writeln!(writer)?;
+        writeln!(writer, "-- frames --")?;
+        for frame in &self.frames {
+            let function = frame.function.as_deref().unwrap_or("script");
+            writeln!(
+                writer,
+                "[line {}] in {function} at {:04x}, locals from slot {}",
+                frame.line, frame.ip, frame.base
+            )?;
+        }
+        writeln!(writer, "-- globals --")?;
+        for (name, value) in &self.globals {
+            writeln!(writer, "{name} = {value}")?;
+        }
+        writeln!(writer, "-- code --")?;
+        let next = self
+            .frames
+            .first()
+            .map(|frame| format!("{:04x}\t", frame.ip));
+        for line in &self.code {
+            let marker = match &next {
+                Some(next) if line.starts_with(next.as_str()) => "->",
+                _ => "  ",
+            };
+            writeln!(writer, "{marker} {line}")?;
+        }
+        Ok(())
+    }
+
+    fn write_json(&self, writer: &mut impl Write) -> io::Result<()> {
+        let strings = |values: &mut dyn Iterator<Item = &String>| {
+            values
+                .map(|value| json_string(value))
+                .collect::<Vec<_>>()
+                .join(",")
+        };
+        write!(
+            writer,
+            "{{\"stack\":[{}],\"frames\":[",
+            strings(&mut self.stack.iter())
+        )?;
+        for (i, frame) in self.frames.iter().enumerate() {
+            let separator = if i == 0 { "" } else { "," };
+            let function = frame.function.as_deref().map_or("null".into(), json_string);
+            write!(
+                writer,
+                "{separator}{{\"function\":{function},\"line\":{},\"ip\":{},\"base\":{}}}",
+                frame.line, frame.ip, frame.base
+            )?;
+        }
+        write!(writer, "],\"globals\":[")?;
+        for (i, (name, value)) in self.globals.iter().enumerate() {
+            let separator = if i == 0 { "" } else { "," };
+            write!(
+                writer,
+                "{separator}{{\"name\":{},\"value\":{}}}",
+                json_string(name),
+                json_string(value)
+            )?;
+        }
+        let ip = self
+            .frames
+            .first()
+            .map_or("null".into(), |frame| frame.ip.to_string());
+        writeln!(
+            writer,
+            "],\"code\":[{}],\"ip\":{ip}}}",
+            strings(&mut self.code.iter())
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{StepResult, VM};
+
+    /// The state of the VM after `steps` steps of `source`.
+    fn state_after(source: &str, steps: usize, format: StateFormat) -> String {
+        let mut vm = VM::new(Vec::new(), Vec::new());
+        let program = vm.compile(source).expect("Failed to compile");
+        vm.prepare(&program).expect("Failed to prepare program");
+        for _ in 0..steps {
+            assert_eq!(vm.step(), StepResult::Executed);
+        }
+        let mut out = Vec::new();
+        vm.dump_state(&mut out, format).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn it_writes_text() {
+        let state = state_after("var a = 1;\nprint a + 2;", 3, StateFormat::Text);
+        assert_eq!(
+            state,
+            "-- stack --\n\
+             [ <script> ][ 1 ]\n\
+             -- frames --\n\
+             [line 2] in script at 0006, locals from slot 0\n\
+             -- globals --\n\
+             a = 1\n\
+             -- code --\n   \
+             0000\t   1\tOP_CONSTANT\t   0\t'1'\n   \
+             0002\t    |\tOP_DEFINE_GLOBAL\t   0\t'a'\n   \
+             0004\t   2\tOP_GET_GLOBAL\t   0\t'a'\n\
+             -> 0006\t    |\tOP_CONSTANT\t   1\t'2'\n   \
+             0008\t    |\tOP_ADD\n   \
+             0009\t    |\tOP_PRINT\n   \
+             000a\t    |\tOP_NIL\n   \
+             000b\t    |\tOP_RETURN\n"
+        );
+    }
+
+    #[test]
+    fn it_writes_json() {
+        let state = state_after("fun f(x) { return x; }\nf(\"a\");", 5, StateFormat::Json);
+        assert!(state.starts_with(
+            "{\"stack\":[\"<script>\",\"<fn f arity=1 upvalues=0>\",\"a\"],\"frames\":[\
+             {\"function\":\"f\",\"line\":1,\"ip\":0,\"base\":1},\
+             {\"function\":null,\"line\":2,\"ip\":10,\"base\":0}],\
+             \"globals\":[{\"name\":\"f\",\"value\":\"<fn f arity=1 upvalues=0>\"}],\"code\":["
+        ));
+        assert!(state.ends_with("],\"ip\":0}\n"));
+    }
+
+    #[test]
+    fn it_shows_an_idle_machine() {
+        let vm = VM::new(Vec::new(), Vec::new());
+        let mut out = Vec::new();
+        vm.dump_state(&mut out, StateFormat::Json).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "{\"stack\":[],\"frames\":[],\"globals\":[],\"code\":[],\"ip\":null}\n"
+        );
+    }
+}
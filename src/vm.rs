@@ -1,31 +1,160 @@
 use std::{
-    collections::BTreeMap,
-    io::{Stderr, Stdout, Write},
+    collections::{BTreeMap, HashSet},
+    fmt::Debug,
+    fs,
+    io::{self, Read, Stderr, Stdout, Write},
+    ops::Range,
+    path::{Path, PathBuf},
     ptr::NonNull,
-    time::{SystemTime, UNIX_EPOCH},
 };
 
+#[cfg(feature = "track-allocations")]
+use crate::object::tracking;
 use crate::{
     call_frame::CallFrame,
     chunk::{Chunk, OpCode},
-    compiler::Compiler,
-    error::Error,
+    compiler::{diagnostics::Diagnostics, CompileOptions, Compiler, NumberOverflow},
+    coverage::Coverage,
+    debugger::{Breakpoint, StepResult},
+    disassembler::{self, DisassemblyOptions},
+    error::{Error, RuntimeErrorInfo, TraceFrame, RECENT_EVENTS_HEADER},
+    features::FeatureSet,
+    heap::{HeapFormat, HeapGraph},
+    module::{self, ModuleResolver, Source},
+    native,
     object::{
-        obj_native::NativeFn, ObjBoundMethod, ObjClass, ObjClosure, ObjFunction, ObjInstance,
-        ObjNative, ObjString, ObjUpvalue, Pointer, Store,
+        obj_class::{is_private_member, MethodTable},
+        obj_native::{HandleScope, NativeContext, NativeFn, NativeFunction},
+        obj_string::SmallString,
+        ObjBoundMethod, ObjClass, ObjClosure, ObjFunction, ObjInstance, ObjList, ObjNative,
+        ObjRange, ObjString, ObjStringBuilder, ObjUpvalue, ObjectId, Pointer, Store,
     },
+    program::Program,
+    scanner::Keywords,
+    stats::Stats,
     table::Table,
-    value::{ConstantValue, RuntimeValue},
+    trace::{FlightRecorder, TextTrace, TraceEvent, TraceSink, TraceStack},
+    value::{ConstantValue, LoxValue, RuntimeValue},
+    verifier,
+    visualizer::{MachineState, StateFormat},
 };
 
 pub const MAX_FRAMES: usize = 64;
+/// How deeply a class's initializer may run inside itself by default: as
+/// deeply as the stack allows above the script's frame, so the limit only
+/// names the initializer where the stack would overflow anyway
+pub const DEFAULT_INIT_RECURSION_LIMIT: usize = MAX_FRAMES - 1;
 
-fn clock_native(_args: &[RuntimeValue]) -> RuntimeValue {
-    SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .expect("IVME: Failed to get system time")
-        .as_secs_f64()
-        .into()
+/// How many of the innermost frames a runtime error's backtrace lists before
+/// summarizing the frames below them, unless full backtraces are enabled.
+pub const BACKTRACE_HEAD: usize = 10;
+/// How many of the outermost frames the backtrace lists after the summary.
+pub const BACKTRACE_TAIL: usize = 10;
+
+/// Integers widened to floats, for operations that treat all numbers alike.
+fn as_float(value: RuntimeValue) -> RuntimeValue {
+    match value {
+        RuntimeValue::Int(n) => RuntimeValue::Number(n as f64),
+        value => value,
+    }
+}
+
+/// How a module is named in diagnostics.
+fn module_name(path: &Path) -> String {
+    path.file_name()
+        .unwrap_or(path.as_os_str())
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// How a VM is set up. New options may be added, so build these from
+/// [`VmOptions::default`] and set the fields you need.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct VmOptions {
+    /// How scripts are compiled
+    pub compile: CompileOptions,
+    /// Whether to count which lines run, as with [`VM::enable_coverage`]
+    pub coverage: bool,
+    /// Directories searched for imported modules, in order
+    pub module_paths: Vec<PathBuf>,
+    /// How many of the latest trace events to keep for runtime errors, as
+    /// with [`VM::set_flight_recorder`]
+    pub flight_recorder: Option<usize>,
+}
+
+/// Whether a VM can safely run more code.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum VmState {
+    /// The last program ran to completion, or nothing has run yet
+    Ready,
+    /// The last program stopped with a runtime error and may have left values on the stack
+    Errored,
+    /// An internal fault left the VM unusable until it is reset
+    Poisoned,
+}
+
+/// When the VM passes what scripts print on to its output writer. Whatever is
+/// held back is written when a script calls `flushOut()`, before a runtime
+/// error is reported and when each call to `interpret` returns.
+#[derive(Debug, Copy, Clone, Default, Eq, PartialEq)]
+pub enum OutputBuffering {
+    /// Write and flush every line as it's printed
+    #[default]
+    Line,
+    /// Write once at least this many bytes are held back
+    Block(usize),
+    /// Write only when flushed explicitly or the script finishes
+    Manual,
+}
+
+/// Output redirected away from the VM's writers by [`VM::interpret_captured`].
+#[derive(Debug, Default)]
+struct Capture {
+    out: Vec<u8>,
+    e_out: Vec<u8>,
+}
+
+type PrintHook = Box<dyn FnMut(&str)>;
+type ErrorHook = Box<dyn FnMut(&RuntimeErrorInfo)>;
+type BreakpointHook = Box<dyn FnMut(&Breakpoint)>;
+type CompiledHook = Box<dyn FnMut(&Diagnostics)>;
+type HostFn = Box<dyn FnMut(&[LoxValue]) -> Result<LoxValue, String>>;
+
+/// Callbacks the embedder registered to receive output instead of the VM's
+/// writers.
+#[derive(Default)]
+struct Hooks {
+    print: Option<PrintHook>,
+    error: Option<ErrorHook>,
+    breakpoint: Option<BreakpointHook>,
+    compiled: Option<CompiledHook>,
+}
+
+/// A native function the embedder registered with [`VM::register_native`].
+struct HostNative {
+    name: String,
+    arity: usize,
+    function: HostFn,
+}
+
+impl Debug for HostNative {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("HostNative")
+            .field("name", &self.name)
+            .field("arity", &self.arity)
+            .finish()
+    }
+}
+
+impl Debug for Hooks {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Hooks")
+            .field("print", &self.print.is_some())
+            .field("error", &self.error.is_some())
+            .field("breakpoint", &self.breakpoint.is_some())
+            .finish()
+    }
 }
 
 #[derive(Debug)]
@@ -34,6 +163,53 @@ pub struct VM<Out: Write = Stdout, EOut: Write = Stderr> {
     out: Out,
     e_out: EOut,
     init_string: ObjString,
+    iter_string: ObjString,
+    done_string: ObjString,
+    next_string: ObjString,
+    to_string_string: ObjString,
+    /// Instances whose `toString()` is running for `print`, innermost last
+    displaying: Vec<ObjectId>,
+    /// Reused argument buffer for native calls
+    native_args: Vec<RuntimeValue>,
+    state: VmState,
+    capture: Option<Capture>,
+    hooks: Hooks,
+    /// When printed output is written to `out`
+    output_buffering: OutputBuffering,
+    /// Printed output not yet written to `out`
+    pending_out: Vec<u8>,
+    /// Natives registered by the embedder, indexed by [`NativeFunction::Host`]
+    host_natives: Vec<HostNative>,
+    /// How many instructions a single `interpret` may run, if limited
+    instruction_limit: Option<u64>,
+    /// The instruction count at which the running script is stopped
+    instruction_deadline: u64,
+    /// How many calls to one initializer may be active at once, if limited
+    init_recursion_limit: Option<usize>,
+    /// Names announced by `NamedArgs` for the call that follows it
+    named_args: Vec<ObjString>,
+    /// How later scripts are compiled
+    compile_options: CompileOptions,
+    /// What compiling the most recent script gathered
+    diagnostics: Diagnostics,
+    /// Line coverage of interpreted scripts, recorded once enabled
+    coverage: Option<Coverage>,
+    /// Directories searched, in order, for imported modules
+    module_paths: Vec<PathBuf>,
+    /// Finds imported modules in place of the module paths, when set
+    module_resolver: Option<Box<dyn ModuleResolver>>,
+    /// Modules that ran or are running, so each is imported only once
+    modules: HashSet<PathBuf>,
+    /// The modules currently running, each imported by the one before it
+    module_chain: Vec<PathBuf>,
+    /// Whether scripts are verified and decoded before they first run
+    predecode: bool,
+    /// Whether runtime errors list every frame rather than summarizing deep stacks
+    full_backtrace: bool,
+    /// The frame a program prepared by [`VM::prepare`] runs above, while it runs
+    stepping: Option<usize>,
+    /// Whether the program being stepped called `breakpoint()` in this step
+    paused: bool,
 }
 
 impl<Out: Write, EOut: Write> VM<Out, EOut> {
@@ -44,1217 +220,5631 @@ impl<Out: Write, EOut: Write> VM<Out, EOut> {
             out,
             e_out,
             init_string: "init".into(),
+            iter_string: "iter".into(),
+            done_string: "done".into(),
+            next_string: "next".into(),
+            to_string_string: "toString".into(),
+            displaying: Vec::new(),
+            native_args: Vec::new(),
+            state: VmState::Ready,
+            capture: None,
+            hooks: Hooks::default(),
+            output_buffering: OutputBuffering::default(),
+            pending_out: Vec::new(),
+            host_natives: Vec::new(),
+            instruction_limit: None,
+            init_recursion_limit: Some(DEFAULT_INIT_RECURSION_LIMIT),
+            instruction_deadline: u64::MAX,
+            named_args: Vec::new(),
+            compile_options: CompileOptions::default(),
+            diagnostics: Diagnostics::default(),
+            coverage: None,
+            module_paths: Vec::new(),
+            module_resolver: None,
+            modules: HashSet::new(),
+            module_chain: Vec::new(),
+            predecode: true,
+            full_backtrace: false,
+            stepping: None,
+            paused: false,
         };
+        vm.define_natives();
+        vm
+    }
 
-        vm.define_native("clock".into(), clock_native);
-
+    /// A VM writing to `out` and `e_out`, set up by `options`.
+    pub fn with_options(out: Out, e_out: EOut, options: VmOptions) -> Self {
+        let mut vm = Self::new(out, e_out);
+        vm.compile_options = options.compile;
+        if options.coverage {
+            vm.enable_coverage();
+        }
+        vm.module_paths = options.module_paths;
+        vm.set_flight_recorder(options.flight_recorder);
         vm
     }
 
-    pub fn interpret(&mut self, source: &str) -> Result<(), Error> {
-        #[cfg(feature = "debug")]
-        println!("========== CODE ==========");
+    fn define_natives(&mut self) {
+        self.define_native("clock".into(), 0, native::clock);
+        self.define_native("forEach".into(), 2, native::for_each);
+        self.define_native("map".into(), 2, native::map);
+        self.define_native("filter".into(), 2, native::filter);
+        self.define_native("contains".into(), 2, native::contains);
+        self.define_native("match".into(), 2, native::match_);
+        self.define_native("replace".into(), 3, native::replace);
+        self.define_native("same".into(), 2, native::same);
+        self.define_native("id".into(), 1, native::id);
+        self.define_native("sort".into(), 1, native::sort);
+        self.define_native("sortBy".into(), 2, native::sort_by);
+        self.define_native("copy".into(), 1, native::copy);
+        self.define_native("deepCopy".into(), 1, native::deep_copy);
+        self.define_native("locals".into(), 0, native::locals);
+        self.define_native("stringBuilder".into(), 0, native::string_builder);
+        self.define_native("append".into(), 2, native::append);
+        self.define_native("toString".into(), 1, native::to_string);
+        self.define_native("features".into(), 0, native::features);
+        self.define_native("flushOut".into(), 0, native::flush_out);
+        self.define_native("breakpoint".into(), 0, native::breakpoint);
+        self.define_native("load".into(), 1, native::load);
+        let mut eval = self.define_native("eval".into(), 2, native::eval);
+        eval.optional = 1;
+        self.define_native("fieldCount".into(), 1, native::field_count);
+        self.define_native("sizeOf".into(), 1, native::size_of);
+        self.define_native("error".into(), 1, native::error);
+        for (index, host) in self.host_natives.iter().enumerate() {
+            let native = self.store.insert_native(ObjNative {
+                arity: host.arity,
+                optional: 0,
+                function: NativeFunction::Host(index),
+            });
+            self.store
+                .globals
+                .insert(host.name.as_str().into(), native.into());
+        }
+    }
 
-        let compiler = Compiler::new(source.into());
+    /// Compiles later scripts with source maps, so runtime errors point at the
+    /// offending source with a caret.
+    pub fn set_debug_info(&mut self, enabled: bool) {
+        self.compile_options.debug_info = enabled;
+    }
 
-        let function = compiler.compile()?;
-        #[cfg(feature = "debug")]
-        {
-            println!("== {} ==", function);
-            println!("{}", function.chunk.borrow());
-        }
+    /// Limits how deeply expressions in later scripts may nest.
+    pub fn set_max_nesting_depth(&mut self, depth: usize) {
+        self.compile_options.max_nesting_depth = depth;
+    }
 
-        let function_ref = self.store.insert_function(function);
-        self.push_value(function_ref.into());
-        let closure = self.new_closure(function_ref);
-        self.pop_value();
-        self.push_value(closure.into());
-        self.call(closure, 0)?;
-        self.run()?;
-        self.pop_value();
-        Ok(())
+    /// Limits how many locals functions in later scripts may have in scope at
+    /// once, up to [`limits`](crate::limits)' cap.
+    pub fn set_max_locals(&mut self, count: usize) {
+        self.compile_options.max_locals = count;
     }
 
-    fn define_native(&mut self, name: ObjString, function: NativeFn) {
-        let native_pointer = self.new_native(function).into();
-        self.store.globals.insert(name, native_pointer);
+    /// Limits how many variables closures in later scripts may capture, up to
+    /// [`limits`](crate::limits)' cap.
+    pub fn set_max_upvalues(&mut self, count: usize) {
+        self.compile_options.max_upvalues = count;
     }
 
-    fn println(&mut self, string: impl Into<String>) {
-        let string: String = string.into() + "\n";
-        self.out
-            .write_all(string.as_bytes())
-            .expect("IVME: Failed to write data");
-        self.out.flush().expect("IVME: Failed to flush data");
+    /// Makes integer literals in later scripts integers rather than floats.
+    pub fn set_integers(&mut self, enabled: bool) {
+        self.compile_options.integers = enabled;
     }
 
-    fn eprint(&mut self, string: impl Into<String>) {
-        let string: String = string.into();
-        self.e_out
-            .write_all(string.as_bytes())
-            .expect("IVME: Failed to write data");
-        self.e_out.flush().expect("IVME: Failed to flush data");
+    /// Sets what float literals in later scripts compile to when they're too
+    /// large to be finite.
+    pub fn set_number_overflow(&mut self, overflow: NumberOverflow) {
+        self.compile_options.number_overflow = overflow;
     }
 
-    fn reset_stack(&mut self) {
-        self.store.frame_stack_top = 0;
-        self.store.open_upvalues = BTreeMap::default();
+    /// Records how later scripts resolve captured variables, see [`VM::diagnostics`].
+    pub fn set_upvalue_trace(&mut self, enabled: bool) {
+        self.compile_options.upvalue_trace = enabled;
     }
 
-    fn runtime_error(&mut self, message: String) {
-        self.eprint(message);
+    /// Reports locals in later scripts that shadow outer variables, see [`VM::diagnostics`].
+    pub fn set_shadowing_warnings(&mut self, enabled: bool) {
+        self.compile_options.shadowing_warnings = enabled;
+    }
 
-        while self.store.frame_stack_top > 0 {
-            let frame = self.pop_frame();
-            let function = frame.closure.function;
-            let line = unsafe { (*frame.chunk).lines[frame.ip] };
-            self.eprint(format!("[line {line}] in "));
-            if let Some(name) = function.name.as_ref() {
-                self.eprint(format!("{name}\n"));
-            } else {
-                self.eprint("script\n");
-            };
-        }
+    /// Reports globals later scripts declare more than once, see [`VM::diagnostics`].
+    pub fn set_redefinition_warnings(&mut self, enabled: bool) {
+        self.compile_options.redefinition_warnings = enabled;
+    }
 
-        self.reset_stack();
+    /// Reports statements in later scripts that can't run because a `return`
+    /// comes before them, see [`VM::diagnostics`].
+    pub fn set_unreachable_warnings(&mut self, enabled: bool) {
+        self.compile_options.unreachable_warnings = enabled;
     }
 
-    fn current_frame(&self) -> &CallFrame {
-        &self.store.frame_stack[self.store.frame_stack_top - 1]
+    /// Compiles the `debug { ... }` blocks of later scripts, which are
+    /// otherwise skipped.
+    pub fn set_debug_blocks(&mut self, enabled: bool) {
+        self.compile_options.debug_blocks = enabled;
     }
 
-    fn current_frame_mut(&mut self) -> &mut CallFrame {
-        &mut self.store.frame_stack[self.store.frame_stack_top - 1]
+    /// Scans later scripts with the reserved words of `keywords`, e.g. a
+    /// dialect aliasing `function` to `fun`.
+    pub fn set_keywords(&mut self, keywords: Keywords) {
+        self.compile_options.keywords = keywords;
     }
 
-    fn current_closure(&self) -> Pointer<ObjClosure> {
-        self.current_frame().closure
+    /// Scans later scripts as canonical Lox, ignoring keyword aliases.
+    pub fn set_conformance(&mut self, enabled: bool) {
+        self.compile_options.conformance = enabled;
     }
 
-    fn current_chunk(&self) -> &Chunk {
-        unsafe {
-            self.current_frame()
-                .chunk
-                .as_ref()
-                .expect("Failed to get chunk pointer")
-        }
+    /// Hands every line a script prints to `hook` instead of writing it to
+    /// the VM's output. Lines are passed without their trailing newline.
+    pub fn on_print(&mut self, hook: impl FnMut(&str) + 'static) {
+        self.hooks.print = Some(Box::new(hook));
     }
 
-    fn read_byte(&mut self) -> u8 {
-        let ip = self.current_frame().ip;
-        let code = self.current_chunk().code[ip];
-        self.current_frame_mut().ip += 1;
-        code
+    /// Hands every runtime error to `hook` instead of writing it to the VM's
+    /// error output. Compile errors and internal faults are still written.
+    pub fn on_error(&mut self, hook: impl FnMut(&RuntimeErrorInfo) + 'static) {
+        self.hooks.error = Some(Box::new(hook));
     }
 
-    fn read_short(&mut self) -> u16 {
-        let byte_1 = self.read_byte();
-        let byte_2 = self.read_byte();
-        (byte_1 as u16) << 8 | (byte_2 as u16)
+    /// Attaches a debugger: every call scripts make to `breakpoint()` pauses
+    /// them to hand `hook` where they are, resuming once it returns. Locals
+    /// are only available in scripts compiled with debug info.
+    pub fn on_breakpoint(&mut self, hook: impl FnMut(&Breakpoint) + 'static) {
+        self.hooks.breakpoint = Some(Box::new(hook));
     }
 
-    fn read_constant<'a, 'b>(&'a self, index: usize) -> &'b ConstantValue {
-        let raw = NonNull::from(&self.current_chunk().constants[index as usize]);
-        unsafe {
-            // We are guaranteed never to modify constant values,
-            // so we can return a reference to the underlying data
-            // itself without borrowing from `self`
-            raw.as_ref()
-        }
+    /// Hands the diagnostics of every script compiled from now on to `hook`
+    /// as soon as it has compiled, so warnings can be shown before the
+    /// script runs. Modules and `eval` source aren't included.
+    pub fn on_compiled(&mut self, hook: impl FnMut(&Diagnostics) + 'static) {
+        self.hooks.compiled = Some(Box::new(hook));
     }
 
-    fn bind_method(&mut self, class: Pointer<ObjClass>, name: &ObjString) -> Result<(), Error> {
-        let Some(&method) = class.methods.get(name) else {
-            self.runtime_error(format!("Undefined property '{}'", name.chars));
-            return Err(Error::Runtime);
-        };
+    /// Verifies later scripts before they run and dispatches on their
+    /// pre-decoded instructions, which is the default. When disabled, every
+    /// instruction is decoded and checked as it runs instead.
+    pub fn set_predecode(&mut self, enabled: bool) {
+        self.predecode = enabled;
+    }
 
-        let receiver = *self.peek_value(0);
-        let bound = self.new_bound_method(receiver, method);
-        self.pop_value();
-        self.push_value(bound.into());
-        Ok(())
+    /// Lists every frame in the backtraces of runtime errors. Otherwise only
+    /// the innermost [`BACKTRACE_HEAD`] and outermost [`BACKTRACE_TAIL`]
+    /// frames are listed, with a count of the frames between them.
+    pub fn set_full_backtrace(&mut self, enabled: bool) {
+        self.full_backtrace = enabled;
     }
 
-    fn capture_upvalue(&mut self, index: usize) -> Pointer<ObjUpvalue> {
-        let absolute_stack_index = self.current_frame().start_stack_index + index;
-        if let Some(upvalue) = self.store.open_upvalues.get(&absolute_stack_index) {
-            return *upvalue;
+    /// Defines the global `name` as a native function taking `arity`
+    /// arguments. `function` gets copies of the arguments and its result is
+    /// copied back onto the heap; returning an error stops the script with it
+    /// as a runtime error. Registered natives are defined again when
+    /// [`VM::reset`] clears the globals.
+    pub fn register_native(
+        &mut self,
+        name: &str,
+        arity: usize,
+        function: impl FnMut(&[LoxValue]) -> Result<LoxValue, String> + 'static,
+    ) {
+        let native = self.store.insert_native(ObjNative {
+            arity,
+            optional: 0,
+            function: NativeFunction::Host(self.host_natives.len()),
+        });
+        self.store.globals.insert(name.into(), native.into());
+        self.host_natives.push(HostNative {
+            name: name.into(),
+            arity,
+            function: Box::new(function),
+        });
+    }
+
+    /// Sends the trace the `debug` feature produces (disassembled scripts,
+    /// executed instructions and garbage collections) to `out` rather than
+    /// stderr, keeping it apart from both what scripts print and their errors.
+    /// Without the feature, nothing is traced.
+    pub fn set_trace_out(&mut self, out: impl Write + 'static) {
+        if cfg!(feature = "debug") {
+            self.set_trace_sink(TextTrace::new(out));
         }
+    }
 
-        let upvalue = dbg!(ObjUpvalue::Open {
-            location: absolute_stack_index,
-        });
-        let upvalue_ptr = self.store.insert_upvalue(upvalue);
+    /// Sends every [`TraceEvent`] to `sink`, in any build, replacing the
+    /// trace set up before.
+    pub fn set_trace_sink(&mut self, sink: impl TraceSink + 'static) {
+        self.store.tracer.set_sink(sink);
+    }
+
+    /// Keeps the last `capacity` trace events, instructions, calls and
+    /// allocations among them, and reports them with the next runtime error,
+    /// showing what led up to it without tracing everything. `None` stops
+    /// keeping them.
+    pub fn set_flight_recorder(&mut self, capacity: Option<usize>) {
+        self.store.tracer.recorder = capacity.map(FlightRecorder::new);
+    }
+
+    /// Sets when printed output is written to the VM's output writer. Output
+    /// held back under the previous setting is written first.
+    pub fn set_output_buffering(&mut self, buffering: OutputBuffering) {
+        self.flush_output();
+        self.output_buffering = buffering;
+    }
+
+    /// Stops each call to `interpret` with a runtime error once it has run
+    /// `limit` instructions, counting those of imported modules and callbacks.
+    /// `None` lets scripts run for as long as they like.
+    pub fn set_instruction_limit(&mut self, limit: Option<u64>) {
+        self.instruction_limit = limit;
+    }
+
+    /// Stops a class's initializer with a runtime error naming it when
+    /// constructing the class would run it inside `limit` calls of itself,
+    /// rather than letting it run until the stack overflows. Defaults to
+    /// [`DEFAULT_INIT_RECURSION_LIMIT`]; `None` only stops at the overflow.
+    pub fn set_init_recursion_limit(&mut self, limit: Option<usize>) {
+        self.init_recursion_limit = limit;
+    }
+
+    /// The reserved words later scripts are scanned with.
+    pub fn keywords(&self) -> Keywords {
+        self.compile_options.active_keywords()
+    }
+
+    /// Whether `source` stops partway through a declaration, so that more
+    /// lines could make it compile, as a prompt reading it line by line needs
+    /// to know. Nothing is reported on stderr.
+    pub fn is_incomplete(&self, source: &str) -> bool {
+        self.compiler(source).ends_too_soon()
+    }
+
+    /// The features enabled for later scripts, given how they are compiled.
+    pub fn features(&self) -> FeatureSet {
+        FeatureSet::with_options(&self.compile_options)
+    }
+
+    pub fn diagnostics(&self) -> &Diagnostics {
+        &self.diagnostics
+    }
+
+    /// Adds a directory to search for modules named by `import`, after any
+    /// added before it.
+    pub fn add_module_path(&mut self, path: impl Into<PathBuf>) {
+        self.module_paths.push(path.into());
+    }
+
+    /// Finds the modules named by `import` with `resolver` rather than in
+    /// the module paths, replacing the resolver set before. `load()` still
+    /// reads files.
+    pub fn set_module_resolver(&mut self, resolver: impl ModuleResolver + 'static) {
+        self.module_resolver = Some(Box::new(resolver));
+    }
+
+    /// Starts recording which lines of later scripts execute.
+    pub fn enable_coverage(&mut self) {
+        self.coverage.get_or_insert_with(Coverage::default);
+    }
+
+    pub fn coverage(&self) -> Option<&Coverage> {
+        self.coverage.as_ref()
+    }
+
+    pub fn state(&self) -> VmState {
+        self.state
+    }
 
+    /// Snapshots of every defined global, natives included, in no particular order.
+    pub fn globals(&self) -> impl Iterator<Item = (&str, LoxValue)> {
         self.store
-            .open_upvalues
-            .insert(absolute_stack_index, upvalue_ptr);
-        upvalue_ptr
+            .globals
+            .entries()
+            .map(|(name, &value)| (name.chars.as_str(), value.into()))
     }
 
-    fn close_upvalues(&mut self, last_stack_index: usize) {
-        let abs_last_stack_index = self.current_frame().start_stack_index + last_stack_index;
-        let mut closed_upvalues = Vec::new();
-        for (&abs_stack_index, open_upvalue) in self.store.open_upvalues.iter_mut().rev() {
-            if abs_stack_index < abs_last_stack_index {
-                break;
-            }
-            let referenced_value = self.store.value_stack[abs_stack_index];
-            **open_upvalue = ObjUpvalue::Closed {
-                value: referenced_value,
-            };
-            closed_upvalues.push(abs_stack_index);
+    /// The public fields and methods reachable with `.` on the global `name`,
+    /// when it holds a class or an instance.
+    pub fn global_members(&self, name: &str) -> Vec<String> {
+        let (class, fields) = match self.store.globals.get(&name.into()) {
+            Some(RuntimeValue::Class(class)) => (*class, None),
+            Some(RuntimeValue::Instance(instance)) => (instance.class, Some(&instance.fields)),
+            _ => return Vec::new(),
+        };
+        fields
+            .into_iter()
+            .flat_map(Table::entries)
+            .map(|(name, _)| name)
+            .chain(class.methods.entries().map(|(name, _)| name))
+            .filter(|name| !is_private_member(&name.chars))
+            .map(|name| name.chars.to_string())
+            .collect()
+    }
+
+    /// The class held by the global `name` listed with its methods and
+    /// superclasses, if it holds a class.
+    pub fn class_info(&self, name: &str) -> Option<String> {
+        match self.store.globals.get(&name.into()) {
+            Some(RuntimeValue::Class(class)) => Some(class.verbose().to_string()),
+            _ => None,
         }
-        for closed_upvalue in closed_upvalues {
-            self.store.open_upvalues.remove(&closed_upvalue);
+    }
+
+    /// How many bytes the object held by the global `name` owns in the heap,
+    /// as `sizeOf()` reports it, if it holds an object.
+    pub fn global_size(&self, name: &str) -> Option<usize> {
+        self.store.globals.get(&name.into())?.heap_size()
+    }
+
+    /// A snapshot of the global variable `name`, if it is defined.
+    pub fn global(&self, name: &str) -> Option<LoxValue> {
+        self.store
+            .globals
+            .get(&name.into())
+            .map(|&value| value.into())
+    }
+
+    /// Clears the stacks and open upvalues left behind by an error, making the
+    /// VM ready again. Globals are kept unless `clear_globals` is set, in which
+    /// case only the natives, including registered ones, are defined afterwards.
+    pub fn reset(&mut self, clear_globals: bool) {
+        self.store.value_stack.clear();
+        self.reset_stack();
+        self.native_args.clear();
+        self.module_chain.clear();
+        self.stepping = None;
+        if clear_globals {
+            self.store.globals.clear();
+            self.modules.clear();
+            self.define_natives();
         }
+        self.state = VmState::Ready;
     }
 
-    fn define_method(&mut self, name: &ObjString) -> Result<(), Error> {
-        let method = self.peek_typed::<Pointer<ObjClosure>>(0)?;
-        let mut class = self.peek_typed::<Pointer<ObjClass>>(1)?;
-        class.methods.insert(name.clone(), method);
-        self.pop_value();
-        Ok(())
+    pub fn interpret(&mut self, source: &str) -> Result<(), Error> {
+        let compiler = self.compiler(source);
+        self.interpret_with(compiler)
     }
 
-    fn concatenate(&mut self) -> Result<(), Error> {
-        let b = self.peek_typed::<Pointer<ObjString>>(0)?;
-        let a = self.peek_typed::<Pointer<ObjString>>(1)?;
-        let result = a.chars.clone() + &b.chars;
-        let new_string = self.store.insert_string(result.into());
-        self.pop_value();
-        self.pop_value();
-        self.push_value(new_string.into());
-        Ok(())
+    /// Interprets the script read from `reader`, compiling it as it's read
+    /// rather than holding all of it in memory first. Scripts read this way
+    /// have no debug info, so runtime errors can't point into the source.
+    pub fn interpret_reader(&mut self, reader: impl Read + 'static) -> Result<(), Error> {
+        let compiler = Compiler::from_reader_with_options(reader, &self.compile_options);
+        self.interpret_with(compiler)
     }
 
-    fn invoke(&mut self, method_name: &ObjString, arg_count: usize) -> Result<(), Error> {
-        let receiver = self
-            .peek_typed::<Pointer<ObjInstance>>(arg_count)
-            .expect("IVME: Failed to get instance.");
-        let instance_fields = &receiver.fields;
-        if let Some(&value) = instance_fields.get(method_name) {
-            let stack_top = self.store.value_stack.len() - 1;
-            self.store.value_stack[stack_top - arg_count] = value;
-            return self.call_value(value, arg_count);
+    fn interpret_with(&mut self, compiler: Compiler) -> Result<(), Error> {
+        self.run_with(|vm| vm.execute(compiler))
+    }
+
+    /// Compiles `source` the way [`VM::interpret`] would, without running it.
+    /// The program's bytecode is checked now rather than on each run.
+    pub fn compile(&mut self, source: &str) -> Result<Program, Error> {
+        let (result, diagnostics) = self.compile_reporting(self.compiler(source));
+        self.set_diagnostics(diagnostics);
+        self.load_function(result?)
+    }
+
+    /// Wraps a function assembled by hand as a program, checking its bytecode
+    /// first.
+    #[cfg(feature = "internals")]
+    pub fn load(&self, function: ObjFunction) -> Result<Program, Error> {
+        self.load_function(function)
+    }
+
+    fn load_function(&self, function: ObjFunction) -> Result<Program, Error> {
+        if self.predecode {
+            verifier::verify(&function).map_err(Error::InternalFault)?;
         }
-        self.invoke_from_class(receiver.class, method_name, arg_count)
+        Ok(Program { function })
     }
 
-    fn invoke_from_class(
-        &mut self,
-        class: Pointer<ObjClass>,
-        method_name: &ObjString,
-        arg_count: usize,
-    ) -> Result<(), Error> {
-        let Some(&method) = class.methods.get(method_name) else {
-            self.runtime_error(format!("Undefined property '{method_name}'.\n"));
-            return Err(Error::Runtime);
-        };
-        self.call(method, arg_count)
+    /// Runs `program` against this VM's globals, as [`VM::interpret`] runs a
+    /// script it just compiled.
+    pub fn run_program(&mut self, program: &Program) -> Result<(), Error> {
+        self.run_with(|vm| vm.execute_function(program.function.clone()))
     }
 
-    fn call_value(&mut self, callee: RuntimeValue, arg_count: usize) -> Result<(), Error> {
-        match callee {
-            RuntimeValue::BoundMethod(bm) => {
-                *self.peek_value(arg_count) = bm.receiver;
-                self.call(bm.method, arg_count)
-            }
-            RuntimeValue::Class(class) => {
-                let instance = self.new_instance(class);
-                *self.peek_value(arg_count) = instance.into();
-                if let Some(&initializer) = class.methods.get(&self.init_string) {
-                    self.call(initializer, arg_count)?;
-                } else if arg_count != 0 {
-                    self.runtime_error(format!("Expected 0 arguments but got {arg_count}.\n"));
-                    return Err(Error::Runtime);
-                }
+    /// Sets `program` up to run one instruction at a time with [`VM::step`],
+    /// instead of all at once as [`VM::run_program`] would.
+    pub fn prepare(&mut self, program: &Program) -> Result<(), Error> {
+        self.begin_run()?;
+        let function = program.function.clone();
+        if let Some(coverage) = self.coverage.as_mut() {
+            coverage.instrument(&function);
+        }
+        match self.start_script(function) {
+            Ok(base_frame) => {
+                self.stepping = Some(base_frame);
                 Ok(())
             }
-            RuntimeValue::Closure(closure) => self.call(closure, arg_count),
-            RuntimeValue::Native(native) => {
-                let stack_top = self.store.value_stack.len();
-                let args = &self.store.value_stack[stack_top - arg_count..stack_top];
-                let result = (native.function)(args);
+            Err(error) => self.end_run(Err(error)),
+        }
+    }
 
-                self.store.value_stack.truncate(stack_top - arg_count - 1);
-                self.push_value(result);
-                Ok(())
+    /// Runs the next instruction of the program set up by [`VM::prepare`].
+    /// Calls into Lox made by natives, such as `map`'s callback, run to
+    /// completion within the step that called the native.
+    pub fn step(&mut self) -> StepResult {
+        let Some(base_frame) = self.stepping else {
+            return StepResult::Finished;
+        };
+        match self.step_instruction(base_frame) {
+            Ok(false) if std::mem::take(&mut self.paused) => StepResult::Breakpoint,
+            Ok(false) => StepResult::Executed,
+            Ok(true) => {
+                self.stepping = None;
+                let result = self.pop_value().map(|_| ());
+                let _ = self.end_run(result);
+                match result {
+                    Ok(()) => StepResult::Finished,
+                    Err(error) => StepResult::Errored(error),
+                }
             }
-
-            _ => {
-                self.runtime_error("Can only call functions and classes.\n".into());
-                Err(Error::Runtime)
+            Err(error) => {
+                self.stepping = None;
+                self.paused = false;
+                let _ = self.end_run(Err(error));
+                StepResult::Errored(error)
             }
         }
     }
 
-    fn frame_slot_to_peek_distance(&self, slot: usize) -> usize {
-        let slot_distance =
-            self.store.value_stack.len() - 1 - (self.current_frame().start_stack_index + slot);
-        slot_distance
+    /// Runs a script with `run`, updating the VM's state from how it ended.
+    fn run_with(&mut self, run: impl FnOnce(&mut Self) -> Result<(), Error>) -> Result<(), Error> {
+        self.begin_run()?;
+        let result = run(self);
+        self.end_run(result)
     }
 
-    fn run(&mut self) -> Result<(), Error> {
-        loop {
-            let instruction = OpCode::from(self.read_byte());
-            #[cfg(feature = "debug")]
-            {
-                println!();
-                for i in 0..self.store.value_stack.len() {
-                    print!("[ {} ]", self.store.value_stack[i]);
-                }
-                println!();
-                println!("{instruction}");
-            }
-            match instruction {
-                OpCode::Constant => {
-                    let index = self.read_byte() as usize;
-                    let constant = self.read_constant(index);
-                    let runtime_value = match constant {
-                        ConstantValue::Number(n) => RuntimeValue::Number(*n),
-                        ConstantValue::String(s) => {
-                            let obj_string = s.clone();
-                            self.store.insert_string(obj_string).into()
-                        }
-                        ConstantValue::Function(f) => {
-                            let obj_function = *f.clone();
-                            self.store.insert_function(obj_function).into()
-                        }
-                    };
+    fn begin_run(&mut self) -> Result<(), Error> {
+        if self.state == VmState::Poisoned {
+            return Err(Error::InternalFault(
+                "The VM was poisoned by an earlier fault.",
+            ));
+        }
+        self.instruction_deadline = match self.instruction_limit {
+            Some(limit) => self.store.stats.instructions.saturating_add(limit),
+            None => u64::MAX,
+        };
+        Ok(())
+    }
 
-                    self.push_value(runtime_value);
-                }
-                OpCode::Nil => self.push_value(RuntimeValue::Nil),
-                OpCode::True => self.push_value(RuntimeValue::Bool(true)),
-                OpCode::False => self.push_value(RuntimeValue::Bool(false)),
-                OpCode::Pop => {
-                    self.pop_value();
-                }
-                OpCode::GetLocal => {
-                    let slot = self.read_byte() as usize;
-                    let slot_distance = self.frame_slot_to_peek_distance(slot);
+    fn end_run(&mut self, result: Result<(), Error>) -> Result<(), Error> {
+        self.flush_output();
+        #[cfg(feature = "track-allocations")]
+        tracking::leave();
+        match result {
+            Ok(()) => self.state = VmState::Ready,
+            Err(Error::Runtime) => self.state = VmState::Errored,
+            Err(Error::InternalFault(_)) => self.state = VmState::Poisoned,
+            Err(Error::Compile) => {}
+        }
+        result
+    }
 
-                    let value = *self.peek_value(slot_distance);
-                    self.push_value(value);
-                }
-                OpCode::SetLocal => {
-                    let slot = self.read_byte() as usize;
-                    let slot_distance = self.frame_slot_to_peek_distance(slot);
-                    let value = *self.peek_value(0);
-                    *self.peek_value(slot_distance) = value;
-                }
-                OpCode::GetGlobal => {
-                    let index = self.read_byte() as usize;
-                    let ConstantValue::String(name) = self.read_constant(index) else {
-                        panic!("IVME: Unexpected constant value.")
-                    };
-                    let value = match self.store.globals.get(name) {
-                        Some(&v) => v,
-                        None => {
-                            self.runtime_error(format!("Undefined variable '{name}'.\n"));
-                            return Err(Error::Runtime);
-                        }
-                    };
-                    self.push_value(value);
-                }
-                OpCode::SetGlobal => {
-                    let index = self.read_byte() as usize;
-                    let ConstantValue::String(name) = self.read_constant(index) else {
-                        panic!("IVME: Unexpected constant value.")
-                    };
-                    let value = *self.peek_value(0);
-                    if self.store.globals.insert(name.clone(), value) {
-                        self.store.globals.remove(name);
-                        self.runtime_error(format!("Undefined variable '{name}'.\n"));
-                        return Err(Error::Runtime);
-                    }
-                }
-                OpCode::DefineGlobal => {
-                    let index = self.read_byte() as usize;
-                    let ConstantValue::String(name) = self.read_constant(index) else {
-                        panic!("IVME: Unexpected constant value.")
-                    };
-                    let value = self.pop_value();
-                    self.store.globals.insert(name.clone(), value);
-                }
-                OpCode::GetUpvalue => {
-                    let slot = self.read_byte() as usize;
-                    let location = {
-                        let closure = self.current_closure();
-                        let upvalue = closure.upvalues[slot];
-                        match &*upvalue {
-                            ObjUpvalue::Open { location } => *location,
-                            ObjUpvalue::Closed { value } => {
-                                self.push_value(*value);
-                                continue;
-                            }
-                        }
-                    };
-                    let value = self.store.value_stack[location];
-                    self.push_value(value);
-                }
-                OpCode::SetUpvalue => {
-                    let slot = self.read_byte() as usize;
-                    let open_upvalue = self.store.insert_upvalue(ObjUpvalue::Open {
-                        location: self.frame_slot_to_peek_distance(slot),
-                    });
-                    let mut closure = self.current_closure();
-                    closure.upvalues[slot] = open_upvalue;
-                }
-                OpCode::GetProperty => {
-                    let index = self.read_byte() as usize;
-                    let ConstantValue::String(name) = self.read_constant(index) else {
-                        panic!("IVME: Unexpected constant value.")
-                    };
-                    let instance = {
-                        let Ok(instance_ref) = self.peek_typed::<Pointer<ObjInstance>>(0) else {
-                            self.runtime_error("Only instances have fields.\n".into());
-                            return Err(Error::Runtime);
-                        };
-                        instance_ref
-                    };
-                    if let Some(v) = instance.fields.get(name) {
-                        self.pop_value(); // Instance
-                        self.push_value(*v);
-                        continue;
-                    }
+    /// Interprets `source` while collecting everything it prints, returning the
+    /// result along with the captured output and error output, compile
+    /// errors included.
+    pub fn interpret_captured(&mut self, source: &str) -> (Result<(), Error>, String, String) {
+        let previous = self.capture.replace(Capture::default());
+        let result = self.interpret(source);
+        let capture = std::mem::replace(&mut self.capture, previous).unwrap_or_default();
+        (
+            result,
+            String::from_utf8_lossy(&capture.out).into_owned(),
+            String::from_utf8_lossy(&capture.e_out).into_owned(),
+        )
+    }
 
-                    self.bind_method(instance.class, name)?;
-                }
-                OpCode::SetProperty => {
-                    let Ok(mut instance) = self.peek_typed::<Pointer<ObjInstance>>(1) else {
-                        self.runtime_error("Only instances have fields.\n".into());
-                        return Err(Error::Runtime);
-                    };
-                    let index = self.read_byte() as usize;
-                    let ConstantValue::String(name) = self.read_constant(index) else {
-                        panic!("IVME: Unexpected constant value.")
-                    };
-                    let value = *self.peek_value(0);
-                    instance.fields.insert(name.clone(), value);
-                    let value = self.pop_value();
-                    self.pop_value(); // Instance
-                    self.push_value(value);
-                }
-                OpCode::GetSuper => {
-                    let index = self.read_byte() as usize;
-                    let ConstantValue::String(name) = &*self.read_constant(index) else {
-                        panic!("IVME: Unexpected constant value.")
-                    };
-                    let superclass = match self.pop_value() {
-                        RuntimeValue::Class(o) => o,
-                        _ => return Err(Error::Runtime),
-                    };
-                    self.bind_method(superclass, name)?;
-                }
-                OpCode::Equal => {
-                    let a = self.pop_value();
-                    let b = self.pop_value();
-                    self.push_value((a == b).into());
-                }
-                OpCode::Greater => {
-                    if self.peek_typed::<f64>(0).is_err() || self.peek_typed::<f64>(1).is_err() {
-                        self.runtime_error("Operands must be numbers.\n".into());
-                        return Err(Error::Runtime);
-                    }
-                    let b = self.pop_typed::<f64>();
-                    let a = self.pop_typed::<f64>();
-                    self.push_value((a > b).into());
-                }
-                OpCode::Less => {
-                    if self.peek_typed::<f64>(0).is_err() || self.peek_typed::<f64>(1).is_err() {
-                        self.runtime_error("Operands must be numbers.\n".into());
-                        return Err(Error::Runtime);
-                    }
-                    let b = self.pop_typed::<f64>();
-                    let a = self.pop_typed::<f64>();
-                    self.push_value((a < b).into());
-                }
-                OpCode::Add => {
-                    if self.peek_typed::<Pointer<ObjString>>(0).is_ok()
-                        && self.peek_typed::<Pointer<ObjString>>(1).is_ok()
-                    {
-                        self.concatenate()?;
-                        continue;
-                    }
+    /// Interprets `source` as the module at `path`: its relative imports
+    /// resolve next to it, and importing it again is a no-op or a cycle.
+    pub fn interpret_module(&mut self, path: &Path, source: &str) -> Result<(), Error> {
+        let path = module::absolute(path);
+        self.modules.insert(path.clone());
+        self.module_chain.push(path);
+        let result = self.interpret(source);
+        self.module_chain.pop();
+        result
+    }
 
-                    if self.peek_typed::<f64>(0).is_err() || self.peek_typed::<f64>(1).is_err() {
-                        self.runtime_error("Operands must be two numbers or two strings.\n".into());
-                        return Err(Error::Runtime);
-                    }
-                    let b = self.pop_typed::<f64>();
-                    let a = self.pop_typed::<f64>();
-                    self.push_value((a + b).into());
-                }
-                OpCode::Subtract => {
-                    if self.peek_typed::<f64>(0).is_err() || self.peek_typed::<f64>(1).is_err() {
-                        self.runtime_error("Operands must be numbers.\n".into());
-                        return Err(Error::Runtime);
-                    }
-                    let b = self.pop_typed::<f64>();
-                    let a = self.pop_typed::<f64>();
-                    self.push_value((a - b).into());
-                }
-                OpCode::Multiply => {
-                    if self.peek_typed::<f64>(0).is_err() || self.peek_typed::<f64>(1).is_err() {
-                        self.runtime_error("Operands must be numbers.\n".into());
-                        return Err(Error::Runtime);
-                    }
-                    let b = self.pop_typed::<f64>();
-                    let a = self.pop_typed::<f64>();
-                    self.push_value((a * b).into());
-                }
-                OpCode::Divide => {
-                    if self.peek_typed::<f64>(0).is_err() || self.peek_typed::<f64>(1).is_err() {
-                        self.runtime_error("Operands must be numbers.\n".into());
-                        return Err(Error::Runtime);
-                    }
-                    let b = self.pop_typed::<f64>();
-                    let a = self.pop_typed::<f64>();
-                    self.push_value((a / b).into());
-                }
-                OpCode::Not => {
-                    let value = self.pop_value();
-                    self.push_value(value.is_falsey().into());
-                }
-                OpCode::Negate => {
-                    if self.peek_typed::<f64>(0).is_err() {
-                        self.runtime_error("Operand must be a number.\n".into());
-                        return Err(Error::Runtime);
-                    }
-                    let value = self.pop_typed::<f64>();
-                    self.push_value((-value).into());
-                }
-                OpCode::Print => {
-                    let value = self.pop_value();
-                    match value {
-                        RuntimeValue::Bool(b) => self.println(format!("{b}")),
-                        RuntimeValue::Number(n) => {
-                            if n.fract() == 0.0 {
-                                self.println(format!("{n}"));
-                            } else {
-                                self.println(format!("{n:.6}"));
-                            }
-                        }
-                        RuntimeValue::BoundMethod(bm) => {
-                            self.println(format!("{bm}"));
-                        }
-                        RuntimeValue::Class(class) => {
-                            self.println(format!("{class}"));
-                        }
-                        RuntimeValue::Closure(closure) => {
-                            self.println(format!("{closure}"));
-                        }
-                        RuntimeValue::Function(function) => {
-                            self.println(format!("{function}"));
-                        }
-                        RuntimeValue::Instance(instance) => {
-                            self.println(format!("{instance}"));
-                        }
-                        RuntimeValue::Native(native) => {
-                            self.println(format!("{native}"));
-                        }
-                        RuntimeValue::String(string) => {
-                            self.println(format!("{string}"));
-                        }
-                        RuntimeValue::Nil => self.println("nil"),
-                        RuntimeValue::Upvalue(upvalue) => {
-                            self.println(format!("{upvalue}"));
-                        }
-                    }
-                }
-                OpCode::Jump => {
-                    let offset = self.read_short() as usize;
-                    self.current_frame_mut().ip += offset;
-                }
-                OpCode::JumpIfFalse => {
-                    let offset = self.read_short() as usize;
-                    if self.peek_value(0).is_falsey() {
-                        self.current_frame_mut().ip += offset;
-                    }
-                }
-                OpCode::Loop => {
-                    let offset = self.read_short() as usize;
-                    self.current_frame_mut().ip -= offset;
-                }
-                OpCode::Call => {
-                    let arg_count = self.read_byte() as usize;
-                    let callee = *self.peek_value(arg_count);
-                    self.call_value(callee, arg_count)?;
-                }
-                OpCode::Invoke => {
-                    let index = self.read_byte() as usize;
-                    let ConstantValue::String(method_name) = self.read_constant(index) else {
-                        panic!("IVME: Unexpected constant value.")
-                    };
-                    let arg_count = self.read_byte() as usize;
-                    self.invoke(method_name, arg_count)?;
-                }
-                OpCode::SuperInvoke => {
-                    let index = self.read_byte() as usize;
-                    let arg_count = self.read_byte() as usize;
-                    let class = self.pop_typed::<Pointer<ObjClass>>();
-                    let ConstantValue::String(method_name) = self.read_constant(index) else {
-                        panic!("IVME: Unexpected constant value.")
-                    };
-                    self.invoke_from_class(class, method_name, arg_count)?;
-                }
-                OpCode::Closure => {
-                    let index = self.read_byte() as usize;
-                    let ConstantValue::Function(function) = self.read_constant(index) else {
-                        return Err(Error::Runtime);
-                    };
-                    let upvalue_count = function.upvalue_count;
-                    let function = self.store.insert_function(*function.clone());
-                    let mut closure = self.new_closure(function);
-                    self.push_value(closure.into());
-                    for _ in 0..upvalue_count {
-                        let is_local = self.read_byte() != 0;
-                        let index = self.read_byte() as usize;
-                        if is_local {
-                            let upvalue = self.capture_upvalue(index);
-                            closure.upvalues.push(upvalue);
-                        } else {
-                            let current_closure = self.current_closure();
-                            let current_closure_upvalue = current_closure.upvalues[index];
-                            closure.upvalues.push(current_closure_upvalue);
-                        }
-                    }
-                }
-                OpCode::CloseUpvalue => {
-                    let stack_top = self.store.value_stack.len() - 1;
-                    self.close_upvalues(stack_top);
-                    self.pop_value();
-                }
-                OpCode::Return => {
-                    let result = self.pop_value();
-                    let slots = self.current_frame().slots;
-                    self.close_upvalues(slots);
-                    let start_index = self.pop_frame().start_stack_index;
-                    if self.store.frame_stack_top == 0 {
-                        return Ok(());
-                    }
-                    self.store.value_stack.truncate(start_index);
-                    self.push_value(result);
-                }
-                OpCode::Class => {
-                    let index = self.read_byte() as usize;
-                    let ConstantValue::String(name) = self.read_constant(index) else {
-                        panic!("IVME: Unexpected constant value.")
-                    };
-                    let class = self.new_class(name);
-                    self.push_value(class.into());
-                }
-                OpCode::Inherit => {
-                    let Ok(superclass) = self.peek_typed::<Pointer<ObjClass>>(1) else {
-                        self.runtime_error("Superclass must be a class.\n".into());
-                        return Err(Error::Runtime);
-                    };
-                    let mut subclass = self.peek_typed::<Pointer<ObjClass>>(0)?;
-                    let mut methods: Vec<_> =
-                        superclass.methods.iter().cloned().collect::<Vec<_>>();
-                    methods.retain(|x| {
-                        x.clone()
-                            .is_some_and(|y| y.key.is_some() && y.value.is_some())
-                    });
-                    let methods = methods
-                        .into_iter()
-                        .map(Option::unwrap)
-                        .map(|x| (x.key.unwrap(), x.value.unwrap()))
-                        .collect::<Vec<_>>();
-                    for (key, value) in methods {
-                        subclass.methods.insert(key, value);
-                    }
-                    self.pop_value(); // Subclass
-                }
-                OpCode::Method => {
-                    let index = self.read_byte() as usize;
-                    let ConstantValue::String(name) = self.read_constant(index) else {
-                        panic!("IVME: Unexpected constant value.")
-                    };
-                    self.define_method(name)?;
-                }
-                OpCode::Unknown => return Err(Error::Runtime),
+    /// The modules that ran or are running, in no particular order.
+    pub fn modules(&self) -> impl Iterator<Item = &Path> {
+        self.modules.iter().map(PathBuf::as_path)
+    }
+
+    fn execute(&mut self, compiler: Compiler) -> Result<(), Error> {
+        self.store.tracer.emit(TraceEvent::Compile);
+
+        let (result, diagnostics) = self.compile_reporting(compiler);
+        self.set_diagnostics(diagnostics);
+        self.execute_function(result?)
+    }
+
+    /// Compiles with `compiler`, writing any errors to the error output.
+    fn compile_reporting(
+        &mut self,
+        compiler: Compiler,
+    ) -> (Result<ObjFunction, Error>, Diagnostics) {
+        let (result, diagnostics) = compiler.quiet().compile_with_diagnostics();
+        if !diagnostics.errors.is_empty() {
+            // Keep what was printed before ahead of the errors
+            self.flush_output();
+        }
+        for error in &diagnostics.errors {
+            self.eprint(format!("{error}\n"));
+            if let Some(caret) = &error.caret {
+                self.eprint(caret.clone());
             }
         }
+        (result, diagnostics)
     }
 
-    fn call(&mut self, closure: Pointer<ObjClosure>, arg_count: usize) -> Result<(), Error> {
-        let arity = closure.function.arity;
+    /// Keeps the diagnostics of the script just compiled, handing them to
+    /// the compiled hook first.
+    fn set_diagnostics(&mut self, diagnostics: Diagnostics) {
+        if let Some(hook) = self.hooks.compiled.as_mut() {
+            hook(&diagnostics);
+        }
+        self.diagnostics = diagnostics;
+    }
 
-        if arg_count != arity {
-            self.runtime_error(format!(
-                "Expected {} arguments but got {}.\n",
-                arity, arg_count
-            ));
-            return Err(Error::Runtime);
+    fn execute_function(&mut self, function: ObjFunction) -> Result<(), Error> {
+        if let Some(coverage) = self.coverage.as_mut() {
+            coverage.instrument(&function);
         }
-        if self.store.frame_stack_top == MAX_FRAMES {
-            self.runtime_error("Stack overflow.\n".into());
+        self.run_script(function)?;
+        Ok(())
+    }
+
+    /// A compiler for `source` configured like this VM.
+    fn compiler(&self, source: &str) -> Compiler {
+        Compiler::with_options(source, &self.compile_options)
+    }
+
+    /// Runs a compiled script until it returns, on top of whatever is
+    /// running, returning what it returned.
+    fn run_script(&mut self, function: ObjFunction) -> Result<RuntimeValue, Error> {
+        let base_frame = self.start_script(function)?;
+        self.run(base_frame)?;
+        self.pop_value()
+    }
+
+    /// Compiles and runs `source` on top of whatever is running, returning
+    /// the value of its last statement when that's an expression, or nil.
+    /// Isolated source sees only the natives, and whatever globals it
+    /// defines are gone once it returns, so functions it returns see the
+    /// caller's globals.
+    fn eval(&mut self, source: &str, isolated: bool) -> Result<RuntimeValue, Error> {
+        let compiler = self.compiler(source).with_expression_result();
+        let (Ok(function), _) = self.compile_reporting(compiler) else {
+            self.runtime_error("Could not compile eval source.\n".into());
             return Err(Error::Runtime);
+        };
+        if isolated {
+            self.store
+                .globals
+                .isolate(|value| matches!(value, RuntimeValue::Native(_)));
         }
-        let frame = &mut self.store.frame_stack[self.store.frame_stack_top];
-        let function = closure.function;
-        *frame = CallFrame {
-            closure,
-            chunk: &function.chunk as *const Chunk,
-            ip: 0,
-            slots: arg_count,
-            start_stack_index: self.store.value_stack.len() - 1 - arg_count,
+        let result = self.run_script(function);
+        if isolated {
+            self.store.globals.restore();
+        }
+        result
+    }
+
+    /// Calls a compiled script on top of whatever is running, returning the
+    /// frame it runs above.
+    fn start_script(&mut self, mut function: ObjFunction) -> Result<usize, Error> {
+        self.store.globals.link(&mut function);
+        if self.predecode {
+            if let Err(context) = verifier::verify(&function) {
+                return Err(self.fault(context));
+            }
+        }
+        if self.store.tracer.wants_code() {
+            self.trace_functions(&function);
+        }
+
+        let base_frame = self.store.frame_stack_top;
+        let function_ref = self.store.insert_function(function);
+        self.push_value(function_ref.into());
+        let closure = self.new_closure(function_ref);
+        self.pop_value()?;
+        self.push_value(closure.into());
+        self.call(closure, 0)?;
+        Ok(base_frame)
+    }
+
+    /// Traces the disassembly of `function` and every function nested in it.
+    fn trace_functions(&mut self, function: &ObjFunction) {
+        let mut pending = vec![function];
+        while let Some(function) = pending.pop() {
+            self.store.tracer.emit(TraceEvent::Code {
+                function: &function.to_string(),
+                disassembly: &function.chunk.to_string(),
+            });
+            pending.extend(
+                function
+                    .chunk
+                    .constants
+                    .iter()
+                    .filter_map(|constant| match constant {
+                        ConstantValue::Function(function) => Some(&**function),
+                        _ => None,
+                    }),
+            );
+        }
+    }
+
+    /// Runs the module `name` into the globals, unless it already ran.
+    fn import(&mut self, name: &str) -> Result<(), Error> {
+        let spec = self.module_spec(name);
+        let resolved = match self.module_resolver.as_mut() {
+            Some(resolver) => resolver.resolve(&spec).ok(),
+            None => match module::find_file(&spec, &self.module_paths) {
+                Some(path) => Some(self.read_module(name, path)?),
+                None => None,
+            },
         };
-        self.store.frame_stack_top += 1;
+        let Some(source) = resolved else {
+            self.runtime_error(format!("Could not find module '{name}'.\n"));
+            return Err(Error::Runtime);
+        };
+        self.run_module(name, source.path, &source.text)?;
         Ok(())
     }
 
-    fn new_class(&mut self, name: &ObjString) -> Pointer<ObjClass> {
-        let name_ref = self.store.insert_string(name.clone());
-        let class = ObjClass {
-            name: name_ref,
-            methods: Table::default(),
-        };
-        self.store.insert_class(class)
+    /// The module `name` from the file found for it at `path`.
+    fn read_module(&mut self, name: &str, path: PathBuf) -> Result<Source, Error> {
+        match fs::read_to_string(&path) {
+            Ok(text) => Ok(Source {
+                path: module::absolute(&path),
+                text,
+            }),
+            Err(error) => {
+                self.runtime_error(format!(
+                    "Could not read module '{name}' at '{}': {error}.\n",
+                    path.display()
+                ));
+                Err(Error::Runtime)
+            }
+        }
     }
 
-    fn new_closure(&mut self, function: Pointer<ObjFunction>) -> Pointer<ObjClosure> {
-        let upvalues = Vec::with_capacity(function.upvalue_count);
-        let closure = ObjClosure { function, upvalues };
-        self.store.insert_closure(closure)
+    /// The module `name` as the resolver is asked for it: names starting
+    /// with `./` or `../` are joined onto the importing module's directory.
+    fn module_spec(&self, name: &str) -> String {
+        let directory = self
+            .module_chain
+            .last()
+            .and_then(|importer| importer.parent())
+            .filter(|directory| !directory.as_os_str().is_empty());
+        match directory {
+            Some(directory) if module::is_relative(name) => directory
+                .join(name)
+                .components()
+                .collect::<PathBuf>()
+                .to_string_lossy()
+                .into_owned(),
+            _ => name.to_string(),
+        }
     }
 
-    fn new_instance(&mut self, class: Pointer<ObjClass>) -> Pointer<ObjInstance> {
-        let instance = ObjInstance {
-            class,
-            fields: Table::default(),
+    /// Runs the file at `path` into the globals, unless it already ran,
+    /// returning whether it ran. Relative paths are resolved next to the
+    /// running module, or the working directory outside of one. Files are
+    /// tracked with imported modules, so each runs once however it's reached.
+    fn load_file(&mut self, path: &str) -> Result<bool, Error> {
+        let directory = self
+            .module_chain
+            .last()
+            .and_then(|module| module.parent())
+            .unwrap_or(Path::new("."));
+        let resolved = directory.join(path);
+        if !resolved.is_file() {
+            self.runtime_error(format!("Could not find file '{path}'.\n"));
+            return Err(Error::Runtime);
+        }
+        let resolved = module::absolute(&resolved);
+        let Ok(source) = fs::read_to_string(&resolved) else {
+            self.runtime_error(format!("Could not read module '{path}'.\n"));
+            return Err(Error::Runtime);
         };
-        self.store.insert_instance(instance)
+        self.run_module(path, resolved, &source)
     }
 
-    fn new_bound_method(
-        &mut self,
-        receiver: RuntimeValue,
-        method: Pointer<ObjClosure>,
-    ) -> Pointer<ObjBoundMethod> {
-        let bound_method = ObjBoundMethod { receiver, method };
-        self.store.insert_bound_method(bound_method)
+    /// Runs `source` as the module `name` found at `path`, returning whether
+    /// it ran or had already run before.
+    fn run_module(&mut self, name: &str, path: PathBuf, source: &str) -> Result<bool, Error> {
+        if let Some(start) = self.module_chain.iter().position(|module| *module == path) {
+            let chain = self.module_chain[start..]
+                .iter()
+                .chain([&path])
+                .map(|module| module_name(module))
+                .collect::<Vec<_>>()
+                .join(" -> ");
+            self.runtime_error(format!("Import cycle detected: {chain}\n"));
+            return Err(Error::Runtime);
+        }
+        if !self.modules.insert(path.clone()) {
+            return Ok(false);
+        }
+        let (Ok(function), _) = self.compile_reporting(self.compiler(source)) else {
+            self.runtime_error(format!("Could not compile module '{name}'.\n"));
+            return Err(Error::Runtime);
+        };
+        self.module_chain.push(path);
+        let result = self.run_script(function);
+        self.module_chain.pop();
+        result.map(|_| true)
     }
 
-    fn new_native(&mut self, function: NativeFn) -> Pointer<ObjNative> {
-        self.store.insert_native(ObjNative { function })
+    pub fn stats(&self) -> &Stats {
+        &self.store.stats
     }
 
-    fn push_value(&mut self, value: RuntimeValue) {
-        self.store.value_stack.push(value);
+    /// Writes the graph of every live object, with its kind, size and the
+    /// objects it references, to `writer` in `format`.
+    pub fn dump_heap(&self, mut writer: impl Write, format: HeapFormat) -> io::Result<()> {
+        HeapGraph::of(&self.store).write(&mut writer, format)
     }
 
-    fn pop_frame(&mut self) -> CallFrame {
-        self.store.frame_stack_top -= 1;
-        self.store.frame_stack[self.store.frame_stack_top].clone()
+    /// Writes the value stack, call frames, globals and the code about to
+    /// run to `writer`, for watching a program run with [`VM::step`].
+    pub fn dump_state(&self, mut writer: impl Write, format: StateFormat) -> io::Result<()> {
+        MachineState::of(&self.store).write(&mut writer, format)
     }
 
-    fn pop_value(&mut self) -> RuntimeValue {
-        self.store
-            .value_stack
-            .pop()
-            .expect("IVME: Failed to pop value")
+    /// Compiles `source` as later scripts would be, without running it, and
+    /// lists the bytecode of the script and every function in it. Compile
+    /// errors are reported on stderr.
+    pub fn disassemble(&self, source: &str, options: DisassemblyOptions) -> Result<String, Error> {
+        let function = self.compiler(source).compile()?;
+        Ok(disassembler::disassemble(&function, options))
     }
 
-    fn peek_value(&mut self, distance: usize) -> &mut RuntimeValue {
-        let index = self.store.value_stack.len() - distance - 1;
-        &mut self.store.value_stack[index]
+    #[cfg(feature = "metrics")]
+    pub fn metrics(&self) -> &crate::metrics::Metrics {
+        &self.store.metrics
     }
 
-    fn peek_typed<T: TryFrom<RuntimeValue, Error = Error>>(
+    fn define_native(
         &mut self,
-        distance: usize,
-    ) -> Result<T, Error> {
-        (*self.peek_value(distance)).try_into()
+        name: ObjString,
+        arity: usize,
+        function: NativeFn,
+    ) -> Pointer<ObjNative> {
+        // Size the argument buffer so native calls never need to grow it
+        self.native_args.reserve(arity);
+        let native = self.new_native(arity, function);
+        self.store.globals.insert(name, native.into());
+        native
     }
 
-    fn pop_typed<T: TryFrom<RuntimeValue, Error = Error>>(&mut self) -> T {
-        self.pop_value()
-            .try_into()
-            .expect("IVME: failed to convert value")
+    fn println(&mut self, string: impl Into<String>) {
+        let string: String = string.into();
+        if self.capture.is_none() {
+            if let Some(hook) = self.hooks.print.as_mut() {
+                hook(&string);
+                return;
+            }
+        }
+        let string = string + "\n";
+        if let Some(capture) = self.capture.as_mut() {
+            capture.out.extend_from_slice(string.as_bytes());
+            return;
+        }
+        self.pending_out.extend_from_slice(string.as_bytes());
+        let full = match self.output_buffering {
+            OutputBuffering::Line => true,
+            OutputBuffering::Block(size) => self.pending_out.len() >= size,
+            OutputBuffering::Manual => false,
+        };
+        if full {
+            self.flush_output();
+        }
     }
-}
 
-#[cfg(test)]
-mod test {
-    use super::*;
+    /// Writes out the printed output held back by the output buffering.
+    fn flush_output(&mut self) {
+        if self.pending_out.is_empty() {
+            return;
+        }
+        self.out
+            .write_all(&self.pending_out)
+            .expect("IVME: Failed to write data");
+        self.out.flush().expect("IVME: Failed to flush data");
+        self.pending_out.clear();
+    }
 
-    #[derive(Debug, Default)]
-    struct TestOut {
-        buf: Vec<u8>,
-        flushed: Vec<String>,
+    fn eprint(&mut self, string: impl Into<String>) {
+        let string: String = string.into();
+        if let Some(capture) = self.capture.as_mut() {
+            capture.e_out.extend_from_slice(string.as_bytes());
+            return;
+        }
+        self.e_out
+            .write_all(string.as_bytes())
+            .expect("IVME: Failed to write data");
+        self.e_out.flush().expect("IVME: Failed to flush data");
     }
 
-    impl Write for TestOut {
-        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
-            self.buf = buf.into();
-            Ok(self.buf.len())
+    /// Renders the source line of the failing instruction with a caret under
+    /// its span, when the current chunk was compiled with debug info.
+    fn caret_diagnostic(&self) -> Option<String> {
+        if self.store.frame_stack_top == 0 {
+            return None;
         }
+        let frame = self.current_frame();
+        let chunk = self.current_chunk();
+        let debug_info = chunk.debug_info.as_ref()?;
+        let span = chunk.span(frame.ip.saturating_sub(1))?;
+        Some(debug_info.caret(span))
+    }
 
-        fn flush(&mut self) -> std::io::Result<()> {
-            let buf = self.buf.clone();
-            self.flushed.push(String::from_utf8(buf).unwrap());
-            self.buf = Vec::new();
-            Ok(())
+    /// Reports an internal inconsistency such as corrupted bytecode. The VM is
+    /// poisoned afterwards and refuses to run anything else.
+    fn fault(&mut self, context: &'static str) -> Error {
+        self.state = VmState::Poisoned;
+        self.eprint(format!("Internal VM fault: {context}\n"));
+        self.reset_stack();
+        Error::InternalFault(context)
+    }
+
+    fn reset_stack(&mut self) {
+        self.named_args.clear();
+        self.displaying.clear();
+        self.store.frame_stack_top = 0;
+        self.store.open_upvalues = BTreeMap::default();
+    }
+
+    fn runtime_error(&mut self, message: String) {
+        // Keep what the script printed ahead of the error that stopped it
+        self.flush_output();
+        let caret = self.caret_diagnostic();
+        let trace = self.backtrace();
+        let recent_events = self
+            .store
+            .tracer
+            .recorder
+            .as_mut()
+            .map(FlightRecorder::take)
+            .unwrap_or_default();
+        self.reset_stack();
+
+        if self.capture.is_none() {
+            if let Some(hook) = self.hooks.error.as_mut() {
+                hook(&RuntimeErrorInfo {
+                    message: message.trim_end_matches('\n').to_string(),
+                    caret,
+                    trace,
+                    recent_events,
+                });
+                return;
+            }
+        }
+        self.eprint(message);
+        if let Some(caret) = caret {
+            self.eprint(caret);
+        }
+        let omitted = match self.full_backtrace {
+            true => 0,
+            false => trace.len().saturating_sub(BACKTRACE_HEAD + BACKTRACE_TAIL),
+        };
+        let omitted_frames = BACKTRACE_HEAD..BACKTRACE_HEAD + omitted;
+        for (depth, TraceFrame { line, function }) in trace.into_iter().enumerate() {
+            if omitted_frames.contains(&depth) {
+                if depth == BACKTRACE_HEAD {
+                    self.eprint(format!("... {omitted} frames omitted ...\n"));
+                }
+                continue;
+            }
+            self.eprint(format!("[line {line}] in "));
+            match function {
+                Some(name) => self.eprint(format!("{name}\n")),
+                None => self.eprint("script\n"),
+            }
         }
+        if !recent_events.is_empty() {
+            self.eprint(RECENT_EVENTS_HEADER);
+            for event in recent_events {
+                self.eprint(format!("  {event}\n"));
+            }
+        }
+    }
+
+    /// The calls active, innermost first, each at the line it is executing.
+    fn backtrace(&self) -> Vec<TraceFrame> {
+        self.store.frame_stack[..self.store.frame_stack_top]
+            .iter()
+            .rev()
+            .map(|frame| TraceFrame {
+                // The frame's ip is past the instruction that failed or made the call
+                line: unsafe { (&(*frame.chunk).lines)[frame.ip.saturating_sub(1)] },
+                function: frame.closure.function.name.clone(),
+            })
+            .collect()
+    }
+
+    fn current_frame(&self) -> &CallFrame {
+        &self.store.frame_stack[self.store.frame_stack_top - 1]
+    }
+
+    fn current_frame_mut(&mut self) -> &mut CallFrame {
+        &mut self.store.frame_stack[self.store.frame_stack_top - 1]
+    }
+
+    /// The stack slots of the current frame's locals, up to the stack top.
+    fn current_locals(&self) -> Range<usize> {
+        self.current_frame().locals(self.store.value_stack.len())
+    }
+
+    fn current_closure(&self) -> Pointer<ObjClosure> {
+        self.current_frame().closure
+    }
+
+    fn current_chunk(&self) -> &Chunk {
+        unsafe {
+            self.current_frame()
+                .chunk
+                .as_ref()
+                .expect("Failed to get chunk pointer")
+        }
+    }
+
+    fn read_byte(&mut self) -> u8 {
+        let ip = self.current_frame().ip;
+        let code = self.current_chunk().code[ip];
+        self.current_frame_mut().ip += 1;
+        code
+    }
+
+    fn read_short(&mut self) -> u16 {
+        let byte_1 = self.read_byte();
+        let byte_2 = self.read_byte();
+        (byte_1 as u16) << 8 | (byte_2 as u16)
+    }
+
+    /// Moves the ip `offset` bytes forward, or back for loops, faulting
+    /// instead of leaving the chunk.
+    fn jump(&mut self, offset: usize, backward: bool) -> Result<(), Error> {
+        let ip = self.current_frame().ip;
+        let target = if backward {
+            ip.checked_sub(offset)
+        } else {
+            ip.checked_add(offset)
+        };
+        match target {
+            Some(target) if target < self.current_chunk().code.len() => {
+                self.current_frame_mut().ip = target;
+                Ok(())
+            }
+            _ => Err(self.fault("Jump out of range.")),
+        }
+    }
+
+    fn read_constant<'b>(&self, index: usize) -> &'b ConstantValue {
+        let raw = NonNull::from(&self.current_chunk().constants[index]);
+        unsafe {
+            // We are guaranteed never to modify constant values,
+            // so we can return a reference to the underlying data
+            // itself without borrowing from `self`
+            raw.as_ref()
+        }
+    }
+
+    /// Reads a global instruction's operand as the VM slot it was linked to.
+    fn read_global_slot(&mut self) -> usize {
+        let index = self.read_byte() as usize;
+        self.current_chunk().global_slots[index]
+    }
+
+    fn undefined_global(&mut self, slot: usize) -> Error {
+        let name = self.store.globals.name(slot).clone();
+        self.runtime_error(format!("Undefined variable '{name}'.\n"));
+        Error::Runtime
+    }
+
+    fn bind_method(&mut self, class: Pointer<ObjClass>, name: &ObjString) -> Result<(), Error> {
+        let Some(&method) = class.methods.get(name) else {
+            self.runtime_error(format!("Undefined property '{}'", name.chars));
+            return Err(Error::Runtime);
+        };
+
+        let receiver = *self.peek_value(0)?;
+        let bound = self.new_bound_method(receiver, method);
+        self.pop_value()?;
+        self.push_value(bound.into());
+        Ok(())
+    }
+
+    fn capture_upvalue(&mut self, index: usize) -> Pointer<ObjUpvalue> {
+        let absolute_stack_index = self.current_frame().start_stack_index + index;
+        if let Some(upvalue) = self.store.open_upvalues.get(&absolute_stack_index) {
+            return *upvalue;
+        }
+
+        let upvalue = ObjUpvalue::Open {
+            location: absolute_stack_index,
+        };
+        let upvalue_ptr = self.store.insert_upvalue(upvalue);
+
+        self.store
+            .open_upvalues
+            .insert(absolute_stack_index, upvalue_ptr);
+        upvalue_ptr
+    }
+
+    /// Closes every open upvalue at or above the absolute stack index `abs_last_stack_index`.
+    fn close_upvalues(&mut self, abs_last_stack_index: usize) {
+        let mut closed_upvalues = Vec::new();
+        for (&abs_stack_index, open_upvalue) in self.store.open_upvalues.iter_mut().rev() {
+            if abs_stack_index < abs_last_stack_index {
+                break;
+            }
+            let referenced_value = self.store.value_stack[abs_stack_index];
+            **open_upvalue = ObjUpvalue::Closed {
+                value: referenced_value,
+            };
+            closed_upvalues.push(abs_stack_index);
+        }
+        for closed_upvalue in closed_upvalues {
+            self.store.open_upvalues.remove(&closed_upvalue);
+        }
+    }
+
+    fn define_method(&mut self, name: &ObjString, is_static: bool) -> Result<(), Error> {
+        let method = self.peek_typed::<Pointer<ObjClosure>>(0)?;
+        let mut class = self.peek_typed::<Pointer<ObjClass>>(1)?;
+        if is_static {
+            class.statics.insert(name.clone(), method);
+        } else {
+            class.methods.insert(name.clone(), method);
+        }
+        self.pop_value()?;
+        Ok(())
+    }
+
+    fn concatenate(&mut self) -> Result<(), Error> {
+        let b = self.peek_typed::<Pointer<ObjString>>(0)?;
+        let a = self.peek_typed::<Pointer<ObjString>>(1)?;
+        let result = SmallString::concat(&a.chars, &b.chars);
+        let new_string = self.store.insert_string(result.into());
+        self.pop_value()?;
+        self.pop_value()?;
+        self.push_value(new_string.into());
+        Ok(())
+    }
+
+    fn invoke(&mut self, method_name: &ObjString, arg_count: usize) -> Result<(), Error> {
+        if let Ok(class) = self.peek_typed::<Pointer<ObjClass>>(arg_count) {
+            let method = self.static_method(class, method_name)?;
+            return self.call(method, arg_count);
+        }
+        let Ok(receiver) = self.peek_typed::<Pointer<ObjInstance>>(arg_count) else {
+            self.runtime_error("Only instances have methods.\n".into());
+            return Err(Error::Runtime);
+        };
+        let instance_fields = &receiver.fields;
+        if let Some(&value) = instance_fields.get(method_name) {
+            let stack_top = self.store.value_stack.len() - 1;
+            self.store.value_stack[stack_top - arg_count] = value;
+            return self.call_value(value, arg_count);
+        }
+        self.invoke_from_class(receiver.class, method_name, arg_count)
+    }
+
+    /// The static method `name` of `class`, called without a receiver.
+    fn static_method(
+        &mut self,
+        class: Pointer<ObjClass>,
+        name: &ObjString,
+    ) -> Result<Pointer<ObjClosure>, Error> {
+        let Some(&method) = class.statics.get(name) else {
+            self.runtime_error(format!("Undefined static method '{name}'.\n"));
+            return Err(Error::Runtime);
+        };
+        Ok(method)
+    }
+
+    fn invoke_from_class(
+        &mut self,
+        class: Pointer<ObjClass>,
+        method_name: &ObjString,
+        arg_count: usize,
+    ) -> Result<(), Error> {
+        let Some(&method) = class.methods.get(method_name) else {
+            self.runtime_error(format!("Undefined property '{method_name}'.\n"));
+            return Err(Error::Runtime);
+        };
+        self.call(method, arg_count)
+    }
+
+    /// Fails when `name` is a private member, which only instructions on
+    /// `this` may reach.
+    fn check_public(&mut self, name: &ObjString) -> Result<(), Error> {
+        if !is_private_member(&name.chars) {
+            return Ok(());
+        }
+        self.runtime_error(format!(
+            "Can't access private member '{}' outside of 'this'.\n",
+            name.chars
+        ));
+        Err(Error::Runtime)
+    }
+
+    /// Fails when `initializer` is already running as deeply as the init
+    /// recursion limit allows.
+    fn check_init_recursion(
+        &mut self,
+        class: Pointer<ObjClass>,
+        initializer: Pointer<ObjClosure>,
+    ) -> Result<(), Error> {
+        let Some(limit) = self.init_recursion_limit else {
+            return Ok(());
+        };
+        let depth = self.store.frame_stack[..self.store.frame_stack_top]
+            .iter()
+            .filter(|frame| frame.closure == initializer)
+            .count();
+        if depth < limit {
+            return Ok(());
+        }
+        self.runtime_error(format!(
+            "Initializer '{}.init' recursed more than {limit} calls deep.\n",
+            class.name
+        ));
+        Err(Error::Runtime)
+    }
+
+    fn call_value(&mut self, callee: RuntimeValue, arg_count: usize) -> Result<(), Error> {
+        match callee {
+            RuntimeValue::BoundMethod(bm) => {
+                *self.peek_value(arg_count)? = bm.receiver;
+                self.call(bm.method, arg_count)
+            }
+            RuntimeValue::Class(class) => {
+                let instance = self.new_instance(class);
+                *self.peek_value(arg_count)? = instance.into();
+                if let Some(&initializer) = class.methods.get(&self.init_string) {
+                    self.check_init_recursion(class, initializer)?;
+                    self.call(initializer, arg_count)?;
+                } else if arg_count != 0 {
+                    self.runtime_error(format!("Expected 0 arguments but got {arg_count}.\n"));
+                    return Err(Error::Runtime);
+                }
+                Ok(())
+            }
+            RuntimeValue::Closure(closure) => self.call(closure, arg_count),
+            RuntimeValue::Native(native) => {
+                if !self.named_args.is_empty() {
+                    self.runtime_error(
+                        "Named arguments can only be passed to Lox functions.\n".into(),
+                    );
+                    return Err(Error::Runtime);
+                }
+                let required = native.arity - native.optional;
+                if !(required..=native.arity).contains(&arg_count) {
+                    let expected = if required == native.arity {
+                        native.arity.to_string()
+                    } else {
+                        format!("{required} to {}", native.arity)
+                    };
+                    self.runtime_error(format!(
+                        "Expected {expected} arguments but got {arg_count}.\n"
+                    ));
+                    return Err(Error::Runtime);
+                }
+                let stack_top = self.store.value_stack.len();
+                let function = match native.function {
+                    NativeFunction::Builtin(function) => function,
+                    NativeFunction::Host(index) => return self.call_host_native(index, arg_count),
+                };
+                // Natives that call back into the VM find the buffer taken and use a fresh one
+                let mut args = std::mem::take(&mut self.native_args);
+                #[cfg(feature = "metrics")]
+                if args.capacity() < arg_count {
+                    self.store.metrics.record_allocation();
+                }
+                args.extend_from_slice(&self.store.value_stack[stack_top - arg_count..stack_top]);
+                let result = function(self, &args);
+                args.clear();
+                self.native_args = args;
+                let result = result?;
+
+                self.store.value_stack.truncate(stack_top - arg_count - 1);
+                self.push_value(result);
+                Ok(())
+            }
+
+            _ => {
+                self.runtime_error("Can only call functions and classes.\n".into());
+                Err(Error::Runtime)
+            }
+        }
+    }
+
+    fn frame_slot_to_peek_distance(&self, slot: usize) -> usize {
+        let slot_distance =
+            self.store.value_stack.len() - 1 - (self.current_frame().start_stack_index + slot);
+        slot_distance
+    }
+
+    /// Calls `callee` from native code, running it until its frame returns.
+    fn call_function(
+        &mut self,
+        callee: RuntimeValue,
+        args: &[RuntimeValue],
+    ) -> Result<RuntimeValue, Error> {
+        let base_frame = self.store.frame_stack_top;
+        self.push_value(callee);
+        for &arg in args {
+            self.push_value(arg);
+        }
+        self.call_value(callee, args.len())?;
+        if self.store.frame_stack_top > base_frame {
+            self.run(base_frame)?;
+        }
+        self.pop_value()
+    }
+
+    /// The text `print` shows for `value`. An instance whose class defines
+    /// `toString()` shows what that returns, unless it is already being shown
+    /// further up, e.g. by a `toString()` printing `this`.
+    fn display_value(&mut self, value: RuntimeValue) -> Result<String, Error> {
+        let instance = match value {
+            // Fractions print with six places, unless an exponent reads better
+            RuntimeValue::Number(n) if n.fract() != 0.0 && (1e-6..1e21).contains(&n.abs()) => {
+                return Ok(format!("{n:.6}"))
+            }
+            RuntimeValue::Instance(instance) => instance,
+            value => return Ok(value.to_string()),
+        };
+        if instance.class.methods.get(&self.to_string_string).is_none()
+            || self.displaying.contains(&instance.id())
+        {
+            return Ok(instance.to_string());
+        }
+        self.displaying.push(instance.id());
+        let to_string = self.to_string_string.clone();
+        let result = self.invoke_method(value, &to_string)?;
+        self.displaying.pop();
+        let RuntimeValue::String(string) = result else {
+            self.runtime_error("toString() must return a string.\n".into());
+            return Err(Error::Runtime);
+        };
+        Ok(string.chars.to_string())
+    }
+
+    /// Invokes the method `name` on `receiver` from within an instruction,
+    /// running it until its frame returns.
+    fn invoke_method(
+        &mut self,
+        receiver: RuntimeValue,
+        name: &ObjString,
+    ) -> Result<RuntimeValue, Error> {
+        let base_frame = self.store.frame_stack_top;
+        self.push_value(receiver);
+        self.invoke(name, 0)?;
+        if self.store.frame_stack_top > base_frame {
+            self.run(base_frame)?;
+        }
+        self.pop_value()
+    }
+
+    /// Replaces the collection on top of the stack with what a `for ... in`
+    /// loop iterates over. Instances may provide an `iter()` method returning
+    /// the object to iterate instead of themselves.
+    fn iter_init(&mut self) -> Result<(), Error> {
+        let mut collection = *self.peek_value(0)?;
+        if let RuntimeValue::Instance(instance) = collection {
+            let iter_string = self.iter_string.clone();
+            if instance.class.methods.get(&iter_string).is_some()
+                || instance.fields.get(&iter_string).is_some()
+            {
+                collection = self.invoke_method(collection, &iter_string)?;
+                *self.peek_value(0)? = collection;
+            }
+        }
+        match collection {
+            RuntimeValue::List(_)
+            | RuntimeValue::Range(_)
+            | RuntimeValue::String(_)
+            | RuntimeValue::Instance(_) => Ok(()),
+            _ => {
+                self.runtime_error(
+                    "Can only iterate over lists, ranges, strings and instances.\n".into(),
+                );
+                Err(Error::Runtime)
+            }
+        }
+    }
+
+    /// Advances the `for ... in` loop whose collection lives in `slot` and
+    /// whose cursor lives in the slot after it, pushing the next item.
+    /// Returns `false` once the collection is exhausted.
+    fn for_in_next(&mut self, slot: usize) -> Result<bool, Error> {
+        let collection_index = self.current_frame().start_stack_index + slot;
+        let collection = self.store.value_stack[collection_index];
+        let cursor: usize = self.store.value_stack[collection_index + 1].try_into()?;
+        let item = match collection {
+            RuntimeValue::List(list) => {
+                let Some(&item) = list.items.get(cursor) else {
+                    return Ok(false);
+                };
+                self.store.value_stack[collection_index + 1] = (cursor + 1).into();
+                item
+            }
+            RuntimeValue::Range(range) => {
+                let Some(item) = range.get(cursor) else {
+                    return Ok(false);
+                };
+                self.store.value_stack[collection_index + 1] = (cursor + 1).into();
+                item.into()
+            }
+            RuntimeValue::String(string) => {
+                // Strings keep a byte offset as their cursor
+                let Some(c) = string.chars[cursor..].chars().next() else {
+                    return Ok(false);
+                };
+                self.store.value_stack[collection_index + 1] = (cursor + c.len_utf8()).into();
+                self.store.insert_string(c.to_string().into()).into()
+            }
+            RuntimeValue::Instance(_) => {
+                let done_string = self.done_string.clone();
+                if !self.invoke_method(collection, &done_string)?.is_falsey() {
+                    return Ok(false);
+                }
+                let next_string = self.next_string.clone();
+                self.invoke_method(collection, &next_string)?
+            }
+            _ => return Err(self.fault("Iterating over a value that wasn't initialized.")),
+        };
+        self.push_value(item);
+        Ok(true)
+    }
+
+    /// Converts an index into a position within a sequence of `length` items.
+    fn resolve_index(&mut self, index: f64, length: usize) -> Result<usize, Error> {
+        if index < 0.0 || index.fract() != 0.0 {
+            self.runtime_error(format!(
+                "Index must be a non-negative integer but got {index}.\n"
+            ));
+            return Err(Error::Runtime);
+        }
+        if index as usize >= length {
+            self.runtime_error(format!(
+                "Index {index} is out of range for length {length}.\n"
+            ));
+            return Err(Error::Runtime);
+        }
+        Ok(index as usize)
+    }
+
+    /// Converts a range used as an index into the positions it selects
+    /// within a sequence of `length` items.
+    fn resolve_slice(&mut self, range: ObjRange, length: usize) -> Result<Range<usize>, Error> {
+        let end = if range.inclusive {
+            range.end + 1.0
+        } else {
+            range.end
+        };
+        for bound in [range.start, end] {
+            if bound < 0.0 || bound.fract() != 0.0 {
+                self.runtime_error(format!(
+                    "Slice bounds must be non-negative integers but got {range}.\n"
+                ));
+                return Err(Error::Runtime);
+            }
+        }
+        if range.start > end {
+            self.runtime_error(format!("Slice {range} starts after it ends.\n"));
+            return Err(Error::Runtime);
+        }
+        if end as usize > length {
+            self.runtime_error(format!(
+                "Slice {range} is out of range for length {length}.\n"
+            ));
+            return Err(Error::Runtime);
+        }
+        Ok(range.start as usize..end as usize)
+    }
+
+    fn get_index(&mut self) -> Result<(), Error> {
+        let index = as_float(*self.peek_value(0)?);
+        let collection = *self.peek_value(1)?;
+        let value = match (collection, index) {
+            (RuntimeValue::List(list), RuntimeValue::Number(n)) => {
+                let index = self.resolve_index(n, list.items.len())?;
+                list.items[index]
+            }
+            (RuntimeValue::List(list), RuntimeValue::Range(range)) => {
+                let slice = self.resolve_slice(*range, list.items.len())?;
+                let items = list.items[slice].to_vec();
+                self.store.insert_list(ObjList { items }).into()
+            }
+            (RuntimeValue::String(string), RuntimeValue::Number(n)) => {
+                let index = self.resolve_index(n, string.chars.chars().count())?;
+                let Some(c) = string.chars.chars().nth(index) else {
+                    return Err(self.fault("Resolved string index out of range."));
+                };
+                self.store.insert_string(c.to_string().into()).into()
+            }
+            (RuntimeValue::String(string), RuntimeValue::Range(range)) => {
+                let slice = self.resolve_slice(*range, string.chars.chars().count())?;
+                let chars: String = string
+                    .chars
+                    .chars()
+                    .skip(slice.start)
+                    .take(slice.len())
+                    .collect();
+                self.store.insert_string(chars.into()).into()
+            }
+            (RuntimeValue::List(_), _) | (RuntimeValue::String(_), _) => {
+                self.runtime_error("Index must be a number or a range.\n".into());
+                return Err(Error::Runtime);
+            }
+            _ => {
+                self.runtime_error("Can only index lists and strings.\n".into());
+                return Err(Error::Runtime);
+            }
+        };
+        self.pop_value()?; // Index
+        self.pop_value()?; // Collection
+        self.push_value(value);
+        Ok(())
+    }
+
+    fn set_index(&mut self) -> Result<(), Error> {
+        let value = *self.peek_value(0)?;
+        let index = as_float(*self.peek_value(1)?);
+        let Ok(mut list) = self.peek_typed::<Pointer<ObjList>>(2) else {
+            self.runtime_error("Can only assign to indices of lists.\n".into());
+            return Err(Error::Runtime);
+        };
+        let RuntimeValue::Number(n) = index else {
+            self.runtime_error("Index must be a number.\n".into());
+            return Err(Error::Runtime);
+        };
+        let index = self.resolve_index(n, list.items.len())?;
+        list.items[index] = value;
+        self.pop_value()?; // Value
+        self.pop_value()?; // Index
+        self.pop_value()?; // List
+        self.push_value(value);
+        Ok(())
+    }
+
+    /// Executes instructions until the frame count drops back to `base_frame`.
+    fn run(&mut self, base_frame: usize) -> Result<(), Error> {
+        while !self.step_instruction(base_frame)? {}
+        Ok(())
+    }
+
+    /// Executes the instruction at the current frame's ip, returning whether
+    /// it returned from the frame above `base_frame`. Shared by the run loop
+    /// and [`VM::step`], so it's inlined to keep the loop as tight as before.
+    #[inline(always)]
+    fn step_instruction(&mut self, base_frame: usize) -> Result<bool, Error> {
+        let returned = self.execute_instruction(base_frame);
+        #[cfg(feature = "track-allocations")]
+        if let Some(access) = tracking::take_dangling() {
+            self.eprint(format!("{access}\n"));
+            return Err(self.fault("Dangling pointer."));
+        }
+        returned
+    }
+
+    #[inline(always)]
+    fn execute_instruction(&mut self, base_frame: usize) -> Result<bool, Error> {
+        let ip = self.current_frame().ip;
+        let instruction = match self.current_chunk().decoded.get() {
+            // Verified code only ever moves to the start of a decoded instruction
+            Some(decoded) => decoded[ip],
+            None => {
+                let Some(&byte) = self.current_chunk().code.get(ip) else {
+                    return Err(self.fault("Instruction pointer out of range."));
+                };
+                let Ok(instruction) = OpCode::try_from(byte) else {
+                    return Err(self.fault("Unknown opcode."));
+                };
+                instruction
+            }
+        };
+        if self.coverage.is_some() {
+            let line = self.current_chunk().lines[ip];
+            if let Some(coverage) = self.coverage.as_mut() {
+                coverage.record(line);
+            }
+        }
+        self.current_frame_mut().ip = ip + 1;
+        self.store.stats.instructions += 1;
+        if self.store.stats.instructions > self.instruction_deadline {
+            self.runtime_error("Instruction limit exceeded.\n".into());
+            return Err(Error::Runtime);
+        }
+        #[cfg(feature = "metrics")]
+        self.store.metrics.begin(instruction);
+        // Before tracing, which reads the stack on the instruction's behalf
+        #[cfg(feature = "track-allocations")]
+        tracking::enter(
+            instruction.name(),
+            self.current_chunk().lines[ip],
+            &self.current_frame().closure.function,
+        );
+        if self.store.tracer.is_active() {
+            let line = self.current_chunk().lines[ip];
+            let store = &mut self.store;
+            store.tracer.emit(TraceEvent::InstructionExecuted {
+                instruction: instruction.name(),
+                offset: ip,
+                line,
+                depth: store.frame_stack_top,
+                stack: TraceStack(&store.value_stack),
+            });
+        }
+        match instruction {
+            OpCode::Constant => {
+                let index = self.read_byte() as usize;
+                let constant = self.read_constant(index);
+                let runtime_value = self.store.load_constant(constant);
+
+                self.push_value(runtime_value);
+            }
+            OpCode::Nil => self.push_value(RuntimeValue::Nil),
+            OpCode::True => self.push_value(RuntimeValue::Bool(true)),
+            OpCode::False => self.push_value(RuntimeValue::Bool(false)),
+            OpCode::Pop => {
+                self.pop_value()?;
+            }
+            OpCode::GetLocal => {
+                let slot = self.read_byte() as usize;
+                let index = self.current_frame().start_stack_index + slot;
+                let value = self.store.value_stack[index];
+                self.push_value(value);
+            }
+            OpCode::SetLocal => {
+                let slot = self.read_byte() as usize;
+                let slot_distance = self.frame_slot_to_peek_distance(slot);
+                let value = *self.peek_value(0)?;
+                *self.peek_value(slot_distance)? = value;
+            }
+            OpCode::GetGlobal => {
+                let slot = self.read_global_slot();
+                let Some(value) = self.store.globals.get_slot(slot) else {
+                    return Err(self.undefined_global(slot));
+                };
+                self.push_value(value);
+            }
+            OpCode::SetGlobal => {
+                let slot = self.read_global_slot();
+                let value = *self.peek_value(0)?;
+                if !self.store.globals.set_slot(slot, value) {
+                    return Err(self.undefined_global(slot));
+                }
+            }
+            OpCode::DefineGlobal => {
+                let slot = self.read_global_slot();
+                let value = self.pop_value()?;
+                self.store.globals.define_slot(slot, value);
+            }
+            OpCode::GetUpvalue => {
+                let slot = self.read_byte() as usize;
+                let location = {
+                    let closure = self.current_closure();
+                    let upvalue = closure.upvalues[slot];
+                    match &*upvalue {
+                        ObjUpvalue::Open { location } => *location,
+                        ObjUpvalue::Closed { value } => {
+                            self.push_value(*value);
+                            return Ok(false);
+                        }
+                    }
+                };
+                let value = self.store.value_stack[location];
+                self.push_value(value);
+            }
+            OpCode::SetUpvalue => {
+                let slot = self.read_byte() as usize;
+                // Assignment is an expression, so the value stays on the stack
+                let value = *self.peek_value(0)?;
+                let mut upvalue = self.current_closure().upvalues[slot];
+                match &mut *upvalue {
+                    ObjUpvalue::Open { location } => self.store.value_stack[*location] = value,
+                    ObjUpvalue::Closed { value: closed } => *closed = value,
+                }
+            }
+            o @ (OpCode::GetProperty | OpCode::GetThisProperty) => {
+                let index = self.read_byte() as usize;
+                let ConstantValue::String(name) = self.read_constant(index) else {
+                    return Err(self.fault("Unexpected constant value."));
+                };
+                if o == OpCode::GetProperty {
+                    self.check_public(name)?;
+                }
+                if let Ok(class) = self.peek_typed::<Pointer<ObjClass>>(0) {
+                    let method = self.static_method(class, name)?;
+                    *self.peek_value(0)? = method.into();
+                    return Ok(false);
+                }
+                let instance = {
+                    let Ok(instance_ref) = self.peek_typed::<Pointer<ObjInstance>>(0) else {
+                        self.runtime_error("Only instances have fields.\n".into());
+                        return Err(Error::Runtime);
+                    };
+                    instance_ref
+                };
+                if let Some(v) = instance.fields.get(name) {
+                    self.pop_value()?; // Instance
+                    self.push_value(*v);
+                    return Ok(false);
+                }
+
+                self.bind_method(instance.class, name)?;
+            }
+            o @ (OpCode::SetProperty | OpCode::SetThisProperty) => {
+                let Ok(mut instance) = self.peek_typed::<Pointer<ObjInstance>>(1) else {
+                    self.runtime_error("Only instances have fields.\n".into());
+                    return Err(Error::Runtime);
+                };
+                let index = self.read_byte() as usize;
+                let ConstantValue::String(name) = self.read_constant(index) else {
+                    return Err(self.fault("Unexpected constant value."));
+                };
+                if o == OpCode::SetProperty {
+                    self.check_public(name)?;
+                }
+                let value = *self.peek_value(0)?;
+                instance.fields.insert(name.clone(), value);
+                let value = self.pop_value()?;
+                self.pop_value()?; // Instance
+                self.push_value(value);
+            }
+            OpCode::GetSuper => {
+                let index = self.read_byte() as usize;
+                let ConstantValue::String(name) = self.read_constant(index) else {
+                    return Err(self.fault("Unexpected constant value."));
+                };
+                self.check_public(name)?;
+                let superclass = match self.pop_value()? {
+                    RuntimeValue::Class(o) => o,
+                    _ => return Err(Error::Runtime),
+                };
+                self.bind_method(superclass, name)?;
+            }
+            OpCode::Equal => {
+                let a = self.pop_value()?;
+                let b = self.pop_value()?;
+                self.push_value(a.lox_eq(&b).into());
+            }
+            OpCode::SameClass => {
+                let a = self.pop_value()?;
+                let b = self.pop_value()?;
+                let same = match (a, b) {
+                    (RuntimeValue::Instance(a), RuntimeValue::Instance(b)) => a.class == b.class,
+                    _ => false,
+                };
+                self.push_value(same.into());
+            }
+            OpCode::Greater => {
+                if self.peek_typed::<f64>(0).is_err() || self.peek_typed::<f64>(1).is_err() {
+                    self.runtime_error("Operands must be numbers.\n".into());
+                    return Err(Error::Runtime);
+                }
+                if let Some((a, b)) = self.int_operands()? {
+                    self.pop_value()?;
+                    *self.peek_value(0)? = (a > b).into();
+                    return Ok(false);
+                }
+                let b = self.pop_typed::<f64>()?;
+                let a = self.pop_typed::<f64>()?;
+                self.push_value((a > b).into());
+            }
+            OpCode::Less => {
+                if self.peek_typed::<f64>(0).is_err() || self.peek_typed::<f64>(1).is_err() {
+                    self.runtime_error("Operands must be numbers.\n".into());
+                    return Err(Error::Runtime);
+                }
+                if let Some((a, b)) = self.int_operands()? {
+                    self.pop_value()?;
+                    *self.peek_value(0)? = (a < b).into();
+                    return Ok(false);
+                }
+                let b = self.pop_typed::<f64>()?;
+                let a = self.pop_typed::<f64>()?;
+                self.push_value((a < b).into());
+            }
+            OpCode::Add => match (*self.peek_value(1)?, *self.peek_value(0)?) {
+                (RuntimeValue::Number(a), RuntimeValue::Number(b)) => {
+                    self.pop_value()?;
+                    *self.peek_value(0)? = RuntimeValue::Number(a + b);
+                }
+                (RuntimeValue::Int(a), RuntimeValue::Int(b)) => {
+                    self.push_int_result(a.checked_add(b))?
+                }
+                (
+                    a @ (RuntimeValue::Int(_) | RuntimeValue::Number(_)),
+                    b @ (RuntimeValue::Int(_) | RuntimeValue::Number(_)),
+                ) => {
+                    let sum = f64::try_from(a)? + f64::try_from(b)?;
+                    self.pop_value()?;
+                    *self.peek_value(0)? = sum.into();
+                }
+                (RuntimeValue::String(_), RuntimeValue::String(_)) => self.concatenate()?,
+                _ => {
+                    self.runtime_error("Operands must be two numbers or two strings.\n".into());
+                    return Err(Error::Runtime);
+                }
+            },
+            OpCode::Subtract => {
+                if self.peek_typed::<f64>(0).is_err() || self.peek_typed::<f64>(1).is_err() {
+                    self.runtime_error("Operands must be numbers.\n".into());
+                    return Err(Error::Runtime);
+                }
+                if let Some((a, b)) = self.int_operands()? {
+                    self.push_int_result(a.checked_sub(b))?;
+                    return Ok(false);
+                }
+                let b = self.pop_typed::<f64>()?;
+                let a = self.pop_typed::<f64>()?;
+                self.push_value((a - b).into());
+            }
+            OpCode::Multiply => {
+                if self.peek_typed::<f64>(0).is_err() || self.peek_typed::<f64>(1).is_err() {
+                    self.runtime_error("Operands must be numbers.\n".into());
+                    return Err(Error::Runtime);
+                }
+                if let Some((a, b)) = self.int_operands()? {
+                    self.push_int_result(a.checked_mul(b))?;
+                    return Ok(false);
+                }
+                let b = self.pop_typed::<f64>()?;
+                let a = self.pop_typed::<f64>()?;
+                self.push_value((a * b).into());
+            }
+            OpCode::Divide => {
+                if self.peek_typed::<f64>(0).is_err() || self.peek_typed::<f64>(1).is_err() {
+                    self.runtime_error("Operands must be numbers.\n".into());
+                    return Err(Error::Runtime);
+                }
+                let b = self.pop_typed::<f64>()?;
+                let a = self.pop_typed::<f64>()?;
+                self.push_value((a / b).into());
+            }
+            OpCode::Not => {
+                let value = self.pop_value()?;
+                self.push_value(value.is_falsey().into());
+            }
+            OpCode::Negate => {
+                if self.peek_typed::<f64>(0).is_err() {
+                    self.runtime_error("Operand must be a number.\n".into());
+                    return Err(Error::Runtime);
+                }
+                if let RuntimeValue::Int(n) = *self.peek_value(0)? {
+                    let Some(negated) = n.checked_neg() else {
+                        self.runtime_error("Integer overflow.\n".into());
+                        return Err(Error::Runtime);
+                    };
+                    *self.peek_value(0)? = negated.into();
+                    return Ok(false);
+                }
+                let value = self.pop_typed::<f64>()?;
+                self.push_value((-value).into());
+            }
+            OpCode::Print => {
+                let value = self.pop_value()?;
+                let text = self.display_value(value)?;
+                self.println(text);
+            }
+            OpCode::Jump => {
+                let offset = self.read_short() as usize;
+                self.jump(offset, false)?;
+            }
+            OpCode::JumpIfFalse => {
+                let offset = self.read_short() as usize;
+                if self.peek_value(0)?.is_falsey() {
+                    self.jump(offset, false)?;
+                }
+            }
+            OpCode::Loop => {
+                let offset = self.read_short() as usize;
+                self.jump(offset, true)?;
+            }
+            OpCode::Call => {
+                let arg_count = self.read_byte() as usize;
+                let callee = *self.peek_value(arg_count)?;
+                self.call_value(callee, arg_count)?;
+            }
+            o @ (OpCode::Invoke | OpCode::InvokeThis) => {
+                let index = self.read_byte() as usize;
+                let ConstantValue::String(method_name) = self.read_constant(index) else {
+                    return Err(self.fault("Unexpected constant value."));
+                };
+                if o == OpCode::Invoke {
+                    self.check_public(method_name)?;
+                }
+                let arg_count = self.read_byte() as usize;
+                self.invoke(method_name, arg_count)?;
+            }
+            OpCode::SuperInvoke => {
+                let index = self.read_byte() as usize;
+                let arg_count = self.read_byte() as usize;
+                let class = self.pop_typed::<Pointer<ObjClass>>()?;
+                let ConstantValue::String(method_name) = self.read_constant(index) else {
+                    return Err(self.fault("Unexpected constant value."));
+                };
+                self.check_public(method_name)?;
+                self.invoke_from_class(class, method_name, arg_count)?;
+            }
+            OpCode::Closure => {
+                let index = self.read_byte() as usize;
+                let ConstantValue::Function(function) = self.read_constant(index) else {
+                    return Err(self.fault("Unexpected constant value."));
+                };
+                let upvalue_count = function.upvalue_count;
+                #[cfg(feature = "metrics")]
+                self.store.metrics.record_clone();
+                let function = self.store.insert_function(*function.clone());
+                let mut closure = self.new_closure(function);
+                self.push_value(closure.into());
+                for _ in 0..upvalue_count {
+                    let is_local = self.read_byte() != 0;
+                    let index = self.read_byte() as usize;
+                    if is_local {
+                        let upvalue = self.capture_upvalue(index);
+                        closure.upvalues.push(upvalue);
+                    } else {
+                        let current_closure = self.current_closure();
+                        let current_closure_upvalue = current_closure.upvalues[index];
+                        closure.upvalues.push(current_closure_upvalue);
+                    }
+                }
+            }
+            OpCode::CloseUpvalue => {
+                // The local going out of scope is the last in the frame
+                let Some(slot) = self.current_locals().last() else {
+                    return Err(self.fault("No local to close."));
+                };
+                self.close_upvalues(slot);
+                self.pop_value()?;
+            }
+            OpCode::Return => {
+                if self.store.tracer.is_active() {
+                    let function = self.current_frame().closure.function.to_string();
+                    self.store.tracer.emit(TraceEvent::Return {
+                        function: &function,
+                        depth: self.store.frame_stack_top,
+                    });
+                }
+                let result = self.pop_value()?;
+                let locals = self.current_locals();
+                self.close_upvalues(locals.start);
+                self.pop_frame();
+                if self.store.frame_stack_top == 0 {
+                    return Ok(true);
+                }
+                self.store.value_stack.truncate(locals.start);
+                self.push_value(result);
+                if self.store.frame_stack_top == base_frame {
+                    return Ok(true);
+                }
+            }
+            OpCode::Class => {
+                let index = self.read_byte() as usize;
+                let ConstantValue::String(name) = self.read_constant(index) else {
+                    return Err(self.fault("Unexpected constant value."));
+                };
+                let class = self.new_class(name);
+                self.push_value(class.into());
+            }
+            OpCode::Inherit => {
+                let Ok(superclass) = self.peek_typed::<Pointer<ObjClass>>(1) else {
+                    self.runtime_error("Superclass must be a class.\n".into());
+                    return Err(Error::Runtime);
+                };
+                let mut subclass = self.peek_typed::<Pointer<ObjClass>>(0)?;
+                subclass.methods.inherit(&superclass.methods);
+                subclass.statics.inherit(&superclass.statics);
+                subclass.superclass = Some(superclass);
+                self.pop_value()?; // Subclass
+            }
+            OpCode::Mixin => {
+                let mixin_count = self.read_byte() as usize;
+                let mut class = self.peek_typed::<Pointer<ObjClass>>(mixin_count)?;
+                let mut methods: Vec<(ObjString, Pointer<ObjClosure>, Pointer<ObjClass>)> = vec![];
+                for distance in (0..mixin_count).rev() {
+                    let Ok(mixin) = self.peek_typed::<Pointer<ObjClass>>(distance) else {
+                        self.runtime_error("Mixin must be a class.\n".into());
+                        return Err(Error::Runtime);
+                    };
+                    for entry in mixin.methods.iter().flatten() {
+                        let (Some(key), Some(value)) = (&entry.key, entry.value) else {
+                            return Ok(false);
+                        };
+                        if let Some((_, _, other)) = methods.iter().find(|(k, _, _)| k == key) {
+                            self.runtime_error(format!(
+                                "Method '{}' is defined by both mixins '{}' and '{}'.\n",
+                                key.chars, other.name, mixin.name
+                            ));
+                            return Err(Error::Runtime);
+                        }
+                        methods.push((key.clone(), value, mixin));
+                    }
+                }
+                for (key, value, _) in methods {
+                    class.methods.insert(key, value);
+                }
+                for _ in 0..=mixin_count {
+                    self.pop_value()?;
+                }
+            }
+            o @ (OpCode::Method | OpCode::StaticMethod) => {
+                let index = self.read_byte() as usize;
+                let ConstantValue::String(name) = self.read_constant(index) else {
+                    return Err(self.fault("Unexpected constant value."));
+                };
+                self.define_method(name, o == OpCode::StaticMethod)?;
+            }
+            OpCode::BuildList => {
+                let item_count = self.read_byte() as usize;
+                let stack_top = self.store.value_stack.len();
+                let items = self.store.value_stack[stack_top - item_count..].to_vec();
+                // The items stay on the stack until the list owns them
+                let list = self.store.insert_list(ObjList { items });
+                self.store.value_stack.truncate(stack_top - item_count);
+                self.push_value(list.into());
+            }
+            o @ OpCode::Range | o @ OpCode::RangeInclusive => {
+                if self.peek_typed::<f64>(0).is_err() || self.peek_typed::<f64>(1).is_err() {
+                    self.runtime_error("Range bounds must be numbers.\n".into());
+                    return Err(Error::Runtime);
+                }
+                let end = self.pop_typed::<f64>()?;
+                let start = self.pop_typed::<f64>()?;
+                let range = self.store.insert_range(ObjRange {
+                    start,
+                    end,
+                    inclusive: o == OpCode::RangeInclusive,
+                });
+                self.push_value(range.into());
+            }
+            OpCode::GetIndex => self.get_index()?,
+            OpCode::SetIndex => self.set_index()?,
+            OpCode::IterInit => self.iter_init()?,
+            OpCode::ForIn => {
+                let slot = self.read_byte() as usize;
+                let offset = self.read_short() as usize;
+                if !self.for_in_next(slot)? {
+                    self.jump(offset, false)?;
+                }
+            }
+            OpCode::Import => {
+                let index = self.read_byte() as usize;
+                let ConstantValue::String(name) = self.read_constant(index) else {
+                    return Err(self.fault("Unexpected constant value."));
+                };
+                self.import(&name.chars)?;
+            }
+            OpCode::BitAnd => self.bitwise(|a, b| Some(a & b))?,
+            OpCode::BitOr => self.bitwise(|a, b| Some(a | b))?,
+            OpCode::BitXor => self.bitwise(|a, b| Some(a ^ b))?,
+            OpCode::ShiftLeft => self.bitwise(|a, b| a.checked_shl(u32::try_from(b).ok()?))?,
+            OpCode::ShiftRight => self.bitwise(|a, b| a.checked_shr(u32::try_from(b).ok()?))?,
+            OpCode::BitNot => {
+                let value = *self.peek_value(0)?;
+                let Some(n) = value.as_integer() else {
+                    self.runtime_error("Operand must be an integer.\n".into());
+                    return Err(Error::Runtime);
+                };
+                *self.peek_value(0)? = match value {
+                    RuntimeValue::Int(_) => (!n).into(),
+                    _ => ((!n) as f64).into(),
+                };
+            }
+            OpCode::NamedArgs => {
+                let count = self.read_byte() as usize;
+                self.named_args.clear();
+                for _ in 0..count {
+                    let index = self.read_byte() as usize;
+                    let ConstantValue::String(name) = self.read_constant(index) else {
+                        return Err(self.fault("Unexpected constant value."));
+                    };
+                    self.named_args.push(name.clone());
+                }
+            }
+            OpCode::Unknown => return Err(self.fault("Unknown opcode.")),
+        }
+        Ok(false)
+    }
+
+    fn call(&mut self, closure: Pointer<ObjClosure>, arg_count: usize) -> Result<(), Error> {
+        let arg_count = if self.named_args.is_empty() {
+            arg_count
+        } else {
+            self.bind_named_args(closure.function, arg_count)?
+        };
+        let arity = closure.function.arity;
+        let variadic = closure.function.variadic;
+
+        if variadic && arg_count < arity {
+            self.runtime_error(format!(
+                "Expected at least {} arguments but got {}.\n",
+                arity, arg_count
+            ));
+            return Err(Error::Runtime);
+        }
+        if !variadic && arg_count != arity {
+            self.runtime_error(format!(
+                "Expected {} arguments but got {}.\n",
+                arity, arg_count
+            ));
+            return Err(Error::Runtime);
+        }
+        if self.store.frame_stack_top == MAX_FRAMES {
+            self.runtime_error("Stack overflow.\n".into());
+            return Err(Error::Runtime);
+        }
+        let mut arg_count = arg_count;
+        if variadic {
+            // Extra arguments stay on the stack, and so rooted, until the list holds them
+            let rest_start = self.store.value_stack.len() - (arg_count - arity);
+            let items = self.store.value_stack[rest_start..].to_vec();
+            let rest = self.store.insert_list(ObjList { items });
+            self.store.value_stack.truncate(rest_start);
+            self.push_value(rest.into());
+            arg_count = arity + 1;
+        }
+        let frame = &mut self.store.frame_stack[self.store.frame_stack_top];
+        let function = closure.function;
+        *frame = CallFrame {
+            closure,
+            chunk: &function.chunk as *const Chunk,
+            ip: 0,
+            start_stack_index: self.store.value_stack.len() - 1 - arg_count,
+        };
+        self.store.frame_stack_top += 1;
+        if self.store.tracer.is_active() {
+            self.store.tracer.emit(TraceEvent::Call {
+                function: &function.to_string(),
+                arg_count,
+                depth: self.store.frame_stack_top,
+            });
+        }
+        Ok(())
+    }
+
+    /// Moves the trailing named arguments into the positions of the parameters
+    /// they name, returning the resulting argument count.
+    fn bind_named_args(
+        &mut self,
+        function: Pointer<ObjFunction>,
+        arg_count: usize,
+    ) -> Result<usize, Error> {
+        let names = std::mem::take(&mut self.named_args);
+        let positional = arg_count - names.len();
+        let named_start = self.store.value_stack.len() - names.len();
+        let values = self.store.value_stack[named_start..].to_vec();
+        let mut bound = vec![None; function.arity.saturating_sub(positional)];
+        for (name, value) in names.iter().zip(values) {
+            let Some(index) = function
+                .parameters
+                .iter()
+                .position(|p| name.chars == p.as_str())
+            else {
+                self.runtime_error(format!("Unexpected argument '{name}'.\n"));
+                return Err(Error::Runtime);
+            };
+            if index < positional {
+                self.runtime_error(format!("Argument '{name}' is given more than once.\n"));
+                return Err(Error::Runtime);
+            }
+            bound[index - positional] = Some(value);
+        }
+        self.store.value_stack.truncate(named_start);
+        for (offset, value) in bound.iter().enumerate() {
+            let Some(value) = *value else {
+                let name = function.parameters[positional + offset].clone();
+                self.runtime_error(format!("Missing argument '{name}'.\n"));
+                return Err(Error::Runtime);
+            };
+            self.push_value(value);
+        }
+        Ok(positional + bound.len())
+    }
+
+    fn new_class(&mut self, name: &ObjString) -> Pointer<ObjClass> {
+        let name_ref = self.store.insert_string(name.clone());
+        let class = ObjClass {
+            name: name_ref,
+            methods: MethodTable::default(),
+            statics: MethodTable::default(),
+            superclass: None,
+        };
+        self.store.insert_class(class)
+    }
+
+    fn new_closure(&mut self, function: Pointer<ObjFunction>) -> Pointer<ObjClosure> {
+        let upvalues = Vec::with_capacity(function.upvalue_count);
+        let closure = ObjClosure { function, upvalues };
+        self.store.insert_closure(closure)
+    }
+
+    fn new_instance(&mut self, class: Pointer<ObjClass>) -> Pointer<ObjInstance> {
+        let instance = ObjInstance {
+            class,
+            fields: Table::default(),
+        };
+        self.store.insert_instance(instance)
+    }
+
+    fn new_bound_method(
+        &mut self,
+        receiver: RuntimeValue,
+        method: Pointer<ObjClosure>,
+    ) -> Pointer<ObjBoundMethod> {
+        let bound_method = ObjBoundMethod { receiver, method };
+        self.store.insert_bound_method(bound_method)
+    }
+
+    fn new_native(&mut self, arity: usize, function: NativeFn) -> Pointer<ObjNative> {
+        self.store.insert_native(ObjNative {
+            arity,
+            optional: 0,
+            function: NativeFunction::Builtin(function),
+        })
+    }
+
+    /// Calls the registered native at `index` with the `arg_count` values on
+    /// top of the stack, replacing them and the callee with its result.
+    fn call_host_native(&mut self, index: usize, arg_count: usize) -> Result<(), Error> {
+        let stack_top = self.store.value_stack.len();
+        let args: Vec<LoxValue> = self.store.value_stack[stack_top - arg_count..]
+            .iter()
+            .map(|&value| value.into())
+            .collect();
+        let result = (self.host_natives[index].function)(&args)
+            .and_then(|result| self.push_lox_value(&result));
+        if let Err(message) = result {
+            self.runtime_error(format!("{message}\n"));
+            return Err(Error::Runtime);
+        }
+        let result = self.pop_value()?;
+        self.store.value_stack.truncate(stack_top - arg_count - 1);
+        self.push_value(result);
+        Ok(())
+    }
+
+    /// Copies `value` onto the heap and pushes it, keeping what it allocates
+    /// reachable along the way.
+    fn push_lox_value(&mut self, value: &LoxValue) -> Result<(), String> {
+        let value = match value {
+            LoxValue::Nil => RuntimeValue::Nil,
+            LoxValue::Bool(b) => RuntimeValue::Bool(*b),
+            LoxValue::Number(n) => RuntimeValue::Number(*n),
+            LoxValue::Int(n) => RuntimeValue::Int(*n),
+            LoxValue::String(s) => self.store.insert_string(s.as_str().into()).into(),
+            &LoxValue::Range {
+                start,
+                end,
+                inclusive,
+            } => self
+                .store
+                .insert_range(ObjRange {
+                    start,
+                    end,
+                    inclusive,
+                })
+                .into(),
+            LoxValue::List(items) => {
+                let mut list = self.store.insert_list(ObjList::default());
+                self.push_value(list.into());
+                for item in items {
+                    self.push_lox_value(item)?;
+                    let item = self.pop_value().map_err(|error| error.to_string())?;
+                    list.items.push(item);
+                }
+                return Ok(());
+            }
+            LoxValue::Object(printed) => return Err(format!("Can't pass '{printed}' to Lox.")),
+        };
+        self.push_value(value);
+        Ok(())
+    }
+
+    fn push_value(&mut self, value: RuntimeValue) {
+        self.store.value_stack.push(value);
+        let stats = &mut self.store.stats;
+        stats.peak_stack_depth = stats.peak_stack_depth.max(self.store.value_stack.len());
+    }
+
+    fn pop_frame(&mut self) -> CallFrame {
+        self.store.frame_stack_top -= 1;
+        self.store.frame_stack[self.store.frame_stack_top]
+    }
+
+    fn pop_value(&mut self) -> Result<RuntimeValue, Error> {
+        match self.store.value_stack.pop() {
+            Some(value) => Ok(value),
+            None => Err(self.fault("Stack underflow.")),
+        }
+    }
+
+    fn peek_value(&mut self, distance: usize) -> Result<&mut RuntimeValue, Error> {
+        let len = self.store.value_stack.len();
+        let Some(index) = len.checked_sub(distance + 1) else {
+            return Err(self.fault("Stack underflow."));
+        };
+        Ok(&mut self.store.value_stack[index])
+    }
+
+    fn peek_typed<T: TryFrom<RuntimeValue, Error = Error>>(
+        &mut self,
+        distance: usize,
+    ) -> Result<T, Error> {
+        (*self.peek_value(distance)?).try_into()
+    }
+
+    /// The operands of a binary instruction when both are integers.
+    fn int_operands(&mut self) -> Result<Option<(i64, i64)>, Error> {
+        Ok(match (*self.peek_value(1)?, *self.peek_value(0)?) {
+            (RuntimeValue::Int(a), RuntimeValue::Int(b)) => Some((a, b)),
+            _ => None,
+        })
+    }
+
+    /// Replaces the operands of a binary instruction with the result of
+    /// integer arithmetic, which is `None` when it overflowed.
+    fn push_int_result(&mut self, result: Option<i64>) -> Result<(), Error> {
+        let Some(result) = result else {
+            self.runtime_error("Integer overflow.\n".into());
+            return Err(Error::Runtime);
+        };
+        self.pop_value()?;
+        *self.peek_value(0)? = result.into();
+        Ok(())
+    }
+
+    /// Replaces the operands of a bitwise instruction with `op` applied to
+    /// them. Whole numbers are accepted alongside integers, and the result is
+    /// only an integer when both operands were. `op` returns `None` for a
+    /// shift amount outside `0..64`.
+    fn bitwise(&mut self, op: impl FnOnce(i64, i64) -> Option<i64>) -> Result<(), Error> {
+        let (a, b) = (*self.peek_value(1)?, *self.peek_value(0)?);
+        let (Some(x), Some(y)) = (a.as_integer(), b.as_integer()) else {
+            self.runtime_error("Operands must be integers.\n".into());
+            return Err(Error::Runtime);
+        };
+        let Some(result) = op(x, y) else {
+            self.runtime_error("Shift amount must be between 0 and 63.\n".into());
+            return Err(Error::Runtime);
+        };
+        self.pop_value()?;
+        *self.peek_value(0)? = match (a, b) {
+            (RuntimeValue::Int(_), RuntimeValue::Int(_)) => result.into(),
+            _ => (result as f64).into(),
+        };
+        Ok(())
+    }
+
+    fn pop_typed<T: TryFrom<RuntimeValue, Error = Error>>(&mut self) -> Result<T, Error> {
+        self.pop_value()?
+            .try_into()
+            .map_err(|_| self.fault("Popped a value of an unexpected type."))
+    }
+}
+
+impl<Out: Write, EOut: Write> NativeContext for VM<Out, EOut> {
+    fn call(&mut self, callee: RuntimeValue, args: &[RuntimeValue]) -> Result<RuntimeValue, Error> {
+        self.call_function(callee, args)
+    }
+
+    fn new_list(&mut self) -> Pointer<ObjList> {
+        let list = self.store.insert_list(ObjList::default());
+        // The native's stack window is discarded when it returns
+        self.push_value(list.into());
+        list
+    }
+
+    fn new_instance(&mut self, class: Pointer<ObjClass>) -> Pointer<ObjInstance> {
+        let instance = self.store.insert_instance(ObjInstance {
+            class,
+            fields: Table::default(),
+        });
+        self.push_value(instance.into());
+        instance
+    }
+
+    fn new_string_builder(&mut self) -> Pointer<ObjStringBuilder> {
+        let builder = self
+            .store
+            .insert_string_builder(ObjStringBuilder::default());
+        self.push_value(builder.into());
+        builder
+    }
+
+    fn new_string(&mut self, chars: String) -> Pointer<ObjString> {
+        let string = self.store.insert_string(chars.into());
+        self.push_value(string.into());
+        string
+    }
+
+    fn root(&mut self, value: RuntimeValue) {
+        self.push_value(value);
+    }
+
+    fn open_scope(&self) -> HandleScope {
+        HandleScope(self.store.value_stack.len())
+    }
+
+    fn close_scope(&mut self, scope: HandleScope) {
+        self.store.value_stack.truncate(scope.0);
+    }
+
+    fn caller_locals(&self) -> Option<Vec<(String, RuntimeValue)>> {
+        let frame = self.current_frame();
+        let debug_info = self.current_chunk().debug_info.as_ref()?;
+        let slots = &self.store.value_stack[self.current_locals()];
+        // The frame's ip has moved past the call, so look at the call's last byte
+        let locals = debug_info
+            .locals_at(frame.ip - 1)
+            .filter_map(|local| Some((local.name.clone(), *slots.get(local.slot)?)))
+            .collect();
+        Some(locals)
+    }
+
+    fn features(&self) -> FeatureSet {
+        VM::features(self)
+    }
+
+    fn runtime_error(&mut self, message: String) {
+        VM::runtime_error(self, message);
+    }
+
+    fn flush_output(&mut self) {
+        VM::flush_output(self);
+    }
+
+    fn load(&mut self, path: &str) -> Result<bool, Error> {
+        self.load_file(path)
+    }
+
+    fn eval(&mut self, source: &str, isolated: bool) -> Result<RuntimeValue, Error> {
+        VM::eval(self, source, isolated)
+    }
+
+    fn breakpoint(&mut self) {
+        self.paused = self.stepping.is_some();
+        if self.hooks.breakpoint.is_none() {
+            return;
+        }
+        let caller_locals = NativeContext::caller_locals(self);
+        // Locals shadow the globals they share a name with
+        let mut visible: BTreeMap<String, RuntimeValue> = self
+            .store
+            .globals
+            .entries()
+            .map(|(name, &value)| (name.chars.to_string(), value))
+            .collect();
+        visible.extend(caller_locals.iter().flatten().cloned());
+        let classes = visible
+            .into_iter()
+            .filter_map(|(name, value)| match value {
+                RuntimeValue::Class(class) => Some((name, class.verbose().to_string())),
+                _ => None,
+            })
+            .collect();
+        let locals = caller_locals.map(|locals| {
+            locals
+                .into_iter()
+                .map(|(name, value)| (name, value.into()))
+                .collect()
+        });
+        let breakpoint = Breakpoint {
+            trace: self.backtrace(),
+            locals,
+            classes,
+        };
+        // Show everything printed up to the pause
+        self.flush_output();
+        if let Some(hook) = self.hooks.breakpoint.as_mut() {
+            hook(&breakpoint);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    use super::*;
+    use crate::object::HeapSize;
+
+    #[derive(Debug, Default)]
+    struct TestOut {
+        buf: Vec<u8>,
+        flushed: Vec<String>,
+    }
+
+    impl Write for TestOut {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.buf = buf.into();
+            Ok(self.buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            let buf = self.buf.clone();
+            self.flushed.push(String::from_utf8(buf).unwrap());
+            self.buf = Vec::new();
+            Ok(())
+        }
+    }
+
+    /// A fresh directory holding `files`, for tests that import modules.
+    fn module_dir(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!("loxide-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        for (file, source) in files {
+            let path = directory.join(file);
+            fs::create_dir_all(path.parent().unwrap()).expect("Failed to create directory");
+            fs::write(path, source).expect("Failed to write module");
+        }
+        directory
+    }
+
+    #[test]
+    fn it_runs_an_empty_program() {
+        let out = TestOut::default();
+        let e_out = TestOut::default();
+        let source = "";
+        let mut vm = VM::new(out, e_out);
+        vm.interpret(source).expect("Failed to run empty program");
+        assert!(vm.out.flushed.is_empty());
+        assert!(vm.e_out.flushed.is_empty());
+    }
+
+    #[test]
+    fn it_runs_a_program_with_a_single_expression_statement() {
+        let out = TestOut::default();
+        let e_out = TestOut::default();
+        let source = "1;";
+        let mut vm = VM::new(out, e_out);
+        vm.interpret(source).expect("Failed to run program");
+        assert!(vm.out.flushed.is_empty());
+        assert!(vm.e_out.flushed.is_empty());
+    }
+
+    #[test]
+    fn it_runs_a_program_with_a_print_statement() {
+        let out = TestOut::default();
+        let e_out = TestOut::default();
+        let source = "print 1;";
+        let mut vm = VM::new(out, e_out);
+        vm.interpret(source).expect("Failed to run program");
+
+        assert!(!vm.out.flushed.is_empty());
+        assert!(vm.e_out.flushed.is_empty());
+        assert_eq!(vm.out.flushed[0], "1\n".to_string());
+    }
+
+    #[test]
+    fn it_runs_a_program_with_scopes() {
+        let out = TestOut::default();
+        let e_out = TestOut::default();
+        let source = r#"
+            var a = 1; 
+            { 
+                var b = a; 
+                print b;
+            }
+            "#;
+        let mut vm = VM::new(out, e_out);
+        vm.interpret(source).expect("Failed to run program");
+        assert!(!vm.out.flushed.is_empty());
+        assert!(vm.e_out.flushed.is_empty());
+        assert_eq!(vm.out.flushed[0], "1\n".to_string());
+    }
+
+    #[test]
+    fn it_runs_a_program_with_functions() {
+        let out = TestOut::default();
+        let e_out = TestOut::default();
+        let source = r#"
+            fun foo(a, b, c) { 
+                print a + b + c; 
+            } 
+            print foo(1, 2, 3);
+        "#;
+        let mut vm = VM::new(out, e_out);
+        vm.interpret(source).expect("Failed to run program");
+        assert!(!vm.out.flushed.is_empty());
+        assert!(vm.e_out.flushed.is_empty());
+        assert_eq!(vm.out.flushed.len(), 2);
+        assert_eq!(vm.out.flushed[0], "6\n".to_string());
+        assert_eq!(vm.out.flushed[1], "nil\n".to_string());
+    }
+
+    #[test]
+    fn it_runs_a_program_with_control_flow() {
+        let out = TestOut::default();
+        let e_out = TestOut::default();
+        let source = r#"
+            var a = 1; 
+            if (true) { 
+                a = 2; 
+            } else { 
+                a = 3; 
+            } 
+            print a; 
+            if (false) { 
+                a = 4; 
+            } else { 
+                a = 6; 
+            } 
+            print a;
+        "#;
+        let mut vm = VM::new(out, e_out);
+        vm.interpret(source).expect("Failed to run program");
+        assert!(!vm.out.flushed.is_empty());
+        assert!(vm.e_out.flushed.is_empty());
+        assert_eq!(vm.out.flushed.len(), 2);
+        assert_eq!(vm.out.flushed[0], "2\n".to_string());
+        assert_eq!(vm.out.flushed[1], "6\n".to_string());
+    }
+
+    #[test]
+    fn it_runs_a_program_with_a_loop() {
+        let out = TestOut::default();
+        let e_out = TestOut::default();
+        let source = r#"
+            for (var b = 1; b < 4; b = b + 1) {
+                print b;
+            }
+        "#;
+        let mut vm = VM::new(out, e_out);
+        vm.interpret(source).expect("Failed to run program");
+        assert!(!vm.out.flushed.is_empty());
+        assert!(vm.e_out.flushed.is_empty());
+        assert_eq!(vm.out.flushed.len(), 3);
+        assert_eq!(vm.out.flushed[0], "1\n".to_string());
+        assert_eq!(vm.out.flushed[1], "2\n".to_string());
+        assert_eq!(vm.out.flushed[2], "3\n".to_string());
+    }
+
+    #[test]
+    fn it_runs_a_program_with_negation() {
+        let out = TestOut::default();
+        let e_out = TestOut::default();
+        let source = r#"
+            print -1;
+        "#;
+        let mut vm = VM::new(out, e_out);
+        vm.interpret(source).expect("Failed to run program");
+        assert!(!vm.out.flushed.is_empty());
+        assert!(vm.e_out.flushed.is_empty());
+        assert_eq!(vm.out.flushed.len(), 1);
+        assert_eq!(vm.out.flushed[0], "-1\n".to_string());
+    }
+
+    #[test]
+    fn it_runs_a_program_with_simple_binary_ops() {
+        let out = TestOut::default();
+        let e_out = TestOut::default();
+        let source = r#"
+            print 1 + 2; 
+            print 3 * 4; 
+            print 5 / 6; 
+            print 7 - 8; 
+            print 1 == 2; 
+            print 1 == 1; 
+            print 1 != 1; 
+            print 1 != 2; 
+            print 1 < 1; 
+            print 1 < 2; 
+            print 1 < 0; 
+            print 1 <= 2; 
+            print 1 <= 1; 
+            print 1 <= 0; 
+            print 1 > 2; 
+            print 1 > 1; 
+            print 1 > 0; 
+            print 1 >= 2; 
+            print 1 >= 1; 
+            print 1 >= 0; 
+            print true and true; 
+            print true and false; 
+            print true or true; 
+            print true or false; 
+            print false or false; 
+            print "a" + "b";
+        "#;
+        let mut vm = VM::new(out, e_out);
+        vm.interpret(source).expect("Failed to run program");
+        assert!(!vm.out.flushed.is_empty());
+        assert!(vm.e_out.flushed.is_empty());
+        assert_eq!(vm.out.flushed[0], "3\n".to_string()); // 1 + 2
+        assert_eq!(vm.out.flushed[1], "12\n".to_string()); // 3 * 4
+        assert_eq!(vm.out.flushed[2], "0.833333\n".to_string()); // 5 / 6
+        assert_eq!(vm.out.flushed[3], "-1\n".to_string()); // 7 - 8
+        assert_eq!(vm.out.flushed[4], "false\n".to_string()); // 1 == 2
+        assert_eq!(vm.out.flushed[5], "true\n".to_string()); // 1 == 1
+        assert_eq!(vm.out.flushed[6], "false\n".to_string()); // 1 != 1
+        assert_eq!(vm.out.flushed[7], "true\n".to_string()); // 1 != 2
+        assert_eq!(vm.out.flushed[8], "false\n".to_string()); // 1 < 1
+        assert_eq!(vm.out.flushed[9], "true\n".to_string()); // 1 < 2
+        assert_eq!(vm.out.flushed[10], "false\n".to_string()); // 1 < 0
+        assert_eq!(vm.out.flushed[11], "true\n".to_string()); // 1 <= 2
+        assert_eq!(vm.out.flushed[12], "true\n".to_string()); // 1 <= 1
+        assert_eq!(vm.out.flushed[13], "false\n".to_string()); // 1 <= 0
+        assert_eq!(vm.out.flushed[14], "false\n".to_string()); // 1 > 2
+        assert_eq!(vm.out.flushed[15], "false\n".to_string()); // 1 > 1
+        assert_eq!(vm.out.flushed[16], "true\n".to_string()); // 1 > 0
+        assert_eq!(vm.out.flushed[17], "false\n".to_string()); // 1 >= 2
+        assert_eq!(vm.out.flushed[18], "true\n".to_string()); // 1 >= 1
+        assert_eq!(vm.out.flushed[19], "true\n".to_string()); // 1 >= 0
+        assert_eq!(vm.out.flushed[20], "true\n".to_string()); // true and true
+        assert_eq!(vm.out.flushed[21], "false\n".to_string()); // true and false
+        assert_eq!(vm.out.flushed[22], "true\n".to_string()); // true or true
+        assert_eq!(vm.out.flushed[23], "true\n".to_string()); // true or false
+        assert_eq!(vm.out.flushed[24], "false\n".to_string()); // false or false
+        assert_eq!(vm.out.flushed[25], "ab\n".to_string()); // "a" + "b"
+    }
+
+    #[test]
+    fn it_runs_a_program_with_a_closure() {
+        let out = TestOut::default();
+        let e_out = TestOut::default();
+        let source = r#"
+            fun makeClosure(value) { 
+                fun closure() { 
+                    print value; 
+                } 
+                return closure; 
+            } 
+            var doughnut = makeClosure("doughnut"); 
+            var bagel = makeClosure("bagel"); 
+            doughnut(); 
+            bagel();
+        "#;
+        let mut vm = VM::new(out, e_out);
+        vm.interpret(source).expect("Failed to run program");
+        assert!(!vm.out.flushed.is_empty());
+        assert_eq!(vm.out.flushed.len(), 2);
+        assert!(vm.e_out.flushed.is_empty());
+        assert_eq!(vm.out.flushed[0], "doughnut\n".to_string());
+        assert_eq!(vm.out.flushed[1], "bagel\n".to_string());
+    }
+
+    #[test]
+    fn it_runs_a_program_with_a_closure_with_inner_assignment() {
+        let out = TestOut::default();
+        let e_out = TestOut::default();
+        let source = r#"
+            fun makeClosure(value) { 
+                fun closure(b) { 
+                    value = b; 
+                    print value;
+                } 
+                return closure; 
+            } 
+            var breakfast = "eggs";
+            var doughnut = makeClosure(breakfast); 
+            var bagel = makeClosure(breakfast); 
+            doughnut("doughnut"); 
+            bagel("bagel");
+        "#;
+        let mut vm = VM::new(out, e_out);
+        vm.interpret(source).expect("Failed to run program");
+        assert!(!vm.out.flushed.is_empty());
+        assert_eq!(vm.out.flushed.len(), 2);
+        assert!(vm.e_out.flushed.is_empty());
+        assert_eq!(vm.out.flushed[0], "doughnut\n".to_string());
+        assert_eq!(vm.out.flushed[1], "bagel\n".to_string());
+    }
+
+    #[test]
+    fn it_runs_a_program_with_a_deelpy_nested_closure() {
+        let out = TestOut::default();
+        let e_out = TestOut::default();
+        let source = r#"
+            var value;
+            fun makeClosure() { 
+                fun closure(b) { 
+                    fun deepClosure(c) {
+                        value = b + c;
+                    }
+                    return deepClosure;
+                } 
+                return closure; 
+            }
+            {
+                var deep = makeClosure();
+                deep(1)(2);
+                print value;
+            }
+        "#;
+        let mut vm = VM::new(out, e_out);
+        vm.interpret(source).expect("Failed to run program");
+        assert!(!vm.out.flushed.is_empty());
+        assert_eq!(vm.out.flushed.len(), 1);
+        assert!(vm.e_out.flushed.is_empty());
+        assert_eq!(vm.out.flushed[0], "3\n".to_string());
+    }
+
+    #[test]
+    fn it_runs_a_program_with_a_class_definition() {
+        let out = TestOut::default();
+        let e_out = TestOut::default();
+        let source = r#"
+            class TestClass {} 
+            print TestClass;
+        "#;
+        let mut vm = VM::new(out, e_out);
+        vm.interpret(source).expect("Failed to run program");
+        assert!(!vm.out.flushed.is_empty());
+        assert_eq!(vm.out.flushed.len(), 1);
+        assert!(vm.e_out.flushed.is_empty());
+        assert_eq!(vm.out.flushed[0], "TestClass\n".to_string());
+    }
+
+    #[test]
+    fn it_runs_a_program_with_a_class_instance() {
+        let out = TestOut::default();
+        let e_out = TestOut::default();
+        let source = r#"
+            class TestClass {} 
+            print TestClass();
+        "#;
+        let mut vm = VM::new(out, e_out);
+        vm.interpret(source).expect("Failed to run program");
+        assert!(!vm.out.flushed.is_empty());
+        assert_eq!(vm.out.flushed.len(), 1);
+        assert!(vm.e_out.flushed.is_empty());
+        assert_eq!(vm.out.flushed[0], "TestClass instance\n".to_string());
+    }
+
+    #[test]
+    fn it_runs_a_program_with_a_class_initializer() {
+        let out = TestOut::default();
+        let e_out = TestOut::default();
+        let source = r#"
+            class TestClass { 
+                init() { 
+                    this.a = 1; 
+                    this.b = "b"; 
+                } 
+            } 
+            var instance = TestClass(); 
+            print instance.a; 
+            print instance.b;
+        "#;
+        let mut vm = VM::new(out, e_out);
+        vm.interpret(source).expect("Failed to run program");
+        assert!(!vm.out.flushed.is_empty());
+        assert_eq!(vm.out.flushed.len(), 2);
+        assert!(vm.e_out.flushed.is_empty());
+        assert_eq!(vm.out.flushed[0], "1\n".to_string());
+        assert_eq!(vm.out.flushed[1], "b\n".to_string());
+    }
+
+    #[test]
+    fn it_runs_a_program_with_a_class_method() {
+        let out = TestOut::default();
+        let e_out = TestOut::default();
+        let source = r#"
+            class TestClass { 
+                init(c) { 
+                    this.c = c; 
+                } 
+                m(a, b) { 
+                    return a + b + this.c; 
+                } 
+            } 
+            var instance = TestClass(5); 
+            print instance.m(1, 2);
+        "#;
+        let mut vm = VM::new(out, e_out);
+        vm.interpret(source).expect("Failed to run program");
+        assert!(!vm.out.flushed.is_empty());
+        assert_eq!(vm.out.flushed.len(), 1);
+        assert!(vm.e_out.flushed.is_empty());
+        assert_eq!(vm.out.flushed[0], "8\n".to_string());
+    }
+
+    #[test]
+    fn it_runs_a_program_with_a_sub_class_super_method() {
+        let out = TestOut::default();
+        let e_out = TestOut::default();
+        let source = r#"
+            class ParentClass { 
+                init(a) { 
+                    this.a = a; 
+                } 
+                m() { 
+                    print this.a; 
+                } 
+            } 
+            class ChildClass < ParentClass { 
+                m() { 
+                    super.m(); 
+                    print this.a + 1; 
+                }
+            } 
+            var child = ChildClass(1); 
+            child.m();
+        "#;
+        let mut vm = VM::new(out, e_out);
+        vm.interpret(source).expect("Failed to run program");
+        assert!(!vm.out.flushed.is_empty());
+        assert_eq!(vm.out.flushed.len(), 2);
+        assert!(vm.e_out.flushed.is_empty());
+        assert_eq!(vm.out.flushed[0], "1\n".to_string());
+        assert_eq!(vm.out.flushed[1], "2\n".to_string());
+    }
+
+    #[test]
+    fn it_runs_a_program_with_a_sub_class_super_property() {
+        let out = TestOut::default();
+        let e_out = TestOut::default();
+        let source = r#"
+            class ParentClass { 
+                init(a) { 
+                    this.a = a; 
+                } 
+                m() { 
+                    print this.a; 
+                } 
+            } 
+
+            class ChildClass < ParentClass { 
+                m() { 
+                    super.m(); 
+                    print super.m; 
+                } 
+            } 
+            var child = ChildClass(1); 
+            child.m();
+        "#;
+        let mut vm = VM::new(out, e_out);
+        vm.interpret(source).expect("Failed to run program");
+        assert!(!vm.out.flushed.is_empty());
+        assert_eq!(vm.out.flushed.len(), 2);
+        assert!(vm.e_out.flushed.is_empty());
+        assert_eq!(vm.out.flushed[0], "1\n".to_string());
+        assert_eq!(vm.out.flushed[1], "<fn m>\n".to_string());
+    }
+
+    #[test]
+    fn it_runs_a_program_with_this_captured_by_a_nested_function() {
+        let out = TestOut::default();
+        let e_out = TestOut::default();
+        let source = r#"
+            class Counter {
+                init() {
+                    this.count = 1;
+                }
+                getter() {
+                    fun get() {
+                        return this.count;
+                    }
+                    return get;
+                }
+            }
+            var counter = Counter();
+            var get = counter.getter();
+            counter.count = 2;
+            print get();
+        "#;
+        let mut vm = VM::new(out, e_out);
+        vm.interpret(source).expect("Failed to run program");
+        assert_eq!(vm.out.flushed.len(), 1);
+        assert_eq!(vm.out.flushed[0], "2\n".to_string());
+    }
+
+    #[test]
+    fn it_dumps_the_heap() {
+        let mut vm = VM::new(TestOut::default(), TestOut::default());
+        vm.interpret("var pinned = [\"kept\"]; { var dropped = [1]; }")
+            .expect("Failed to run program");
+        let mut dot = Vec::new();
+        vm.dump_heap(&mut dot, HeapFormat::Dot).unwrap();
+        let dot = String::from_utf8(dot).unwrap();
+        assert!(dot.contains("\"global pinned\" -> "));
+        assert!(dot.contains("[label=\"string \\\"kept\\\"\\n"));
+        // The list in the block is unreachable, though not yet collected
+        assert_eq!(dot.matches("list of 1").count(), 1);
+        let mut json = Vec::new();
+        vm.dump_heap(&mut json, HeapFormat::Json).unwrap();
+        let json = String::from_utf8(json).unwrap();
+        assert!(json.contains("{\"root\":\"global pinned\",\"object\":"));
+    }
+
+    #[test]
+    fn it_runs_a_program_with_static_methods() {
+        let source = r#"
+            class Point {
+                init(x) { this.x = x; }
+                class origin() { return Point(0); }
+            }
+            class Point3 < Point {}
+            print Point.origin().x;
+            var origin = Point3.origin;
+            print origin().x;
+        "#;
+        let mut vm = VM::new(TestOut::default(), TestOut::default());
+        vm.interpret(source).expect("Failed to run program");
+        assert_eq!(vm.out.flushed, vec!["0\n", "0\n"]);
+        assert_eq!(vm.interpret("Point.missing();"), Err(Error::Runtime));
+        assert_eq!(vm.e_out.flushed[0], "Undefined static method 'missing'.\n");
+        assert_eq!(vm.interpret("Point(1).origin();"), Err(Error::Runtime));
+    }
+
+    #[test]
+    fn it_prints_instances_with_to_string() {
+        let source = r#"
+            class Point {
+                init(x, y) { this.x = x; this.y = y; }
+                toString() { return "(" + this.name(this.x) + ", " + this.name(this.y) + ")"; }
+                name(n) { if (n == 0) return "zero"; return "one"; }
+            }
+            class Point3 < Point {}
+            class Echo {
+                toString() {
+                    print this;
+                    return "echo";
+                }
+            }
+            print Point(0, 1);
+            print Point3(1, 1);
+            print Echo();
+            print [Point(0, 0)];
+        "#;
+        let mut vm = VM::new(TestOut::default(), TestOut::default());
+        vm.interpret(source).expect("Failed to run program");
+        assert_eq!(
+            vm.out.flushed,
+            vec![
+                "(zero, one)\n",
+                "(one, one)\n",
+                "Echo instance\n",
+                "echo\n",
+                "[Point instance]\n"
+            ]
+        );
+
+        vm.interpret("class Bad { toString() { return nil; } }\nprint Bad();")
+            .expect_err("Expected runtime error");
+        assert_eq!(vm.e_out.flushed[0], "toString() must return a string.\n");
+        vm.reset(false);
+        vm.interpret("class Fails { toString() { return -nil; } }\nprint Fails();")
+            .expect_err("Expected runtime error");
+        vm.reset(false);
+        vm.interpret("print Point(1, 0);")
+            .expect("Failed to run program");
+        assert_eq!(vm.out.flushed.last().unwrap(), "(one, zero)\n");
+    }
+
+    #[test]
+    fn it_runs_a_program_with_mixins() {
+        let out = TestOut::default();
+        let e_out = TestOut::default();
+        let source = r#"
+            class Base {
+                name() { return "base"; }
+                greet() { return "hello"; }
+            }
+            class Named {
+                name() { return "named"; }
+            }
+            class Loud {
+                shout() { return this.name() + "!"; }
+            }
+            class A < Base with Named, Loud {
+                greet() { return "hi"; }
+            }
+            var a = A();
+            print a.name();
+            print a.shout();
+            print a.greet();
+        "#;
+        let mut vm = VM::new(out, e_out);
+        vm.interpret(source).expect("Failed to run program");
+        assert_eq!(vm.out.flushed.len(), 3);
+        assert_eq!(vm.out.flushed[0], "named\n".to_string());
+        assert_eq!(vm.out.flushed[1], "named!\n".to_string());
+        assert_eq!(vm.out.flushed[2], "hi\n".to_string());
+    }
+
+    #[test]
+    fn it_runs_a_program_with_a_data_class() {
+        let out = TestOut::default();
+        let e_out = TestOut::default();
+        let source = r#"
+            class Point(x, y) {
+                sum() {
+                    return this.x + this.y;
+                }
+            }
+            var p = Point(1, 2);
+            print p.x;
+            print p.sum();
+            print p.equals(Point(1, 2));
+            print p.equals(Point(1, 3));
+            class Other(x, y) {}
+            print p.equals(Other(1, 2));
+            print p.equals(nil);
+            print p.equals("Point");
+            class Empty() {}
+            print Empty().equals(Empty());
+            print Empty().equals(Point);
+            class Secret(_x) {}
+            print Secret(1).equals(Secret(1));
+            print Secret(1).equals(Secret(2));
+        "#;
+        let mut vm = VM::new(out, e_out);
+        vm.interpret(source).expect("Failed to run program");
+        assert_eq!(
+            vm.out.flushed,
+            vec![
+                "1\n", "3\n", "true\n", "false\n", "false\n", "false\n", "false\n", "true\n",
+                "false\n", "true\n", "false\n"
+            ]
+        );
+    }
+
+    #[test]
+    fn it_runs_a_program_with_private_members() {
+        let out = TestOut::default();
+        let e_out = TestOut::default();
+        let source = r#"
+            class Account {
+                init(balance) {
+                    this._balance = balance;
+                }
+                _double() {
+                    return this._balance * 2;
+                }
+                report() {
+                    return this._double();
+                }
+            }
+            print Account(21).report();
+        "#;
+        let mut vm = VM::new(out, e_out);
+        vm.interpret(source).expect("Failed to run program");
+        assert_eq!(vm.out.flushed.len(), 1);
+        assert_eq!(vm.out.flushed[0], "42\n".to_string());
+    }
+
+    /// Rewrites the instructions on `this` in `function` and the functions
+    /// it defines into their public counterparts.
+    fn strip_this(function: &mut ObjFunction) {
+        let offsets = function
+            .chunk
+            .instructions()
+            .filter_map(|(offset, instruction)| {
+                let public = match instruction.opcode {
+                    OpCode::GetThisProperty => OpCode::GetProperty,
+                    OpCode::SetThisProperty => OpCode::SetProperty,
+                    OpCode::InvokeThis => OpCode::Invoke,
+                    _ => return None,
+                };
+                Some((offset, public))
+            })
+            .collect::<Vec<_>>();
+        for (offset, public) in offsets {
+            function.chunk.code[offset] = public as u8;
+        }
+        for constant in &mut function.chunk.constants {
+            if let ConstantValue::Function(nested) = constant {
+                strip_this(nested);
+            }
+        }
+    }
+
+    #[test]
+    fn it_reports_private_members_reached_without_this() {
+        let sources = [
+            ("class A { init() { this._x = 1; } } A();", "_x"),
+            ("class A { get() { return this._x; } } A().get();", "_x"),
+            ("class A { _m() {} m() { this._m(); } } A().m();", "_m"),
+        ];
+        for (source, name) in sources {
+            let mut function = Compiler::new(source.into())
+                .compile()
+                .expect("Failed to compile");
+            strip_this(&mut function);
+            let mut vm = VM::new(TestOut::default(), TestOut::default());
+            assert_eq!(vm.run_script(function).map(|_| ()), Err(Error::Runtime));
+            assert_eq!(
+                vm.e_out.flushed[0],
+                format!("Can't access private member '{name}' outside of 'this'.\n")
+            );
+        }
+    }
+
+    #[test]
+    fn it_runs_a_program_with_super_captured_by_a_nested_function() {
+        let out = TestOut::default();
+        let e_out = TestOut::default();
+        let source = r#"
+            class Parent {
+                name() {
+                    return "parent";
+                }
+            }
+            class Child < Parent {
+                name() {
+                    fun parent_name() {
+                        return super.name();
+                    }
+                    return parent_name;
+                }
+            }
+            var parent_name = Child().name();
+            print parent_name();
+        "#;
+        let mut vm = VM::new(out, e_out);
+        vm.interpret(source).expect("Failed to run program");
+        assert_eq!(vm.out.flushed.len(), 1);
+        assert_eq!(vm.out.flushed[0], "parent\n".to_string());
+    }
+
+    #[test]
+    fn it_runs_a_program_with_a_native_function() {
+        let out = TestOut::default();
+        let e_out = TestOut::default();
+        let source = "print clock();";
+        let mut vm = VM::new(out, e_out);
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs_f64()
+            .round();
+        vm.interpret(source).expect("Failed to run program");
+        assert!(!vm.out.flushed.is_empty());
+        assert!(vm.e_out.flushed.is_empty());
+        let printed_time = vm.out.flushed[0].trim().parse::<f64>().unwrap().round();
+        assert!((printed_time - 1.0..printed_time + 1.0).contains(&now));
+    }
+
+    #[test]
+    fn it_runs_a_program_with_a_native_function_print() {
+        let out = TestOut::default();
+        let e_out = TestOut::default();
+        let source = "print clock;";
+        let mut vm = VM::new(out, e_out);
+        vm.interpret(source).expect("Failed to run program");
+        assert!(!vm.out.flushed.is_empty());
+        assert_eq!(vm.out.flushed.len(), 1);
+        assert!(vm.e_out.flushed.is_empty());
+        assert_eq!(vm.out.flushed[0], "<native fn>\n");
+    }
+
+    #[test]
+    fn it_buffers_output_until_flushed() {
+        let source = r#"
+            print "a";
+            print "bc";
+            flushOut();
+            print "d";
+            print "ef";
+        "#;
+        let cases = [
+            (OutputBuffering::Line, vec!["a\n", "bc\n", "d\n", "ef\n"]),
+            (OutputBuffering::Block(4), vec!["a\nbc\n", "d\nef\n"]),
+            (OutputBuffering::Manual, vec!["a\nbc\n", "d\nef\n"]),
+            (OutputBuffering::Block(100), vec!["a\nbc\n", "d\nef\n"]),
+        ];
+        for (buffering, expected) in cases {
+            let mut vm = VM::new(TestOut::default(), TestOut::default());
+            vm.set_output_buffering(buffering);
+            vm.interpret(source).expect("Failed to run program");
+            assert_eq!(vm.out.flushed, expected, "{buffering:?}");
+        }
+    }
+
+    #[test]
+    fn it_flushes_buffered_output_when_a_script_stops() {
+        let mut vm = VM::new(TestOut::default(), TestOut::default());
+        vm.set_output_buffering(OutputBuffering::Manual);
+        vm.interpret("print 1; print 2;")
+            .expect("Failed to run program");
+        assert_eq!(vm.out.flushed, vec!["1\n2\n"]);
+
+        vm.interpret("print 3; nil + 1;")
+            .expect_err("Expected runtime error");
+        assert_eq!(vm.out.flushed, vec!["1\n2\n", "3\n"]);
+        assert_eq!(
+            vm.e_out.flushed[0],
+            "Operands must be two numbers or two strings.\n"
+        );
+
+        // Switching modes writes out what the old one held back
+        vm.reset(false);
+        vm.set_output_buffering(OutputBuffering::Block(1024));
+        vm.interpret("print 4;").expect("Failed to run program");
+        assert_eq!(vm.out.flushed.len(), 3);
+    }
+
+    #[test]
+    fn it_traces_to_its_own_writer() {
+        use std::{cell::RefCell, rc::Rc};
+
+        #[derive(Default, Clone)]
+        struct SharedOut(Rc<RefCell<Vec<u8>>>);
+
+        impl Write for SharedOut {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.borrow_mut().extend_from_slice(buf);
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let trace = SharedOut::default();
+        let mut vm = VM::new(TestOut::default(), TestOut::default());
+        vm.set_trace_out(trace.clone());
+        vm.interpret("fun f() { return 1; } print f();")
+            .expect("Failed to run program");
+        assert_eq!(vm.out.flushed, vec!["1\n"]);
+        assert!(vm.e_out.flushed.is_empty());
+        let trace = String::from_utf8(trace.0.take()).expect("Expected UTF-8 trace");
+        if cfg!(feature = "debug") {
+            assert!(trace.contains("== <fn f> ==\n"), "{trace}");
+            assert!(trace.contains("OP_RETURN"), "{trace}");
+        } else {
+            assert!(trace.is_empty());
+        }
+    }
+
+    #[test]
+    fn it_sends_trace_events_to_a_sink() {
+        use std::{cell::RefCell, rc::Rc};
+
+        let events = Rc::new(RefCell::new(vec![]));
+        let recorded = events.clone();
+        let mut vm = VM::new(TestOut::default(), TestOut::default());
+        vm.set_trace_sink(move |event: &TraceEvent| {
+            let summary = match *event {
+                TraceEvent::Call {
+                    function,
+                    arg_count,
+                    depth,
+                } => format!("call {function}/{arg_count} at {depth}"),
+                TraceEvent::Return { function, depth } => format!("return {function} at {depth}"),
+                TraceEvent::InstructionExecuted {
+                    instruction: "OP_ADD",
+                    line,
+                    stack,
+                    ..
+                } => format!("add {stack} on line {line}"),
+                TraceEvent::Alloc {
+                    kind: "closure", ..
+                } => "alloc closure".into(),
+                _ => return,
+            };
+            recorded.borrow_mut().push(summary);
+        });
+        vm.interpret("fun f(a, b) {\n  return a + b;\n}\nprint f(1, 2);")
+            .expect("Failed to run program");
+        assert_eq!(vm.out.flushed, vec!["3\n"]);
+        assert_eq!(
+            *events.borrow(),
+            vec![
+                "alloc closure",
+                "call <script>/0 at 1",
+                "alloc closure",
+                "call <fn f>/2 at 2",
+                "add [ <script> ][ <fn f arity=2 upvalues=0> ][ 1 ][ 2 ][ 1 ][ 2 ] on line 2",
+                "return <fn f> at 2",
+                "return <script> at 1",
+            ]
+        );
+    }
+
+    #[test]
+    fn it_runs_a_compiled_program_many_times() {
+        let mut vm = VM::new(TestOut::default(), TestOut::default());
+        let program = vm
+            .compile("count = count + 1; print count;")
+            .expect("Failed to compile");
+        vm.interpret("var count = 0;")
+            .expect("Failed to run program");
+        vm.run_program(&program).expect("Failed to run program");
+        vm.run_program(&program).expect("Failed to run program");
+        assert_eq!(vm.out.flushed, vec!["1\n", "2\n"]);
+
+        // Another VM runs the same program against its own globals and limits
+        let mut other = VM::new(TestOut::default(), TestOut::default());
+        other
+            .interpret("var count = 10;")
+            .expect("Failed to run program");
+        other.run_program(&program).expect("Failed to run program");
+        assert_eq!(other.out.flushed, vec!["11\n"]);
+        other.set_instruction_limit(Some(2));
+        other
+            .run_program(&program)
+            .expect_err("Expected runtime error");
+        assert_eq!(other.e_out.flushed[0], "Instruction limit exceeded.\n");
+        assert_eq!(other.state(), VmState::Errored);
+        assert_eq!(vm.global("count"), Some(LoxValue::Number(2.0)));
+    }
+
+    #[test]
+    fn it_runs_programs_compiled_on_another_thread() {
+        let program = std::thread::spawn(|| {
+            Program::compile(
+                "fun f(n) { return n * 2; } print f(21);",
+                &CompileOptions::default(),
+            )
+        })
+        .join()
+        .expect("Failed to join compiler thread")
+        .expect("Failed to compile");
+        let mut vm = VM::new(TestOut::default(), TestOut::default());
+        vm.run_program(&program).expect("Failed to run program");
+        assert_eq!(vm.out.flushed, vec!["42\n"]);
+    }
+
+    #[cfg(feature = "internals")]
+    #[test]
+    fn it_checks_loaded_functions() {
+        let vm = VM::new(TestOut::default(), TestOut::default());
+        let mut function = ObjFunction::script(Chunk::default());
+        function.chunk.write(u8::MAX, 1);
+        assert_eq!(
+            vm.load(function).map(|_| ()),
+            Err(Error::InternalFault("Unknown opcode."))
+        );
+        assert_eq!(vm.state(), VmState::Ready);
+    }
+
+    #[test]
+    fn it_runs_debug_blocks_only_when_enabled() {
+        let source = r#"
+            fun check(n) {
+                debug {
+                    if (n < 0) print "negative";
+                    fun twice() { return n * 2; }
+                    print twice();
+                }
+                return n;
+            }
+            print check(-2);
+        "#;
+        let mut vm = VM::new(TestOut::default(), TestOut::default());
+        vm.interpret(source).expect("Failed to run program");
+        assert_eq!(vm.out.flushed, vec!["-2\n"]);
+
+        vm.set_debug_blocks(true);
+        vm.interpret(source).expect("Failed to run program");
+        assert_eq!(vm.out.flushed[1..], ["negative\n", "-4\n", "-2\n"]);
+    }
+
+    #[test]
+    fn it_pauses_at_breakpoints_when_a_debugger_is_attached() {
+        use std::{cell::RefCell, rc::Rc};
+
+        let source = "fun f(a) {\n  var b = a + 1;\n  breakpoint();\n  return b;\n}\nprint f(1);";
+        let mut vm = VM::new(TestOut::default(), TestOut::default());
+        // Without a debugger the call does nothing
+        vm.interpret(source).expect("Failed to run program");
+        assert_eq!(vm.out.flushed, vec!["2\n"]);
+
+        let paused = Rc::new(RefCell::new(Vec::new()));
+        let breakpoints = paused.clone();
+        vm.on_breakpoint(move |breakpoint| breakpoints.borrow_mut().push(breakpoint.clone()));
+        vm.interpret(source).expect("Failed to run program");
+        vm.set_debug_info(true);
+        vm.interpret(source).expect("Failed to run program");
+        assert_eq!(vm.out.flushed, vec!["2\n", "2\n", "2\n"]);
+
+        let paused = paused.borrow();
+        assert_eq!(paused.len(), 2);
+        assert_eq!(paused[0].line(), 3);
+        assert_eq!(paused[0].to_string(), "Paused at [line 3] in f");
+        assert_eq!(
+            paused[0].trace[1],
+            TraceFrame {
+                line: 6,
+                function: None
+            }
+        );
+        assert_eq!(paused[0].locals, None);
+        assert_eq!(
+            paused[1].locals,
+            Some(vec![
+                ("a".into(), LoxValue::Number(1.0)),
+                ("b".into(), LoxValue::Number(2.0)),
+            ])
+        );
+    }
+
+    #[test]
+    fn it_steps_through_a_prepared_program() {
+        let mut vm = VM::new(TestOut::default(), TestOut::default());
+        assert_eq!(vm.step(), StepResult::Finished);
+
+        // Two constants, Add, Print, then the implicit Nil and Return
+        let program = vm.compile("print 1 + 2;").expect("Failed to compile");
+        vm.prepare(&program).expect("Failed to prepare program");
+        for _ in 0..3 {
+            assert_eq!(vm.step(), StepResult::Executed);
+        }
+        assert!(vm.out.flushed.is_empty());
+        assert_eq!(vm.step(), StepResult::Executed);
+        assert_eq!(vm.out.flushed, vec!["3\n"]);
+        assert_eq!(vm.step(), StepResult::Executed);
+        assert_eq!(vm.step(), StepResult::Finished);
+        assert_eq!(vm.step(), StepResult::Finished);
+        assert!(vm.store.value_stack.is_empty());
+        assert_eq!(vm.stats().instructions, 6);
+
+        let program = vm
+            .compile("breakpoint(); print nil + 1;")
+            .expect("Failed to compile");
+        vm.prepare(&program).expect("Failed to prepare program");
+        let mut steps = Vec::new();
+        loop {
+            let step = vm.step();
+            if step != StepResult::Executed {
+                steps.push(step);
+            }
+            if matches!(step, StepResult::Finished | StepResult::Errored(_)) {
+                break;
+            }
+        }
+        assert_eq!(
+            steps,
+            vec![StepResult::Breakpoint, StepResult::Errored(Error::Runtime)]
+        );
+        assert_eq!(vm.state(), VmState::Errored);
+        assert_eq!(
+            vm.e_out.flushed[0],
+            "Operands must be two numbers or two strings.\n"
+        );
+    }
+
+    #[test]
+    fn it_calls_registered_natives() {
+        use std::{cell::RefCell, rc::Rc};
+
+        let mut vm = VM::new(TestOut::default(), TestOut::default());
+        let calls = Rc::new(RefCell::new(Vec::new()));
+        let recorded = calls.clone();
+        vm.register_native("record", 2, move |args| {
+            recorded.borrow_mut().push(args.to_vec());
+            Ok(LoxValue::List(vec![
+                args[0].clone(),
+                LoxValue::String("seen".into()),
+            ]))
+        });
+        vm.register_native("fail", 0, |_| Err("Host refused.".into()));
+        vm.register_native("leak", 0, |_| Ok(LoxValue::Object("<fn f>".into())));
+        vm.interpret("print record(1, [true, nil]);")
+            .expect("Failed to run program");
+        assert_eq!(vm.out.flushed, vec!["[1, seen]\n"]);
+        assert_eq!(
+            *calls.borrow(),
+            vec![vec![
+                LoxValue::Number(1.0),
+                LoxValue::List(vec![LoxValue::Bool(true), LoxValue::Nil]),
+            ]]
+        );
+
+        vm.interpret("fail();").expect_err("Expected runtime error");
+        assert_eq!(vm.e_out.flushed[0], "Host refused.\n");
+        vm.interpret("leak();").expect_err("Expected runtime error");
+        assert_eq!(vm.e_out.flushed[3], "Can't pass '<fn f>' to Lox.\n");
+
+        // Registered natives survive clearing the globals
+        vm.reset(true);
+        vm.interpret("print record(\"again\", 2);")
+            .expect("Failed to run program");
+        assert_eq!(vm.out.flushed[1], "[again, seen]\n");
+    }
+
+    #[test]
+    fn it_names_initializers_that_recurse_too_deeply() {
+        let mut vm = VM::new(TestOut::default(), TestOut::default());
+        let source = "class Node { init(depth) { if (depth > 0) this.next = Node(depth - 1); } }";
+        vm.interpret(source).expect("Failed to run program");
+        // Anything that fits on the stack is allowed by default
+        vm.interpret("Node(40); Node(62);")
+            .expect("Failed to run program");
+        vm.interpret("class Loop { init() { this.self = Loop(); } } Loop();")
+            .expect_err("Expected runtime error");
+        assert_eq!(
+            vm.e_out.flushed[0],
+            "Initializer 'Loop.init' recursed more than 63 calls deep.\n"
+        );
+
+        vm.reset(false);
+        vm.e_out.flushed.clear();
+        vm.set_init_recursion_limit(Some(10));
+        vm.interpret("Node(20);")
+            .expect_err("Expected runtime error");
+        assert_eq!(
+            vm.e_out.flushed[0],
+            "Initializer 'Node.init' recursed more than 10 calls deep.\n"
+        );
+
+        vm.reset(false);
+        vm.e_out.flushed.clear();
+        vm.set_init_recursion_limit(None);
+        vm.interpret("Loop();").expect_err("Expected runtime error");
+        assert_eq!(vm.e_out.flushed[0], "Stack overflow.\n");
+    }
+
+    #[test]
+    fn it_stops_scripts_at_the_instruction_limit() {
+        let mut vm = VM::new(TestOut::default(), TestOut::default());
+        vm.set_instruction_limit(Some(1000));
+        vm.interpret("while (true) {}")
+            .expect_err("Expected runtime error");
+        assert_eq!(vm.e_out.flushed[0], "Instruction limit exceeded.\n");
+
+        // Each script gets the whole budget
+        vm.reset(false);
+        for _ in 0..3 {
+            vm.interpret("for (var i = 0; i < 50; i = i + 1) {}")
+                .expect("Failed to run program");
+        }
+        vm.set_instruction_limit(None);
+        vm.interpret("for (var i = 0; i < 500; i = i + 1) {}")
+            .expect("Failed to run program");
+    }
+
+    #[test]
+    fn it_runs_a_program_with_a_list() {
+        let out = TestOut::default();
+        let e_out = TestOut::default();
+        let source = r#"
+            var a = [1, "two", [nil]];
+            print a;
+            print [];
+        "#;
+        let mut vm = VM::new(out, e_out);
+        vm.interpret(source).expect("Failed to run program");
+        assert!(vm.e_out.flushed.is_empty());
+        assert_eq!(vm.out.flushed.len(), 2);
+        assert_eq!(vm.out.flushed[0], "[1, two, [nil]]\n");
+        assert_eq!(vm.out.flushed[1], "[]\n");
+    }
+
+    #[test]
+    fn it_runs_a_program_with_for_in_loops() {
+        let out = TestOut::default();
+        let e_out = TestOut::default();
+        let source = r#"
+            for (var x in [1, 2]) {
+                print x;
+            }
+            for (var c in "hi") print c;
+            class Countdown {
+                init(n) {
+                    this.n = n;
+                }
+                done() {
+                    return this.n == 0;
+                }
+                next() {
+                    this.n = this.n - 1;
+                    return this.n + 1;
+                }
+            }
+            class Launch {
+                iter() {
+                    return Countdown(2);
+                }
+            }
+            for (var n in Launch()) print n;
+            fun collect(x) {
+                fun printer() {
+                    print x;
+                }
+                return printer;
+            }
+            for (var f in map(["a", "b"], collect)) f();
+        "#;
+        let mut vm = VM::new(out, e_out);
+        vm.interpret(source).expect("Failed to run program");
+        assert!(vm.e_out.flushed.is_empty());
+        assert_eq!(
+            vm.out.flushed,
+            vec!["1\n", "2\n", "h\n", "i\n", "2\n", "1\n", "a\n", "b\n"]
+        );
+    }
+
+    #[test]
+    fn it_runs_a_program_with_closures_capturing_for_in_variables() {
+        let out = TestOut::default();
+        let e_out = TestOut::default();
+        let source = r#"
+            var first;
+            var second;
+            for (var x in [1, 2]) {
+                fun printer() {
+                    print x;
+                }
+                if (first == nil) first = printer;
+                else second = printer;
+            }
+            first();
+            second();
+        "#;
+        let mut vm = VM::new(out, e_out);
+        vm.interpret(source).expect("Failed to run program");
+        assert_eq!(vm.out.flushed, vec!["1\n", "2\n"]);
+    }
+
+    #[test]
+    fn it_runs_a_program_with_ranges() {
+        let out = TestOut::default();
+        let e_out = TestOut::default();
+        let source = r#"
+            var n = 2;
+            for (var i in 0..n + 1) print i;
+            for (var i in 5..=5) print i;
+            for (var i in 3..1) print i;
+            print 1..10;
+            print contains(1..10, 9);
+            print contains(1..10, 10);
+            print contains(1..=10, 10);
+            print contains([1, 2], 2);
+        "#;
+        let mut vm = VM::new(out, e_out);
+        vm.interpret(source).expect("Failed to run program");
+        assert!(vm.e_out.flushed.is_empty());
+        assert_eq!(
+            vm.out.flushed,
+            vec!["0\n", "1\n", "2\n", "5\n", "1..10\n", "true\n", "false\n", "true\n", "true\n"]
+        );
+    }
+
+    #[test]
+    fn it_reports_a_runtime_error_non_number_range() {
+        let out = TestOut::default();
+        let e_out = TestOut::default();
+        let source = r#"var r = 1.."a";"#;
+        let mut vm = VM::new(out, e_out);
+        vm.interpret(source).expect_err("Expected runtime error");
+        assert_eq!(vm.e_out.flushed[0], "Range bounds must be numbers.\n");
+    }
+
+    #[test]
+    fn it_runs_a_program_with_indexing() {
+        let out = TestOut::default();
+        let e_out = TestOut::default();
+        let source = r#"
+            var s = "hello";
+            print s[0];
+            print s[1..3];
+            print s[1..=3];
+            print s[5..5];
+            var l = [1, 2, 3];
+            l[1] = 5;
+            print l[1];
+            print l[0..2];
+        "#;
+        let mut vm = VM::new(out, e_out);
+        vm.interpret(source).expect("Failed to run program");
+        assert!(vm.e_out.flushed.is_empty());
+        assert_eq!(
+            vm.out.flushed,
+            vec!["h\n", "el\n", "ell\n", "\n", "5\n", "[1, 5]\n"]
+        );
+    }
+
+    #[test]
+    fn it_reports_runtime_errors_for_bad_indices() {
+        let cases = [
+            (
+                r#""abc"[-1];"#,
+                "Index must be a non-negative integer but got -1.\n",
+            ),
+            (r#""abc"[3];"#, "Index 3 is out of range for length 3.\n"),
+            (
+                r#""abc"[0..4];"#,
+                "Slice 0..4 is out of range for length 3.\n",
+            ),
+            (r#""abc"[2..1];"#, "Slice 2..1 starts after it ends.\n"),
+            (r#""abc"["a"];"#, "Index must be a number or a range.\n"),
+            ("1[0];", "Can only index lists and strings.\n"),
+            (
+                r#""abc"[0] = "d";"#,
+                "Can only assign to indices of lists.\n",
+            ),
+        ];
+        for (source, message) in cases {
+            let out = TestOut::default();
+            let e_out = TestOut::default();
+            let mut vm = VM::new(out, e_out);
+            vm.interpret(source).expect_err("Expected runtime error");
+            assert_eq!(vm.e_out.flushed[0], message);
+        }
+    }
+
+    #[test]
+    fn it_reports_a_runtime_error_non_iterable() {
+        let out = TestOut::default();
+        let e_out = TestOut::default();
+        let source = "for (var x in 1) print x;";
+        let mut vm = VM::new(out, e_out);
+        vm.interpret(source).expect_err("Expected runtime error");
+        assert_eq!(
+            vm.e_out.flushed[0],
+            "Can only iterate over lists, ranges, strings and instances.\n"
+        );
+    }
+
+    #[test]
+    fn it_runs_a_program_comparing_closure_identity() {
+        let out = TestOut::default();
+        let e_out = TestOut::default();
+        let source = r#"
+            fun make() {
+                fun f() {}
+                return f;
+            }
+            var a = make();
+            var b = make();
+            print same(a, a);
+            print same(a, b);
+            print a == b;
+            print a;
+        "#;
+        let mut vm = VM::new(out, e_out);
+        vm.interpret(source).expect("Failed to run program");
+        assert_eq!(
+            vm.out.flushed,
+            vec!["true\n", "false\n", "false\n", "<fn f>\n"]
+        );
+    }
+
+    #[cfg(feature = "metrics")]
+    #[test]
+    fn it_keeps_hot_handlers_allocation_free() {
+        let out = TestOut::default();
+        let e_out = TestOut::default();
+        let source = r#"
+            fun add(a, b) {
+                return a + b;
+            }
+            var total = 0;
+            for (var i = 0; i < 100; i = i + 1) {
+                total = add(total, i);
+                same(total, i);
+            }
+            print total;
+        "#;
+        let mut vm = VM::new(out, e_out);
+        vm.interpret(source).expect("Failed to run program");
+        assert_eq!(vm.out.flushed, vec!["4950\n"]);
+
+        let metrics = vm.metrics();
+        for op in [OpCode::Add, OpCode::GetLocal, OpCode::Call] {
+            assert!(metrics.executions(op) >= 100, "{op} barely ran");
+            assert_eq!(metrics.allocations(op), 0, "{op} allocated");
+            assert_eq!(metrics.clones(op), 0, "{op} cloned");
+        }
+    }
+
+    #[cfg(feature = "metrics")]
+    #[test]
+    fn it_concatenates_strings_with_a_single_allocation() {
+        let out = TestOut::default();
+        let e_out = TestOut::default();
+        let source = r#"
+            var s = "x";
+            for (var i = 0; i < 10; i = i + 1) {
+                s = s + "a";
+            }
+            print s;
+        "#;
+        let mut vm = VM::new(out, e_out);
+        vm.interpret(source).expect("Failed to run program");
+        assert_eq!(vm.out.flushed, vec!["xaaaaaaaaaa\n"]);
+
+        let metrics = vm.metrics();
+        assert_eq!(metrics.executions(OpCode::Add), 20);
+        assert_eq!(metrics.allocations(OpCode::Add), 10);
+        assert_eq!(metrics.clones(OpCode::Add), 0);
+    }
+
+    #[test]
+    fn it_handles_a_runtime_error_invoking_a_non_instance() {
+        let out = TestOut::default();
+        let e_out = TestOut::default();
+        let source = r#"
+            var a = 1;
+            a.foo();
+        "#;
+        let mut vm = VM::new(out, e_out);
+        assert_eq!(vm.interpret(source), Err(Error::Runtime));
+        assert_eq!(
+            vm.e_out.flushed,
+            vec!["Only instances have methods.\n", "[line 3] in ", "script\n"]
+        );
+    }
+
+    #[test]
+    fn it_poisons_the_vm_on_an_internal_fault() {
+        let out = TestOut::default();
+        let e_out = TestOut::default();
+        let mut vm = VM::new(out, e_out);
+        let mut function = ObjFunction::script(Chunk::default());
+        function.chunk.write(u8::MAX, 1);
+        let function = vm.store.insert_function(function);
+        let closure = vm.new_closure(function);
+        vm.push_value(closure.into());
+        vm.call(closure, 0).expect("Failed to call closure");
+
+        assert_eq!(vm.run(0), Err(Error::InternalFault("Unknown opcode.")));
+        assert_eq!(
+            vm.e_out.flushed,
+            vec!["Internal VM fault: Unknown opcode.\n"]
+        );
+        assert_eq!(
+            vm.interpret("print 1;"),
+            Err(Error::InternalFault(
+                "The VM was poisoned by an earlier fault."
+            ))
+        );
+        assert!(vm.out.flushed.is_empty());
+    }
+
+    #[test]
+    fn it_tracks_the_vm_state_across_errors() {
+        let out = TestOut::default();
+        let e_out = TestOut::default();
+        let mut vm = VM::new(out, e_out);
+        assert_eq!(vm.state(), VmState::Ready);
+
+        vm.interpret("var a = 1;").expect("Failed to run program");
+        assert_eq!(vm.state(), VmState::Ready);
+        assert_eq!(vm.interpret("var b = 2 + nil;"), Err(Error::Runtime));
+        assert_eq!(vm.state(), VmState::Errored);
+        assert!(!vm.store.value_stack.is_empty());
+
+        vm.reset(false);
+        assert_eq!(vm.state(), VmState::Ready);
+        assert!(vm.store.value_stack.is_empty());
+        vm.interpret("print a;").expect("Failed to run program");
+        assert_eq!(vm.out.flushed, vec!["1\n"]);
+    }
+
+    #[test]
+    fn it_resets_a_poisoned_vm_and_its_globals() {
+        let out = TestOut::default();
+        let e_out = TestOut::default();
+        let mut vm = VM::new(out, e_out);
+        vm.interpret("var a = 1;").expect("Failed to run program");
+        vm.state = VmState::Poisoned;
+        assert!(matches!(
+            vm.interpret("print a;"),
+            Err(Error::InternalFault(_))
+        ));
+
+        vm.reset(true);
+        assert_eq!(vm.state(), VmState::Ready);
+        vm.interpret("print same(clock, clock);")
+            .expect("Failed to run program");
+        assert_eq!(vm.interpret("print a;"), Err(Error::Runtime));
+        assert_eq!(vm.out.flushed, vec!["true\n"]);
+        assert_eq!(vm.e_out.flushed[0], "Undefined variable 'a'.\n");
+    }
+
+    /// Runs a hand-assembled script, as if it had been compiled.
+    fn run_code(vm: &mut VM<TestOut, TestOut>, code: &[u8]) -> Result<(), Error> {
+        let mut chunk = Chunk::default();
+        for &byte in code {
+            chunk.write(byte, 1);
+        }
+        vm.run_script(ObjFunction::script(chunk))?;
+        Ok(())
+    }
+
+    #[test]
+    fn it_faults_on_jumps_out_of_the_chunk() {
+        let jump = OpCode::Jump as u8;
+        let loop_ = OpCode::Loop as u8;
+        let nil = OpCode::Nil as u8;
+        let ret = OpCode::Return as u8;
+        let malformed: [&[u8]; 4] = [
+            &[jump, 0xff, 0xff, nil, ret],
+            &[jump, 0, 2, nil, ret],
+            &[nil, loop_, 0, 10, ret],
+            &[nil],
+        ];
+        // Verified up front by default, or caught as they run otherwise
+        for predecode in [true, false] {
+            for code in malformed {
+                let mut vm = VM::new(TestOut::default(), TestOut::default());
+                vm.set_predecode(predecode);
+                assert!(
+                    matches!(run_code(&mut vm, code), Err(Error::InternalFault(_))),
+                    "{code:?}"
+                );
+                assert_eq!(vm.state(), VmState::Poisoned);
+            }
+
+            let mut vm = VM::new(TestOut::default(), TestOut::default());
+            vm.set_predecode(predecode);
+            assert_eq!(run_code(&mut vm, &[jump, 0, 1, loop_, nil, ret]), Ok(()));
+            assert_eq!(
+                run_code(&mut vm, &[jump, 0, 3]),
+                Err(Error::InternalFault("Jump out of range."))
+            );
+            assert_eq!(
+                vm.e_out.flushed,
+                vec!["Internal VM fault: Jump out of range.\n"]
+            );
+        }
+    }
+
+    #[test]
+    fn it_faults_on_code_that_underflows_the_stack() {
+        let pop = OpCode::Pop as u8;
+        let add = OpCode::Add as u8;
+        let nil = OpCode::Nil as u8;
+        let ret = OpCode::Return as u8;
+        // Slot zero holds the script itself, so the second pop underflows
+        let underflowing: [&[u8]; 2] = [&[pop, pop, nil, ret], &[pop, add, nil, ret]];
+        for code in underflowing {
+            let mut vm = VM::new(TestOut::default(), TestOut::default());
+            assert_eq!(
+                run_code(&mut vm, code),
+                Err(Error::InternalFault("Stack underflow.")),
+                "{code:?}"
+            );
+            assert_eq!(vm.state(), VmState::Poisoned);
+        }
+    }
+
+    #[test]
+    fn it_verifies_code_before_running_it() {
+        let print = OpCode::Print as u8;
+        let constant = OpCode::Constant as u8;
+        let ret = OpCode::Return as u8;
+        let mut vm = VM::new(TestOut::default(), TestOut::default());
+        vm.interpret("print 1;").expect("Failed to run program");
+        // Nothing runs when verification fails, not even the code before the fault
+        assert_eq!(
+            run_code(&mut vm, &[OpCode::Nil as u8, print, constant, 0, ret]),
+            Err(Error::InternalFault("Constant index out of range."))
+        );
+        assert_eq!(vm.out.flushed, vec!["1\n"]);
+    }
+
+    #[test]
+    fn it_loads_bool_and_nil_constants() {
+        let constant = OpCode::Constant as u8;
+        let print = OpCode::Print as u8;
+        let mut chunk = Chunk::default();
+        chunk.add_constant(true.into());
+        chunk.add_constant(ConstantValue::Nil);
+        chunk.add_constant(false.into());
+        for byte in [
+            constant,
+            0,
+            print,
+            constant,
+            1,
+            print,
+            constant,
+            2,
+            OpCode::Not as u8,
+            print,
+            OpCode::Nil as u8,
+            OpCode::Return as u8,
+        ] {
+            chunk.write(byte, 1);
+        }
+        let listing = chunk.to_string();
+        assert!(listing.contains("'true'"));
+        assert!(listing.contains("'nil'"));
+        let mut vm = VM::new(TestOut::default(), TestOut::default());
+        vm.run_script(ObjFunction::script(chunk))
+            .expect("Failed to run chunk");
+        assert_eq!(vm.out.flushed, vec!["true\n", "nil\n", "true\n"]);
+    }
+
+    #[test]
+    fn it_reads_globals_as_host_values() {
+        let out = TestOut::default();
+        let e_out = TestOut::default();
+        let mut vm = VM::new(out, e_out);
+        vm.interpret("var list = [1, \"a\", nil, nil]; list[3] = list; fun f() {}")
+            .expect("Failed to run program");
+        assert_eq!(
+            vm.global("list"),
+            Some(LoxValue::List(vec![
+                1.0.into(),
+                "a".into(),
+                LoxValue::Nil,
+                LoxValue::Object("[...]".into())
+            ]))
+        );
+        assert_eq!(vm.global("f"), Some(LoxValue::Object("<fn f>".into())));
+        assert_eq!(vm.global("g"), None);
+    }
+
+    #[test]
+    fn it_exposes_the_upvalue_trace() {
+        let out = TestOut::default();
+        let e_out = TestOut::default();
+        let mut vm = VM::new(out, e_out);
+        vm.set_upvalue_trace(true);
+        vm.interpret("fun f() { var x = 1; fun g() { return x; } return g; } print f()();")
+            .expect("Failed to run program");
+        assert_eq!(vm.out.flushed, vec!["1\n"]);
+        assert_eq!(
+            vm.diagnostics().upvalues[0].to_string(),
+            "x: <fn g> upvalue 0 -> local 1 of <fn f>"
+        );
+
+        vm.interpret("print 1;").expect("Failed to run program");
+        assert!(vm.diagnostics().upvalues.is_empty());
+    }
+
+    #[test]
+    fn it_imports_modules_once_from_module_paths() {
+        let directory = module_dir(
+            "imports",
+            &[
+                (
+                    "src/util.lox",
+                    "print \"loading util\"; fun twice(x) { return x * 2; }",
+                ),
+                ("lib/util.lox", "print \"shadowed\";"),
+                ("lib/math.lox", "var pi = 3;"),
+            ],
+        );
+        let out = TestOut::default();
+        let e_out = TestOut::default();
+        let mut vm = VM::new(out, e_out);
+        vm.add_module_path(directory.join("src"));
+        vm.add_module_path(directory.join("lib"));
+        vm.interpret("import \"util\"; import \"util.lox\"; import \"math\"; print twice(pi);")
+            .expect("Failed to run program");
+        assert_eq!(vm.out.flushed, vec!["loading util\n", "6\n"]);
+        fs::remove_dir_all(directory).expect("Failed to clean up");
+    }
+
+    #[test]
+    fn it_imports_modules_relative_to_the_importer() {
+        let directory = module_dir(
+            "relative-imports",
+            &[
+                ("main.lox", "import \"./lib/a\"; print a;"),
+                ("lib/a.lox", "import \"../shared/b\"; var a = \"a\" + b;"),
+                ("shared/b.lox", "var b = \"b\";"),
+            ],
+        );
+        let out = TestOut::default();
+        let e_out = TestOut::default();
+        let mut vm = VM::new(out, e_out);
+        let main = directory.join("main.lox");
+        let source = fs::read_to_string(&main).unwrap();
+        vm.interpret_module(&main, &source)
+            .expect("Failed to run program");
+        assert_eq!(vm.out.flushed, vec!["ab\n"]);
+
+        let mut modules = vm
+            .modules()
+            .map(|path| {
+                path.strip_prefix(directory.canonicalize().unwrap())
+                    .unwrap()
+            })
+            .map(|path| path.to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        modules.sort();
+        assert_eq!(modules, vec!["lib/a.lox", "main.lox", "shared/b.lox"]);
+        fs::remove_dir_all(directory).expect("Failed to clean up");
+    }
+
+    #[test]
+    fn it_reports_an_import_cycle() {
+        let directory = module_dir(
+            "import-cycle",
+            &[
+                ("main.lox", "import \"./a\";"),
+                ("a.lox", "import \"./b\";"),
+                ("b.lox", "import \"./a.lox\";"),
+            ],
+        );
+        let out = TestOut::default();
+        let e_out = TestOut::default();
+        let mut vm = VM::new(out, e_out);
+        let main = directory.join("main.lox");
+        let source = fs::read_to_string(&main).unwrap();
+        assert_eq!(vm.interpret_module(&main, &source), Err(Error::Runtime));
+        assert_eq!(
+            vm.e_out.flushed[0],
+            "Import cycle detected: a.lox -> b.lox -> a.lox\n"
+        );
+
+        // The chain unwinds with the error, so the entry can import again
+        vm.reset(true);
+        vm.e_out.flushed.clear();
+        assert_eq!(vm.interpret_module(&main, &source), Err(Error::Runtime));
+        assert_eq!(
+            vm.e_out.flushed[0],
+            "Import cycle detected: a.lox -> b.lox -> a.lox\n"
+        );
+        fs::remove_dir_all(directory).expect("Failed to clean up");
+    }
+
+    #[test]
+    fn it_loads_files_into_the_globals_once() {
+        let directory = module_dir(
+            "load",
+            &[
+                (
+                    "main.lox",
+                    "print load(\"lib/util.lox\"); print load(\"./lib/util.lox\");
+                     import \"./lib/util\"; print twice(2); load(\"lib/cycle.lox\");",
+                ),
+                (
+                    "lib/util.lox",
+                    "fun twice(x) { return x * 2; } print \"util\";",
+                ),
+                ("lib/cycle.lox", "load(\"cycle.lox\");"),
+            ],
+        );
+        let out = TestOut::default();
+        let e_out = TestOut::default();
+        let mut vm = VM::new(out, e_out);
+        let main = directory.join("main.lox");
+        let source = fs::read_to_string(&main).unwrap();
+        assert_eq!(vm.interpret_module(&main, &source), Err(Error::Runtime));
+        assert_eq!(vm.out.flushed, vec!["util\n", "true\n", "false\n", "4\n"]);
+        assert_eq!(
+            vm.e_out.flushed[0],
+            "Import cycle detected: cycle.lox -> cycle.lox\n"
+        );
+
+        vm.reset(true);
+        vm.e_out.flushed.clear();
+        assert_eq!(vm.interpret("load(\"missing.lox\");"), Err(Error::Runtime));
+        assert_eq!(vm.e_out.flushed[0], "Could not find file 'missing.lox'.\n");
+        fs::remove_dir_all(directory).expect("Failed to clean up");
+    }
+
+    #[test]
+    fn it_imports_modules_from_a_host_resolver() {
+        use std::{cell::RefCell, rc::Rc};
+
+        let modules = [
+            (
+                "app",
+                "import \"lib/main\"; import \"lib/util\"; print twice(2);",
+            ),
+            ("lib/main", "import \"./util\"; print \"main\";"),
+            ("lib/util", "print \"util\"; fun twice(x) { return x * 2; }"),
+        ];
+        let asked = Rc::new(RefCell::new(vec![]));
+        let log = asked.clone();
+        let out = TestOut::default();
+        let e_out = TestOut::default();
+        let mut vm = VM::new(out, e_out);
+        vm.set_module_resolver(move |spec: &str| {
+            log.borrow_mut().push(spec.to_string());
+            let (path, text) = modules
+                .iter()
+                .find(|(path, _)| *path == spec)
+                .ok_or(Error::Runtime)?;
+            Ok(Source {
+                path: path.into(),
+                text: text.to_string(),
+            })
+        });
+        vm.interpret("import \"app\";")
+            .expect("Failed to run program");
+        assert_eq!(vm.out.flushed, vec!["util\n", "main\n", "4\n"]);
+        // Relative to the importer, and asked for again but run once
+        assert_eq!(*asked.borrow(), ["app", "lib/main", "lib/util", "lib/util"]);
+
+        assert_eq!(vm.interpret("import \"missing\";"), Err(Error::Runtime));
+        assert_eq!(vm.e_out.flushed[0], "Could not find module 'missing'.\n");
+    }
+
+    #[test]
+    fn it_reports_a_module_it_cannot_read() {
+        let directory = module_dir("unreadable", &[("good.lox", "")]);
+        fs::write(directory.join("bad.lox"), [0xff, 0xfe]).unwrap();
+        let out = TestOut::default();
+        let e_out = TestOut::default();
+        let mut vm = VM::new(out, e_out);
+        vm.add_module_path(&directory);
+        assert_eq!(vm.interpret("import \"bad\";"), Err(Error::Runtime));
+        assert_eq!(
+            vm.e_out.flushed[0],
+            format!(
+                "Could not read module 'bad' at '{}': stream did not contain valid UTF-8.\n",
+                directory.join("bad.lox").display()
+            )
+        );
+        fs::remove_dir_all(directory).expect("Failed to clean up");
+    }
+
+    #[test]
+    fn it_reports_a_missing_module() {
+        let out = TestOut::default();
+        let e_out = TestOut::default();
+        let mut vm = VM::new(out, e_out);
+        assert_eq!(vm.interpret("import \"missing\";"), Err(Error::Runtime));
+        assert_eq!(
+            vm.e_out.flushed,
+            vec![
+                "Could not find module 'missing'.\n",
+                "[line 1] in ",
+                "script\n"
+            ]
+        );
+    }
+
+    #[test]
+    fn it_reports_an_error_in_an_imported_module() {
+        let directory = module_dir("import-error", &[("bad.lox", "print nope;")]);
+        let out = TestOut::default();
+        let e_out = TestOut::default();
+        let mut vm = VM::new(out, e_out);
+        vm.add_module_path(&directory);
+        assert_eq!(vm.interpret("\nimport \"bad\";"), Err(Error::Runtime));
+        assert_eq!(
+            vm.e_out.flushed,
+            vec![
+                "Undefined variable 'nope'.\n",
+                "[line 1] in ",
+                "script\n",
+                "[line 2] in ",
+                "script\n"
+            ]
+        );
+        fs::remove_dir_all(directory).expect("Failed to clean up");
+    }
+
+    #[test]
+    fn it_lists_globals() {
+        let out = TestOut::default();
+        let e_out = TestOut::default();
+        let mut vm = VM::new(out, e_out);
+        vm.interpret("var a = 1; var b = \"b\"; class C {}")
+            .expect("Failed to run program");
+        let mut globals = vm
+            .globals()
+            .filter(|(name, _)| ["a", "b", "C"].contains(name))
+            .collect::<Vec<_>>();
+        globals.sort_by(|a, b| a.0.cmp(b.0));
+        assert_eq!(
+            globals,
+            vec![
+                ("C", LoxValue::Object("C".into())),
+                ("a", 1.0.into()),
+                ("b", "b".into())
+            ]
+        );
+        assert!(vm.globals().any(|(name, _)| name == "clock"));
+    }
+
+    #[test]
+    fn it_lists_members_of_globals() {
+        let out = TestOut::default();
+        let e_out = TestOut::default();
+        let mut vm = VM::new(out, e_out);
+        vm.interpret("class A { init() { this.x = 1; this._y = 2; } f() {} _g() {} } var a = A();")
+            .expect("Failed to run program");
+        let mut members = vm.global_members("a");
+        members.sort();
+        assert_eq!(members, vec!["f", "init", "x"]);
+        let mut members = vm.global_members("A");
+        members.sort();
+        assert_eq!(members, vec!["f", "init"]);
+        assert!(vm.global_members("clock").is_empty());
+        assert!(vm.global_members("missing").is_empty());
+    }
+
+    #[test]
+    fn it_evaluates_source_at_runtime() {
+        let out = TestOut::default();
+        let e_out = TestOut::default();
+        let mut vm = VM::new(out, e_out);
+        vm.interpret(
+            "var x = 10;
+             print eval(\"var y = x * 2; y + 1\", false);
+             print y;
+             print eval(\"var x = 1; x\", true);
+             print x;
+             print eval(\"print 3;\", false);",
+        )
+        .expect("Failed to run program");
+        assert_eq!(
+            vm.out.flushed,
+            vec!["21\n", "20\n", "1\n", "10\n", "3\n", "nil\n"]
+        );
+
+        // Not isolated unless asked
+        vm.interpret("print eval(\"y + x\");")
+            .expect("Failed to run program");
+        assert_eq!(vm.out.flushed.last().unwrap(), "30\n");
+        assert_eq!(vm.interpret("eval();"), Err(Error::Runtime));
+        assert_eq!(
+            vm.e_out.flushed[0],
+            "Expected 1 to 2 arguments but got 0.\n"
+        );
+
+        vm.e_out.flushed.clear();
+        assert_eq!(vm.interpret("eval(\"x\", true);"), Err(Error::Runtime));
+        assert_eq!(vm.e_out.flushed[0], "Undefined variable 'x'.\n");
+        // The caller's globals come back even when isolated code fails
+        vm.reset(false);
+        vm.interpret("print x;").expect("Failed to run program");
+        assert_eq!(vm.out.flushed.last().unwrap(), "10\n");
+
+        // Malformed functions are compile errors, not crashes
+        let sources = [
+            (
+                "eval(\"fun(\", false);",
+                "Error at (: Expect function name.",
+            ),
+            (
+                "eval(\"fun f()\", true);",
+                "Error at end: Expect '{' before block.",
+            ),
+        ];
+        for (source, error) in sources {
+            vm.reset(false);
+            vm.e_out.flushed.clear();
+            assert_eq!(vm.interpret(source), Err(Error::Runtime));
+            assert_eq!(vm.e_out.flushed[0], format!("[line 1] {error}\n"));
+            assert_eq!(vm.e_out.flushed[1], "Could not compile eval source.\n");
+        }
+    }
+
+    #[test]
+    fn it_describes_classes_with_their_methods_and_superclasses() {
+        let out = TestOut::default();
+        let e_out = TestOut::default();
+        let mut vm = VM::new(out, e_out);
+        vm.interpret(
+            "class A { init(x) {} f() {} class make(a, b) {} }
+             class B < A { f() {} g(...rest) {} }
+             var a = A(1);",
+        )
+        .expect("Failed to run program");
+        assert_eq!(
+            vm.class_info("B").as_deref(),
+            Some("class B < A\n  f/0\n  g/0+\nclass A\n  f/0\n  init/1\n  static make/2")
+        );
+        assert_eq!(vm.class_info("a"), None);
+        assert_eq!(vm.class_info("missing"), None);
+    }
+
+    #[cfg(feature = "track-allocations")]
+    #[test]
+    fn it_faults_on_pointers_to_freed_objects() {
+        let out = TestOut::default();
+        let e_out = TestOut::default();
+        let mut vm = VM::new(out, e_out);
+        // Nothing holds on to the string, so loading the script frees it
+        let stale = vm.store.insert_string("stale".into());
+        vm.store.next_gc = 0;
+        vm.interpret("var s = \"fresh\";")
+            .expect("Failed to run program");
+        vm.store.globals.insert("stale".into(), stale.into());
+        assert_eq!(
+            vm.interpret("print stale;"),
+            Err(Error::InternalFault("Dangling pointer."))
+        );
+        assert_eq!(
+            vm.e_out.flushed,
+            vec![
+                "Dangling pointer to ObjString allocated outside any instruction, freed outside any instruction, accessed by OP_PRINT in <script> on line 1.\n",
+                "Internal VM fault: Dangling pointer.\n",
+            ]
+        );
+    }
+
+    #[test]
+    fn it_shares_inherited_methods_until_a_subclass_defines_one() {
+        let out = TestOut::default();
+        let e_out = TestOut::default();
+        let mut vm = VM::new(out, e_out);
+        vm.interpret(
+            "class A { f() { return \"A.f\"; } g() { return \"A.g\"; } class make() { return \"made\"; } }
+             class B < A {}
+             class C < A { g() { return \"C.g\"; } }
+             print B().f() + B().g() + C().f() + C().g() + A().g() + C.make();",
+        )
+        .expect("Failed to run program");
+        assert_eq!(vm.out.flushed, vec!["A.fA.gA.fC.gA.gmade\n"]);
+        let class = |name: &str| match vm.store.globals.get(&name.into()) {
+            Some(RuntimeValue::Class(class)) => *class,
+            _ => panic!("Expected class {name}"),
+        };
+        assert!(class("B").methods.is_shared());
+        assert!(!class("C").methods.is_shared());
+        assert!(class("C").statics.is_shared());
+        // C's override left the table it had shared with A alone
+        let g = "g".into();
+        assert_ne!(class("A").methods.get(&g), class("C").methods.get(&g));
+        assert_eq!(
+            vm.class_info("C").as_deref(),
+            Some("class C < A\n  g/0\nclass A\n  f/0\n  g/0\n  static make/0")
+        );
+    }
+
+    #[test]
+    fn it_interprets_a_script_from_a_reader() {
+        let out = TestOut::default();
+        let e_out = TestOut::default();
+        let mut vm = VM::new(out, e_out);
+        vm.set_integers(true);
+        let mut source = String::from("var total = 0; var one = 1;\n");
+        for i in 0..20000 {
+            source.push_str(&format!("total = total + one; // line {i}\n"));
+        }
+        source.push_str("print total;");
+        vm.interpret_reader(std::io::Cursor::new(source.into_bytes()))
+            .expect("Failed to run program");
+        assert_eq!(vm.out.flushed, vec!["20000\n"]);
+        assert_eq!(
+            vm.interpret_reader(std::io::Cursor::new(b"print ;".to_vec())),
+            Err(Error::Compile)
+        );
+    }
+
+    #[test]
+    fn it_shares_global_slots_across_scripts() {
+        let out = TestOut::default();
+        let e_out = TestOut::default();
+        let mut vm = VM::new(out, e_out);
+        vm.interpret("fun get() { return a; } var a = 1;")
+            .expect("Failed to run program");
+        vm.interpret("a = a + 1; print get();")
+            .expect("Failed to run program");
+        assert_eq!(vm.out.flushed, vec!["2\n"]);
+
+        vm.reset(true);
+        assert_eq!(vm.interpret("print a;"), Err(Error::Runtime));
+        assert_eq!(vm.e_out.flushed[0], "Undefined variable 'a'.\n");
+        assert_eq!(vm.interpret("a = 3;"), Err(Error::Runtime));
+        assert_eq!(vm.global("a"), None);
+        assert!(!vm.globals().any(|(name, _)| name == "a"));
+
+        vm.reset(false);
+        vm.interpret("var a = 4; print a; print clock() > 0;")
+            .expect("Failed to run program");
+        assert_eq!(vm.out.flushed[1..], ["4\n", "true\n"]);
+    }
+
+    #[test]
+    fn it_counts_work_in_stats() {
+        let out = TestOut::default();
+        let e_out = TestOut::default();
+        let mut vm = VM::new(out, e_out);
+        vm.interpret("var l = [1, 2, 3]; print \"a\" + \"b\";")
+            .expect("Failed to run program");
+        let stats = vm.stats();
+        // Constants 1, 2, 3, BuildList, DefineGlobal, two string constants,
+        // Add, Print, then the script's implicit Nil and Return
+        assert_eq!(stats.instructions, 11);
+        assert_eq!(stats.peak_stack_depth, 4);
+        assert_eq!(stats.allocations.lists, 1);
+        // "a", "b" and "ab", on top of the natives' names
+        assert_eq!(stats.allocations.strings, 3);
+        assert_eq!(stats.allocations.closures, 1);
+        assert_eq!(stats.allocations.natives, 25);
+    }
+
+    #[test]
+    fn it_captures_output_per_interpret() {
+        let out = TestOut::default();
+        let e_out = TestOut::default();
+        let mut vm = VM::new(out, e_out);
+        let (result, out, e_out) = vm.interpret_captured("print 1; print \"two\";");
+        assert_eq!(result, Ok(()));
+        assert_eq!(out, "1\ntwo\n");
+        assert_eq!(e_out, "");
+
+        let (result, out, e_out) = vm.interpret_captured("print 3;\nprint -nil;");
+        assert_eq!(result, Err(Error::Runtime));
+        assert_eq!(out, "3\n");
+        assert_eq!(e_out, "Operand must be a number.\n[line 2] in script\n");
+
+        vm.set_debug_info(true);
+        let (result, out, e_out) = vm.interpret_captured("print 5 @;");
+        assert_eq!(result, Err(Error::Compile));
+        assert_eq!(out, "");
+        assert_eq!(
+            e_out,
+            "[line 1] Error: Unexpected character '@'\n    print 5 @;\n            ^\n"
+        );
+        vm.set_debug_info(false);
+
+        vm.reset(false);
+        vm.interpret("print 4;").expect("Failed to run program");
+        assert_eq!(vm.out.flushed, vec!["4\n"]);
+        assert!(vm.e_out.flushed.is_empty());
+    }
+
+    #[test]
+    fn it_hands_output_to_hooks() {
+        use std::{cell::RefCell, rc::Rc};
+
+        let printed = Rc::new(RefCell::new(Vec::new()));
+        let errors = Rc::new(RefCell::new(Vec::new()));
+        let mut vm = VM::new(TestOut::default(), TestOut::default());
+        vm.on_print({
+            let printed = printed.clone();
+            move |line| printed.borrow_mut().push(line.to_string())
+        });
+        vm.on_error({
+            let errors = errors.clone();
+            move |info| errors.borrow_mut().push(info.clone())
+        });
+        let source = "fun f() {\n  return -nil;\n}\nprint 1;\nprint f();";
+        assert_eq!(vm.interpret(source), Err(Error::Runtime));
+        assert_eq!(*printed.borrow(), vec!["1"]);
+        let info = RuntimeErrorInfo {
+            message: "Operand must be a number.".to_string(),
+            caret: None,
+            trace: vec![
+                TraceFrame {
+                    line: 2,
+                    function: Some("f".to_string()),
+                },
+                TraceFrame {
+                    line: 5,
+                    function: None,
+                },
+            ],
+            recent_events: vec![],
+        };
+        assert_eq!(*errors.borrow(), vec![info.clone()]);
+        assert_eq!(
+            info.to_string(),
+            "Operand must be a number.\n[line 2] in f\n[line 5] in script\n"
+        );
+        assert!(vm.out.flushed.is_empty());
+        assert!(vm.e_out.flushed.is_empty());
+
+        // Capturing takes precedence over the hooks
+        vm.reset(false);
+        let (_, out, e_out) = vm.interpret_captured("print 2;\nprint -nil;");
+        assert_eq!(out, "2\n");
+        assert_eq!(e_out, "Operand must be a number.\n[line 2] in script\n");
+        assert_eq!(printed.borrow().len(), 1);
+        assert_eq!(errors.borrow().len(), 1);
+    }
+
+    #[test]
+    fn it_hands_diagnostics_to_a_hook_before_running() {
+        use std::{cell::RefCell, rc::Rc};
+
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let mut vm = VM::new(TestOut::default(), TestOut::default());
+        vm.set_redefinition_warnings(true);
+        vm.on_print({
+            let events = events.clone();
+            move |line| events.borrow_mut().push(line.to_string())
+        });
+        vm.on_compiled({
+            let events = events.clone();
+            move |diagnostics| {
+                for warning in &diagnostics.redefinitions {
+                    events.borrow_mut().push(warning.to_string());
+                }
+            }
+        });
+        vm.interpret("var a = 1; var a = 2; print a;")
+            .expect("Failed to run program");
+        let events = events.borrow();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0], vm.diagnostics().redefinitions[0].to_string());
+        assert_eq!(events[1], "2");
+    }
+
+    #[test]
+    fn it_runs_a_program_closing_over_parameters() {
+        let out = TestOut::default();
+        let e_out = TestOut::default();
+        let source = r#"
+            fun make(a, b) {
+                fun get() {
+                    return a + b;
+                }
+                return get;
+            }
+            var f = make(1, 2);
+            var g = make(10, 20);
+            print f();
+            print g();
+        "#;
+        let mut vm = VM::new(out, e_out);
+        vm.interpret(source).expect("Failed to run program");
+        assert_eq!(vm.out.flushed, vec!["3\n", "30\n"]);
+    }
+
+    #[test]
+    fn it_runs_a_program_closing_over_locals_returned_early() {
+        let out = TestOut::default();
+        let e_out = TestOut::default();
+        let source = r#"
+            fun outer(n) {
+                var before = 1;
+                {
+                    var inner = n * 2;
+                    fun get() { return inner + before; }
+                    if (n > 1) return get;
+                }
+                var after = 3;
+                fun late() { return before + after + n; }
+                return late;
+            }
+            fun first(limit) {
+                for (var i = 0; i < limit; i = i + 1) {
+                    var j = i * 10;
+                    fun get() { return j + limit; }
+                    if (i == 2) return get;
+                }
+                return nil;
+            }
+            var early = outer(5);
+            var late = outer(0);
+            var found = first(5);
+            // Reuse the stack slots the returned frames held
+            var a = 100; var b = 200; var c = 300;
+            print early();
+            print late();
+            print found();
+            print first(1);
+        "#;
+        let mut vm = VM::new(out, e_out);
+        vm.interpret(source).expect("Failed to run program");
+        assert_eq!(vm.out.flushed, vec!["11\n", "4\n", "25\n", "nil\n"]);
+        assert!(vm.store.open_upvalues.is_empty());
+        assert!(vm.store.value_stack.is_empty());
+    }
+
+    #[test]
+    fn it_shares_assigned_captures_between_sibling_closures() {
+        let out = TestOut::default();
+        let e_out = TestOut::default();
+        let source = r#"
+            var increment;
+            var read;
+            fun make() {
+                var count = 0;
+                fun inc() { count = count + 1; return count; }
+                fun get() { return count; }
+                increment = inc;
+                read = get;
+                // Open: the captures still point at this frame's slot
+                inc();
+                print count;
+                print get();
+                count = 10;
+                print get();
+            }
+            make();
+            // Closed: the captures share the closed cell
+            print increment();
+            print read();
+            print increment();
+            print read();
+
+            fun outer() {
+                var x = "before";
+                fun middle() {
+                    fun inner() { x = "inner"; }
+                    inner();
+                    return x;
+                }
+                print middle();
+                print x;
+            }
+            outer();
+        "#;
+        let mut vm = VM::new(out, e_out);
+        vm.interpret(source).expect("Failed to run program");
+        assert_eq!(
+            vm.out.flushed,
+            vec!["1\n", "1\n", "10\n", "11\n", "11\n", "12\n", "12\n", "inner\n", "inner\n"]
+        );
+        assert!(vm.store.open_upvalues.is_empty());
+    }
+
+    #[test]
+    fn it_lists_locals_declared_after_parameters() {
+        let source = r#"
+            fun f(a) {
+                var b = a + 1;
+                {
+                    var c = b + 1;
+                    fun g() { return c; }
+                    if (a > 0) {
+                        print locals();
+                        return g;
+                    }
+                }
+                print locals();
+                return nil;
+            }
+            print f(1)();
+            print f(0);
+        "#;
+        let mut vm = VM::new(TestOut::default(), TestOut::default());
+        vm.set_debug_info(true);
+        vm.interpret(source).expect("Failed to run program");
+        assert_eq!(
+            vm.out.flushed,
+            vec![
+                "[[a, 1], [b, 2], [c, 3], [g, <fn g>]]\n",
+                "3\n",
+                "[[a, 0], [b, 1]]\n",
+                "nil\n"
+            ]
+        );
+    }
+
+    #[test]
+    fn it_runs_a_program_with_variadic_functions() {
+        let out = TestOut::default();
+        let e_out = TestOut::default();
+        let source = r#"
+            fun log(prefix, ...args) {
+                print prefix;
+                print args;
+            }
+            log("none");
+            log("some", 1, "two", nil);
+
+            fun collector(...items) {
+                fun count() {
+                    var n = 0;
+                    for (var item in items) n = n + 1;
+                    return n;
+                }
+                return count;
+            }
+            print collector(1, 2, 3)();
+
+            class Greeter {
+                init(...names) {
+                    this.names = names;
+                }
+                greet(greeting, ...names) {
+                    fun greetOne(name) {
+                        print greeting + " " + name;
+                    }
+                    forEach(names, greetOne);
+                }
+            }
+            var g = Greeter("a", "b");
+            print g.names;
+            g.greet("hi", "c", "d");
+            var greet = g.greet;
+            greet("yo");
+        "#;
+        let mut vm = VM::new(out, e_out);
+        vm.interpret(source).expect("Failed to run program");
+        assert_eq!(
+            vm.out.flushed,
+            vec![
+                "none\n",
+                "[]\n",
+                "some\n",
+                "[1, two, nil]\n",
+                "3\n",
+                "[a, b]\n",
+                "hi c\n",
+                "hi d\n",
+            ]
+        );
     }
 
     #[test]
-    fn it_runs_an_empty_program() {
+    fn it_handles_a_runtime_error_missing_variadic_arguments() {
         let out = TestOut::default();
         let e_out = TestOut::default();
-        let source = "";
+        let source = r#"
+            fun f(a, b, ...rest) {}
+            f(1);
+        "#;
         let mut vm = VM::new(out, e_out);
-        vm.interpret(source).expect("Failed to run empty program");
-        assert!(vm.out.flushed.is_empty());
-        assert!(vm.e_out.flushed.is_empty());
+        assert_eq!(vm.interpret(source), Err(Error::Runtime));
+        assert_eq!(
+            vm.e_out.flushed,
+            vec![
+                "Expected at least 2 arguments but got 1.\n",
+                "[line 3] in ",
+                "script\n"
+            ]
+        );
     }
 
     #[test]
-    fn it_runs_a_program_with_a_single_expression_statement() {
+    fn it_runs_a_program_with_named_arguments() {
         let out = TestOut::default();
         let e_out = TestOut::default();
-        let source = "1;";
+        let source = r#"
+            fun point(x, y, z) {
+                print x;
+                print y;
+                print z;
+            }
+            point(z: 3, x: 1, y: 2);
+            point(1, z: 30, y: 20);
+
+            class Box(width, height) {
+                area(scale, ...extra) {
+                    return this.width * this.height * scale;
+                }
+            }
+            var box = Box(height: 2, width: 3);
+            print box.area(scale: 10);
+
+            class Sub < Box {
+                area(scale) {
+                    return super.area(scale: scale) + 1;
+                }
+            }
+            print Sub(1, 1).area(scale: 5);
+        "#;
         let mut vm = VM::new(out, e_out);
         vm.interpret(source).expect("Failed to run program");
-        assert!(vm.out.flushed.is_empty());
-        assert!(vm.e_out.flushed.is_empty());
+        assert_eq!(
+            vm.out.flushed,
+            vec!["1\n", "2\n", "3\n", "1\n", "20\n", "30\n", "60\n", "6\n"]
+        );
     }
 
     #[test]
-    fn it_runs_a_program_with_a_print_statement() {
+    fn it_handles_runtime_errors_with_named_arguments() {
+        let cases = [
+            ("fun f(a) {} f(b: 1);", "Unexpected argument 'b'.\n"),
+            (
+                "fun f(a) {} f(1, a: 1);",
+                "Argument 'a' is given more than once.\n",
+            ),
+            ("fun f(a, b) {} f(b: 1);", "Missing argument 'a'.\n"),
+            (
+                "same(a: 1, b: 2);",
+                "Named arguments can only be passed to Lox functions.\n",
+            ),
+        ];
+        for (source, message) in cases {
+            let out = TestOut::default();
+            let e_out = TestOut::default();
+            let mut vm = VM::new(out, e_out);
+            assert_eq!(vm.interpret(source), Err(Error::Runtime));
+            assert_eq!(vm.e_out.flushed, vec![message, "[line 1] in ", "script\n"]);
+        }
+    }
+
+    #[test]
+    fn it_lists_the_callers_locals_with_debug_info() {
         let out = TestOut::default();
         let e_out = TestOut::default();
-        let source = "print 1;";
+        let source = r#"
+            fun f(a, b) {
+                var c = a + b;
+                {
+                    var d = c * 2;
+                    print locals();
+                }
+                var e = 1;
+                print locals();
+            }
+            f(1, 2);
+            class A {
+                m(x) {
+                    print locals();
+                }
+            }
+            A().m(5);
+            for (var i in [7]) {
+                print locals();
+            }
+            print locals();
+        "#;
         let mut vm = VM::new(out, e_out);
+        vm.set_debug_info(true);
         vm.interpret(source).expect("Failed to run program");
+        assert_eq!(
+            vm.out.flushed,
+            vec![
+                "[[a, 1], [b, 2], [c, 3], [d, 6]]\n",
+                "[[a, 1], [b, 2], [c, 3], [e, 1]]\n",
+                "[[this, A instance], [x, 5]]\n",
+                "[[i, 7]]\n",
+                "[]\n",
+            ]
+        );
 
-        assert!(!vm.out.flushed.is_empty());
-        assert!(vm.e_out.flushed.is_empty());
-        assert_eq!(vm.out.flushed[0], "1\n".to_string());
+        vm.set_debug_info(false);
+        assert_eq!(vm.interpret("locals();"), Err(Error::Runtime));
+        assert_eq!(vm.e_out.flushed[0], "locals() requires debug info.\n");
     }
 
     #[test]
-    fn it_runs_a_program_with_scopes() {
+    fn it_points_at_runtime_errors_with_debug_info() {
         let out = TestOut::default();
         let e_out = TestOut::default();
-        let source = r#"
-            var a = 1; 
-            { 
-                var b = a; 
-                print b;
-            }
-            "#;
+        let source = r#"fun add(a, b) {
+  return a + b;
+}
+print add(1, nil);"#;
         let mut vm = VM::new(out, e_out);
-        vm.interpret(source).expect("Failed to run program");
-        assert!(!vm.out.flushed.is_empty());
-        assert!(vm.e_out.flushed.is_empty());
-        assert_eq!(vm.out.flushed[0], "1\n".to_string());
+        vm.set_debug_info(true);
+        assert_eq!(vm.interpret(source), Err(Error::Runtime));
+        assert_eq!(
+            vm.e_out.flushed,
+            vec![
+                "Operands must be two numbers or two strings.\n",
+                "      return a + b;\n               ^\n",
+                "[line 2] in ",
+                "add\n",
+                "[line 4] in ",
+                "script\n"
+            ]
+        );
     }
 
     #[test]
-    fn it_runs_a_program_with_functions() {
+    fn it_records_line_coverage() {
         let out = TestOut::default();
         let e_out = TestOut::default();
-        let source = r#"
-            fun foo(a, b, c) { 
-                print a + b + c; 
-            } 
-            print foo(1, 2, 3);
-        "#;
+        let source = "var a = 1;\nif (a > 1) {\n  print a;\n}\nprint -a;";
         let mut vm = VM::new(out, e_out);
+        assert_eq!(vm.coverage(), None);
+        vm.enable_coverage();
         vm.interpret(source).expect("Failed to run program");
-        assert!(!vm.out.flushed.is_empty());
-        assert!(vm.e_out.flushed.is_empty());
-        assert_eq!(vm.out.flushed.len(), 2);
-        assert_eq!(vm.out.flushed[0], "6\n".to_string());
-        assert_eq!(vm.out.flushed[1], "nil\n".to_string());
+        let coverage = vm.coverage().expect("Missing coverage");
+        assert_eq!(
+            coverage.listing(source),
+            "    +:    1:var a = 1;\n    +:    2:if (a > 1) {\n#####:    3:  print a;\n    +:    4:}\n    +:    5:print -a;\n"
+        );
     }
 
     #[test]
-    fn it_runs_a_program_with_control_flow() {
+    fn it_runs_a_program_with_integers() {
         let out = TestOut::default();
         let e_out = TestOut::default();
-        let source = r#"
-            var a = 1; 
-            if (true) { 
-                a = 2; 
-            } else { 
-                a = 3; 
-            } 
-            print a; 
-            if (false) { 
-                a = 4; 
-            } else { 
-                a = 6; 
-            } 
-            print a;
-        "#;
         let mut vm = VM::new(out, e_out);
+        vm.set_integers(true);
+        let source = "print 7 - 2 * 3; print 7 / 2; print 1 + 0.5; print -3 < 2; print 2 == 2.0; print [1, 2, 3][1]; print contains([1.0], 1); print 0.1 + 0.2 == 0.3;";
         vm.interpret(source).expect("Failed to run program");
-        assert!(!vm.out.flushed.is_empty());
-        assert!(vm.e_out.flushed.is_empty());
-        assert_eq!(vm.out.flushed.len(), 2);
-        assert_eq!(vm.out.flushed[0], "2\n".to_string());
-        assert_eq!(vm.out.flushed[1], "6\n".to_string());
+        assert_eq!(
+            vm.out.flushed,
+            vec![
+                "1\n",
+                "3.500000\n",
+                "1.500000\n",
+                "true\n",
+                "true\n",
+                "2\n",
+                "true\n",
+                "false\n"
+            ]
+        );
+        assert_eq!(vm.global("x"), None);
+        vm.interpret("var x = 4 * 5;")
+            .expect("Failed to run program");
+        assert_eq!(vm.global("x"), Some(LoxValue::Int(20)));
     }
 
     #[test]
-    fn it_runs_a_program_with_a_loop() {
+    fn it_runs_a_program_with_options() {
+        let mut options = VmOptions::default();
+        options.compile.integers = true;
+        options.coverage = true;
+        let mut vm = VM::with_options(TestOut::default(), TestOut::default(), options);
+        vm.interpret("print 7 / 2;\nprint 6 / 2;")
+            .expect("Failed to run program");
+        assert_eq!(vm.out.flushed, vec!["3.500000\n", "3\n"]);
+        assert!(vm.coverage().is_some());
+    }
+
+    #[test]
+    fn it_runs_a_dialect_unless_conforming() {
+        let mut keywords = Keywords::canonical();
+        assert!(keywords.alias("function", "fun"));
+        assert!(keywords.alias("let", "var"));
+        let mut vm = VM::new(TestOut::default(), TestOut::default());
+        vm.set_keywords(keywords);
+        vm.interpret("function twice(x) { return x * 2; }\nlet a = twice(2);\nprint a;")
+            .expect("Failed to run program");
+        assert_eq!(vm.out.flushed, vec!["4\n"]);
+        vm.set_conformance(true);
+        assert!(vm.keywords().is_canonical());
+        assert_eq!(vm.interpret("let b = 1;"), Err(Error::Compile));
+    }
+
+    #[test]
+    fn it_reports_integer_overflow() {
         let out = TestOut::default();
         let e_out = TestOut::default();
-        let source = r#"
-            for (var b = 1; b < 4; b = b + 1) {
-                print b;
-            }
-        "#;
         let mut vm = VM::new(out, e_out);
-        vm.interpret(source).expect("Failed to run program");
-        assert!(!vm.out.flushed.is_empty());
-        assert!(vm.e_out.flushed.is_empty());
-        assert_eq!(vm.out.flushed.len(), 3);
-        assert_eq!(vm.out.flushed[0], "1\n".to_string());
-        assert_eq!(vm.out.flushed[1], "2\n".to_string());
-        assert_eq!(vm.out.flushed[2], "3\n".to_string());
+        vm.set_integers(true);
+        assert_eq!(
+            vm.interpret("print 9223372036854775807 + 1;"),
+            Err(Error::Runtime)
+        );
+        assert_eq!(vm.e_out.flushed[0], "Integer overflow.\n");
+        assert_eq!(
+            vm.interpret("print 99999999999999999999;"),
+            Err(Error::Compile)
+        );
     }
 
     #[test]
-    fn it_runs_a_program_with_negation() {
+    fn it_keeps_integer_literals_as_floats_by_default() {
         let out = TestOut::default();
         let e_out = TestOut::default();
-        let source = r#"
-            print -1;
-        "#;
         let mut vm = VM::new(out, e_out);
-        vm.interpret(source).expect("Failed to run program");
-        assert!(!vm.out.flushed.is_empty());
-        assert!(vm.e_out.flushed.is_empty());
-        assert_eq!(vm.out.flushed.len(), 1);
-        assert_eq!(vm.out.flushed[0], "-1\n".to_string());
+        vm.interpret("var x = 9223372036854775807 + 1;")
+            .expect("Failed to run program");
+        assert_eq!(
+            vm.global("x"),
+            Some(LoxValue::Number(9223372036854775808.0))
+        );
     }
 
     #[test]
-    fn it_runs_a_program_with_simple_binary_ops() {
+    fn it_runs_a_program_with_bitwise_operators() {
         let out = TestOut::default();
         let e_out = TestOut::default();
-        let source = r#"
-            print 1 + 2; 
-            print 3 * 4; 
-            print 5 / 6; 
-            print 7 - 8; 
-            print 1 == 2; 
-            print 1 == 1; 
-            print 1 != 1; 
-            print 1 != 2; 
-            print 1 < 1; 
-            print 1 < 2; 
-            print 1 < 0; 
-            print 1 <= 2; 
-            print 1 <= 1; 
-            print 1 <= 0; 
-            print 1 > 2; 
-            print 1 > 1; 
-            print 1 > 0; 
-            print 1 >= 2; 
-            print 1 >= 1; 
-            print 1 >= 0; 
-            print true and true; 
-            print true and false; 
-            print true or true; 
-            print true or false; 
-            print false or false; 
-            print "a" + "b";
-        "#;
         let mut vm = VM::new(out, e_out);
+        vm.set_integers(true);
+        let source = "print 12 & 10; print 12 | 10; print 12 ^ 10; print ~5; print 1 << 4; print -16 >> 2; print 1 | 2 == 3; print 1 + 1 << 2; print 6 & 3 ^ 1;";
         vm.interpret(source).expect("Failed to run program");
-        assert!(!vm.out.flushed.is_empty());
-        assert!(vm.e_out.flushed.is_empty());
-        assert_eq!(vm.out.flushed[0], "3\n".to_string()); // 1 + 2
-        assert_eq!(vm.out.flushed[1], "12\n".to_string()); // 3 * 4
-        assert_eq!(vm.out.flushed[2], "0.833333\n".to_string()); // 5 / 6
-        assert_eq!(vm.out.flushed[3], "-1\n".to_string()); // 7 - 8
-        assert_eq!(vm.out.flushed[4], "false\n".to_string()); // 1 == 2
-        assert_eq!(vm.out.flushed[5], "true\n".to_string()); // 1 == 1
-        assert_eq!(vm.out.flushed[6], "false\n".to_string()); // 1 != 1
-        assert_eq!(vm.out.flushed[7], "true\n".to_string()); // 1 != 2
-        assert_eq!(vm.out.flushed[8], "false\n".to_string()); // 1 < 1
-        assert_eq!(vm.out.flushed[9], "true\n".to_string()); // 1 < 2
-        assert_eq!(vm.out.flushed[10], "false\n".to_string()); // 1 < 0
-        assert_eq!(vm.out.flushed[11], "true\n".to_string()); // 1 <= 2
-        assert_eq!(vm.out.flushed[12], "true\n".to_string()); // 1 <= 1
-        assert_eq!(vm.out.flushed[13], "false\n".to_string()); // 1 <= 0
-        assert_eq!(vm.out.flushed[14], "false\n".to_string()); // 1 > 2
-        assert_eq!(vm.out.flushed[15], "false\n".to_string()); // 1 > 1
-        assert_eq!(vm.out.flushed[16], "true\n".to_string()); // 1 > 0
-        assert_eq!(vm.out.flushed[17], "false\n".to_string()); // 1 >= 2
-        assert_eq!(vm.out.flushed[18], "true\n".to_string()); // 1 >= 1
-        assert_eq!(vm.out.flushed[19], "true\n".to_string()); // 1 >= 0
-        assert_eq!(vm.out.flushed[20], "true\n".to_string()); // true and true
-        assert_eq!(vm.out.flushed[21], "false\n".to_string()); // true and false
-        assert_eq!(vm.out.flushed[22], "true\n".to_string()); // true or true
-        assert_eq!(vm.out.flushed[23], "true\n".to_string()); // true or false
-        assert_eq!(vm.out.flushed[24], "false\n".to_string()); // false or false
-        assert_eq!(vm.out.flushed[25], "ab\n".to_string()); // "a" + "b"
+        assert_eq!(
+            vm.out.flushed,
+            vec!["8\n", "14\n", "6\n", "-6\n", "16\n", "-4\n", "true\n", "8\n", "3\n"]
+        );
+        vm.interpret("var x = 12.0 & 10;")
+            .expect("Failed to run program");
+        assert_eq!(vm.global("x"), Some(LoxValue::Number(8.0)));
     }
 
     #[test]
-    fn it_runs_a_program_with_a_closure() {
+    fn it_reports_bitwise_errors() {
         let out = TestOut::default();
         let e_out = TestOut::default();
-        let source = r#"
-            fun makeClosure(value) { 
-                fun closure() { 
-                    print value; 
-                } 
-                return closure; 
-            } 
-            var doughnut = makeClosure("doughnut"); 
-            var bagel = makeClosure("bagel"); 
-            doughnut(); 
-            bagel();
-        "#;
         let mut vm = VM::new(out, e_out);
-        vm.interpret(source).expect("Failed to run program");
-        assert!(!vm.out.flushed.is_empty());
-        assert_eq!(vm.out.flushed.len(), 2);
-        assert!(vm.e_out.flushed.is_empty());
-        assert_eq!(vm.out.flushed[0], "doughnut\n".to_string());
-        assert_eq!(vm.out.flushed[1], "bagel\n".to_string());
+        vm.set_integers(true);
+        for (source, message) in [
+            ("print 1.5 & 1;", "Operands must be integers.\n"),
+            ("print \"a\" | 1;", "Operands must be integers.\n"),
+            ("print ~nil;", "Operand must be an integer.\n"),
+            ("print 1 << 64;", "Shift amount must be between 0 and 63.\n"),
+            ("print 1 >> -1;", "Shift amount must be between 0 and 63.\n"),
+        ] {
+            assert_eq!(vm.interpret(source), Err(Error::Runtime));
+            assert_eq!(vm.e_out.flushed[0], message);
+            vm.e_out.flushed.clear();
+        }
     }
 
     #[test]
-    fn it_runs_a_program_with_a_closure_with_inner_assignment() {
+    fn it_prints_scientific_notation_literals() {
         let out = TestOut::default();
         let e_out = TestOut::default();
-        let source = r#"
-            fun makeClosure(value) { 
-                fun closure(b) { 
-                    value = b; 
-                    print value;
-                } 
-                return closure; 
-            } 
-            var breakfast = "eggs";
-            var doughnut = makeClosure(breakfast); 
-            var bagel = makeClosure(breakfast); 
-            doughnut("doughnut"); 
-            bagel("bagel");
-        "#;
         let mut vm = VM::new(out, e_out);
+        let source =
+            "print 1e9; print 2.5e-3; print 6E+2; print 1e3 == 1000; print 1.5e300 * 1e10;";
         vm.interpret(source).expect("Failed to run program");
-        assert!(!vm.out.flushed.is_empty());
-        assert_eq!(vm.out.flushed.len(), 2);
-        assert!(vm.e_out.flushed.is_empty());
-        assert_eq!(vm.out.flushed[0], "doughnut\n".to_string());
-        assert_eq!(vm.out.flushed[1], "bagel\n".to_string());
+        assert_eq!(
+            vm.out.flushed,
+            vec!["1000000000\n", "0.002500\n", "600\n", "true\n", "inf\n"]
+        );
+        // Tiny and huge numbers print with an exponent that reads back as
+        // the same number
+        for x in ["1e-9", "1e300", "-2.5e-300"] {
+            vm.out.flushed.clear();
+            vm.interpret(&format!("print {x};"))
+                .expect("Failed to run program");
+            let printed = vm.out.flushed[0].trim_end().to_string();
+            assert!(printed.len() < 24, "{x} printed as {printed}");
+            vm.out.flushed.clear();
+            vm.interpret(&format!("print {x} == {printed};"))
+                .expect("Failed to run program");
+            assert_eq!(vm.out.flushed, vec!["true\n"], "{x} printed as {printed}");
+        }
+        vm.set_integers(true);
+        vm.interpret("var x = 1e3;").expect("Failed to run program");
+        assert_eq!(vm.global("x"), Some(LoxValue::Number(1000.0)));
+        assert_eq!(vm.global("x").unwrap().to_string(), "1000");
     }
 
     #[test]
-    fn it_runs_a_program_with_a_deelpy_nested_closure() {
+    fn it_prints_cyclic_lists() {
         let out = TestOut::default();
         let e_out = TestOut::default();
-        let source = r#"
-            var value;
-            fun makeClosure() { 
-                fun closure(b) { 
-                    fun deepClosure(c) {
-                        value = b + c;
-                    }
-                    return deepClosure;
-                } 
-                return closure; 
-            }
-            {
-                var deep = makeClosure();
-                deep(1)(2);
-                print value;
-            }
-        "#;
         let mut vm = VM::new(out, e_out);
+        let source = r#"
+            class Node { init(value) { this.value = value; this.children = []; } }
+            var root = Node(1);
+            var child = Node(2);
+            root.children = [child.value, child.children];
+            child.children = [root.children];
+            root.children[1] = child.children;
+            print root.children;
+            print [root.children, root.children];
+        "#;
         vm.interpret(source).expect("Failed to run program");
-        assert!(!vm.out.flushed.is_empty());
-        assert_eq!(vm.out.flushed.len(), 1);
-        assert!(vm.e_out.flushed.is_empty());
-        assert_eq!(vm.out.flushed[0], "3\n".to_string());
+        assert_eq!(
+            vm.out.flushed,
+            vec!["[2, [[...]]]\n", "[[2, [[...]]], [2, [[...]]]]\n"]
+        );
     }
 
     #[test]
-    fn it_runs_a_program_with_a_class_definition() {
+    fn it_runs_a_program_comparing_strings() {
         let out = TestOut::default();
         let e_out = TestOut::default();
         let source = r#"
-            class TestClass {} 
-            print TestClass;
+            var a = "ab";
+            print a == "a" + "b";
+            print same(a, "a" + "b");
+            print a == "ba";
         "#;
         let mut vm = VM::new(out, e_out);
         vm.interpret(source).expect("Failed to run program");
-        assert!(!vm.out.flushed.is_empty());
-        assert_eq!(vm.out.flushed.len(), 1);
-        assert!(vm.e_out.flushed.is_empty());
-        assert_eq!(vm.out.flushed[0], "TestClass\n".to_string());
+        assert_eq!(vm.out.flushed, vec!["true\n", "true\n", "false\n"]);
     }
 
     #[test]
-    fn it_runs_a_program_with_a_class_instance() {
+    fn it_runs_a_program_with_pattern_natives() {
         let out = TestOut::default();
         let e_out = TestOut::default();
         let source = r#"
-            class TestClass {} 
-            print TestClass();
+            print match("order 66 now", "[0-9]+");
+            print match("no digits", "\d");
+            print replace("a  b   c", " +", " ");
         "#;
         let mut vm = VM::new(out, e_out);
         vm.interpret(source).expect("Failed to run program");
-        assert!(!vm.out.flushed.is_empty());
-        assert_eq!(vm.out.flushed.len(), 1);
         assert!(vm.e_out.flushed.is_empty());
-        assert_eq!(vm.out.flushed[0], "TestClass instance\n".to_string());
+        assert_eq!(vm.out.flushed, vec!["66\n", "nil\n", "a b c\n"]);
     }
 
     #[test]
-    fn it_runs_a_program_with_a_class_initializer() {
+    fn it_reports_a_runtime_error_invalid_pattern() {
+        let out = TestOut::default();
+        let e_out = TestOut::default();
+        let source = r#"match("a", "[a");"#;
+        let mut vm = VM::new(out, e_out);
+        vm.interpret(source).expect_err("Expected runtime error");
+        assert_eq!(
+            vm.e_out.flushed[0],
+            "Invalid pattern '[a': Unterminated character class.\n"
+        );
+    }
+
+    #[test]
+    fn it_runs_a_program_with_higher_order_natives() {
         let out = TestOut::default();
         let e_out = TestOut::default();
         let source = r#"
-            class TestClass { 
-                init() { 
-                    this.a = 1; 
-                    this.b = "b"; 
-                } 
-            } 
-            var instance = TestClass(); 
-            print instance.a; 
-            print instance.b;
+            var total = 0;
+            fun add(x) {
+                total = total + x;
+            }
+            fun double(x) {
+                return x * 2;
+            }
+            fun even(x) {
+                return x == 2 or x == 4;
+            }
+            class Box {
+                init(value) {
+                    this.value = value;
+                }
+            }
+            var numbers = [1, 2, 3, 4];
+            forEach(numbers, add);
+            print total;
+            print map(numbers, double);
+            print filter(numbers, even);
+            print map(map(numbers, Box), double);
         "#;
         let mut vm = VM::new(out, e_out);
-        vm.interpret(source).expect("Failed to run program");
-        assert!(!vm.out.flushed.is_empty());
-        assert_eq!(vm.out.flushed.len(), 2);
-        assert!(vm.e_out.flushed.is_empty());
-        assert_eq!(vm.out.flushed[0], "1\n".to_string());
-        assert_eq!(vm.out.flushed[1], "b\n".to_string());
+        vm.interpret(source).expect_err("Expected runtime error");
+        assert_eq!(vm.out.flushed.len(), 3);
+        assert_eq!(vm.out.flushed[0], "10\n");
+        assert_eq!(vm.out.flushed[1], "[2, 4, 6, 8]\n");
+        assert_eq!(vm.out.flushed[2], "[2, 4]\n");
+        assert_eq!(vm.e_out.flushed.len(), 5);
+        assert_eq!(vm.e_out.flushed[0], "Operands must be numbers.\n");
+        assert_eq!(vm.e_out.flushed[1], "[line 7] in ");
+        assert_eq!(vm.e_out.flushed[2], "double\n");
+        assert_eq!(vm.e_out.flushed[3], "[line 22] in ");
+        assert_eq!(vm.e_out.flushed[4], "script\n");
     }
 
     #[test]
-    fn it_runs_a_program_with_a_class_method() {
+    fn it_runs_a_program_with_sorting_natives() {
         let out = TestOut::default();
         let e_out = TestOut::default();
         let source = r#"
-            class TestClass { 
-                init(c) { 
-                    this.c = c; 
-                } 
-                m(a, b) { 
-                    return a + b + this.c; 
-                } 
-            } 
-            var instance = TestClass(5); 
-            print instance.m(1, 2);
+            class Pair {
+                init(key, name) {
+                    this.key = key;
+                    this.name = name;
+                }
+            }
+            fun byKey(a, b) {
+                return a.key - b.key;
+            }
+            fun name(pair) {
+                return pair.name;
+            }
+            fun descending(a, b) {
+                return b - a;
+            }
+            var numbers = [3, 1.5, -2, 10, 1.5];
+            print sort(numbers);
+            print numbers;
+            print sort(["pear", "apple", "fig"]);
+            print sortBy(numbers, descending);
+            var pairs = [Pair(2, "a"), Pair(1, "b"), Pair(2, "c"), Pair(1, "d")];
+            print map(sortBy(pairs, byKey), name);
+            print sort([]);
         "#;
         let mut vm = VM::new(out, e_out);
         vm.interpret(source).expect("Failed to run program");
-        assert!(!vm.out.flushed.is_empty());
-        assert_eq!(vm.out.flushed.len(), 1);
-        assert!(vm.e_out.flushed.is_empty());
-        assert_eq!(vm.out.flushed[0], "8\n".to_string());
+        assert_eq!(
+            vm.out.flushed,
+            vec![
+                "[-2, 1.5, 1.5, 3, 10]\n",
+                "[3, 1.5, -2, 10, 1.5]\n",
+                "[apple, fig, pear]\n",
+                "[10, 3, 1.5, 1.5, -2]\n",
+                "[b, d, a, c]\n",
+                "[]\n",
+            ]
+        );
     }
 
     #[test]
-    fn it_runs_a_program_with_a_sub_class_super_method() {
+    fn it_reports_sorting_errors() {
+        for (source, message) in [
+            (
+                "sort([1, \"a\"]);",
+                "sort() can only compare two numbers or two strings.\n",
+            ),
+            ("sort(1);", "sort() expects a list as its first argument.\n"),
+            (
+                "fun f(a, b) { return nil; } sortBy([1, 2], f);",
+                "sortBy() expects the comparator to return a number.\n",
+            ),
+            (
+                "fun f(a, b) { return a.key; } sortBy([1, 2], f);",
+                "Only instances have fields.\n",
+            ),
+        ] {
+            let mut vm = VM::new(TestOut::default(), TestOut::default());
+            assert_eq!(vm.interpret(source), Err(Error::Runtime), "{source}");
+            assert_eq!(vm.e_out.flushed[0], message, "{source}");
+        }
+        let mut vm = VM::new(TestOut::default(), TestOut::default());
+        vm.interpret("fun f(a, b) { return a.key; } sortBy([1, 2], f);")
+            .expect_err("Expected runtime error");
+        assert_eq!(
+            vm.e_out.flushed[1..],
+            ["[line 1] in ", "f\n", "[line 1] in ", "script\n"]
+        );
+    }
+
+    #[test]
+    fn it_reports_field_counts_and_sizes() {
+        let mut vm = VM::new(TestOut::default(), TestOut::default());
+        vm.interpret(
+            "class P { init() { this.x = 1; this.y = 2; } }
+             var p = P();
+             var small = [1];
+             var large = [1, 2, 3, 4, 5, 6, 7, 8, 9];
+             print fieldCount(p);
+             print fieldCount(P());
+             print sizeOf(large) > sizeOf(small);
+             print sizeOf(nil) == sizeOf(1);",
+        )
+        .expect("Failed to run program");
+        assert_eq!(vm.out.flushed, vec!["2\n", "2\n", "true\n", "true\n"]);
+        let RuntimeValue::List(large) = vm.store.globals.get(&"large".into()).copied().unwrap()
+        else {
+            panic!("Expected a list");
+        };
+        assert_eq!(vm.global_size("large"), Some(HeapSize::size(&*large)));
+        assert_eq!(vm.global_size("missing"), None);
+
+        assert_eq!(vm.interpret("fieldCount(1);"), Err(Error::Runtime));
+        assert_eq!(
+            vm.e_out.flushed[0],
+            "fieldCount() expects an instance as its first argument.\n"
+        );
+    }
+
+    #[test]
+    fn it_runs_a_program_with_a_string_builder() {
+        let source = r#"
+            var builder = stringBuilder();
+            for (var i = 0; i < 3; i = i + 1) {
+                append(append(builder, "ab"), "c");
+            }
+            print toString(builder);
+            print toString(stringBuilder()) == "";
+            print builder;
+
+            // Copies are builders of their own
+            var b = append(stringBuilder(), "x");
+            var c = copy(b);
+            var d = deepCopy([b, b]);
+            append(c, "y");
+            append(d[0], "z");
+            print toString(b) + " " + toString(c) + " " + toString(d[1]);
+            print same(d[0], d[1]);
+        "#;
+        let mut vm = VM::new(TestOut::default(), TestOut::default());
+        vm.interpret(source).expect("Failed to run program");
+        assert_eq!(
+            vm.out.flushed,
+            vec![
+                "abcabcabc\n",
+                "true\n",
+                "<string builder>\n",
+                "x xy xz\n",
+                "true\n"
+            ]
+        );
+        let errors = [
+            (
+                "append(stringBuilder(), 1);",
+                "append() expects a string as its second argument.\n",
+            ),
+            (
+                "append([], \"a\");",
+                "append() expects a string builder as its first argument.\n",
+            ),
+            (
+                "toString([\"a\"]);",
+                "toString() expects a string builder as its first argument.\n",
+            ),
+        ];
+        for (source, error) in errors {
+            vm.e_out.flushed.clear();
+            assert_eq!(vm.interpret(source), Err(Error::Runtime));
+            assert_eq!(vm.e_out.flushed[0], error);
+        }
+    }
+
+    #[test]
+    fn it_runs_a_program_checking_features() {
+        let source = r#"
+            fun enabled(name) {
+                for (var pair in features()) if (pair[0] == name) return pair[1];
+                return nil;
+            }
+            print enabled("lists");
+            print enabled("integers");
+            print enabled("closures");
+        "#;
+        let mut vm = VM::new(TestOut::default(), TestOut::default());
+        vm.interpret(source).expect("Failed to run program");
+        assert_eq!(vm.out.flushed, vec!["true\n", "false\n", "nil\n"]);
+        vm.set_integers(true);
+        assert!(vm.features().is_enabled("integers"));
+        vm.interpret("print enabled(\"integers\");")
+            .expect("Failed to run program");
+        assert_eq!(vm.out.flushed[3], "true\n");
+    }
+
+    #[test]
+    fn it_runs_a_program_with_object_ids() {
+        let source = r#"
+            class A {}
+            class B {}
+            var classes = [A, B];
+            fun lookup(c) {
+                for (var i = 0; i < 2; i = i + 1) {
+                    if (id(classes[i]) == id(c)) return i;
+                }
+                return nil;
+            }
+            print lookup(B);
+            print id(A) == id(A);
+            print id(A()) == id(A());
+            print id("a") == id("a");
+        "#;
+        let mut vm = VM::new(TestOut::default(), TestOut::default());
+        vm.interpret(source).expect("Failed to run program");
+        assert_eq!(vm.out.flushed, vec!["1\n", "true\n", "false\n", "true\n"]);
+        assert_eq!(vm.interpret("id(1);"), Err(Error::Runtime));
+        assert_eq!(
+            vm.e_out.flushed[0],
+            "id() expects an object as its first argument.\n"
+        );
+    }
+
+    #[test]
+    fn it_runs_a_program_with_copy_natives() {
         let out = TestOut::default();
         let e_out = TestOut::default();
         let source = r#"
-            class ParentClass { 
-                init(a) { 
-                    this.a = a; 
-                } 
-                m() { 
-                    print this.a; 
-                } 
-            } 
-            class ChildClass < ParentClass { 
-                m() { 
-                    super.m(); 
-                    print this.a + 1; 
+            class Point {
+                init() {
+                    this.tags = ["a"];
+                    this.self = this;
                 }
-            } 
-            var child = ChildClass(1); 
-            child.m();
+            }
+            var p = Point();
+            var shallow = copy(p);
+            shallow.tags[0] = "b";
+            print p.tags;
+            print same(shallow, p) or !same(shallow.self, p);
+            var deep = deepCopy(p);
+            deep.tags[0] = "c";
+            print p.tags;
+            print same(deep.self, deep);
+            var shared = [1];
+            var both = deepCopy([shared, shared]);
+            print same(both[0], both[1]) and !same(both[0], shared);
+            print same(copy(Point), Point) and same(deepCopy(clock), clock);
+            print copy(1.5);
         "#;
         let mut vm = VM::new(out, e_out);
         vm.interpret(source).expect("Failed to run program");
-        assert!(!vm.out.flushed.is_empty());
-        assert_eq!(vm.out.flushed.len(), 2);
-        assert!(vm.e_out.flushed.is_empty());
-        assert_eq!(vm.out.flushed[0], "1\n".to_string());
-        assert_eq!(vm.out.flushed[1], "2\n".to_string());
+        assert_eq!(
+            vm.out.flushed,
+            vec![
+                "[b]\n",
+                "false\n",
+                "[b]\n",
+                "true\n",
+                "true\n",
+                "true\n",
+                "1.500000\n"
+            ]
+        );
     }
 
     #[test]
-    fn it_runs_a_program_with_a_sub_class_super_property() {
+    fn it_releases_native_handles_when_their_scope_closes() {
+        let mut vm = VM::new(TestOut::default(), TestOut::default());
+        let scope = NativeContext::open_scope(&vm);
+        let kept = NativeContext::new_list(&mut vm);
+        let inner = NativeContext::open_scope(&vm);
+        NativeContext::new_string(&mut vm, "released".into());
+        NativeContext::root(&mut vm, RuntimeValue::Number(1.0));
+        assert_eq!(vm.store.value_stack.len(), 3);
+        NativeContext::close_scope(&mut vm, inner);
+        assert_eq!(vm.store.value_stack, vec![kept.into()]);
+        NativeContext::close_scope(&mut vm, scope);
+        assert!(vm.store.value_stack.is_empty());
+
+        // Copying a thousand lists only ever holds a handful of handles
+        vm.interpret("var l = nil; for (var i = 0; i < 1000; i = i + 1) l = [i, l]; deepCopy(l);")
+            .expect("Failed to run program");
+        assert!(vm.stats().peak_stack_depth < 16);
+    }
+
+    #[test]
+    fn it_deep_copies_long_chains_across_collections() {
         let out = TestOut::default();
         let e_out = TestOut::default();
+        // Enough lists to run the collector while the copy is being built
         let source = r#"
-            class ParentClass { 
-                init(a) { 
-                    this.a = a; 
-                } 
-                m() { 
-                    print this.a; 
-                } 
-            } 
-
-            class ChildClass < ParentClass { 
-                m() { 
-                    super.m(); 
-                    print super.m; 
-                } 
-            } 
-            var child = ChildClass(1); 
-            child.m();
+            var chain = nil;
+            for (var i = 0; i < 50000; i = i + 1) {
+                chain = [i, chain];
+            }
+            var copied = deepCopy(chain);
+            var total = 0;
+            while (copied != nil) {
+                total = total + copied[0];
+                copied = copied[1];
+            }
+            print total;
         "#;
         let mut vm = VM::new(out, e_out);
         vm.interpret(source).expect("Failed to run program");
-        assert!(!vm.out.flushed.is_empty());
-        assert_eq!(vm.out.flushed.len(), 2);
-        assert!(vm.e_out.flushed.is_empty());
-        assert_eq!(vm.out.flushed[0], "1\n".to_string());
-        assert_eq!(vm.out.flushed[1], "<fn m>\n".to_string());
+        assert_eq!(vm.out.flushed, vec!["1249975000\n"]);
+        assert!(vm.stats().gc.collections > 0);
     }
 
     #[test]
-    fn it_runs_a_program_with_a_native_function() {
+    fn it_reports_a_runtime_error_native_arity() {
         let out = TestOut::default();
         let e_out = TestOut::default();
-        let source = "print clock();";
+        let source = "map([1]);";
         let mut vm = VM::new(out, e_out);
-        let now = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_secs_f64()
-            .round();
-        vm.interpret(source).expect("Failed to run program");
-        assert!(!vm.out.flushed.is_empty());
-        assert!(vm.e_out.flushed.is_empty());
-        let printed_time = vm.out.flushed[0].trim().parse::<f64>().unwrap().round();
-        assert!((printed_time - 1.0..printed_time + 1.0).contains(&now));
+        vm.interpret(source).expect_err("Expected runtime error");
+        assert_eq!(vm.e_out.flushed[0], "Expected 2 arguments but got 1.\n");
     }
 
     #[test]
-    fn it_runs_a_program_with_a_native_function_print() {
+    fn it_reports_a_runtime_error_native_argument_type() {
         let out = TestOut::default();
         let e_out = TestOut::default();
-        let source = "print clock;";
+        let source = "fun f(x) {} forEach(1, f);";
         let mut vm = VM::new(out, e_out);
-        vm.interpret(source).expect("Failed to run program");
-        assert!(!vm.out.flushed.is_empty());
-        assert_eq!(vm.out.flushed.len(), 1);
-        assert!(vm.e_out.flushed.is_empty());
-        assert_eq!(vm.out.flushed[0], "<native fn>\n");
+        vm.interpret(source).expect_err("Expected runtime error");
+        assert_eq!(
+            vm.e_out.flushed[0],
+            "forEach() expects a list as its first argument.\n"
+        );
+    }
+
+    #[test]
+    fn it_reports_recent_events_with_a_runtime_error() {
+        let out = TestOut::default();
+        let e_out = TestOut::default();
+        let mut vm = VM::with_options(
+            out,
+            e_out,
+            VmOptions {
+                flight_recorder: Some(4),
+                ..Default::default()
+            },
+        );
+        let source = "fun f(x) {\n  return -x;\n}\nf(\"a\");";
+        assert_eq!(vm.interpret(source), Err(Error::Runtime));
+        assert_eq!(
+            vm.e_out.flushed,
+            vec![
+                "Operand must be a number.\n",
+                "[line 2] in ",
+                "f\n",
+                "[line 4] in ",
+                "script\n",
+                "Recent events, oldest first:\n",
+                "  OP_CALL on line 4 with [ <script> ][ <fn f arity=1 upvalues=0> ][ a ]\n",
+                "  call <fn f> with 1 arguments at depth 2\n",
+                "  OP_GET_LOCAL on line 2 with [ <script> ][ <fn f arity=1 upvalues=0> ][ a ]\n",
+                "  OP_NEGATE on line 2 with [ <script> ][ <fn f arity=1 upvalues=0> ][ a ][ a ]\n",
+            ]
+        );
+    }
+
+    #[test]
+    fn it_reports_a_runtime_error_raised_by_the_script() {
+        let out = TestOut::default();
+        let e_out = TestOut::default();
+        let source = r#"
+            fun check(n) {
+                if (n < 0) error("Expected a positive number.");
+                return n;
+            }
+            print check(1);
+            check(-1);
+            print "unreachable";
+        "#;
+        let mut vm = VM::new(out, e_out);
+        assert_eq!(vm.interpret(source), Err(Error::Runtime));
+        assert_eq!(vm.out.flushed, vec!["1\n"]);
+        assert_eq!(
+            vm.e_out.flushed,
+            vec![
+                "Expected a positive number.\n",
+                "[line 3] in ",
+                "check\n",
+                "[line 7] in ",
+                "script\n"
+            ]
+        );
+
+        assert_eq!(vm.interpret("error(1);"), Err(Error::Runtime));
+        assert_eq!(
+            vm.e_out.flushed[5],
+            "error() expects a string as its first argument.\n"
+        );
     }
 
     #[test]
@@ -1295,6 +5885,42 @@ mod test {
         assert_eq!(vm.e_out.flushed[2], "script\n".to_string());
     }
 
+    #[test]
+    fn it_reports_a_runtime_error_mixin_conflict() {
+        let out = TestOut::default();
+        let e_out = TestOut::default();
+        let source = r#"
+            class A { m() {} }
+            class B { m() {} }
+            class C with A, B {}
+        "#;
+        let mut vm = VM::new(out, e_out);
+        vm.interpret(source).expect_err("Expected runtime error");
+        assert!(vm.out.flushed.is_empty());
+        assert_eq!(vm.e_out.flushed.len(), 3);
+        assert_eq!(
+            vm.e_out.flushed[0],
+            "Method 'm' is defined by both mixins 'A' and 'B'.\n".to_string()
+        );
+        assert_eq!(vm.e_out.flushed[1], "[line 4] in ".to_string());
+        assert_eq!(vm.e_out.flushed[2], "script\n".to_string());
+    }
+
+    #[test]
+    fn it_reports_a_runtime_error_non_class_mixin() {
+        let out = TestOut::default();
+        let e_out = TestOut::default();
+        let source = r#"
+            var A = 1;
+            class C with A {}
+        "#;
+        let mut vm = VM::new(out, e_out);
+        vm.interpret(source).expect_err("Expected runtime error");
+        assert!(vm.out.flushed.is_empty());
+        assert_eq!(vm.e_out.flushed.len(), 3);
+        assert_eq!(vm.e_out.flushed[0], "Mixin must be a class.\n");
+    }
+
     #[test]
     fn it_reports_a_runtime_error_non_instance_field_get() {
         let out = TestOut::default();
@@ -1313,6 +5939,35 @@ mod test {
         assert_eq!(vm.e_out.flushed[2], "script\n".to_string());
     }
 
+    #[test]
+    fn it_reports_a_runtime_error_non_instance_invoke() {
+        let receivers = [
+            "1",
+            "\"s\"",
+            "nil",
+            "true",
+            "[1]",
+            "1..2",
+            "clock",
+            "copy",
+            "f",
+            "Point(1).sum",
+            "Point(1).x",
+        ];
+        for receiver in receivers {
+            let mut vm = VM::new(TestOut::default(), TestOut::default());
+            vm.set_integers(true);
+            vm.interpret("class Point { init(x) { this.x = x; } sum() {} } fun f() {}")
+                .expect("Failed to run program");
+            let source = format!("var r = {receiver};\nr.foo(1, 2);");
+            assert_eq!(vm.interpret(&source), Err(Error::Runtime), "{receiver}");
+            assert_eq!(
+                vm.e_out.flushed,
+                vec!["Only instances have methods.\n", "[line 2] in ", "script\n"]
+            );
+        }
+    }
+
     #[test]
     fn it_reports_a_runtime_error_non_instance_field_set() {
         let out = TestOut::default();
@@ -1571,9 +6226,21 @@ mod test {
         vm.interpret(source).expect_err("Expected runtime error");
         assert!(vm.out.flushed.is_empty());
         assert!(!vm.e_out.flushed.is_empty());
-        assert_eq!(vm.e_out.flushed.len(), 129);
+        // The message, the first and last ten frames and the omitted count
+        assert_eq!(vm.e_out.flushed.len(), 42);
         assert_eq!(vm.e_out.flushed[0], "Stack overflow.\n");
         assert_eq!(vm.e_out.flushed[1], "[line 2] in ".to_string());
         assert_eq!(vm.e_out.flushed[2], "foo\n".to_string());
+        assert_eq!(vm.e_out.flushed[21], "... 44 frames omitted ...\n");
+        assert_eq!(vm.e_out.flushed[22], "[line 2] in ".to_string());
+        assert_eq!(vm.e_out.flushed[40], "[line 3] in ".to_string());
+        assert_eq!(vm.e_out.flushed[41], "script\n".to_string());
+
+        vm.e_out.flushed.clear();
+        vm.reset(false);
+        vm.set_full_backtrace(true);
+        vm.interpret(source).expect_err("Expected runtime error");
+        assert_eq!(vm.e_out.flushed.len(), 129);
+        assert!(!vm.e_out.flushed.iter().any(|line| line.contains("omitted")));
     }
 }